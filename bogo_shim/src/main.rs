@@ -9,6 +9,7 @@
 
 use std::{env, io::Write, net::TcpStream, process};
 
+use bertie::ErrorKind;
 use simple_https_client::tls13client;
 use simple_https_server::{tls13server, AppError};
 use tracing::Level;
@@ -207,7 +208,9 @@ fn main() {
         Role::Server => {
             if let Err(e) = tls13server(stream, &options.hostname) {
                 match e {
-                    AppError::TLS(137) => eprintln!("Wrong TLS protocol version {:?}", e),
+                    AppError::TLS(err) if err.kind() == ErrorKind::ProtocolVersion => {
+                        eprintln!("Wrong TLS protocol version {:?}", e)
+                    }
                     _ => eprintln!("Bertie server error {:?}", e),
                 }
             }
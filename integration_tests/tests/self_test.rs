@@ -21,7 +21,8 @@ fn self_test() {
             listener.accept().unwrap()
         };
 
-        tls13server(stream, "127.0.0.1").unwrap();
+        // The server certificate is issued for bertie.hacspec.
+        tls13server(stream, "bertie.hacspec").unwrap();
 
         println!("Server finished.");
     });
@@ -34,10 +35,10 @@ fn self_test() {
     println!("Client connected to 127.0.0.1:{}.", port);
 
     let (_, _, data) = tls13client(
-        "127.0.0.1",
+        "bertie.hacspec",
         client,
         None,
-        "GET / HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n",
+        "GET / HTTP/1.1\r\nHost: bertie.hacspec\r\n\r\n",
     )
     .unwrap();
 
@@ -14,7 +14,7 @@ mod stream;
 pub enum AppError {
     #[error("I/O error: {0:?}")]
     Io(std::io::Error),
-    #[error("TLS error: {0:?}")]
+    #[error("TLS error: {0}")]
     TLS(TLSError),
 }
 
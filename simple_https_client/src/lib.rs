@@ -7,7 +7,7 @@
 #![allow(non_upper_case_globals)]
 
 use anyhow::Result;
-use bertie::{tls13api::*, tls13crypto::*, tls13utils::*};
+use bertie::{parse_alert_record, tls13api::*, tls13crypto::*, tls13utils::*, PaddingPolicy};
 use std::fmt::Debug;
 use std::io::{Read, Write};
 
@@ -15,7 +15,6 @@ use rand::*;
 use record::{AppError, RecordStream};
 use tracing::info;
 
-#[allow(dead_code)]
 const SHA256_Aes128Gcm_EcdsaSecp256r1Sha256_X25519: Algorithms = Algorithms(
     HashAlgorithm::SHA256,
     AeadAlgorithm::Aes128Gcm,
@@ -124,6 +123,7 @@ const SHA384_Aes256Gcm_EcdsaSecp256r1Sha256_P256: Algorithms = Algorithms(
     false,
 );
 
+/// The algorithms offered by default, in order of preference.
 pub fn ciphersuites() -> Vec<Algorithms> {
     vec![
         // SHA256_Chacha20Poly1305_RsaPssRsaSha256_X25519,
@@ -131,11 +131,11 @@ pub fn ciphersuites() -> Vec<Algorithms> {
         SHA256_Chacha20Poly1305_EcdsaSecp256r1Sha256_P256,
         SHA256_Chacha20Poly1305_RsaPssRsaSha256_P256,
         // SHA256_Aes128Gcm_EcdsaSecp256r1Sha256_P256,
-        // SHA256_Aes128Gcm_EcdsaSecp256r1Sha256_X25519,
+        SHA256_Aes128Gcm_EcdsaSecp256r1Sha256_X25519,
         // SHA256_Aes128Gcm_RsaPssRsaSha256_P256,
         // SHA256_Aes128Gcm_RsaPssRsaSha256_X25519,
         // SHA384_Aes256Gcm_EcdsaSecp256r1Sha256_P256,
-        // SHA384_Aes256Gcm_EcdsaSecp256r1Sha256_X25519,
+        SHA384_Aes256Gcm_EcdsaSecp256r1Sha256_X25519,
         // SHA384_Aes256Gcm_RsaPssRsaSha256_P256,
         // SHA384_Aes256Gcm_RsaPssRsaSha256_X25519,
     ]
@@ -145,7 +145,7 @@ pub fn ciphersuites() -> Vec<Algorithms> {
 pub fn tls13client<Stream>(
     host: &str,
     stream: Stream,
-    algorithms: impl Into<Option<Algorithms>> + Debug,
+    algorithms: impl Into<Option<Vec<Algorithms>>> + Debug,
     request: &str,
 ) -> Result<(RecordStream<Stream>, Client, Vec<u8>), AppError>
 where
@@ -153,7 +153,7 @@ where
 {
     let algorithms = match algorithms.into() {
         Some(a) => a,
-        None => SHA256_Chacha20Poly1305_EcdsaSecp256r1Sha256_X25519,
+        None => ciphersuites(),
     };
     // Create a stream that is framed by TLS records.
     let mut stream = RecordStream::new(stream);
@@ -163,59 +163,83 @@ where
     // Initialize TLS 1.3 client.
     let (client_hello, cstate) = {
         let sni = Bytes::from(host.as_bytes());
+        // The client random, a key share for each group, and the key shares
+        // of a HelloRetryRequest.
         let ent = {
-            let mut entropy = [0u8; 64];
+            let mut entropy = [0u8; 256];
             thread_rng().fill(&mut entropy);
             Entropy::from(&entropy)
         };
 
-        client_connect(algorithms, &sni, None, None, ent)?
+        client_connect(
+            &algorithms,
+            &sni,
+            None,
+            None,
+            &[],
+            None,
+            PaddingPolicy::NoPadding,
+            ent,
+        )?
     };
 
     stream.write_record(client_hello)?;
 
     let server_hello = stream.read_record()?;
 
-    // TODO: Who should do this check?
-    if eq1(server_hello[0], 21.into()) {
-        // Alert
+    if let Ok((_, ad)) = parse_alert_record(&server_hello) {
         eprintln!(
-            "Server does not support proposed algorithms. {:?}",
-            algorithms
+            "Server sent alert {:?}, proposed algorithms: {:?}",
+            ad, algorithms
         );
-        return Err(UNSUPPORTED_ALGORITHM.into());
+        return Err(got_alert(ad).into());
     }
 
     let cstate = match client_read_handshake(&server_hello, cstate) {
-        Ok((_, cstate)) => cstate,
+        Ok((None, cstate)) => cstate,
+        Ok((Some(client_hello), cstate)) => {
+            // The server sent a HelloRetryRequest: send the second ClientHello
+            // and process the real ServerHello.
+            stream.write_record(client_hello)?;
+            let mut server_hello = stream.read_record()?;
+            if verify_ccs_message(server_hello.clone()).is_ok() {
+                server_hello = stream.read_record()?;
+            }
+            match client_read_handshake(&server_hello, cstate) {
+                Ok((None, cstate)) => cstate,
+                Ok((Some(_), _)) => return Err(INCORRECT_STATE.into()),
+                Err(e) => {
+                    eprintln!("Bertie client error: {}", e);
+                    return Err(e.into());
+                }
+            }
+        }
         Err(e) => {
-            match e {
-                6 => eprintln!("Server does not support proposed algorithms."),
-                137 => eprintln!("Wrong TLS protocol version TLS({:?})", e),
-                138 => eprintln!("Server sent application data instead of a handshake message."),
-                139 => eprintln!("Hello message was missing a key share."),
-                _ => eprintln!("Bertie client error {}", e),
+            match e.kind() {
+                ErrorKind::UnsupportedAlgorithm | ErrorKind::NegotiationMismatch => {
+                    eprintln!("Server does not support proposed algorithms.")
+                }
+                _ => eprintln!("Bertie client error: {}", e),
             }
             return Err(e.into());
         }
     };
 
-    let change_cipher_spec = stream.read_record()?;
-    verify_ccs_message(change_cipher_spec)?;
-
     let mut cf_rec = None;
     let mut cstate = cstate;
     while cf_rec.is_none() {
         let rec = stream.read_record()?;
 
+        // The server's single CCS may come after the HelloRetryRequest or
+        // after the ServerHello.
+        if verify_ccs_message(rec.clone()).is_ok() {
+            continue;
+        }
+
         let (new_cf_rec, new_cstate) = match client_read_handshake(&rec, cstate) {
             Ok((new_cf_rec, new_cstate)) => (new_cf_rec, new_cstate),
             Err(e) => {
-                match e {
-                    7 => eprintln!("Invalid server signature"), // signature verification failed
-                    140 => eprintln!("Invalid server signature"), // parsing of the certificate failed
-                    _ => eprintln!("Bertie client error {}", e),
-                }
+                eprintln!("Bertie client error: {}", e);
                 return Err(e.into());
             }
         };
@@ -250,7 +274,16 @@ where
         let rec = stream.read_record()?;
 
         let (new_ad, new_cstate) = client_read(&rec, cstate)?;
-        ad = new_ad;
+        ad = match new_ad {
+            Some(Received::AppData(d)) => Some(d),
+            Some(Received::Alert(level, ad)) => {
+                eprintln!("Server sent alert {:?} ({:?})", ad, level);
+                return Err(got_alert(ad).into());
+            }
+            // The server closed the connection without a response.
+            Some(Received::Closed) => Some(app_data(Bytes::new())),
+            None => None,
+        };
         cstate = new_cstate;
     }
 
@@ -279,7 +312,16 @@ where
         let rec = stream.read_record()?;
 
         let (new_ad, new_cstate) = client_read(&rec, cstate)?;
-        ad = new_ad;
+        ad = match new_ad {
+            Some(Received::AppData(d)) => Some(d),
+            Some(Received::Alert(level, ad)) => {
+                eprintln!("Server sent alert {:?} ({:?})", ad, level);
+                return Err(got_alert(ad).into());
+            }
+            // The server closed the connection without a response.
+            Some(Received::Closed) => Some(app_data(Bytes::new())),
+            None => None,
+        };
         cstate = new_cstate;
     }
 
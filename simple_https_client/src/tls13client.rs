@@ -1,16 +1,13 @@
 use std::{env, net::TcpStream, str::FromStr};
 
 use anyhow::Context;
-use bertie::tls13utils::*;
-use record::AppError;
-use simple_https_client::{ciphersuites, tls13client};
+use simple_https_client::tls13client;
 use tracing::{error, trace};
 
 /// This is a demo of a simple HTTPS client.
 ///
 /// The client connects to host:port via TCP, executes a TLS 1.3 handshake,
 /// sends an encrypted HTTP GET, and prints the servers HTTP response.
-#[allow(clippy::never_loop)]
 fn main() -> anyhow::Result<()> {
     // Setup tracing.
     tracing_subscriber::fmt::init();
@@ -32,34 +29,22 @@ fn main() -> anyhow::Result<()> {
 
     let request = format!("GET / HTTP/1.1\r\nHost: {}\r\n\r\n", host);
 
-    // FIXME: #51 This is going to go away as soon as Bertie supports multiple
-    //        ciphersuites.
-    let mut ciphersuites = ciphersuites();
-    let mut response_prefix = Vec::new();
-    for algorithms in ciphersuites.drain(..) {
-        // Initiate HTTPS connection to host:port.
-        let stream = TcpStream::connect((host.clone(), port))?;
-        stream.set_nodelay(true).expect("set_nodelay call failed");
-        trace!(
-            host = format!("{}:{}", host, port),
-            "Opened TCP connection to host."
-        );
-
-        response_prefix = match tls13client(&host, stream, algorithms, &request) {
-            Ok((_, _, response_prefix)) => response_prefix,
-            Err(e) => {
-                // We ignore all errors here for now and keep trying.
-                eprintln!("tls13connet failed with {}", e);
-                break; // TODO FIX
-                       //continue;
-            }
-        };
-        break;
-    }
-    if response_prefix.is_empty() {
-        error!("Unable to connect with the configured ciphersuites.");
-        return Err(AppError::TLS(UNSUPPORTED_ALGORITHM).into());
-    }
+    // Initiate HTTPS connection to host:port.
+    let stream = TcpStream::connect((host.clone(), port))?;
+    stream.set_nodelay(true).expect("set_nodelay call failed");
+    trace!(
+        host = format!("{}:{}", host, port),
+        "Opened TCP connection to host."
+    );
+
+    // All default ciphersuites are offered at once and the server picks one.
+    let response_prefix = match tls13client(&host, stream, None, &request) {
+        Ok((_, _, response_prefix)) => response_prefix,
+        Err(e) => {
+            error!("Unable to connect: {}", e);
+            return Err(e.into());
+        }
+    };
 
     println!("[!] Received HTTP response (prefix):");
     println!("{}", String::from_utf8_lossy(&response_prefix));
@@ -55,14 +55,14 @@ fn test_adhoc() {
         let _ = tls13client(
             "127.0.0.1",
             stream,
-            Algorithms(
+            vec![Algorithms(
                 HashAlgorithm::SHA256,
                 AeadAlgorithm::Chacha20Poly1305,
                 SignatureScheme::EcdsaSecp256r1Sha256,
                 KemScheme::Secp256r1,
                 false,
                 false,
-            ),
+            )],
             "",
         );
     }
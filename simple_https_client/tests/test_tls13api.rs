@@ -10,6 +10,14 @@ fn load_hex(s: &str) -> Bytes {
     Bytes::from_hex(&s_no_ws)
 }
 
+// The application data returned by a read.
+fn received_data(r: Option<Received>) -> Bytes {
+    match r {
+        Some(Received::AppData(d)) => app_data_bytes(d),
+        _ => panic!("expected application data"),
+    }
+}
+
 // ECDH keys
 
 const client_x25519_priv: &str = "49 af 42 ba 7f 79 94 85 2d 71 3e f2 78
@@ -64,7 +72,6 @@ const ECDSA_P256_SHA256_Key: [u8; 32] = [
     0xA6, 0xDE, 0x48, 0x21, 0x0E, 0x56, 0x12, 0xDD, 0x95, 0x3A, 0x91, 0x4E, 0x9F, 0x56, 0xC3, 0xA2,
     0xDB, 0x7A, 0x36, 0x20, 0x08, 0xE9, 0x52, 0xEE, 0xDB, 0xCE, 0xAC, 0x3B, 0x26, 0xF9, 0x20, 0xBD,
 ];
-
 const TLS_AES_128_GCM_SHA256_X25519_RSA: Algorithms = Algorithms(
     HashAlgorithm::SHA256,
     AeadAlgorithm::Aes128Gcm,
@@ -96,28 +103,41 @@ fn test_full_round_trip() {
     let cr = random_bytes(32);
     let x = load_hex(client_x25519_priv);
     let ent_c = cr.concat(&x);
-    let sn = load_hex("6c 6f 63 61 6c 68 6f 73 74");
-    let sn_ = load_hex("6c 6f 63 61 6c 68 6f 73 74");
+    let sn = Bytes::from("bertie.hacspec".as_bytes());
+    let sn_ = Bytes::from("bertie.hacspec".as_bytes());
     let sr = random_bytes(32);
     let y = load_hex(server_x25519_priv);
-    let ent_s = sr.concat(&y);
+    // The server random, the key share and the signature.
+    let ent_s = sr.concat(&y).concat(&random_bytes(32));
 
     let db = ServerDB(
         sn_,
-        Bytes::from(&ECDSA_P256_SHA256_CERT),
+        vec![Bytes::from(&ECDSA_P256_SHA256_CERT)],
         bertie::tls13crypto::SignatureKey::from(&ECDSA_P256_SHA256_Key),
         None,
+        None,
+        vec![],
+        PaddingPolicy::NoPadding,
     );
 
     let mut b = true;
-    match client_connect(TLS_CHACHA20_POLY1305_SHA256_X25519, &sn, None, None, ent_c) {
+    match client_connect(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        &sn,
+        None,
+        None,
+        &[],
+        None,
+        PaddingPolicy::NoPadding,
+        ent_c,
+    ) {
         Err(x) => {
             println!("Client0 Error {}", x);
             b = false;
         }
         Ok((ch, cstate)) => {
             println!("Client0 Complete");
-            match server_accept(TLS_CHACHA20_POLY1305_SHA256_X25519, db, &ch, ent_s) {
+            match server_accept(&[TLS_CHACHA20_POLY1305_SHA256_X25519], db, &ch, ent_s) {
                 Err(x) => {
                     println!("ServerInit Error {}", x);
                     b = false;
@@ -157,7 +177,7 @@ fn test_full_round_trip() {
                                         let (ap, cstate) =
                                             client_write(app_data(data.clone()), cstate).unwrap();
                                         let (apo, sstate) = server_read(&ap, sstate).unwrap();
-                                        assert!(eq(&data, &app_data_bytes(apo.unwrap())));
+                                        assert!(eq(&data, &received_data(apo)));
 
                                         // Send data from server to client.
                                         let data =
@@ -165,7 +185,7 @@ fn test_full_round_trip() {
                                         let (ap, _sstate) =
                                             server_write(app_data(data.clone()), sstate).unwrap();
                                         let (apo, _cstate) = client_read(&ap, cstate).unwrap();
-                                        assert!(eq(&data, &app_data_bytes(apo.unwrap())));
+                                        assert!(eq(&data, &received_data(apo)));
                                     }
                                 }
                             }
@@ -3,13 +3,14 @@
 // Import hacspec and all needed definitions.
 use std::io::prelude::*;
 
-use bertie::{tls13api::*, tls13crypto::*, tls13utils::*, ServerDB};
+use bertie::{
+    alert_record, error_alert, tls13api::*, tls13crypto::*, tls13utils::*, PaddingPolicy, ServerDB,
+};
 
 use rand::*;
 pub use record::AppError;
 use record::RecordStream;
 
-#[allow(dead_code)]
 const SHA256_Aes128Gcm_EcdsaSecp256r1Sha256_X25519: Algorithms = Algorithms(
     HashAlgorithm::SHA256,
     AeadAlgorithm::Aes128Gcm,
@@ -48,7 +49,11 @@ const SHA256_Chacha20Poly1305_RsaPssRsaSha256_X25519: Algorithms = Algorithms(
     false,
 );
 
-const default_algs: Algorithms = SHA256_Chacha20Poly1305_EcdsaSecp256r1Sha256_X25519;
+// The server's choices, in order of preference.
+const default_algs: [Algorithms; 2] = [
+    SHA256_Chacha20Poly1305_EcdsaSecp256r1Sha256_X25519,
+    SHA256_Aes128Gcm_EcdsaSecp256r1Sha256_X25519,
+];
 
 // Cert and Key
 const ECDSA_P256_SHA256_CERT: [u8; 522] = [
@@ -107,51 +112,79 @@ where
 
         ServerDB(
             sni,
-            Bytes::from(&ECDSA_P256_SHA256_CERT),
+            vec![Bytes::from(&ECDSA_P256_SHA256_CERT)],
             SignatureKey::from(&ECDSA_P256_SHA256_Key),
             None,
+            None,
+            vec![],
+            PaddingPolicy::NoPadding,
         )
     };
 
-    let ent_s = {
-        let mut entropy = [0u8; 64];
-        thread_rng().fill(&mut entropy);
-
-        Entropy::from(&entropy)
-    };
-
-    match server_accept(default_algs, db, &ch_rec, ent_s) {
+    match server_accept(&default_algs, db, &ch_rec, entropy()) {
         Err(x) => {
             println!("ServerInit Error {}", x);
-            match x {
-                136 => {
-                    stream.write_record(Bytes::from(&[21, 03, 03, 00, 02, 2, 47]))?;
-                }
-                137 => {
-                    stream.write_record(Bytes::from(&[21, 03, 03, 00, 02, 2, 70]))?;
-                }
-                139 => {
-                    // alerts here are optional
-                    eprintln!("Hello message was missing a key share.");
-                }
-                _ => unimplemented!("unknown error {}", x),
-            }
+            stream.write_record(alert_record(error_alert(x))?)?;
             Err(x.into())
         }
-        Ok((sh, sf, sstate)) => {
+        Ok((mut sh, mut sf, mut sstate)) => {
+            // The ClientHello may span several records.
+            while in_client_hello(&sstate) && !in_hello_retry(&sstate) {
+                let ch_rec = stream.read_record()?;
+                (sh, sf, sstate) = server_accept_retry(&ch_rec, sstate, entropy())?;
+            }
             println!("Negotiation Complete");
-            stream.write_record(sh)?;
             let ccs_rec = Bytes::from_hex("140303000101");
-            stream.write_record(ccs_rec)?;
+            let (sf, sstate) = if in_hello_retry(&sstate) {
+                // The first ClientHello had no usable key share: send the
+                // HelloRetryRequest and process the second ClientHello.
+                println!("Sending HelloRetryRequest");
+                stream.write_record(sh)?;
+                stream.write_record(ccs_rec)?;
+                let mut ch_rec = stream.read_record()?;
+                if check_ccs_message(&ch_rec.declassify()).is_ok() {
+                    ch_rec = stream.read_record()?;
+                }
+                let (mut sh, mut sf, mut sstate) = server_accept_retry(&ch_rec, sstate, entropy())?;
+                while in_client_hello(&sstate) {
+                    let ch_rec = stream.read_record()?;
+                    (sh, sf, sstate) = server_accept_retry(&ch_rec, sstate, entropy())?;
+                }
+                stream.write_record(sh)?;
+                (sf, sstate)
+            } else {
+                stream.write_record(sh)?;
+                stream.write_record(ccs_rec)?;
+                (sf, sstate)
+            };
             stream.write_record(sf)?;
             //println!("Server0.5 Complete");
 
-            let ccs = stream.read_record()?;
-            check_ccs_message(&ccs.declassify())?;
-            //println!("Got CCS message");
-            let cf_rec = stream.read_record()?;
+            // The client sends a single CCS, either before its second
+            // ClientHello or before its Finished.
+            let mut cf_rec = stream.read_record()?;
+            if check_ccs_message(&cf_rec.declassify()).is_ok() {
+                //println!("Got CCS message");
+                cf_rec = stream.read_record()?;
+            }
             //println!("Got fin record");
-            let sstate = server_read_handshake(&cf_rec, sstate)?;
+            let alerts = server_alert_sender(&sstate);
+            let mut sstate = sstate;
+            // The client's flight may span several records.
+            let sstate = loop {
+                match server_read_handshake(&cf_rec, sstate) {
+                    Ok(next) if in_handshake(&next) => {
+                        sstate = next;
+                        cf_rec = stream.read_record()?;
+                    }
+                    Ok(next) => break next,
+                    Err(x) => {
+                        println!("Server Finish Error {}", x);
+                        stream.write_record(send_alert(error_alert(x), &alerts)?)?;
+                        return Err(x.into());
+                    }
+                }
+            };
             println!("Handshake Complete");
 
             let app_rec = stream.read_record()?;
@@ -159,15 +192,25 @@ where
             println!("Got HTTP Request");
 
             let http_get_resp = Bytes::from(response.as_bytes());
-            let (ap, _sstate) = server_write(app_data(http_get_resp), sstate)?;
+            let (ap, sstate) = server_write(app_data(http_get_resp), sstate)?;
             stream.write_record(ap)?;
             println!("Sent HTTP Response: Hello from localhost");
 
+            stream.write_record(server_close(sstate)?)?;
+
             Ok(())
         }
     }
 }
 
+// The server random, the key share and the signature.
+fn entropy() -> Entropy {
+    let mut entropy = [0u8; 256];
+    thread_rng().fill(&mut entropy);
+
+    Entropy::from(&entropy)
+}
+
 fn check_ccs_message(buf: &[u8]) -> Result<(), TLSError> {
     if buf.len() == 6
         && buf[0] == 0x14
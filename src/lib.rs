@@ -22,5 +22,7 @@ pub mod tls13api;
 pub use std::convert::TryInto;
 pub use tls13api::*;
 pub use tls13formats::*;
+pub use tls13handshake::{SessionTicket, MAX_EARLY_DATA_SIZE, MAX_TICKET_LIFETIME};
+pub use tls13record::PaddingPolicy;
 pub mod server;
 pub use server::*;
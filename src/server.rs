@@ -1,10 +1,25 @@
-use crate::{check_eq, eq, parse_failed, Bytes, SignatureKey, TLSError, PSK, PSK_MODE_MISMATCH};
+use crate::{
+    check_eq, eq, parse_failed, Bytes, PaddingPolicy, SignatureKey, TLSError, PSK,
+    PSK_MODE_MISMATCH,
+};
 
+/// The server configuration: its name, certificate chain (the end-entity
+/// certificate first, then the intermediates) and signature key, an
+/// optional ticket with its PSK, the trust anchors and current time to
+/// validate client certificates with, if it requests one, the application
+/// protocols it supports in order of preference, and the padding of its
+/// records.
+///
+/// The trust anchors are DER encoded certificates and the time is in seconds
+/// since the UNIX epoch, like the client's in `client_connect`.
 pub struct ServerDB(
     pub Bytes,
-    pub Bytes,
+    pub Vec<Bytes>,
     pub SignatureKey,
     pub Option<(Bytes, PSK)>,
+    pub Option<(Vec<Bytes>, u64)>,
+    pub Vec<Bytes>,
+    pub PaddingPolicy,
 );
 
 pub fn lookup_db(
@@ -12,8 +27,8 @@ pub fn lookup_db(
     db: &ServerDB,
     sni: &Bytes,
     tkt: &Option<Bytes>,
-) -> Result<(Bytes, SignatureKey, Option<PSK>), TLSError> {
-    let ServerDB(server_name, cert, sk, psk_opt) = db;
+) -> Result<(Vec<Bytes>, SignatureKey, Option<PSK>), TLSError> {
+    let ServerDB(server_name, cert, sk, psk_opt, _client_auth, _protocols, _padding) = db;
     if eq(sni, &Bytes::new()) || eq(sni, server_name) {
         match (crate::psk_mode(&algs), tkt, psk_opt) {
             (true, Some(ctkt), Some((stkt, psk))) => {
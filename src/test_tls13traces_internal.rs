@@ -265,12 +265,12 @@ d8 7f 38 f8 03 38 ac 98 fc 46 de b3 84 bd 1c ae ac ab 68 67 d7
             Err(x) => {
                 println!("Error: {}", x);
             }
-            Ok((cr, sid, sn, gx, tkto, bo, l)) => {
+            Ok((cr, sid, sn, gx, tkto, bo, l, ed, alpn)) => {
                 println!("Parsed CH!");
                 println!("cr: {}", cr.to_hex());
                 println!("sid: {}", sid.to_hex());
                 println!("sn: {}", sn.to_hex());
-                println!("gx: {}", gx.to_hex());
+                println!("gx: {}", gx.unwrap().to_hex());
                 println!("trunc_len: {}", l);
             }
         }
@@ -293,12 +293,12 @@ d8 7f 38 f8 03 38 ac 98 fc 46 de b3 84 bd 1c ae ac ab 68 67 d7
                     println!("Error: {}", x);
                     b = false;
                 }
-                Ok((cr, sid, sn, gx, tkto, bo, l)) => {
+                Ok((cr, sid, sn, gx, tkto, bo, l, ed, alpn)) => {
                     println!("Parsed CH!");
                     println!("cr: {}", cr.to_hex());
                     println!("sid: {}", sid.to_hex());
                     println!("sn: {}", sn.to_hex());
-                    println!("gx: {}", gx.to_hex());
+                    println!("gx: {}", gx.unwrap().to_hex());
                     println!("trunc_len: {}", l);
                 }
             },
@@ -312,11 +312,13 @@ d8 7f 38 f8 03 38 ac 98 fc 46 de b3 84 bd 1c ae ac ab 68 67 d7
         let gx = Bytes::from_hex(client_x25519_pub);
         let sn = Bytes::zeroes(23);
         let ch = crate::tls13formats::client_hello(
-            &TLS_AES_128_GCM_SHA256_X25519_RSA,
+            &[TLS_AES_128_GCM_SHA256_X25519_RSA],
             &cr,
-            &gx,
+            &[(KemScheme::X25519, gx)],
             &sn,
             &None,
+            &None,
+            &[],
         );
         let mut b = true;
         match ch {
@@ -332,12 +334,12 @@ d8 7f 38 f8 03 38 ac 98 fc 46 de b3 84 bd 1c ae ac ab 68 67 d7
                     Err(x) => {
                         println!("Error: {}", x);
                     }
-                    Ok((cr, sid, sn, gx, tkto, bo, l)) => {
+                    Ok((cr, sid, sn, gx, tkto, bo, l, ed, alpn)) => {
                         println!("Parsed CH!");
                         println!("cr: {}", cr.to_hex());
                         println!("sid: {}", sid.to_hex());
                         println!("sn: {}", sn.to_hex());
-                        println!("gx: {}", gx.to_hex());
+                        println!("gx: {}", gx.unwrap().to_hex());
                         println!("trunc_len: {}", l);
                     }
                 }
@@ -346,6 +348,23 @@ d8 7f 38 f8 03 38 ac 98 fc 46 de b3 84 bd 1c ae ac ab 68 67 d7
         assert!(b);
     }
 
+    #[test]
+    fn test_hello_retry_request_roundtrip() {
+        let sid = Bytes::zeroes(32);
+        let cookie = Bytes::from_hex("c00c1e");
+        let hrr = hello_retry_request(
+            &TLS_AES_128_GCM_SHA256_X25519_RSA,
+            &sid,
+            &Some(cookie.clone()),
+        )
+        .unwrap();
+        assert!(is_hello_retry_request(&hrr));
+        let (group, ck) =
+            parse_hello_retry_request(&TLS_AES_128_GCM_SHA256_X25519_RSA, &hrr).unwrap();
+        assert_eq!(group, Some(KemScheme::X25519));
+        assert_eq!(ck.unwrap().to_hex(), cookie.to_hex());
+    }
+
     #[test]
     fn test_parse_server_hello() {
         let sh = handshake_data(Bytes::from_hex(server_hello));
@@ -414,8 +433,10 @@ d8 7f 38 f8 03 38 ac 98 fc 46 de b3 84 bd 1c ae ac ab 68 67 d7
             Err(x) => {
                 println!("Error: {}", x);
             }
-            Ok(()) => {
+            Ok((ed, alpn)) => {
                 println!("Parsed EE!");
+                println!("early data: {}", ed);
+                println!("alpn: {:?}", alpn);
             }
         }
         assert!(b);
@@ -446,8 +467,9 @@ d8 7f 38 f8 03 38 ac 98 fc 46 de b3 84 bd 1c ae ac ab 68 67 d7
             Err(x) => {
                 println!("Error: {}", x);
             }
-            Ok(_) => {
+            Ok((sa, _)) => {
                 println!("Parsed CV!");
+                assert_eq!(sa, SignatureScheme::RsaPssRsaSha256);
             }
         }
         assert!(b);
@@ -541,7 +563,7 @@ d8 7f 38 f8 03 38 ac 98 fc 46 de b3 84 bd 1c ae ac ab 68 67 d7
                                     Err(x) => {
                                         println!("Error: {}", x);
                                     }
-                                    Ok(((k1, iv1), (k2, iv2), ms)) => {
+                                    Ok(((k1, iv1), (k2, iv2), _cats, _sats, ms)) => {
                                         println!("Derive Succeeded!");
                                         println!("cak: key {} \n iv {}", k1.to_hex(), iv1.to_hex());
                                         println!("sak: key {} \n iv {}", k2.to_hex(), iv2.to_hex());
@@ -557,6 +579,33 @@ d8 7f 38 f8 03 38 ac 98 fc 46 de b3 84 bd 1c ae ac ab 68 67 d7
         assert!(b);
     }
 
+    #[test]
+    fn test_exporter() {
+        let exp =
+            Bytes::from_hex("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+        let ekm = derive_exporter(
+            &HashAlgorithm::SHA256,
+            &exp,
+            &Bytes::from(b"EXPORTER-Channel-Binding"),
+            &Bytes::new(),
+            32,
+        )
+        .unwrap();
+        assert_eq!(
+            ekm.to_hex(),
+            "34a93b1dd3c2b63ebc1bced0d7d37ade4e53c057e4d368578a5bef73771e2a1b"
+        );
+        let ekm = derive_exporter(
+            &HashAlgorithm::SHA256,
+            &exp,
+            &Bytes::from(b"EXPORTER-Test"),
+            &Bytes::from(b"context"),
+            20,
+        )
+        .unwrap();
+        assert_eq!(ekm.to_hex(), "5ede04ef3d64d557ccea3ba191c05a0b6458e2a8");
+    }
+
     /* #[test]
        fn test_ecdh() {
            let x = Bytes::from_hex(client_x25519_priv);
@@ -6,155 +6,1088 @@ use crate::tls13handshake::*;
 use crate::tls13record::*;
 use crate::tls13utils::*;
 
+// What a read on an established connection yields: application data, an
+// alert from the peer, or the end of the stream when the peer sent a
+// close_notify. A transport that ends without Closed was truncated.
+pub enum Received {
+    AppData(AppData),
+    Alert(AlertLevel, AlertDescription),
+    Closed,
+}
+
+fn read_alert(payload: &Bytes) -> Result<Received, TLSError> {
+    match parse_alert(payload)? {
+        (_, AlertDescription::CloseNotify) => Ok(Received::Closed),
+        (level, ad) => Ok(Received::Alert(level, ad)),
+    }
+}
+
+// The keys for sending an alert, taken from a connection state before an
+// operation that may fail and consume it, with the connection's padding
+// policy. Without keys, alerts are sent in plaintext.
+pub struct AlertSender(Option<(SenderCipherState, PaddingPolicy)>);
+
+// Encodes an alert record for `ad`, at the level the alert requires.
+pub fn send_alert(ad: AlertDescription, sender: &AlertSender) -> Result<Bytes, TLSError> {
+    match sender {
+        AlertSender(None) => alert_record(ad),
+        AlertSender(Some((cipher, pad))) => encrypt_alert(&alert(ad), pad, cipher),
+    }
+}
+
+// The handshake states buffer the handshake messages received so far, as a
+// message may span several records. All states keep the padding policy for
+// our records.
 pub enum Client {
-    Client0(ClientPostClientHello, Option<ClientCipherState0>),
+    Client0(
+        ClientPostClientHello,
+        Option<ClientCipherState0>,
+        HandshakeData,
+        PaddingPolicy,
+    ),
     ClientH(
         ClientPostServerHello,
         Option<ClientCipherState0>,
         DuplexCipherStateH,
         HandshakeData,
+        PaddingPolicy,
+    ),
+    // The bool is set when the server asked for a KeyUpdate that we have not
+    // sent yet.
+    Client1(
+        ClientPostClientFinished,
+        DuplexCipherState1,
+        Vec<SessionTicket>,
+        bool,
+        PaddingPolicy,
     ),
-    Client1(ClientPostClientFinished, DuplexCipherState1),
+}
+
+// The stage a client in state `c` is in. The functions below tag their errors
+// with the stage they occur in where they call into the handshake and the
+// record layer.
+fn client_stage(c: &Client) -> Stage {
+    match c {
+        Client::Client0(_, _, _, _) => Stage::ServerHello,
+        Client::ClientH(_, _, _, _, _) => Stage::ServerFlight,
+        Client::Client1(_, _, _, _, _) => Stage::Connected,
+    }
+}
+
+// Fails because `c` is the wrong state for an operation.
+fn client_state_error<T>(c: &Client) -> Result<T, TLSError> {
+    in_stage(Err(INCORRECT_STATE), client_stage(c))
 }
 
 pub fn in_psk_mode(c: &Client) -> bool {
     match c {
-        Client::Client0(cstate, _) => psk_mode(&algs_post_client_hello(cstate)),
-        Client::ClientH(cstate, _, _, _) => psk_mode(&algs_post_server_hello(cstate)),
-        Client::Client1(cstate, _) => psk_mode(&algs_post_client_finished(cstate)),
+        Client::Client0(cstate, _, _, _) => psk_mode(&algs_post_client_hello(cstate)),
+        Client::ClientH(cstate, _, _, _, _) => psk_mode(&algs_post_server_hello(cstate)),
+        Client::Client1(cstate, _, _, _, _) => psk_mode(&algs_post_client_finished(cstate)),
+    }
+}
+
+// Tells whether the server accepted our early data. Only meaningful once the
+// handshake is complete.
+pub fn early_data_accepted(c: &Client) -> bool {
+    match c {
+        Client::Client1(cstate, _, _, _, _) => zero_rtt(&algs_post_client_finished(cstate)),
+        _ => false,
+    }
+}
+
+// The application protocol selected by the server, once the handshake is
+// complete.
+pub fn client_alpn(c: &Client) -> Option<Bytes> {
+    match c {
+        Client::Client1(cstate, _, _, _, _) => alpn_post_client_finished(cstate),
+        _ => None,
+    }
+}
+
+// The algorithms negotiated with the server, once the handshake is complete.
+pub fn client_algorithms(c: &Client) -> Option<Algorithms> {
+    match c {
+        Client::Client1(cstate, _, _, _, _) => Some(algs_post_client_finished(cstate)),
+        _ => None,
+    }
+}
+
+// The server's certificate chain, once the handshake is complete. It is
+// empty in PSK mode.
+pub fn client_peer_certificates(c: &Client) -> Vec<CertificateEntry> {
+    match c {
+        Client::Client1(cstate, _, _, _, _) => peer_certificates_post_client_finished(cstate),
+        _ => Vec::new(),
+    }
+}
+
+pub fn client_alert_sender(c: &Client) -> AlertSender {
+    match c {
+        Client::Client0(_, _, _, _) => AlertSender(None),
+        Client::ClientH(_, _, cipher_hs, _, pad) => {
+            AlertSender(Some((sender_cipher_state_hs(cipher_hs), *pad)))
+        }
+        Client::Client1(_, cipher1, _, _, pad) => {
+            AlertSender(Some((sender_cipher_state1(cipher1), *pad)))
+        }
     }
 }
 
 // Connect
+// Connect, offering the algorithms in `algs` in order of preference.
+// If the server requests a client certificate, we present the certificate
+// chain of `cert`, the end-entity certificate first, and sign with its key;
+// without one, we answer with an empty Certificate.
+// A non-empty `protocols` list is offered to the server for ALPN.
+// With `trust`, the trust anchors as DER encoded certificates and the current
+// time in seconds since the UNIX epoch, the server's certificate chain must
+// lead to one of the trust anchors and be valid at that time; without, any
+// chain is accepted. A non-empty `sn` must match the server certificate.
+// Our encrypted records are padded according to `pad`.
+// With a `ticket`, we resume its session, with `algs` in PSK mode. Algorithms
+// with 0-RTT only offer early data if the ticket allows it, and the
+// application must not write more early data than the ticket allows.
+// The groups of `algs` are offered in order, with key shares for the first
+// `MAX_KEY_SHARES` of them.
+// `ent` starts with 32 bytes for the client random, followed by
+// `kem_priv_len` bytes for each of these key shares. The rest is
+// kept for the key share of a HelloRetryRequest and for signing with `cert`,
+// which takes 32 bytes; all randomness of the handshake comes from `ent`.
+#[allow(clippy::too_many_arguments)]
 pub fn client_connect(
-    algs: Algorithms,
+    algs: &[Algorithms],
     sn: &Bytes,
-    tkt: Option<Bytes>,
-    psk: Option<Key>,
+    ticket: Option<SessionTicket>,
+    cert: Option<(Vec<Bytes>, SignatureKey)>,
+    protocols: &[Bytes],
+    trust: Option<(Vec<Bytes>, u64)>,
+    pad: PaddingPolicy,
     ent: Entropy,
 ) -> Result<(Bytes, Client), TLSError> {
-    let (ch, cipher0, cstate) = client_init(algs, sn, tkt, psk, ent)?;
-    let mut ch_rec = handshake_record(&ch)?;
-    ch_rec[2] = U8::from(0x01);
-    Ok((ch_rec, Client::Client0(cstate, cipher0)))
+    let (tkt, psk, early_data) = match ticket {
+        Some(SessionTicket(tkt, _lifetime, _age_add, max_early_data, _nonce, psk)) => {
+            (Some(tkt), Some(psk), max_early_data > 0)
+        }
+        None => (None, None, false),
+    };
+    let mut offered = Vec::new();
+    for a in algs {
+        let Algorithms(ha, ae, sa, ks, psk_mode, zero_rtt) = *a;
+        offered.push(Algorithms(ha, ae, sa, ks, psk_mode, zero_rtt && early_data));
+    }
+    let (ch, cipher0, cstate) = in_stage(
+        client_init(&offered, sn, tkt, psk, cert, protocols, trust, ent),
+        Stage::ClientHello,
+    )?;
+    let ch_rec = in_stage(client_hello_record(&ch), Stage::ClientHello)?;
+    let buf = handshake_data(Bytes::new());
+    Ok((ch_rec, Client::Client0(cstate, cipher0, buf, pad)))
+}
+
+// Appends the handshake messages in the plaintext records `d` to `buf`, up
+// to the record that completes a message, and returns the records after it.
+// The legacy record version of a ClientHello is ignored.
+fn read_plaintext_records(
+    d: &Bytes,
+    buf: HandshakeData,
+    client_hello: bool,
+) -> Result<(HandshakeData, Bytes), TLSError> {
+    let mut d = d.clone();
+    let mut buf = buf;
+    while !d.is_empty() && !has_handshake_message(&buf) {
+        let (mut rec, rest) = get_first_record(&d)?;
+        if client_hello {
+            rec[2] = U8::from(0x03);
+        }
+        buf = handshake_concat(buf, &get_handshake_record(&rec)?);
+        d = rest;
+    }
+    Ok((buf, d))
+}
+
+// The hello message in `buf`. The keys change after it, so it has to end its
+// record.
+fn get_hello_message(buf: &HandshakeData) -> Result<HandshakeData, TLSError> {
+    let (msg, rest) = get_first_handshake_message(buf)?;
+    if handshake_data_len(&rest) != 0 {
+        tlserr(UNEXPECTED_MESSAGE)
+    } else {
+        Ok(msg)
+    }
+}
+
+// Nothing may follow a hello message before we answered it.
+fn check_no_records(d: &Bytes) -> Result<(), TLSError> {
+    if !d.is_empty() {
+        tlserr(UNEXPECTED_MESSAGE)
+    } else {
+        Ok(())
+    }
+}
+
+// Decrypts the handshake records `d` and appends their messages to `buf`.
+fn read_handshake_records(
+    d: &Bytes,
+    buf: HandshakeData,
+    cipher_hs: DuplexCipherStateH,
+) -> Result<(HandshakeData, DuplexCipherStateH), TLSError> {
+    let mut d = d.clone();
+    let mut buf = buf;
+    let mut cipher_hs = cipher_hs;
+    while !d.is_empty() {
+        let (rec, rest) = get_first_record(&d)?;
+        let (hd, next) = decrypt_handshake(&rec, cipher_hs)?;
+        buf = handshake_concat(buf, &hd);
+        cipher_hs = next;
+        d = rest;
+    }
+    Ok((buf, cipher_hs))
 }
 
 // The following function reads handshake records and decrypts them using the TLS 1.3 record protocol
 // A slightly modified version would work for QUIC
+// `d` holds one or more records. Until a message is complete, nothing is
+// returned and its start is kept in the state.
 pub fn client_read_handshake(d: &Bytes, st: Client) -> Result<(Option<Bytes>, Client), TLSError> {
     match st {
-        Client::Client0(cstate, cipher0) => {
-            let sf = get_handshake_record(d)?;
+        Client::Client0(cstate, cipher0, buf, pad) => in_stage(
+            read_server_hello(d, cstate, cipher0, buf, pad),
+            Stage::ServerHello,
+        ),
+        Client::ClientH(cstate, cipher0, cipher_hs, buf, pad) => in_stage(
+            read_server_flight(d, cstate, cipher0, cipher_hs, buf, pad),
+            Stage::ServerFlight,
+        ),
+        st => client_state_error(&st),
+    }
+}
+
+// Reads the ServerHello, or a HelloRetryRequest that we answer with a second
+// ClientHello. The server's encrypted flight may follow the ServerHello in `d`.
+fn read_server_hello(
+    d: &Bytes,
+    cstate: ClientPostClientHello,
+    cipher0: Option<ClientCipherState0>,
+    buf: HandshakeData,
+    pad: PaddingPolicy,
+) -> Result<(Option<Bytes>, Client), TLSError> {
+    let (buf, rest) = read_plaintext_records(d, buf, false)?;
+    if has_handshake_message(&buf) {
+        let sf = get_hello_message(&buf)?;
+        let buf = handshake_data(Bytes::new());
+        if is_hello_retry_request(&sf) {
+            // Answer the HelloRetryRequest with a second ClientHello.
+            // Any 0-RTT state is dropped.
+            check_no_records(&rest)?;
+            let (ch, cstate) = client_retry(&sf, cstate)?;
+            let ch_rec = handshake_record(&ch)?;
+            Ok((Some(ch_rec), Client::Client0(cstate, None, buf, pad)))
+        } else {
             let (cipher1, cstate) = client_set_params(&sf, cstate)?;
-            let buf = handshake_data(Bytes::new());
-            Ok((None, Client::ClientH(cstate, cipher0, cipher1, buf)))
-        }
-        Client::ClientH(cstate, cipher0, cipher_hs, buf) => {
-            let (hd, cipher_hs) = decrypt_handshake(d, cipher_hs)?;
-            let buf = handshake_concat(buf, &hd);
-            if find_handshake_message(HandshakeType::Finished, &buf, 0) {
-                let (cfin, cipher1, cstate) = client_finish(&buf, cstate)?;
-                let (cf_rec, _cipher_hs) = encrypt_handshake(cfin, 0, cipher_hs)?;
-                Ok((Some(cf_rec), Client::Client1(cstate, cipher1)))
-            } else {
-                Ok((None, Client::ClientH(cstate, cipher0, cipher_hs, buf)))
-            }
+            in_stage(
+                read_server_flight(&rest, cstate, cipher0, cipher1, buf, pad),
+                Stage::ServerFlight,
+            )
         }
-        _ => Err(INCORRECT_STATE),
+    } else {
+        Ok((None, Client::Client0(cstate, cipher0, buf, pad)))
     }
 }
 
-// Reads AppData and Tickets
-pub fn client_read(d: &Bytes, st: Client) -> Result<(Option<AppData>, Client), TLSError> {
+// Reads the server's encrypted flight and, once it is complete, returns our
+// Finished, preceded by an EndOfEarlyData if the server accepted early data.
+fn read_server_flight(
+    d: &Bytes,
+    cstate: ClientPostServerHello,
+    cipher0: Option<ClientCipherState0>,
+    cipher_hs: DuplexCipherStateH,
+    buf: HandshakeData,
+    pad: PaddingPolicy,
+) -> Result<(Option<Bytes>, Client), TLSError> {
+    let (buf, cipher_hs) = read_handshake_records(d, buf, cipher_hs)?;
+    if find_handshake_message(HandshakeType::Finished, &buf, 0) {
+        let (eoed, cfin, cipher1, cstate) = client_finish(&buf, cstate)?;
+        let (cf_rec, _cipher_hs) = encrypt_handshake(cfin, &pad, cipher_hs)?;
+        // Accepted early data ends with an EndOfEarlyData under the 0-RTT keys.
+        let cf_rec = match (eoed, cipher0) {
+            (Some(eoed), Some(cipher0)) => {
+                let (eoed_rec, _cipher0) = encrypt_zerortt_handshake(eoed, &pad, cipher0)?;
+                eoed_rec.concat(&cf_rec)
+            }
+            (Some(_), None) => tlserr(ZERO_RTT_DISABLED)?,
+            (None, _) => cf_rec,
+        };
+        Ok((
+            Some(cf_rec),
+            Client::Client1(cstate, cipher1, Vec::new(), false, pad),
+        ))
+    } else {
+        Ok((None, Client::ClientH(cstate, cipher0, cipher_hs, buf, pad)))
+    }
+}
+
+// Process a KeyUpdate from the peer, which has to end its record.
+// Returns the new cipher state and whether the peer requests an update.
+fn read_key_update(
+    algs: &Algorithms,
+    ku: &HandshakeData,
+    rest: &HandshakeData,
+    cipher1: DuplexCipherState1,
+) -> Result<(bool, DuplexCipherState1), TLSError> {
+    if handshake_data_len(rest) != 0 {
+        Err(PARSE_FAILED)
+    } else {
+        let update_requested = parse_key_update(algs, ku)?;
+        let cipher1 = update_receiver_keys(algs, cipher1)?;
+        Ok((update_requested, cipher1))
+    }
+}
+
+// Send a KeyUpdate and switch to the next sending keys.
+fn send_key_update(
+    algs: &Algorithms,
+    update_requested: bool,
+    pad: &PaddingPolicy,
+    cipher1: DuplexCipherState1,
+) -> Result<(Bytes, DuplexCipherState1), TLSError> {
+    let ku = key_update(algs, update_requested)?;
+    let (by, cipher1) = encrypt_post_handshake(ku, pad, cipher1)?;
+    let cipher1 = update_sender_keys(algs, cipher1)?;
+    Ok((by, cipher1))
+}
+
+// Reads AppData, Alerts, Tickets and KeyUpdates
+// Session tickets are kept in the client state until the application takes
+// them with client_take_tickets. If the server requests a KeyUpdate, ours is
+// sent with the next client_write, or explicitly with client_send_key_update.
+pub fn client_read(d: &Bytes, st: Client) -> Result<(Option<Received>, Client), TLSError> {
     match st {
-        Client::Client1(cstate, cipher1) => {
-            let (ty, hd, cipher1) = decrypt_data_or_hs(d, cipher1)?;
+        Client::Client1(cstate, cipher1, tickets, pending, pad) => {
+            let (ty, hd, cipher1) = in_stage(decrypt_data_or_hs(d, cipher1), Stage::Connected)?;
             match ty {
-                ContentType::ApplicationData => {
-                    Ok((Some(app_data(hd)), Client::Client1(cstate, cipher1)))
-                }
+                ContentType::ApplicationData => Ok((
+                    Some(Received::AppData(app_data(hd))),
+                    Client::Client1(cstate, cipher1, tickets, pending, pad),
+                )),
+                ContentType::Alert => Ok((
+                    Some(in_stage(read_alert(&hd), Stage::Connected)?),
+                    Client::Client1(cstate, cipher1, tickets, pending, pad),
+                )),
                 ContentType::Handshake => {
-                    println!("Received Session Ticket");
-                    Ok((None, Client::Client1(cstate, cipher1)))
+                    let (cipher1, tickets, pending) = in_stage(
+                        read_post_handshake(handshake_data(hd), &cstate, cipher1, tickets, pending),
+                        Stage::Connected,
+                    )?;
+                    Ok((
+                        None,
+                        Client::Client1(cstate, cipher1, tickets, pending, pad),
+                    ))
                 }
-                _ => Err(PARSE_FAILED),
+                _ => in_stage(Err(PARSE_FAILED), Stage::Connected),
             }
         }
-        _ => Err(INCORRECT_STATE),
+        st => client_state_error(&st),
     }
 }
 
-// Writes AppData
+// Reads the KeyUpdates and NewSessionTickets in `msgs`, adding the tickets to
+// `tickets` and setting `pending` if the server requests a KeyUpdate.
+fn read_post_handshake(
+    msgs: HandshakeData,
+    cstate: &ClientPostClientFinished,
+    cipher1: DuplexCipherState1,
+    tickets: Vec<SessionTicket>,
+    pending: bool,
+) -> Result<(DuplexCipherState1, Vec<SessionTicket>, bool), TLSError> {
+    let algs = algs_post_client_finished(cstate);
+    let mut msgs = msgs;
+    let mut cipher1 = cipher1;
+    let mut tickets = tickets;
+    let mut pending = pending;
+    while handshake_data_len(&msgs) > 0 {
+        let (msg, rest) = get_first_handshake_message(&msgs)?;
+        if find_handshake_message(HandshakeType::KeyUpdate, &msg, 0) {
+            let (update_requested, next) = read_key_update(&algs, &msg, &rest, cipher1)?;
+            cipher1 = next;
+            pending = pending || update_requested;
+        } else {
+            tickets.push(client_ticket(&msg, cstate)?);
+        }
+        msgs = rest;
+    }
+    Ok((cipher1, tickets, pending))
+}
+
+// Takes the session tickets received so far.
+pub fn client_take_tickets(st: Client) -> (Vec<SessionTicket>, Client) {
+    match st {
+        Client::Client1(cstate, cipher1, tickets, pending, pad) => (
+            tickets,
+            Client::Client1(cstate, cipher1, Vec::new(), pending, pad),
+        ),
+        st => (Vec::new(), st),
+    }
+}
+
+// Writes early data in the first flight of a resumed connection, before the
+// handshake is complete. The server may still reject it, see
+// early_data_accepted.
+pub fn client_write_early(d: AppData, st: Client) -> Result<(Bytes, Client), TLSError> {
+    match st {
+        Client::Client0(cstate, Some(cipher0), buf, pad) => {
+            let (by, cipher0) = in_stage(encrypt_zerortt(d, &pad, cipher0), Stage::EarlyData)?;
+            Ok((by, Client::Client0(cstate, Some(cipher0), buf, pad)))
+        }
+        Client::ClientH(cstate, Some(cipher0), cipher_hs, buf, pad) => {
+            let (by, cipher0) = in_stage(encrypt_zerortt(d, &pad, cipher0), Stage::EarlyData)?;
+            Ok((
+                by,
+                Client::ClientH(cstate, Some(cipher0), cipher_hs, buf, pad),
+            ))
+        }
+        Client::Client0(_, None, _, _) | Client::ClientH(_, None, _, _, _) => {
+            in_stage(Err(ZERO_RTT_DISABLED), Stage::EarlyData)
+        }
+        st => client_state_error(&st),
+    }
+}
+
+// Sends a KeyUpdate and switches to the next sending keys. If
+// `update_requested` is set, the server will update its keys as well.
+pub fn client_send_key_update(
+    update_requested: bool,
+    st: Client,
+) -> Result<(Bytes, Client), TLSError> {
+    match st {
+        Client::Client1(cstate, cipher1, tickets, _pending, pad) => {
+            let algs = algs_post_client_finished(&cstate);
+            let (by, cipher1) = in_stage(
+                send_key_update(&algs, update_requested, &pad, cipher1),
+                Stage::Connected,
+            )?;
+            Ok((by, Client::Client1(cstate, cipher1, tickets, false, pad)))
+        }
+        st => client_state_error(&st),
+    }
+}
+
+// Writes AppData, preceded by a KeyUpdate if the server asked for one.
 pub fn client_write(d: AppData, st: Client) -> Result<(Bytes, Client), TLSError> {
     match st {
-        Client::Client1(cstate, cipher1) => {
-            let (by, cipher1) = encrypt_data(d, 0, cipher1)?;
-            Ok((by, Client::Client1(cstate, cipher1)))
+        Client::Client1(cstate, cipher1, tickets, true, pad) => {
+            let (ku, st) = client_send_key_update(
+                false,
+                Client::Client1(cstate, cipher1, tickets, true, pad),
+            )?;
+            let (by, st) = client_write(d, st)?;
+            Ok((ku.concat(&by), st))
         }
-        _ => Err(INCORRECT_STATE),
+        Client::Client1(cstate, cipher1, tickets, false, pad) => {
+            let (by, cipher1) = in_stage(encrypt_data(d, &pad, cipher1), Stage::Connected)?;
+            Ok((by, Client::Client1(cstate, cipher1, tickets, false, pad)))
+        }
+        st => client_state_error(&st),
     }
 }
 
+// Closes the connection with a close_notify, protected with the current keys.
+pub fn client_close(st: Client) -> Result<Bytes, TLSError> {
+    send_alert(AlertDescription::CloseNotify, &client_alert_sender(&st))
+}
+
+// Exports `len` bytes of keying material for `label` and `context`, once the
+// handshake is complete (RFC 8446 Section 7.5). The server derives the same
+// bytes with server_export_keying_material.
+pub fn client_export_keying_material(
+    label: &Bytes,
+    context: &Bytes,
+    len: usize,
+    st: &Client,
+) -> Result<Bytes, TLSError> {
+    let res = match st {
+        Client::Client1(cstate, cipher1, _, _, _) => derive_exporter(
+            &hash_alg(&algs_post_client_finished(cstate)),
+            &exporter_master_secret(cipher1),
+            label,
+            context,
+            len,
+        ),
+        _ => Err(INCORRECT_STATE),
+    };
+    in_stage(res, client_stage(st))
+}
+
+// Exports keying material from the early exporter master secret, while we
+// can send early data.
+pub fn client_export_early_keying_material(
+    label: &Bytes,
+    context: &Bytes,
+    len: usize,
+    st: &Client,
+) -> Result<Bytes, TLSError> {
+    let res = match st {
+        Client::Client0(cstate, Some(cipher0), _, _) => derive_exporter(
+            &hash_alg(&algs_post_client_hello(cstate)),
+            &client_early_exporter_master_secret(cipher0),
+            label,
+            context,
+            len,
+        ),
+        Client::ClientH(cstate, Some(cipher0), _, _, _) => derive_exporter(
+            &hash_alg(&algs_post_server_hello(cstate)),
+            &client_early_exporter_master_secret(cipher0),
+            label,
+            context,
+            len,
+        ),
+        Client::Client0(_, None, _, _) | Client::ClientH(_, None, _, _, _) => {
+            Err(ZERO_RTT_DISABLED)
+        }
+        _ => Err(INCORRECT_STATE),
+    };
+    in_stage(res, Stage::EarlyData)
+}
+
+// As for the client, the handshake states buffer a partial message, and all
+// states keep the padding policy, which is taken from the ServerDB.
 pub enum Server {
+    // Waiting for the rest of the first ClientHello.
+    ServerCH(Vec<Algorithms>, ServerDB, HandshakeData),
+    // The u32 is the amount of early data that we rejected and still skip,
+    // see MAX_EARLY_DATA_SIZE.
+    Server0(
+        ServerPostHelloRetryRequest,
+        HandshakeData,
+        u32,
+        PaddingPolicy,
+    ),
+    // The Bytes are early data that came along with the ClientHello, see
+    // server_take_early_data.
     ServerH(
         ServerPostServerFinished,
         Option<ServerCipherState0>,
         DuplexCipherStateH,
         DuplexCipherState1,
+        HandshakeData,
+        u32,
+        Bytes,
+        PaddingPolicy,
+    ),
+    // As for the client, the bool is set when a KeyUpdate is owed.
+    Server1(
+        ServerPostClientFinished,
+        DuplexCipherState1,
+        bool,
+        PaddingPolicy,
     ),
-    Server1(ServerPostClientFinished, DuplexCipherState1),
 }
 
+// The stage a server in state `s` is in, as for the client.
+fn server_stage(s: &Server) -> Stage {
+    match s {
+        Server::ServerCH(_, _, _) | Server::Server0(_, _, _, _) => Stage::ClientHello,
+        Server::ServerH(_, Some(_), _, _, _, _, _, _) => Stage::EarlyData,
+        Server::ServerH(_, None, _, _, _, _, _, _) => Stage::ClientFlight,
+        Server::Server1(_, _, _, _) => Stage::Connected,
+    }
+}
+
+fn server_state_error<T>(s: &Server) -> Result<T, TLSError> {
+    in_stage(Err(INCORRECT_STATE), server_stage(s))
+}
+
+pub fn in_hello_retry(s: &Server) -> bool {
+    matches!(s, Server::Server0(_, _, _, _))
+}
+
+// Tells whether the server waits for a ClientHello, or the rest of one,
+// which has to be passed to server_accept_retry.
+pub fn in_client_hello(s: &Server) -> bool {
+    matches!(s, Server::ServerCH(_, _, _) | Server::Server0(_, _, _, _))
+}
+
+// Tells whether the server waits for the client's Finished, or the rest of
+// the client's flight, which has to be passed to server_read_handshake.
+pub fn in_handshake(s: &Server) -> bool {
+    matches!(s, Server::ServerH(_, None, _, _, _, _, _, _))
+}
+
+// The certificate the client authenticated with, once the handshake is complete.
+pub fn server_peer_certificate(s: &Server) -> Option<Bytes> {
+    match s {
+        Server::Server1(sstate, _, _, _) => peer_certificate_post_client_finished(sstate),
+        _ => None,
+    }
+}
+
+// The application protocol selected for the client. It is known as soon as
+// the ClientHello is accepted, so early data can be dispatched on it too.
+pub fn server_alpn(s: &Server) -> Option<Bytes> {
+    match s {
+        Server::ServerCH(_, _, _) | Server::Server0(_, _, _, _) => None,
+        Server::ServerH(sstate, _, _, _, _, _, _, _) => alpn_post_server_finished(sstate),
+        Server::Server1(sstate, _, _, _) => alpn_post_server_client_finished(sstate),
+    }
+}
+
+pub fn server_alert_sender(s: &Server) -> AlertSender {
+    match s {
+        Server::ServerCH(_, _, _) | Server::Server0(_, _, _, _) => AlertSender(None),
+        // The server's Finished is sent, so the client expects alerts under
+        // the application keys.
+        Server::ServerH(_, _, _, cipher1, _, _, _, pad) | Server::Server1(_, cipher1, _, pad) => {
+            AlertSender(Some((sender_cipher_state1(cipher1), *pad)))
+        }
+    }
+}
+
+// Tells whether the server accepted early data and still expects it, i.e.
+// whether the next records have to be passed to server_read_early.
+pub fn in_early_data(s: &Server) -> bool {
+    matches!(s, Server::ServerH(_, Some(_), _, _, _, _, _, _))
+}
+
+// Accept a ClientHello, picking the first of `algs` the client supports.
+// If it carries no key share for that group, the first record returned is a
+// HelloRetryRequest, the second one is empty, and the second ClientHello has
+// to be passed to server_accept_retry.
+// A ClientHello may span several records: while it is incomplete, both
+// records are empty and the rest has to be passed to server_accept_retry.
+// 0-RTT records may follow the ClientHello in `ch_rec`: the early data we
+// accept is kept for server_take_early_data, the one we reject is skipped.
+// `ent` holds 32 bytes for the server random, `kem_priv_len` bytes for our key
+// share and, unless we resume a session, 32 bytes for our signature.
 pub fn server_accept(
-    algs: Algorithms,
+    algs: &[Algorithms],
     db: ServerDB,
     ch_rec: &Bytes,
     ent: Entropy,
 ) -> Result<(Bytes, Bytes, Server), TLSError> {
-    let mut ch_rec = ch_rec.clone();
-    ch_rec[2] = U8::from(0x03);
-    let ch = get_handshake_record(&ch_rec)?;
-    //println!("pre-init succeeded");
-    let (sh, sf, cipher0, cipher_hs, cipher1, sstate) = server_init(algs, &ch, db, ent)?;
-    //println!("init succeeded");
+    let buf = handshake_data(Bytes::new());
+    server_accept_retry(ch_rec, Server::ServerCH(algs.to_vec(), db, buf), ent)
+}
+
+pub fn server_accept_retry(
+    ch_rec: &Bytes,
+    st: Server,
+    ent: Entropy,
+) -> Result<(Bytes, Bytes, Server), TLSError> {
+    match st {
+        Server::ServerCH(algs, db, buf) => in_stage(
+            read_client_hello(ch_rec, algs, db, buf, ent),
+            Stage::ClientHello,
+        ),
+        Server::Server0(sstate, buf, skip, pad) => in_stage(
+            read_retried_client_hello(ch_rec, sstate, buf, skip, pad, ent),
+            Stage::ClientHello,
+        ),
+        st => server_state_error(&st),
+    }
+}
+
+// Reads the first ClientHello and answers it with our flight, or with a
+// HelloRetryRequest if it has no key share for the group we picked.
+// If the client offers early data that we do not accept, we skip it.
+fn read_client_hello(
+    ch_rec: &Bytes,
+    algs: Vec<Algorithms>,
+    db: ServerDB,
+    buf: HandshakeData,
+    ent: Entropy,
+) -> Result<(Bytes, Bytes, Server), TLSError> {
+    let (buf, rest) = read_plaintext_records(ch_rec, buf, true)?;
+    if has_handshake_message(&buf) {
+        let ch = get_hello_message(&buf)?;
+        let pad = db.6;
+        //println!("pre-init succeeded");
+        let algs = server_select(&algs, &ch, &db)?;
+        let skip = if early_data_offered(&algs, &ch)? {
+            MAX_EARLY_DATA_SIZE
+        } else {
+            0
+        };
+        if server_needs_retry(&algs, &ch)? {
+            let (hrr, sstate) = server_retry(algs, &ch, db)?;
+            let (_, _, skip) = read_early_records(&rest, None, skip)?;
+            let hrr_rec = handshake_record(&hrr)?;
+            let buf = handshake_data(Bytes::new());
+            Ok((
+                hrr_rec,
+                Bytes::new(),
+                Server::Server0(sstate, buf, skip, pad),
+            ))
+        } else {
+            let (sh, sf, cipher0, cipher_hs, cipher1, sstate) = server_init(algs, &ch, db, ent)?;
+            //println!("init succeeded");
+            let skip = if cipher0.is_some() { 0 } else { skip };
+            let (early, cipher0, skip) = read_early_records(&rest, cipher0, skip)?;
+            server_flight(
+                sh, sf, cipher0, cipher_hs, cipher1, sstate, skip, early, pad,
+            )
+        }
+    } else {
+        Ok((Bytes::new(), Bytes::new(), Server::ServerCH(algs, db, buf)))
+    }
+}
+
+// Reads the second ClientHello, sent in reply to our HelloRetryRequest.
+// Early data sent with the first ClientHello may still come before it and is
+// skipped.
+fn read_retried_client_hello(
+    ch_rec: &Bytes,
+    sstate: ServerPostHelloRetryRequest,
+    buf: HandshakeData,
+    skip: u32,
+    pad: PaddingPolicy,
+    ent: Entropy,
+) -> Result<(Bytes, Bytes, Server), TLSError> {
+    let (ch_rec, skip) = skip_early_data(ch_rec, None, skip)?;
+    let (buf, rest) = read_plaintext_records(&ch_rec, buf, true)?;
+    if has_handshake_message(&buf) {
+        let ch = get_hello_message(&buf)?;
+        check_no_records(&rest)?;
+        let (sh, sf, cipher0, cipher_hs, cipher1, sstate) = server_init_retry(&ch, sstate, ent)?;
+        let early = Bytes::new();
+        server_flight(sh, sf, cipher0, cipher_hs, cipher1, sstate, 0, early, pad)
+    } else {
+        Ok((
+            Bytes::new(),
+            Bytes::new(),
+            Server::Server0(sstate, buf, skip, pad),
+        ))
+    }
+}
+
+// Drops the early data records we rejected from the start of `d`, as long as
+// they fit in the `skip` bytes of early data we still skip. Before we have
+// handshake keys, i.e. after a HelloRetryRequest, these are the application
+// data records; afterwards, the records that do not decrypt under the
+// handshake keys `cipher_hs`. Nothing is skipped after the first record that
+// does decrypt. Returns the rest of `d` and how much we still skip.
+fn skip_early_data(
+    d: &Bytes,
+    cipher_hs: Option<&DuplexCipherStateH>,
+    skip: u32,
+) -> Result<(Bytes, u32), TLSError> {
+    let mut d = d.clone();
+    let mut skip = skip;
+    while skip > 0 && !d.is_empty() {
+        let (rec, rest) = get_first_record(&d)?;
+        let early = rec[0].declassify() == content_type(ContentType::ApplicationData)
+            && match cipher_hs {
+                None => true,
+                Some(cipher_hs) => !decrypts_as_handshake(&rec, cipher_hs),
+            };
+        if !early {
+            skip = 0;
+        } else if early_data_len(&rec) > skip {
+            tlserr(APPLICATION_DATA_INSTEAD_OF_HANDSHAKE)?;
+        } else {
+            skip -= early_data_len(&rec);
+            d = rest;
+        }
+    }
+    Ok((d, skip))
+}
+
+// Reads the 0-RTT records `d` that came along with the ClientHello. With the
+// 0-RTT keys `cipher0`, we decrypt them and return their early data;
+// otherwise we skip them. Nothing else may follow before we answer.
+fn read_early_records(
+    d: &Bytes,
+    cipher0: Option<ServerCipherState0>,
+    skip: u32,
+) -> Result<(Bytes, Option<ServerCipherState0>, u32), TLSError> {
+    match cipher0 {
+        Some(cipher0) => {
+            let mut d = d.clone();
+            let mut early = Bytes::new();
+            let mut cipher0 = cipher0;
+            while !d.is_empty() {
+                let (rec, rest) = get_first_record(&d)?;
+                let (ad, next) = decrypt_zerortt(&rec, cipher0)?;
+                early = early.concat(&app_data_bytes(ad));
+                cipher0 = next;
+                d = rest;
+            }
+            Ok((early, Some(cipher0), skip))
+        }
+        None => {
+            let (d, skip) = skip_early_data(d, None, skip)?;
+            check_no_records(&d)?;
+            Ok((Bytes::new(), None, skip))
+        }
+    }
+}
+
+// The records of the ServerHello and of the server's encrypted flight.
+#[allow(clippy::too_many_arguments)]
+fn server_flight(
+    sh: HandshakeData,
+    sf: HandshakeData,
+    cipher0: Option<ServerCipherState0>,
+    cipher_hs: DuplexCipherStateH,
+    cipher1: DuplexCipherState1,
+    sstate: ServerPostServerFinished,
+    skip: u32,
+    early: Bytes,
+    pad: PaddingPolicy,
+) -> Result<(Bytes, Bytes, Server), TLSError> {
     let sh_rec = handshake_record(&sh)?;
-    let (sf_rec, cipher_hs) = encrypt_handshake(sf, 0, cipher_hs)?;
+    let (sf_rec, cipher_hs) = encrypt_handshake(sf, &pad, cipher_hs)?;
+    let buf = handshake_data(Bytes::new());
     Ok((
         sh_rec,
         sf_rec,
-        Server::ServerH(sstate, cipher0, cipher_hs, cipher1),
+        Server::ServerH(sstate, cipher0, cipher_hs, cipher1, buf, skip, early, pad),
     ))
 }
 
+// Takes the early data that came along with the ClientHello passed to
+// server_accept. Later early data is read with server_read_early.
+pub fn server_take_early_data(st: Server) -> (Option<AppData>, Server) {
+    match st {
+        Server::ServerH(sstate, cipher0, cipher_hs, cipher1, buf, skip, early, pad)
+            if !early.is_empty() =>
+        {
+            (
+                Some(app_data(early)),
+                Server::ServerH(
+                    sstate,
+                    cipher0,
+                    cipher_hs,
+                    cipher1,
+                    buf,
+                    skip,
+                    Bytes::new(),
+                    pad,
+                ),
+            )
+        }
+        st => (None, st),
+    }
+}
+
+// Reads early data until the client's EndOfEarlyData, which yields None.
+// Early data that the server rejected is skipped by server_accept_retry and
+// server_read_handshake instead, up to MAX_EARLY_DATA_SIZE bytes.
+pub fn server_read_early(d: &Bytes, st: Server) -> Result<(Option<AppData>, Server), TLSError> {
+    match st {
+        Server::ServerH(sstate, Some(cipher0), cipher_hs, cipher1, buf, skip, early, pad) => {
+            let (ty, hd, cipher0) = in_stage(decrypt_zerortt_or_hs(d, cipher0), Stage::EarlyData)?;
+            match ty {
+                ContentType::ApplicationData => Ok((
+                    Some(app_data(hd)),
+                    Server::ServerH(
+                        sstate,
+                        Some(cipher0),
+                        cipher_hs,
+                        cipher1,
+                        buf,
+                        skip,
+                        early,
+                        pad,
+                    ),
+                )),
+                ContentType::Handshake => {
+                    let sstate = in_stage(
+                        server_end_of_early_data(&handshake_data(hd), sstate),
+                        Stage::EarlyData,
+                    )?;
+                    Ok((
+                        None,
+                        Server::ServerH(sstate, None, cipher_hs, cipher1, buf, 0, early, pad),
+                    ))
+                }
+                _ => in_stage(Err(PARSE_FAILED), Stage::EarlyData),
+            }
+        }
+        Server::ServerH(_, None, _, _, _, _, _, _) => {
+            in_stage(Err(ZERO_RTT_DISABLED), Stage::ClientFlight)
+        }
+        st => server_state_error(&st),
+    }
+}
+
+// Reads the client's flight. Until its Finished is complete, the server stays
+// in the handshake, see in_handshake.
 pub fn server_read_handshake(cfin_rec: &Bytes, st: Server) -> Result<Server, TLSError> {
     match st {
-        Server::ServerH(sstate, _cipher0, cipher_hs, cipher1) => {
-            //println!("to decrypt");
-            let (cf, _cipher_hs) = decrypt_handshake(cfin_rec, cipher_hs)?;
-            //println!("decrypted");
-            let sstate = server_finish(&cf, sstate)?;
-            Ok(Server::Server1(sstate, cipher1))
+        Server::ServerH(sstate, None, cipher_hs, cipher1, buf, skip, early, pad) => in_stage(
+            read_client_flight(cfin_rec, sstate, cipher_hs, cipher1, buf, skip, early, pad),
+            Stage::ClientFlight,
+        ),
+        st => server_state_error(&st),
+    }
+}
+
+// Reads the client's encrypted flight, up to its Finished, after skipping
+// the early data we rejected.
+#[allow(clippy::too_many_arguments)]
+fn read_client_flight(
+    cfin_rec: &Bytes,
+    sstate: ServerPostServerFinished,
+    cipher_hs: DuplexCipherStateH,
+    cipher1: DuplexCipherState1,
+    buf: HandshakeData,
+    skip: u32,
+    early: Bytes,
+    pad: PaddingPolicy,
+) -> Result<Server, TLSError> {
+    let (cfin_rec, skip) = skip_early_data(cfin_rec, Some(&cipher_hs), skip)?;
+    //println!("to decrypt");
+    let (buf, cipher_hs) = read_handshake_records(&cfin_rec, buf, cipher_hs)?;
+    //println!("decrypted");
+    if find_handshake_message(HandshakeType::Finished, &buf, 0) {
+        let sstate = server_finish(&buf, sstate)?;
+        Ok(Server::Server1(sstate, cipher1, false, pad))
+    } else {
+        Ok(Server::ServerH(
+            sstate, None, cipher_hs, cipher1, buf, skip, early, pad,
+        ))
+    }
+}
+
+// Sends a NewSessionTicket for the ticket identity `tkt` after the handshake,
+// allowing up to `max_early_data` bytes of early data on resumption.
+// Returns the record and the resumption PSK, which the server stores with
+// `tkt` to accept the ticket later on.
+pub fn server_send_ticket(
+    tkt: &Bytes,
+    lifetime: u32,
+    max_early_data: u32,
+    st: Server,
+    ent: Entropy,
+) -> Result<(Bytes, PSK, Server), TLSError> {
+    match st {
+        Server::Server1(sstate, cipher1, pending, pad) => {
+            let (nst, psk) = in_stage(
+                server_ticket(tkt, lifetime, max_early_data, &sstate, ent),
+                Stage::Connected,
+            )?;
+            let (by, cipher1) =
+                in_stage(encrypt_post_handshake(nst, &pad, cipher1), Stage::Connected)?;
+            Ok((by, psk, Server::Server1(sstate, cipher1, pending, pad)))
         }
-        _ => Err(INCORRECT_STATE),
+        st => server_state_error(&st),
     }
 }
 
+// Sends a KeyUpdate and switches to the next sending keys.
+pub fn server_send_key_update(
+    update_requested: bool,
+    st: Server,
+) -> Result<(Bytes, Server), TLSError> {
+    match st {
+        Server::Server1(sstate, cipher1, _pending, pad) => {
+            let algs = algs_post_server_client_finished(&sstate);
+            let (by, cipher1) = in_stage(
+                send_key_update(&algs, update_requested, &pad, cipher1),
+                Stage::Connected,
+            )?;
+            Ok((by, Server::Server1(sstate, cipher1, false, pad)))
+        }
+        st => server_state_error(&st),
+    }
+}
+
+// Writes AppData, preceded by a KeyUpdate if the client asked for one.
 pub fn server_write(d: AppData, st: Server) -> Result<(Bytes, Server), TLSError> {
     match st {
-        Server::Server1(sstate, cipher1) => {
-            let (by, cipher1) = encrypt_data(d, 0, cipher1)?;
-            Ok((by, Server::Server1(sstate, cipher1)))
+        Server::Server1(sstate, cipher1, true, pad) => {
+            let (ku, st) =
+                server_send_key_update(false, Server::Server1(sstate, cipher1, true, pad))?;
+            let (by, st) = server_write(d, st)?;
+            Ok((ku.concat(&by), st))
         }
-        _ => Err(INCORRECT_STATE),
+        Server::Server1(sstate, cipher1, false, pad) => {
+            let (by, cipher1) = in_stage(encrypt_data(d, &pad, cipher1), Stage::Connected)?;
+            Ok((by, Server::Server1(sstate, cipher1, false, pad)))
+        }
+        st => server_state_error(&st),
     }
 }
 
-pub fn server_read(d: &Bytes, st: Server) -> Result<(Option<AppData>, Server), TLSError> {
+// Reads AppData, Alerts and KeyUpdates
+pub fn server_read(d: &Bytes, st: Server) -> Result<(Option<Received>, Server), TLSError> {
     match st {
-        Server::Server1(sstate, cipher1) => {
-            let (ad, cipher1) = decrypt_data(d, cipher1)?;
-            Ok((Some(ad), Server::Server1(sstate, cipher1)))
+        Server::Server1(sstate, cipher1, pending, pad) => {
+            let (ty, hd, cipher1) = in_stage(decrypt_data_or_hs(d, cipher1), Stage::Connected)?;
+            match ty {
+                ContentType::ApplicationData => Ok((
+                    Some(Received::AppData(app_data(hd))),
+                    Server::Server1(sstate, cipher1, pending, pad),
+                )),
+                ContentType::Alert => Ok((
+                    Some(in_stage(read_alert(&hd), Stage::Connected)?),
+                    Server::Server1(sstate, cipher1, pending, pad),
+                )),
+                ContentType::Handshake => {
+                    let algs = algs_post_server_client_finished(&sstate);
+                    let (ku, rest) = in_stage(
+                        get_first_handshake_message(&handshake_data(hd)),
+                        Stage::Connected,
+                    )?;
+                    let (update_requested, cipher1) = in_stage(
+                        read_key_update(&algs, &ku, &rest, cipher1),
+                        Stage::Connected,
+                    )?;
+                    Ok((
+                        None,
+                        Server::Server1(sstate, cipher1, pending || update_requested, pad),
+                    ))
+                }
+                _ => in_stage(Err(PARSE_FAILED), Stage::Connected),
+            }
         }
-        _ => Err(INCORRECT_STATE),
+        st => server_state_error(&st),
     }
 }
+
+// Closes the connection with a close_notify, protected with the current keys.
+pub fn server_close(st: Server) -> Result<Bytes, TLSError> {
+    send_alert(AlertDescription::CloseNotify, &server_alert_sender(&st))
+}
+
+// Exports keying material once the handshake is complete, as for the client.
+pub fn server_export_keying_material(
+    label: &Bytes,
+    context: &Bytes,
+    len: usize,
+    st: &Server,
+) -> Result<Bytes, TLSError> {
+    let res = match st {
+        Server::Server1(sstate, cipher1, _, _) => derive_exporter(
+            &hash_alg(&algs_post_server_client_finished(sstate)),
+            &exporter_master_secret(cipher1),
+            label,
+            context,
+            len,
+        ),
+        _ => Err(INCORRECT_STATE),
+    };
+    in_stage(res, server_stage(st))
+}
+
+// Exports keying material from the early exporter master secret, while the
+// server expects early data, see in_early_data.
+pub fn server_export_early_keying_material(
+    label: &Bytes,
+    context: &Bytes,
+    len: usize,
+    st: &Server,
+) -> Result<Bytes, TLSError> {
+    let res = match st {
+        Server::ServerH(sstate, Some(cipher0), _, _, _, _, _, _) => derive_exporter(
+            &hash_alg(&algs_post_server_finished(sstate)),
+            &server_early_exporter_master_secret(cipher0),
+            label,
+            context,
+            len,
+        ),
+        Server::ServerH(_, None, _, _, _, _, _, _) => Err(ZERO_RTT_DISABLED),
+        _ => Err(INCORRECT_STATE),
+    };
+    in_stage(res, Stage::EarlyData)
+}
@@ -1,4 +1,5 @@
-//! Zero-copy ASN.1 parser to read public keys from X.509 certificates.
+//! Zero-copy ASN.1 parser to read public keys from X.509 certificates, and
+//! to validate certificate chains against a set of trust anchors.
 //!
 //! This parser is very limited and only for this specific use case.
 //! It may or may not be extended to support more of the ASN.1 syntax.
@@ -20,12 +21,14 @@ use crate::*;
 #[derive(Clone, Copy)]
 pub struct CertificateKey(usize, usize);
 
-pub type Asn1Error = u8;
-pub const ASN1_SEQUENCE_TOO_LONG: Asn1Error = 21u8;
-pub const ASN1_INVALID_TAG: Asn1Error = 22u8;
-pub const ASN1_INVALID_CERTIFICATE: Asn1Error = 23u8;
-pub const ASN1_UNSUPPORTED_ALGORITHM: Asn1Error = 24u8;
-pub const ASN1_ERROR: Asn1Error = 25u8;
+pub type Asn1Error = TLSError;
+pub const ASN1_SEQUENCE_TOO_LONG: Asn1Error = TLSError(ErrorKind::Asn1SequenceTooLong, None, None);
+pub const ASN1_INVALID_TAG: Asn1Error = TLSError(ErrorKind::Asn1InvalidTag, None, None);
+pub const ASN1_INVALID_CERTIFICATE: Asn1Error =
+    TLSError(ErrorKind::Asn1InvalidCertificate, None, None);
+pub const ASN1_UNSUPPORTED_ALGORITHM: Asn1Error =
+    TLSError(ErrorKind::Asn1UnsupportedAlgorithm, None, None);
+pub const ASN1_ERROR: Asn1Error = TLSError(ErrorKind::Asn1Error, None, None);
 
 type UsizeResult = Result<usize, Asn1Error>;
 type DoubleUsizeResult = Result<(usize, usize), Asn1Error>;
@@ -46,6 +49,8 @@ pub fn asn1err<T>(err: Asn1Error) -> Result<T, Asn1Error> {
 fn long_length(b: &Bytes, offset: usize, len: usize) -> UsizeResult {
     if len > 4 {
         asn1err(ASN1_SEQUENCE_TOO_LONG)
+    } else if offset + len > b.len() {
+        asn1err(ASN1_ERROR)
     } else {
         let mut u32word: Bytes = Bytes::zeroes(4);
         u32word[0..len].copy_from_slice(&b[offset..offset + len]);
@@ -162,9 +167,15 @@ pub fn x962_ec_public_key_oid() -> Bytes {
 pub fn ecdsa_secp256r1_sha256_oid() -> Bytes {
     [0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07].into()
 }
+pub fn ecdsa_secp384r1_sha384_oid() -> Bytes {
+    [0x2B, 0x81, 0x04, 0x00, 0x22].into()
+}
 pub fn rsa_pkcs1_encryption_oid() -> Bytes {
     [0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01, 0x01].into()
 }
+pub fn ed25519_oid() -> Bytes {
+    [0x2B, 0x65, 0x70].into()
+}
 
 fn check_success(val: bool) -> Result<(), Asn1Error> {
     if val {
@@ -188,12 +199,13 @@ fn read_spki(cert: &Bytes, mut offset: usize) -> SpkiResult {
     check_tag(cert, offset, 0x06u8)?;
     // OID length
     let (mut oid_offset, oid_len) = length(cert, offset + 1)?;
-    let (mut ec_pk_oid, mut ecdsa_p256, mut rsa_pk_oid) = (false, false, false);
+    let (mut ec_curve, mut rsa_pk_oid) = (None, false);
     let ec_oid = x962_ec_public_key_oid();
     let rsa_oid = rsa_pkcs1_encryption_oid();
+    let ed25519_pk_oid = eq(&cert.slice(oid_offset, oid_len), &ed25519_oid());
     // Check OID
     if ec_oid.len() == oid_len {
-        ec_pk_oid = true;
+        let mut ec_pk_oid = true;
         for i in 0..ec_oid.len() {
             let oid_byte_equal = cert[oid_offset + i].declassify() == ec_oid[i].declassify();
             ec_pk_oid = ec_pk_oid && oid_byte_equal;
@@ -202,15 +214,16 @@ fn read_spki(cert: &Bytes, mut offset: usize) -> SpkiResult {
             oid_offset = oid_offset + oid_len;
             check_tag(cert, oid_offset, 0x06u8)?;
             oid_offset = oid_offset + 1;
-            let (oid_offset, _oid_len) = length(cert, oid_offset)?;
-            ecdsa_p256 = true;
+            let (oid_offset, oid_len) = length(cert, oid_offset)?;
             // In this case we also need to read the curve OID.
-            let ec_oid = ecdsa_secp256r1_sha256_oid();
-            for i in 0..ec_oid.len() {
-                let oid_byte_equal = cert[oid_offset + i].declassify() == ec_oid[i].declassify();
-                ecdsa_p256 = ecdsa_p256 && oid_byte_equal;
+            check_success(oid_offset + oid_len <= cert.len())?;
+            let curve_oid = cert.slice(oid_offset, oid_len);
+            if eq(&curve_oid, &ecdsa_secp256r1_sha256_oid()) {
+                ec_curve = Some(SignatureScheme::EcdsaSecp256r1Sha256);
+            } else if eq(&curve_oid, &ecdsa_secp384r1_sha384_oid()) {
+                ec_curve = Some(SignatureScheme::EcdsaSecp384r1Sha384);
             }
-            check_success(ecdsa_p256)?;
+            check_success(ec_curve.is_some())?;
         }
     }
     if rsa_oid.len() == oid_len {
@@ -220,7 +233,7 @@ fn read_spki(cert: &Bytes, mut offset: usize) -> SpkiResult {
             rsa_pk_oid = rsa_pk_oid && oid_byte_equal;
         }
     }
-    check_success((ec_pk_oid && ecdsa_p256) || rsa_pk_oid)?;
+    check_success(ec_curve.is_some() || rsa_pk_oid || ed25519_pk_oid)?;
 
     // Skip all the way to the end of the sequence.
     // RSA has a NULL element in there as well. We don't care.
@@ -235,14 +248,16 @@ fn read_spki(cert: &Bytes, mut offset: usize) -> SpkiResult {
         offset = offset + 1; // There's a 0x00 at the end of the length
     }
 
-    if ec_pk_oid && ecdsa_p256 {
+    if let Some(scheme) = ec_curve {
+        SpkiResult::Ok((scheme, CertificateKey(offset, bit_string_len - 1)))
+    } else if rsa_pk_oid {
         SpkiResult::Ok((
-            SignatureScheme::EcdsaSecp256r1Sha256,
+            SignatureScheme::RsaPssRsaSha256,
             CertificateKey(offset, bit_string_len - 1),
         ))
-    } else if rsa_pk_oid {
+    } else if ed25519_pk_oid {
         SpkiResult::Ok((
-            SignatureScheme::RsaPssRsaSha256,
+            SignatureScheme::ED25519,
             CertificateKey(offset, bit_string_len - 1),
         ))
     } else {
@@ -284,6 +299,13 @@ pub fn ecdsa_public_key(cert: &Bytes, indices: CertificateKey) -> VerificationKe
     VerificationKeyResult::Ok(cert.slice(offset + 1, len - 1)) // Drop the 0x04 here.
 }
 
+/// Read the Ed25519 PK from the cert.
+pub fn ed25519_public_key(cert: &Bytes, indices: CertificateKey) -> VerificationKeyResult {
+    let CertificateKey(offset, len) = indices;
+    check_success(len == 32)?;
+    VerificationKeyResult::Ok(cert.slice(offset, len))
+}
+
 pub fn rsa_public_key(cert: &Bytes, indices: CertificateKey) -> RsaVerificationKeyResult {
     let CertificateKey(mut offset, _len) = indices;
 
@@ -311,18 +333,991 @@ pub fn rsa_public_key(cert: &Bytes, indices: CertificateKey) -> RsaVerificationK
 
 pub fn cert_public_key(cert: &Bytes, spki: &Spki) -> PkResult {
     match spki.0 {
-        SignatureScheme::ED25519 => asn1err(ASN1_UNSUPPORTED_ALGORITHM),
-        SignatureScheme::EcdsaSecp256r1Sha256 => {
+        SignatureScheme::ED25519 => {
+            let pk = ed25519_public_key(cert, spki.1)?;
+            PkResult::Ok(PublicVerificationKey::EcDsa(pk))
+        }
+        SignatureScheme::EcdsaSecp256r1Sha256 | SignatureScheme::EcdsaSecp384r1Sha384 => {
             let pk = ecdsa_public_key(cert, spki.1)?;
             PkResult::Ok(PublicVerificationKey::EcDsa(pk))
         }
-        SignatureScheme::RsaPssRsaSha256 => {
+        SignatureScheme::RsaPssRsaSha256
+        | SignatureScheme::RsaPssRsaSha384
+        | SignatureScheme::RsaPssRsaSha512 => {
             let pk = rsa_public_key(cert, spki.1)?;
             PkResult::Ok(PublicVerificationKey::Rsa(pk))
         }
     }
 }
 
+/// Read the header of the element with the given `tag` at `offset`, checking
+/// that its content is within the bytes.
+///
+/// Returns: (offset, length) of the content
+fn read_element(b: &Bytes, offset: usize, tag: u8) -> DoubleUsizeResult {
+    if offset + 2 > b.len() {
+        asn1err(ASN1_ERROR)
+    } else {
+        check_tag(b, offset, tag)?;
+        let (content_offset, content_len) = length(b, offset + 1)?;
+        if content_offset + content_len > b.len() {
+            asn1err(ASN1_ERROR)
+        } else {
+            DoubleUsizeResult::Ok((content_offset, content_len))
+        }
+    }
+}
+
+/// Read the header of the element at `offset`, whatever its tag.
+///
+/// Returns: (offset, length) of the content
+fn read_any_element(b: &Bytes, offset: usize) -> DoubleUsizeResult {
+    if offset >= b.len() {
+        asn1err(ASN1_ERROR)
+    } else {
+        read_element(b, offset, b[offset].declassify())
+    }
+}
+
+/// Skip an element, whatever its tag.
+///
+/// Returns the new offset into the bytes.
+fn skip_element(b: &Bytes, offset: usize) -> UsizeResult {
+    let (content_offset, content_len) = read_any_element(b, offset)?;
+    UsizeResult::Ok(content_offset + content_len)
+}
+
+/// The AlgorithmIdentifier of ecdsa-with-SHA256.
+pub fn ecdsa_with_sha256_algorithm() -> Bytes {
+    [
+        0x30, 0x0A, 0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x02,
+    ]
+    .into()
+}
+/// The AlgorithmIdentifier of ecdsa-with-SHA384.
+pub fn ecdsa_with_sha384_algorithm() -> Bytes {
+    [
+        0x30, 0x0A, 0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x03,
+    ]
+    .into()
+}
+/// The AlgorithmIdentifier of Ed25519.
+pub fn ed25519_algorithm() -> Bytes {
+    [0x30, 0x05, 0x06, 0x03, 0x2B, 0x65, 0x70].into()
+}
+/// The OID of RSASSA-PSS signatures.
+pub fn rsa_pss_oid() -> Bytes {
+    [0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01, 0x0A].into()
+}
+/// The OID of the MGF1 mask generation function of RSASSA-PSS.
+pub fn mgf1_oid() -> Bytes {
+    [0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01, 0x08].into()
+}
+/// The OIDs of sha256WithRSAEncryption, sha384WithRSAEncryption and
+/// sha512WithRSAEncryption (PKCS#1 v1.5), which end in 11, 12 and 13.
+pub fn rsa_pkcs1_oid_prefix() -> Bytes {
+    [0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01].into()
+}
+/// The OIDs of SHA-256, SHA-384 and SHA-512, which end in 1, 2 and 3.
+pub fn sha2_oid_prefix() -> Bytes {
+    [0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02].into()
+}
+pub fn basic_constraints_oid() -> Bytes {
+    [0x55, 0x1D, 0x13].into()
+}
+pub fn subject_alt_name_oid() -> Bytes {
+    [0x55, 0x1D, 0x11].into()
+}
+pub fn common_name_oid() -> Bytes {
+    [0x55, 0x04, 0x03].into()
+}
+pub fn key_usage_oid() -> Bytes {
+    [0x55, 0x1D, 0x0F].into()
+}
+pub fn ext_key_usage_oid() -> Bytes {
+    [0x55, 0x1D, 0x25].into()
+}
+pub fn name_constraints_oid() -> Bytes {
+    [0x55, 0x1D, 0x1E].into()
+}
+/// The OID of anyExtendedKeyUsage.
+pub fn any_extended_key_usage_oid() -> Bytes {
+    [0x55, 0x1D, 0x25, 0x00].into()
+}
+/// The extended key usage OID of TLS server certificates.
+pub fn server_auth_oid() -> Bytes {
+    [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x01].into()
+}
+/// The extended key usage OID of TLS client certificates.
+pub fn client_auth_oid() -> Bytes {
+    [0x2B, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x02].into()
+}
+
+/// The algorithm of a certificate signature.
+///
+/// Besides the TLS signature schemes, certificates may be signed with
+/// PKCS#1 v1.5 RSA signatures, and RSA-PSS signatures carry their own hash
+/// and salt length.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CertificateSignatureAlgorithm {
+    /// An ECDSA or Ed25519 signature.
+    Scheme(SignatureScheme),
+    /// An RSASSA-PKCS1-v1_5 signature with the hash.
+    RsaPkcs1(HashAlgorithm),
+    /// An RSASSA-PSS signature with the hash, also used by MGF1, and the
+    /// salt length.
+    RsaPss(HashAlgorithm, usize),
+}
+
+/// Read the SHA-2 hash from the `oid` of a hash algorithm.
+fn sha2_hash_algorithm(oid: &Bytes) -> Result<HashAlgorithm, Asn1Error> {
+    let prefix = sha2_oid_prefix();
+    if oid.len() == prefix.len() + 1 && eq(&oid.slice(0, prefix.len()), &prefix) {
+        match oid[prefix.len()].declassify() {
+            1 => Ok(HashAlgorithm::SHA256),
+            2 => Ok(HashAlgorithm::SHA384),
+            3 => Ok(HashAlgorithm::SHA512),
+            _ => asn1err(ASN1_UNSUPPORTED_ALGORITHM),
+        }
+    } else {
+        asn1err(ASN1_UNSUPPORTED_ALGORITHM)
+    }
+}
+
+/// Read the hash of the AlgorithmIdentifier at `offset`, whose parameters
+/// are absent or NULL.
+fn read_hash_algorithm(b: &Bytes, offset: usize) -> Result<HashAlgorithm, Asn1Error> {
+    let (alg_offset, alg_len) = read_element(b, offset, 0x30u8)?;
+    let (oid_offset, oid_len) = read_element(b, alg_offset, 0x06u8)?;
+    let params = b.slice_range(oid_offset + oid_len..alg_offset + alg_len);
+    check_success(params.is_empty() || eq(&params, &Bytes::from([0x05u8, 0x00])))?;
+    sha2_hash_algorithm(&b.slice(oid_offset, oid_len))
+}
+
+/// Read a non-negative INTEGER of up to four bytes at `offset`.
+fn read_small_integer(b: &Bytes, offset: usize) -> Result<usize, Asn1Error> {
+    let (int_offset, int_len) = read_element(b, offset, 0x02u8)?;
+    check_success(int_len > 0 && int_len <= 4 && b[int_offset].declassify() < 0x80)?;
+    let mut value = 0;
+    for i in 0..int_len {
+        value = (value << 8) + b[int_offset + i].declassify() as usize;
+    }
+    Ok(value)
+}
+
+/// Read the RSASSA-PSS-params at `offset` (RFC 4055):
+/// ```ignore
+/// RSASSA-PSS-params ::= SEQUENCE {
+///     hashAlgorithm      [0] HashAlgorithm DEFAULT sha1,
+///     maskGenAlgorithm   [1] MaskGenAlgorithm DEFAULT mgf1SHA1,
+///     saltLength         [2] INTEGER DEFAULT 20,
+///     trailerField       [3] INTEGER DEFAULT 1 }
+/// ```
+///
+/// We support SHA-2 hashes with MGF1 over the same hash and any salt length.
+fn read_rsa_pss_params(
+    b: &Bytes,
+    offset: usize,
+) -> Result<CertificateSignatureAlgorithm, Asn1Error> {
+    let (mut offset, len) = read_element(b, offset, 0x30u8)?;
+    let end = offset + len;
+    // The SHA-1 defaults are not supported.
+    let mut hash = None;
+    let mut mgf_hash = None;
+    let mut salt_len = 20;
+    while offset < end {
+        let (field_offset, field_len) = read_any_element(b, offset)?;
+        match b[offset].declassify() {
+            0xA0u8 => hash = Some(read_hash_algorithm(b, field_offset)?),
+            0xA1u8 => {
+                let (mgf_offset, _mgf_len) = read_element(b, field_offset, 0x30u8)?;
+                let (oid_offset, oid_len) = read_element(b, mgf_offset, 0x06u8)?;
+                if !eq(&b.slice(oid_offset, oid_len), &mgf1_oid()) {
+                    asn1err(ASN1_UNSUPPORTED_ALGORITHM)?
+                }
+                mgf_hash = Some(read_hash_algorithm(b, oid_offset + oid_len)?);
+            }
+            0xA2u8 => salt_len = read_small_integer(b, field_offset)?,
+            0xA3u8 => check_success(read_small_integer(b, field_offset)? == 1)?,
+            _ => asn1err(ASN1_INVALID_TAG)?,
+        }
+        offset = field_offset + field_len;
+    }
+    match (hash, mgf_hash) {
+        (Some(hash), Some(mgf_hash)) if hash == mgf_hash => {
+            Ok(CertificateSignatureAlgorithm::RsaPss(hash, salt_len))
+        }
+        _ => asn1err(ASN1_UNSUPPORTED_ALGORITHM),
+    }
+}
+
+/// Get the algorithm of a certificate signature from its
+/// AlgorithmIdentifier.
+///
+/// ECDSA signatures only name the digest, so we expect the curve that TLS
+/// pairs with it, i.e. P-256 for SHA-256 and P-384 for SHA-384.
+fn certificate_signature_algorithm(
+    alg: &Bytes,
+) -> Result<CertificateSignatureAlgorithm, Asn1Error> {
+    if eq(alg, &ecdsa_with_sha256_algorithm()) {
+        Ok(CertificateSignatureAlgorithm::Scheme(
+            SignatureScheme::EcdsaSecp256r1Sha256,
+        ))
+    } else if eq(alg, &ecdsa_with_sha384_algorithm()) {
+        Ok(CertificateSignatureAlgorithm::Scheme(
+            SignatureScheme::EcdsaSecp384r1Sha384,
+        ))
+    } else if eq(alg, &ed25519_algorithm()) {
+        Ok(CertificateSignatureAlgorithm::Scheme(
+            SignatureScheme::ED25519,
+        ))
+    } else {
+        let (alg_offset, alg_len) = read_element(alg, 0, 0x30u8)?;
+        let (oid_offset, oid_len) = read_element(alg, alg_offset, 0x06u8)?;
+        let oid = alg.slice(oid_offset, oid_len);
+        let params = alg.slice_range(oid_offset + oid_len..alg_offset + alg_len);
+        let prefix = rsa_pkcs1_oid_prefix();
+        if eq(&oid, &rsa_pss_oid()) {
+            read_rsa_pss_params(alg, oid_offset + oid_len)
+        } else if oid.len() == prefix.len() + 1 && eq(&oid.slice(0, prefix.len()), &prefix) {
+            // The parameters of PKCS#1 v1.5 signatures are NULL.
+            check_success(params.is_empty() || eq(&params, &Bytes::from([0x05u8, 0x00])))?;
+            match oid[prefix.len()].declassify() {
+                11 => Ok(CertificateSignatureAlgorithm::RsaPkcs1(
+                    HashAlgorithm::SHA256,
+                )),
+                12 => Ok(CertificateSignatureAlgorithm::RsaPkcs1(
+                    HashAlgorithm::SHA384,
+                )),
+                13 => Ok(CertificateSignatureAlgorithm::RsaPkcs1(
+                    HashAlgorithm::SHA512,
+                )),
+                _ => asn1err(ASN1_UNSUPPORTED_ALGORITHM),
+            }
+        } else {
+            asn1err(ASN1_UNSUPPORTED_ALGORITHM)
+        }
+    }
+}
+
+/// The parts of a certificate that are needed to validate a chain:
+/// the signed tbsCertificate, the issuer and the subject name, the extensions,
+/// and the signature algorithm and signature of the issuer.
+///
+/// Names are kept DER encoded and compared byte by byte, the extensions are
+/// the content of the extensions sequence, empty if there are none.
+/// ECDSA signatures are converted to their raw `r || s` form.
+pub struct CertificateParts(
+    pub Bytes,
+    pub Bytes,
+    pub Bytes,
+    pub Bytes,
+    pub CertificateSignatureAlgorithm,
+    pub Bytes,
+);
+
+/// Split an X.509 certificate into its [CertificateParts].
+pub fn certificate_parts(cert: &Bytes) -> Result<CertificateParts, Asn1Error> {
+    let (cert_offset, cert_len) = read_element(cert, 0, 0x30u8)?;
+    let (tbs_offset, tbs_len) = read_element(cert, cert_offset, 0x30u8)?;
+    let tbs_end = tbs_offset + tbs_len;
+    let tbs = cert.slice_range(cert_offset..tbs_end);
+
+    let mut offset = read_version_number(cert, tbs_offset)?; // x509 version number
+    offset = read_integer(cert, offset)?; // serial number
+    offset = skip_element(cert, offset)?; // signature algorithm
+    let issuer_end = skip_element(cert, offset)?;
+    let issuer = cert.slice_range(offset..issuer_end);
+    offset = skip_element(cert, issuer_end)?; // validity
+    let subject_end = skip_element(cert, offset)?;
+    let subject = cert.slice_range(offset..subject_end);
+    offset = skip_element(cert, subject_end)?; // subject public key info
+
+    // The optional unique identifiers and extensions.
+    let mut extensions = Bytes::new();
+    while offset < tbs_end {
+        if cert[offset].declassify() == 0xA3u8 {
+            let (exts_offset, _exts_len) = read_element(cert, offset, 0xA3u8)?;
+            let (exts_offset, exts_len) = read_element(cert, exts_offset, 0x30u8)?;
+            extensions = cert.slice(exts_offset, exts_len);
+        }
+        offset = skip_element(cert, offset)?;
+    }
+
+    let alg_end = skip_element(cert, tbs_end)?;
+    let alg = certificate_signature_algorithm(&cert.slice_range(tbs_end..alg_end))?;
+    let (sig_offset, sig_len) = read_element(cert, alg_end, 0x03u8)?;
+    // The signature is a bit string without unused bits.
+    check_success(sig_len > 0 && cert[sig_offset].declassify() == 0x00)?;
+    check_success(sig_offset + sig_len == cert_offset + cert_len)?;
+    let sig = cert.slice(sig_offset + 1, sig_len - 1);
+    let sig = match alg {
+        CertificateSignatureAlgorithm::Scheme(
+            scheme
+            @ (SignatureScheme::EcdsaSecp256r1Sha256 | SignatureScheme::EcdsaSecp384r1Sha384),
+        ) => parse_ecdsa_signature(&scheme, sig)?,
+        _ => sig,
+    };
+
+    Ok(CertificateParts(tbs, issuer, subject, extensions, alg, sig))
+}
+
+/// Read a BOOLEAN at `offset`.
+///
+/// Returns the value and the new offset into the bytes.
+fn read_boolean(b: &Bytes, offset: usize) -> Result<(bool, usize), Asn1Error> {
+    let (bool_offset, bool_len) = read_element(b, offset, 0x01u8)?;
+    check_success(bool_len == 1)?;
+    Ok((
+        b[bool_offset].declassify() != 0x00u8,
+        bool_offset + bool_len,
+    ))
+}
+
+/// Read the `extensions` of a certificate.
+///
+/// Returns the OID, the critical flag and the content of the value of each
+/// extension.
+fn read_extensions(extensions: &Bytes) -> Result<Vec<(Bytes, bool, Bytes)>, Asn1Error> {
+    let mut offset = 0;
+    let mut result = Vec::new();
+    while offset < extensions.len() {
+        let (ext_offset, ext_len) = read_element(extensions, offset, 0x30u8)?;
+        let (oid_offset, oid_len) = read_element(extensions, ext_offset, 0x06u8)?;
+        let mut value_offset = oid_offset + oid_len;
+        let mut critical = false;
+        if value_offset < extensions.len() && extensions[value_offset].declassify() == 0x01u8 {
+            (critical, value_offset) = read_boolean(extensions, value_offset)?;
+        }
+        let (value_offset, value_len) = read_element(extensions, value_offset, 0x04u8)?;
+        result.push((
+            extensions.slice(oid_offset, oid_len),
+            critical,
+            extensions.slice(value_offset, value_len),
+        ));
+        offset = ext_offset + ext_len;
+    }
+    Ok(result)
+}
+
+/// Find the extension with the given `oid` in the `extensions` of a
+/// certificate.
+///
+/// Returns the content of the extension value, if the extension is present.
+pub fn certificate_extension(extensions: &Bytes, oid: &Bytes) -> Result<Option<Bytes>, Asn1Error> {
+    let mut value = None;
+    for (ext_oid, _critical, ext_value) in read_extensions(extensions)? {
+        if eq(&ext_oid, oid) {
+            value = Some(ext_value);
+        }
+    }
+    Ok(value)
+}
+
+/// Check that a certificate has no critical extensions other than the ones
+/// that are processed here: basicConstraints, keyUsage, extKeyUsage,
+/// subjectAltName and nameConstraints.
+fn check_critical_extensions(parts: &CertificateParts) -> Result<(), TLSError> {
+    let known = [
+        basic_constraints_oid(),
+        key_usage_oid(),
+        ext_key_usage_oid(),
+        subject_alt_name_oid(),
+        name_constraints_oid(),
+    ];
+    let mut result = Ok(());
+    for (oid, critical, _value) in read_extensions(&parts.3)? {
+        if critical && !known.iter().any(|known_oid| eq(known_oid, &oid)) {
+            result = tlserr(BAD_CERTIFICATE);
+        }
+    }
+    result
+}
+
+/// Read the basicConstraints extension of a certificate.
+///
+/// Returns whether the certificate belongs to a CA, and the
+/// pathLenConstraint, if there is one.
+fn basic_constraints(parts: &CertificateParts) -> Result<(bool, Option<usize>), Asn1Error> {
+    match certificate_extension(&parts.3, &basic_constraints_oid())? {
+        Some(bc) => {
+            let (mut offset, len) = read_element(&bc, 0, 0x30u8)?;
+            let end = offset + len;
+            // The cA boolean is optional and defaults to false.
+            let mut ca = false;
+            if offset < end && bc[offset].declassify() == 0x01u8 {
+                (ca, offset) = read_boolean(&bc, offset)?;
+            }
+            let path_len = if offset < end {
+                Some(read_small_integer(&bc, offset)?)
+            } else {
+                None
+            };
+            Ok((ca, path_len))
+        }
+        None => Ok((false, None)),
+    }
+}
+
+/// Check the basicConstraints extension of a certificate to see whether it
+/// belongs to a CA.
+fn is_ca(parts: &CertificateParts) -> Result<bool, Asn1Error> {
+    Ok(basic_constraints(parts)?.0)
+}
+
+/// The digitalSignature bit of the keyUsage extension.
+const KEY_USAGE_DIGITAL_SIGNATURE: usize = 0;
+/// The keyCertSign bit of the keyUsage extension.
+const KEY_USAGE_KEY_CERT_SIGN: usize = 5;
+
+/// Check that the keyUsage extension of a certificate, if it has one, has
+/// the given `bit` set.
+fn has_key_usage(parts: &CertificateParts, bit: usize) -> Result<bool, Asn1Error> {
+    match certificate_extension(&parts.3, &key_usage_oid())? {
+        Some(ku) => {
+            // A bit string, the first byte is the number of unused bits.
+            let (offset, len) = read_element(&ku, 0, 0x03u8)?;
+            check_success(len > 0)?;
+            Ok(bit / 8 + 1 < len
+                && (ku[offset + 1 + bit / 8].declassify() >> (7 - bit % 8)) & 1u8 == 1u8)
+        }
+        None => Ok(true),
+    }
+}
+
+/// Check that the extKeyUsage extension of a certificate, if it has one,
+/// allows the key `purpose` or any purpose.
+fn has_extended_key_usage(parts: &CertificateParts, purpose: &Bytes) -> Result<bool, Asn1Error> {
+    match certificate_extension(&parts.3, &ext_key_usage_oid())? {
+        Some(eku) => {
+            let (mut offset, len) = read_element(&eku, 0, 0x30u8)?;
+            let end = offset + len;
+            let mut allowed = false;
+            while offset < end {
+                let (oid_offset, oid_len) = read_element(&eku, offset, 0x06u8)?;
+                let oid = eku.slice(oid_offset, oid_len);
+                allowed = allowed || eq(&oid, purpose) || eq(&oid, &any_extended_key_usage_oid());
+                offset = oid_offset + oid_len;
+            }
+            Ok(allowed)
+        }
+        None => Ok(true),
+    }
+}
+
+/// Read the GeneralSubtrees of a nameConstraints extension.
+///
+/// Returns the DNS name and the IP address subtrees. Other kinds of names
+/// are not supported.
+fn general_subtrees(b: &Bytes) -> Result<(Vec<Bytes>, Vec<Bytes>), TLSError> {
+    let mut dns_names = Vec::new();
+    let mut ip_addresses = Vec::new();
+    let mut offset = 0;
+    while offset < b.len() {
+        let (subtree_offset, subtree_len) = read_element(b, offset, 0x30u8)?;
+        let tag = b[subtree_offset].declassify();
+        let (name_offset, name_len) = read_any_element(b, subtree_offset)?;
+        if tag == 0x82u8 {
+            dns_names.push(b.slice(name_offset, name_len)); // dNSName
+        } else if tag == 0x87u8 {
+            ip_addresses.push(b.slice(name_offset, name_len)); // iPAddress
+        } else {
+            tlserr(BAD_CERTIFICATE)?;
+        }
+        offset = subtree_offset + subtree_len;
+    }
+    Ok((dns_names, ip_addresses))
+}
+
+/// Check whether the DNS `name` is within the subtree of the DNS name
+/// constraint `base`, ignoring case and a trailing dot.
+///
+/// A constraint covers the name itself and all names below it, or only the
+/// names below it if it starts with a dot.
+fn dns_name_in_subtree(name: &[u8], base: &[u8]) -> bool {
+    let name = name.to_ascii_lowercase();
+    let base = base.to_ascii_lowercase();
+    let name = name.strip_suffix(b".").unwrap_or(&name);
+    let base = base.strip_suffix(b".").unwrap_or(&base);
+    base.is_empty()
+        || (base.starts_with(b".") && name.ends_with(base))
+        || name == base
+        || (name.len() > base.len()
+            && name.ends_with(base)
+            && name[name.len() - base.len() - 1] == b'.')
+}
+
+/// Check whether the DNS `name`, or any name that a wildcard `name` matches,
+/// is within the subtree of the DNS name constraint `base`.
+fn dns_name_may_be_in_subtree(name: &[u8], base: &[u8]) -> bool {
+    dns_name_in_subtree(name, base)
+        || match name.strip_prefix(b"*.") {
+            Some(suffix) => dns_name_in_subtree(base.strip_prefix(b".").unwrap_or(base), suffix),
+            None => false,
+        }
+}
+
+/// Check whether the IP address `ip` is within the subnet `base`, which is
+/// an address followed by a mask of the same length.
+fn ip_address_in_subtree(ip: &[u8], base: &[u8]) -> bool {
+    base.len() == 2 * ip.len()
+        && (0..ip.len()).all(|i| ip[i] & base[ip.len() + i] == base[i] & base[ip.len() + i])
+}
+
+/// Check the DNS names and IP addresses in `names` against the
+/// nameConstraints extension of a CA certificate, if it has one.
+///
+/// Each name has to be within one of the permitted subtrees of its kind, if
+/// there are any, and must not be within any of the excluded subtrees.
+fn check_name_constraints(
+    parts: &CertificateParts,
+    names: &(Vec<Bytes>, Vec<Bytes>),
+) -> Result<(), TLSError> {
+    match certificate_extension(&parts.3, &name_constraints_oid())? {
+        Some(nc) => {
+            let (mut offset, len) = read_element(&nc, 0, 0x30u8)?;
+            let end = offset + len;
+            let mut permitted = (Vec::new(), Vec::new());
+            let mut excluded = (Vec::new(), Vec::new());
+            while offset < end {
+                let tag = nc[offset].declassify();
+                let (subtrees_offset, subtrees_len) = read_any_element(&nc, offset)?;
+                let subtrees = general_subtrees(&nc.slice(subtrees_offset, subtrees_len))?;
+                if tag == 0xA0u8 {
+                    permitted = subtrees; // permittedSubtrees
+                } else if tag == 0xA1u8 {
+                    excluded = subtrees; // excludedSubtrees
+                } else {
+                    asn1err(ASN1_INVALID_TAG)?;
+                }
+                offset = subtrees_offset + subtrees_len;
+            }
+            let dns_names_allowed = names.0.iter().all(|name| {
+                let name = name.declassify();
+                (permitted.0.is_empty()
+                    || permitted
+                        .0
+                        .iter()
+                        .any(|base| dns_name_in_subtree(&name, &base.declassify())))
+                    && !excluded
+                        .0
+                        .iter()
+                        .any(|base| dns_name_may_be_in_subtree(&name, &base.declassify()))
+            });
+            let ip_addresses_allowed = names.1.iter().all(|ip| {
+                let ip = ip.declassify();
+                (permitted.1.is_empty()
+                    || permitted
+                        .1
+                        .iter()
+                        .any(|base| ip_address_in_subtree(&ip, &base.declassify())))
+                    && !excluded
+                        .1
+                        .iter()
+                        .any(|base| ip_address_in_subtree(&ip, &base.declassify()))
+            });
+            if dns_names_allowed && ip_addresses_allowed {
+                Ok(())
+            } else {
+                tlserr(BAD_CERTIFICATE)
+            }
+        }
+        None => Ok(()),
+    }
+}
+
+/// Read a number from ASCII digits.
+fn read_digits(digits: &[u8]) -> Result<u64, Asn1Error> {
+    let mut value = 0u64;
+    for digit in digits {
+        check_success(digit.is_ascii_digit())?;
+        value = value * 10 + (digit - b'0') as u64;
+    }
+    Ok(value)
+}
+
+/// The number of days from 1970-01-01 to the given date, which may be
+/// negative.
+fn days_from_civil(year: u64, month: u64, day: u64) -> i64 {
+    let (year, month, day) = (year as i64, month as i64, day as i64);
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Read a UTCTime or GeneralizedTime in UTC as seconds since the UNIX epoch.
+/// Times before the epoch are read as the epoch.
+///
+/// Returns the time and the new offset into the bytes.
+fn read_time(b: &Bytes, offset: usize) -> Result<(u64, usize), Asn1Error> {
+    let (time_offset, time_len) = read_any_element(b, offset)?;
+    let time = b.slice(time_offset, time_len).declassify();
+    let (year, time) = match (b[offset].declassify(), time_len) {
+        // UTCTime: YYMMDDHHMMSSZ, with years from 1950 to 2049
+        (0x17u8, 13) => {
+            let year = read_digits(&time[0..2])?;
+            if year >= 50 {
+                Ok((1900 + year, &time[2..]))
+            } else {
+                Ok((2000 + year, &time[2..]))
+            }
+        }
+        // GeneralizedTime: YYYYMMDDHHMMSSZ
+        (0x18u8, 15) => Ok((read_digits(&time[0..4])?, &time[4..])),
+        _ => asn1err(ASN1_INVALID_TAG),
+    }?;
+    let month = read_digits(&time[0..2])?;
+    let day = read_digits(&time[2..4])?;
+    let hour = read_digits(&time[4..6])?;
+    let minute = read_digits(&time[6..8])?;
+    let second = read_digits(&time[8..10])?;
+    check_success(
+        (1..=12).contains(&month)
+            && (1..=31).contains(&day)
+            && hour < 24
+            && minute < 60
+            && second <= 60
+            && time[10] == b'Z',
+    )?;
+    let seconds =
+        days_from_civil(year, month, day) * 86400 + (hour * 3600 + minute * 60 + second) as i64;
+    Ok((seconds.max(0) as u64, time_offset + time_len))
+}
+
+/// Read the validity period of a certificate.
+///
+/// Returns the notBefore and notAfter times in seconds since the UNIX epoch.
+pub fn certificate_validity(cert: &Bytes) -> Result<(u64, u64), Asn1Error> {
+    let (offset, _cert_len) = read_element(cert, 0, 0x30u8)?;
+    let (offset, _tbs_len) = read_element(cert, offset, 0x30u8)?;
+    let mut offset = read_version_number(cert, offset)?; // x509 version number
+    offset = read_integer(cert, offset)?; // serial number
+    offset = skip_element(cert, offset)?; // signature algorithm
+    offset = skip_element(cert, offset)?; // issuer
+    let (offset, _validity_len) = read_element(cert, offset, 0x30u8)?;
+    let (not_before, offset) = read_time(cert, offset)?;
+    let (not_after, _offset) = read_time(cert, offset)?;
+    Ok((not_before, not_after))
+}
+
+/// Check that `now`, in seconds since the UNIX epoch, is within the validity
+/// period of a certificate.
+pub fn check_certificate_validity(cert: &Bytes, now: u64) -> Result<(), TLSError> {
+    let (not_before, not_after) = certificate_validity(cert)?;
+    if now < not_before || now > not_after {
+        tlserr(CERTIFICATE_EXPIRED)
+    } else {
+        Ok(())
+    }
+}
+
+/// The maximum number of certificates in a path, not counting the trust
+/// anchor.
+pub const MAX_PATH_LENGTH: usize = 8;
+
+/// Check that the certificate in `parts` is signed with the key of the
+/// `issuer` certificate.
+/// PKCS#1 v1.5 signatures are not supported, as libcrux does not implement
+/// them.
+fn verify_certificate_signature(parts: &CertificateParts, issuer: &Bytes) -> Result<(), TLSError> {
+    let spki = verification_key_from_cert(issuer)?;
+    let pk = cert_public_key(issuer, &spki)?;
+    let res = match (parts.4, &pk) {
+        (CertificateSignatureAlgorithm::RsaPkcs1(_), _) => Err(UNSUPPORTED_ALGORITHM),
+        (CertificateSignatureAlgorithm::Scheme(scheme), _) if scheme == spki.0 => {
+            verify(&scheme, &pk, &parts.0, &parts.5)
+        }
+        (CertificateSignatureAlgorithm::RsaPss(ha, salt_len), PublicVerificationKey::Rsa(pk)) => {
+            verify_rsa_pss(&ha, salt_len, pk, &parts.0, &parts.5)
+        }
+        _ => Err(BAD_CERTIFICATE),
+    };
+    match res {
+        Ok(()) => Ok(()),
+        Err(e) if e == UNSUPPORTED_ALGORITHM => tlserr(e),
+        Err(_) => tlserr(BAD_CERTIFICATE),
+    }
+}
+
+/// The maximum number of certificate signatures checked while looking for a
+/// path.
+pub const MAX_SIGNATURE_CHECKS: usize = 32;
+
+/// Check that the certificate in `parts` is signed with the key of the
+/// `issuer` certificate, if any of the `checks` are left.
+fn check_certificate_signature(
+    parts: &CertificateParts,
+    issuer: &Bytes,
+    checks: &mut usize,
+) -> Result<(), TLSError> {
+    if *checks == 0 {
+        tlserr(BAD_CERTIFICATE)
+    } else {
+        *checks = *checks - 1;
+        verify_certificate_signature(parts, issuer)
+    }
+}
+
+/// Check that the CA certificate in `parts` may issue the next certificate
+/// on a path, with `intermediates_below` intermediates between it and the
+/// end-entity certificate with the given `names`.
+///
+/// The CA certificate must not have unknown critical extensions, its
+/// keyUsage has to allow keyCertSign, its pathLenConstraint has to allow the
+/// intermediates below it, and the names have to satisfy its
+/// nameConstraints.
+fn check_issuer(
+    parts: &CertificateParts,
+    names: &(Vec<Bytes>, Vec<Bytes>),
+    intermediates_below: usize,
+) -> Result<(), TLSError> {
+    check_critical_extensions(parts)?;
+    let (_ca, path_len) = basic_constraints(parts)?;
+    if !has_key_usage(parts, KEY_USAGE_KEY_CERT_SIGN)?
+        || matches!(path_len, Some(path_len) if path_len < intermediates_below)
+    {
+        tlserr(BAD_CERTIFICATE)
+    } else {
+        check_name_constraints(parts, names)
+    }
+}
+
+/// Check that the end-entity certificate in `parts` has no unknown critical
+/// extensions, and that its keyUsage allows digitalSignature and its
+/// extKeyUsage the key `purpose`.
+fn check_end_entity(parts: &CertificateParts, purpose: &Bytes) -> Result<(), TLSError> {
+    check_critical_extensions(parts)?;
+    if has_key_usage(parts, KEY_USAGE_DIGITAL_SIGNATURE)? && has_extended_key_usage(parts, purpose)?
+    {
+        Ok(())
+    } else {
+        tlserr(BAD_CERTIFICATE)
+    }
+}
+
+/// The names of an end-entity certificate that name constraints apply to:
+/// the subjectAltName DNS names or, if there are none, the subject common
+/// name, and the subjectAltName IP addresses.
+fn end_entity_names(parts: &CertificateParts) -> Result<(Vec<Bytes>, Vec<Bytes>), Asn1Error> {
+    let (mut dns_names, ip_addresses) = subject_alt_names(parts)?;
+    if dns_names.is_empty() {
+        if let Some(cn) = common_name(parts)? {
+            dns_names.push(cn);
+        }
+    }
+    Ok((dns_names, ip_addresses))
+}
+
+/// Build a path from `cert` to one of the `trust_anchors` with at most
+/// `depth` more certificates from `intermediates`, all valid at `now`, and
+/// at most `checks` more signature checks.
+///
+/// Each intermediate on the path is checked with [check_issuer] against the
+/// `names` of the end-entity certificate.
+/// An intermediate is taken out of the pool once it is on the path, and
+/// intermediates and trust anchors that can't be parsed are skipped.
+fn verify_path(
+    cert: &Bytes,
+    intermediates: &[Bytes],
+    trust_anchors: &[Bytes],
+    now: u64,
+    names: &(Vec<Bytes>, Vec<Bytes>),
+    depth: usize,
+    checks: &mut usize,
+) -> Result<(), TLSError> {
+    check_certificate_validity(cert, now)?;
+    if trust_anchors.iter().any(|anchor| eq(anchor, cert)) {
+        Ok(())
+    } else if depth == 0 {
+        tlserr(UNKNOWN_CA)
+    } else {
+        let parts = certificate_parts(cert)?;
+        let mut result = Err(UNKNOWN_CA);
+        for anchor in trust_anchors {
+            if let Ok(CertificateParts(_, _, subject, _, _, _)) = certificate_parts(anchor) {
+                if result.is_err() && eq(&subject, &parts.1) {
+                    result = check_certificate_signature(&parts, anchor, checks);
+                }
+            }
+        }
+        for i in 0..intermediates.len() {
+            let issuer = &intermediates[i];
+            if let Ok(issuer_parts) = certificate_parts(issuer) {
+                if result.is_err()
+                    && eq(&issuer_parts.2, &parts.1)
+                    && matches!(is_ca(&issuer_parts), Ok(true))
+                {
+                    let pool = [&intermediates[..i], &intermediates[i + 1..]].concat();
+                    result = check_issuer(&issuer_parts, names, MAX_PATH_LENGTH - depth)
+                        .and_then(|_| check_certificate_signature(&parts, issuer, checks))
+                        .and_then(|_| {
+                            verify_path(issuer, &pool, trust_anchors, now, names, depth - 1, checks)
+                        });
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Validate a certificate chain, the end-entity certificate first, against
+/// a set of trust anchors.
+///
+/// Starting from the end-entity certificate, the issuer of each certificate
+/// is looked up by name among the trust anchors and then the intermediates in
+/// the chain, which must be CA certificates, and its signature is checked.
+/// The path ends at a trust anchor, which may be the end-entity certificate
+/// itself.
+/// All certificates on the path, but not a trust anchor that issued one of
+/// them, have to be valid at `now`, in seconds since the UNIX epoch.
+/// The search for a path fails with `BAD_CERTIFICATE` once it needs more than
+/// [MAX_SIGNATURE_CHECKS] signature checks.
+///
+/// The extensions are processed as in RFC 5280: the end-entity certificate
+/// has to be usable for signatures and for the key `purpose`, e.g.
+/// [server_auth_oid] or [client_auth_oid], and the keyUsage, the
+/// pathLenConstraint and the DNS and IP address nameConstraints of the
+/// intermediates are enforced, where the path length counts every
+/// intermediate.
+/// Certificates with critical extensions that are not processed are rejected.
+/// Trust anchors are taken as they are, their extensions are not checked.
+pub fn verify_certificate_chain(
+    chain: &[Bytes],
+    trust_anchors: &[Bytes],
+    now: u64,
+    purpose: &Bytes,
+) -> Result<(), TLSError> {
+    match chain.split_first() {
+        Some((cert, intermediates)) => {
+            let parts = certificate_parts(cert)?;
+            check_end_entity(&parts, purpose)?;
+            let names = end_entity_names(&parts)?;
+            let mut checks = MAX_SIGNATURE_CHECKS;
+            verify_path(
+                cert,
+                intermediates,
+                trust_anchors,
+                now,
+                &names,
+                MAX_PATH_LENGTH,
+                &mut checks,
+            )
+        }
+        None => tlserr(BAD_CERTIFICATE),
+    }
+}
+
+/// Read the DNS names and the IP addresses from the subjectAltName extension
+/// of a certificate.
+fn subject_alt_names(parts: &CertificateParts) -> Result<(Vec<Bytes>, Vec<Bytes>), Asn1Error> {
+    let mut dns_names = Vec::new();
+    let mut ip_addresses = Vec::new();
+    if let Some(san) = certificate_extension(&parts.3, &subject_alt_name_oid())? {
+        let (mut offset, san_len) = read_element(&san, 0, 0x30u8)?;
+        let end = offset + san_len;
+        while offset < end {
+            let tag = san[offset].declassify();
+            let (name_offset, name_len) = read_any_element(&san, offset)?;
+            if tag == 0x82u8 {
+                dns_names.push(san.slice(name_offset, name_len)); // dNSName
+            } else if tag == 0x87u8 {
+                ip_addresses.push(san.slice(name_offset, name_len)); // iPAddress
+            }
+            offset = name_offset + name_len;
+        }
+    }
+    Ok((dns_names, ip_addresses))
+}
+
+/// Read the most specific, i.e. the last, common name in the subject of a
+/// certificate.
+fn common_name(parts: &CertificateParts) -> Result<Option<Bytes>, Asn1Error> {
+    let subject = &parts.2;
+    let mut cn = None;
+    let (mut offset, subject_len) = read_element(subject, 0, 0x30u8)?;
+    let end = offset + subject_len;
+    while offset < end {
+        // Each relative distinguished name is a set of attributes.
+        let (mut attr_offset, rdn_len) = read_element(subject, offset, 0x31u8)?;
+        let rdn_end = attr_offset + rdn_len;
+        while attr_offset < rdn_end {
+            let (type_offset, attr_len) = read_element(subject, attr_offset, 0x30u8)?;
+            let (oid_offset, oid_len) = read_element(subject, type_offset, 0x06u8)?;
+            if eq(&subject.slice(oid_offset, oid_len), &common_name_oid()) {
+                // The name can be any kind of string.
+                let (value_offset, value_len) = read_any_element(subject, oid_offset + oid_len)?;
+                cn = Some(subject.slice(value_offset, value_len));
+            }
+            attr_offset = type_offset + attr_len;
+        }
+        offset = rdn_end;
+    }
+    Ok(cn)
+}
+
+/// Match a DNS name from a certificate against a host name, ignoring case
+/// and a trailing dot.
+///
+/// A wildcard is only allowed as the complete left-most label, where it
+/// matches exactly one label, and must be followed by at least two labels.
+fn dns_name_matches(pattern: &Bytes, name: &Bytes) -> bool {
+    let pattern = pattern.declassify().to_ascii_lowercase();
+    let name = name.declassify().to_ascii_lowercase();
+    let pattern = pattern.strip_suffix(b".").unwrap_or(&pattern);
+    let name = name.strip_suffix(b".").unwrap_or(&name);
+    match pattern.strip_prefix(b"*.") {
+        Some(suffix) => {
+            let label_end = name.iter().position(|c| *c == b'.');
+            suffix.contains(&b'.')
+                && !suffix.contains(&b'*')
+                && match label_end {
+                    Some(label_end) => label_end > 0 && &name[label_end + 1..] == suffix,
+                    None => false,
+                }
+        }
+        None => !name.is_empty() && !pattern.contains(&b'*') && pattern == name,
+    }
+}
+
+/// Read a host name that is an IPv4 or IPv6 address into its bytes.
+pub fn ip_address(name: &Bytes) -> Option<Vec<u8>> {
+    match String::from_utf8(name.declassify()).map(|name| name.parse()) {
+        Ok(Ok(std::net::IpAddr::V4(ip))) => Some(ip.octets().to_vec()),
+        Ok(Ok(std::net::IpAddr::V6(ip))) => Some(ip.octets().to_vec()),
+        _ => None,
+    }
+}
+
+/// Check that the end-entity certificate `cert` is valid for the host `name`.
+///
+/// An IP address has to be one of the subjectAltName IP addresses.
+/// A DNS name has to match one of the subjectAltName DNS names or, if there
+/// are none, the subject common name.
+pub fn verify_certificate_name(cert: &Bytes, name: &Bytes) -> Result<(), TLSError> {
+    let parts = certificate_parts(cert)?;
+    let (dns_names, ip_addresses) = subject_alt_names(&parts)?;
+    let valid = match ip_address(name) {
+        Some(ip) => ip_addresses
+            .iter()
+            .any(|address| address.declassify() == ip),
+        None if dns_names.is_empty() => match common_name(&parts)? {
+            Some(cn) => dns_name_matches(&cn, name),
+            None => false,
+        },
+        None => dns_names
+            .iter()
+            .any(|pattern| dns_name_matches(pattern, name)),
+    };
+    if valid {
+        Ok(())
+    } else {
+        tlserr(BAD_CERTIFICATE)
+    }
+}
+
 #[cfg(test)]
 mod unit_test {
     use std::{fs, io::Read};
@@ -356,6 +1351,269 @@ mod unit_test {
         test(&cert);
     }
 
+    #[test]
+    fn certificate_parts_and_ca() {
+        let parts =
+            certificate_parts(&CLOUDFLARE_COM_DER.into()).expect("Error splitting the cert");
+        assert_eq!(
+            parts.4,
+            CertificateSignatureAlgorithm::Scheme(SignatureScheme::EcdsaSecp256r1Sha256)
+        );
+        assert_eq!(parts.5.len(), 64);
+        assert!(!is_ca(&parts).unwrap());
+    }
+
+    fn read_test_cert(name: &str) -> Bytes {
+        fs::read(format!("test_certs/{}", name))
+            .expect("Error reading file")
+            .into()
+    }
+
+    #[test]
+    fn rsa_certificate_signatures() {
+        // Self-signed certificates with PKCS#1 v1.5 signatures are read, but
+        // their signatures are not supported.
+        let goo_gl: Bytes = GOO_GL_DER.into();
+        for (cert, ha) in [
+            (goo_gl, HashAlgorithm::SHA256),
+            (
+                read_test_cert("rsa_pkcs1_sha384.der"),
+                HashAlgorithm::SHA384,
+            ),
+            (
+                read_test_cert("rsa_pkcs1_sha512.der"),
+                HashAlgorithm::SHA512,
+            ),
+        ] {
+            let parts = certificate_parts(&cert).expect("Error splitting the cert");
+            assert_eq!(parts.4, CertificateSignatureAlgorithm::RsaPkcs1(ha));
+            assert_eq!(
+                verify_certificate_signature(&parts, &cert),
+                Err(UNSUPPORTED_ALGORITHM)
+            );
+        }
+
+        // A self-signed certificate with an RSA-PSS signature, where the
+        // salt length is the default of 20 bytes.
+        let cert = read_test_cert("rsa_pss_sha384_salt20.der");
+        let mut parts = certificate_parts(&cert).expect("Error splitting the cert");
+        assert_eq!(
+            parts.4,
+            CertificateSignatureAlgorithm::RsaPss(HashAlgorithm::SHA384, 20)
+        );
+        assert!(verify_certificate_signature(&parts, &cert).is_ok());
+        parts.0[10] = parts.0[10] ^ U8::from(1);
+        assert_eq!(
+            verify_certificate_signature(&parts, &cert),
+            Err(BAD_CERTIFICATE)
+        );
+        // The signature is checked with the algorithm it names.
+        parts.0[10] = parts.0[10] ^ U8::from(1);
+        parts.4 = CertificateSignatureAlgorithm::RsaPss(HashAlgorithm::SHA256, 32);
+        assert_eq!(
+            verify_certificate_signature(&parts, &cert),
+            Err(BAD_CERTIFICATE)
+        );
+
+        // MGF1 has to use the signature hash.
+        assert_eq!(
+            certificate_parts(&read_test_cert("rsa_pss_sha512_mgf1_sha256.der")).err(),
+            Some(ASN1_UNSUPPORTED_ALGORITHM)
+        );
+    }
+
+    // A time at which the pki_ test certificates are valid.
+    const PKI_NOW: u64 = 1_800_000_000;
+
+    fn verify_pki_chain(names: &[&str], purpose: &Bytes) -> Result<(), TLSError> {
+        let chain: Vec<Bytes> = names
+            .iter()
+            .map(|name| read_test_cert(&format!("pki_{}.der", name)))
+            .collect();
+        let root = read_test_cert("pki_root.der");
+        verify_certificate_chain(&chain, &[root], PKI_NOW, purpose)
+    }
+
+    #[test]
+    fn certificate_extensions() {
+        let server = server_auth_oid();
+        let client = client_auth_oid();
+        assert!(verify_pki_chain(&["leaf"], &server).is_ok());
+
+        // Unknown extensions may only be ignored if they are not critical.
+        assert!(verify_pki_chain(&["leaf_unknown_non_critical"], &server).is_ok());
+        assert_eq!(
+            verify_pki_chain(&["leaf_unknown_critical"], &server),
+            Err(BAD_CERTIFICATE)
+        );
+
+        // The end-entity key has to be usable for signatures and for the
+        // purpose it is used for.
+        assert_eq!(
+            verify_pki_chain(&["leaf_key_encipherment"], &server),
+            Err(BAD_CERTIFICATE)
+        );
+        assert_eq!(verify_pki_chain(&["leaf"], &client), Err(BAD_CERTIFICATE));
+        assert!(verify_pki_chain(&["leaf_client_auth"], &client).is_ok());
+        assert_eq!(
+            verify_pki_chain(&["leaf_client_auth"], &server),
+            Err(BAD_CERTIFICATE)
+        );
+
+        // An issuer has to be allowed to sign certificates.
+        assert_eq!(
+            verify_pki_chain(&["leaf_of_no_cert_sign", "ca_no_cert_sign"], &server),
+            Err(BAD_CERTIFICATE)
+        );
+    }
+
+    #[test]
+    fn certificate_path_length() {
+        let server = server_auth_oid();
+        assert!(verify_pki_chain(&["leaf_of_path_len_0", "ca_path_len_0"], &server).is_ok());
+        assert_eq!(
+            verify_pki_chain(
+                &[
+                    "leaf_below_path_len_0",
+                    "ca_below_path_len_0",
+                    "ca_path_len_0"
+                ],
+                &server
+            ),
+            Err(BAD_CERTIFICATE)
+        );
+    }
+
+    #[test]
+    fn certificate_name_constraints() {
+        // The CA permits example.com and 10.0.0.0/8, but not bad.example.com.
+        let server = server_auth_oid();
+        assert!(
+            verify_pki_chain(&["leaf_permitted_names", "ca_name_constraints"], &server).is_ok()
+        );
+        for leaf in [
+            "leaf_not_permitted_dns",
+            "leaf_excluded_dns",
+            "leaf_not_permitted_ip",
+        ] {
+            assert_eq!(
+                verify_pki_chain(&[leaf, "ca_name_constraints"], &server),
+                Err(BAD_CERTIFICATE)
+            );
+        }
+
+        assert!(dns_name_in_subtree(b"www.Example.com.", b"example.com"));
+        assert!(dns_name_in_subtree(b"example.com", b"example.com"));
+        assert!(!dns_name_in_subtree(b"badexample.com", b"example.com"));
+        assert!(!dns_name_in_subtree(b"example.com", b".example.com"));
+        assert!(dns_name_may_be_in_subtree(
+            b"*.example.com",
+            b"bad.example.com"
+        ));
+        assert!(ip_address_in_subtree(
+            &[10, 1, 2, 3],
+            &[10, 0, 0, 0, 255, 0, 0, 0]
+        ));
+        assert!(!ip_address_in_subtree(
+            &[11, 1, 2, 3],
+            &[10, 0, 0, 0, 255, 0, 0, 0]
+        ));
+    }
+
+    fn name(s: &str) -> Bytes {
+        s.as_bytes().into()
+    }
+
+    #[test]
+    fn certificate_names() {
+        let cert = CLOUDFLARE_COM_DER.into();
+        for host in [
+            "cloudflare.com",
+            "www.cloudflare.com",
+            "WWW.Cloudflare.COM.",
+            "one.dns.cloudflare.com",
+        ] {
+            assert!(verify_certificate_name(&cert, &name(host)).is_ok());
+        }
+        for host in [
+            "a.b.cloudflare.com",
+            "dns.cloudflare.org",
+            "example.com",
+            "1.1.1.1",
+            "",
+        ] {
+            assert_eq!(
+                verify_certificate_name(&cert, &name(host)),
+                Err(BAD_CERTIFICATE)
+            );
+        }
+    }
+
+    #[test]
+    fn wildcard_names() {
+        assert!(dns_name_matches(
+            &name("*.example.com"),
+            &name("a.example.com")
+        ));
+        assert!(!dns_name_matches(
+            &name("*.example.com"),
+            &name("example.com")
+        ));
+        assert!(!dns_name_matches(
+            &name("*.example.com"),
+            &name(".example.com")
+        ));
+        assert!(!dns_name_matches(&name("*.com"), &name("example.com")));
+        assert!(!dns_name_matches(
+            &name("f*.example.com"),
+            &name("foo.example.com")
+        ));
+        assert!(!dns_name_matches(&name("*.*.com"), &name("a.b.com")));
+        assert!(!dns_name_matches(&name("*"), &name("localhost")));
+    }
+
+    #[test]
+    fn certificate_times() {
+        let validity = certificate_validity(&CLOUDFLARE_COM_DER.into());
+        assert_eq!(validity, Ok((1651622400, 1683244799)));
+        let cert = CLOUDFLARE_COM_DER.into();
+        assert!(check_certificate_validity(&cert, 1651622400).is_ok());
+        assert!(check_certificate_validity(&cert, 1683244799).is_ok());
+        assert_eq!(
+            check_certificate_validity(&cert, 1683244800),
+            Err(CERTIFICATE_EXPIRED)
+        );
+
+        let time = Bytes::from(&[0x18u8, 0x0f]).concat(&name("20500101000000Z"));
+        assert_eq!(read_time(&time, 0), Ok((2524608000, 17)));
+        let time = Bytes::from(&[0x17u8, 0x0d]).concat(&name("500101000000Z"));
+        assert_eq!(read_time(&time, 0), Ok((0, 15)));
+        let time = Bytes::from(&[0x17u8, 0x0d]).concat(&name("491332000000Z"));
+        assert!(read_time(&time, 0).is_err());
+    }
+
+    #[test]
+    fn ecdsa_signatures() {
+        // A P-256 signature with a short `r` and an `s` with the high bit set.
+        let der = Bytes::from(&[0x30u8, 0x44, 0x02, 0x1F])
+            .concat(&Bytes::from(&[0x11u8; 31]))
+            .concat(&Bytes::from(&[0x02u8, 0x21, 0x00]))
+            .concat(&Bytes::from(&[0x99u8; 32]));
+        let sig = parse_ecdsa_signature(&SignatureScheme::EcdsaSecp256r1Sha256, der).unwrap();
+        let expected = Bytes::from(&[0x00u8])
+            .concat(&Bytes::from(&[0x11u8; 31]))
+            .concat(&Bytes::from(&[0x99u8; 32]));
+        assert!(eq(&sig, &expected));
+
+        // P-384 values are 48 bytes long.
+        let der = Bytes::from(&[0x30u8, 0x64, 0x02, 0x30])
+            .concat(&Bytes::from(&[0x11u8; 48]))
+            .concat(&Bytes::from(&[0x02u8, 0x30]))
+            .concat(&Bytes::from(&[0x22u8; 48]));
+        assert!(parse_ecdsa_signature(&SignatureScheme::EcdsaSecp384r1Sha384, der.clone()).is_ok());
+        assert!(parse_ecdsa_signature(&SignatureScheme::EcdsaSecp256r1Sha256, der).is_err());
+    }
+
     #[test]
     fn read_cert() {
         let files = fs::read_dir("test_certs").expect("Error listing files.");
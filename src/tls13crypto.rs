@@ -3,10 +3,25 @@ use libcrux::{
     *,
 };
 
-use crate::{eq, tlserr, Bytes, Declassify, TLSError, CRYPTO_ERROR, UNSUPPORTED_ALGORITHM};
+use crate::{
+    check, eq, tlserr, Bytes, Declassify, TLSError, CRYPTO_ERROR, INSUFFICIENT_ENTROPY,
+    INVALID_KEY_SHARE, UNSUPPORTED_ALGORITHM,
+};
 
 pub type Random = Bytes; //was [U8;32]
 pub type Entropy = Bytes;
+
+/// A random number generator for libcrux, seeded with the first 32 bytes of
+/// `ent`, so that all our randomness comes from the caller's entropy.
+fn entropy_rng(ent: &Entropy) -> Result<rand::rngs::StdRng, TLSError> {
+    if ent.len() < 32 {
+        tlserr(INSUFFICIENT_ENTROPY)
+    } else {
+        let mut seed = [0u8; 32];
+        seed.copy_from_slice(&ent.slice(0, 32).declassify());
+        Ok(rand::SeedableRng::from_seed(seed))
+    }
+}
 pub type SignatureKey = Bytes;
 pub type PSK = Bytes;
 pub type Key = Bytes;
@@ -214,23 +229,88 @@ pub fn aead_decrypt(
     }
 }
 
+/// The signature schemes we implement, all through libcrux.
+/// ECDSA on P-521 (ecdsa_secp521r1_sha512) is not among them, because
+/// libcrux does not implement it; such certificate keys are unsupported.
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum SignatureScheme {
     RsaPssRsaSha256,
+    RsaPssRsaSha384,
+    RsaPssRsaSha512,
     EcdsaSecp256r1Sha256,
+    EcdsaSecp384r1Sha384,
     ED25519,
 }
 
 pub fn to_libcrux_sig_alg(a: &SignatureScheme) -> Result<signature::Algorithm, TLSError> {
     match a {
-        SignatureScheme::RsaPssRsaSha256 => tlserr(UNSUPPORTED_ALGORITHM),
+        SignatureScheme::RsaPssRsaSha256 => Ok(signature::Algorithm::RsaPss(
+            signature::DigestAlgorithm::Sha256,
+        )),
+        SignatureScheme::RsaPssRsaSha384 => Ok(signature::Algorithm::RsaPss(
+            signature::DigestAlgorithm::Sha384,
+        )),
+        SignatureScheme::RsaPssRsaSha512 => Ok(signature::Algorithm::RsaPss(
+            signature::DigestAlgorithm::Sha512,
+        )),
         SignatureScheme::ED25519 => Ok(signature::Algorithm::Ed25519),
         SignatureScheme::EcdsaSecp256r1Sha256 => Ok(signature::Algorithm::EcDsaP256(
             signature::DigestAlgorithm::Sha256,
         )),
+        SignatureScheme::EcdsaSecp384r1Sha384 => Ok(signature::Algorithm::EcDsaP384(
+            signature::DigestAlgorithm::Sha384,
+        )),
     }
 }
 
+fn to_libcrux_digest_alg(alg: &HashAlgorithm) -> signature::DigestAlgorithm {
+    match alg {
+        HashAlgorithm::SHA256 => signature::DigestAlgorithm::Sha256,
+        HashAlgorithm::SHA384 => signature::DigestAlgorithm::Sha384,
+        HashAlgorithm::SHA512 => signature::DigestAlgorithm::Sha512,
+    }
+}
+
+/// The hash and salt length of an RSA-PSS signature scheme.
+fn rsa_pss_digest(alg: &SignatureScheme) -> Result<(HashAlgorithm, usize), TLSError> {
+    match alg {
+        SignatureScheme::RsaPssRsaSha256 => Ok((HashAlgorithm::SHA256, 32)),
+        SignatureScheme::RsaPssRsaSha384 => Ok((HashAlgorithm::SHA384, 48)),
+        SignatureScheme::RsaPssRsaSha512 => Ok((HashAlgorithm::SHA512, 64)),
+        _ => tlserr(UNSUPPORTED_ALGORITHM),
+    }
+}
+
+/// Whether `alg` is one of the RSA-PSS signature schemes.
+pub fn is_rsa_pss(alg: &SignatureScheme) -> bool {
+    rsa_pss_digest(alg).is_ok()
+}
+
+/// The length of the `r` and `s` values of an ECDSA signature scheme, which
+/// is the length of a coordinate of the curve.
+pub fn ecdsa_scalar_len(alg: &SignatureScheme) -> Result<usize, TLSError> {
+    match alg {
+        SignatureScheme::EcdsaSecp256r1Sha256 => Ok(32),
+        SignatureScheme::EcdsaSecp384r1Sha384 => Ok(48),
+        _ => tlserr(UNSUPPORTED_ALGORITHM),
+    }
+}
+
+/// Whether `alg` is one of the ECDSA signature schemes.
+pub fn is_ecdsa(alg: &SignatureScheme) -> bool {
+    ecdsa_scalar_len(alg).is_ok()
+}
+
+/// Whether signatures with `alg` can be made with a certificate key of type
+/// `key`, as read by `verification_key_from_cert`.
+/// All RSA-PSS schemes work with an RSA key.
+pub fn signature_scheme_matches_key(alg: &SignatureScheme, key: &SignatureScheme) -> bool {
+    alg == key || (is_rsa_pss(alg) && is_rsa_pss(key))
+}
+
+/// Sign `input` with the key `sk`, taking the randomness from the first 32
+/// bytes of `ent`.
+/// RSA keys need the public key as well, see [sign_rsa].
 pub fn sign(
     alg: &SignatureScheme,
     sk: &Bytes,
@@ -241,7 +321,7 @@ pub fn sign(
         to_libcrux_sig_alg(alg)?,
         &input.declassify(),
         &sk.declassify(),
-        &mut rand::thread_rng(),
+        &mut entropy_rng(&ent)?,
     );
     match sig {
         Ok(signature::Signature::Ed25519(sig)) => Ok(sig.as_bytes().into()),
@@ -249,11 +329,58 @@ pub fn sign(
             let (r, s) = sig.as_bytes();
             Ok(Bytes::from(r).concat(&Bytes::from(s)))
         }
+        Ok(signature::Signature::EcDsaP384(sig)) => {
+            let (r, s) = sig.as_bytes();
+            Ok(Bytes::from(r).concat(&Bytes::from(s)))
+        }
         Ok(signature::Signature::RsaPss(sig)) => Ok(sig.as_bytes().into()),
         Err(_) => tlserr(CRYPTO_ERROR),
     }
 }
 
+/// Sign `input` with RSA-PSS, using the private exponent `sk` together with
+/// the modulus `n` and public exponent `e` of the certificate.
+/// The salt is as long as the digest and taken from the start of `ent`.
+pub fn sign_rsa(
+    alg: &SignatureScheme,
+    sk: &Bytes,
+    n: &Bytes,
+    e: &Bytes,
+    input: &Bytes,
+    ent: Bytes,
+) -> Result<Bytes, TLSError> {
+    let (ha, salt_len) = rsa_pss_digest(alg)?;
+    if ent.len() < salt_len {
+        tlserr(INSUFFICIENT_ENTROPY)
+    } else {
+        let pk = rsa_pss_public_key(n, e)?;
+        let sig = match signature::rsa_pss::RsaPssPrivateKey::new(&pk, &sk.declassify()) {
+            Ok(sk) => sk.sign(
+                to_libcrux_digest_alg(&ha),
+                &ent.slice(0, salt_len).declassify(),
+                &input.declassify(),
+            ),
+            Err(e) => Err(e),
+        };
+        match sig {
+            Ok(sig) => Ok(sig.as_bytes().into()),
+            Err(_) => tlserr(CRYPTO_ERROR),
+        }
+    }
+}
+
+/// The signature schemes that `verify` supports, e.g. for a CertificateRequest.
+pub fn verification_schemes() -> Vec<SignatureScheme> {
+    vec![
+        SignatureScheme::EcdsaSecp256r1Sha256,
+        SignatureScheme::EcdsaSecp384r1Sha384,
+        SignatureScheme::ED25519,
+        SignatureScheme::RsaPssRsaSha256,
+        SignatureScheme::RsaPssRsaSha384,
+        SignatureScheme::RsaPssRsaSha512,
+    ]
+}
+
 pub fn verify(
     alg: &SignatureScheme,
     pk: &PublicVerificationKey,
@@ -288,10 +415,90 @@ pub fn verify(
                 Err(_) => tlserr(CRYPTO_ERROR),
             }
         }
+        (SignatureScheme::EcdsaSecp384r1Sha384, PublicVerificationKey::EcDsa(pk)) => {
+            let res = signature::verify(
+                &input.declassify(),
+                &signature::Signature::EcDsaP384(signature::EcDsaP384Signature::from_bytes(
+                    sig.declassify_array()?,
+                    signature::Algorithm::EcDsaP384(signature::DigestAlgorithm::Sha384),
+                )),
+                &pk.declassify(),
+            );
+            match res {
+                Ok(res) => Ok(res),
+                Err(_) => tlserr(CRYPTO_ERROR),
+            }
+        }
+        (
+            SignatureScheme::RsaPssRsaSha256
+            | SignatureScheme::RsaPssRsaSha384
+            | SignatureScheme::RsaPssRsaSha512,
+            PublicVerificationKey::Rsa((n, e)),
+        ) => {
+            let (ha, salt_len) = rsa_pss_digest(alg)?;
+            verify_rsa_pss(&ha, salt_len, &(n.clone(), e.clone()), input, sig)
+        }
         _ => tlserr(UNSUPPORTED_ALGORITHM),
     }
 }
 
+/// Verify an RSASSA-PSS signature with the hash `ha`, MGF1 with the same
+/// hash, and a salt of `salt_len` bytes.
+pub fn verify_rsa_pss(
+    ha: &HashAlgorithm,
+    salt_len: usize,
+    pk: &RsaVerificationKey,
+    input: &Bytes,
+    sig: &Bytes,
+) -> Result<(), TLSError> {
+    let (n, e) = pk;
+    let pk = rsa_pss_public_key(n, e)?;
+    let res = pk.verify(
+        to_libcrux_digest_alg(ha),
+        &sig.declassify()[..].into(),
+        &input.declassify(),
+        salt_len,
+    );
+    match res {
+        Ok(res) => Ok(res),
+        Err(_) => tlserr(CRYPTO_ERROR),
+    }
+}
+
+/// Build an RSA public key from the modulus and exponent integers of a
+/// certificate, which may carry a leading zero byte.
+/// Only the public exponent 65537 is supported.
+fn rsa_pss_public_key(
+    n: &Bytes,
+    e: &Bytes,
+) -> Result<signature::rsa_pss::RsaPssPublicKey, TLSError> {
+    let n = n.declassify();
+    let n = match n.iter().position(|b| *b != 0) {
+        Some(i) => &n[i..],
+        None => &n[..],
+    };
+    let key_size = match n.len() {
+        256 => Some(signature::rsa_pss::RsaPssKeySize::N2048),
+        384 => Some(signature::rsa_pss::RsaPssKeySize::N3072),
+        512 => Some(signature::rsa_pss::RsaPssKeySize::N4096),
+        768 => Some(signature::rsa_pss::RsaPssKeySize::N6144),
+        1024 => Some(signature::rsa_pss::RsaPssKeySize::N8192),
+        _ => None,
+    };
+    match key_size {
+        Some(key_size) if e.declassify() == [0x01, 0x00, 0x01] => {
+            match signature::rsa_pss::RsaPssPublicKey::new(key_size, n) {
+                Ok(pk) => Ok(pk),
+                Err(_) => tlserr(CRYPTO_ERROR),
+            }
+        }
+        _ => tlserr(UNSUPPORTED_ALGORITHM),
+    }
+}
+
+/// The key exchange groups, implemented through libcrux.
+/// X448 and P-521 are recognized, but libcrux does not implement them, so
+/// generating or using their key shares fails with `UNSUPPORTED_ALGORITHM`.
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum KemScheme {
     X25519,
@@ -299,53 +506,193 @@ pub enum KemScheme {
     X448,
     Secp384r1,
     Secp521r1,
+    MlKem768,
+    X25519MlKem768,
 }
 
 pub fn kem_priv_len(alg: &KemScheme) -> usize {
     match alg {
         KemScheme::X25519 => 32,
         KemScheme::Secp256r1 => 32,
-        _ => 64, //Check
+        KemScheme::X448 => 56,
+        KemScheme::Secp384r1 => 48,
+        KemScheme::Secp521r1 => 66,
+        KemScheme::MlKem768 => 64,
+        KemScheme::X25519MlKem768 => 96,
+    }
+}
+
+/// The length of a key share of `alg`, i.e. the public key a client sends.
+/// Points on the NIST curves are sent uncompressed, with a leading 0x04.
+pub fn kem_pub_len(alg: &KemScheme) -> usize {
+    match alg {
+        KemScheme::X25519 => 32,
+        KemScheme::Secp256r1 => 65,
+        KemScheme::X448 => 56,
+        KemScheme::Secp384r1 => 97,
+        KemScheme::Secp521r1 => 133,
+        KemScheme::MlKem768 => 1184,
+        KemScheme::X25519MlKem768 => 1184 + 32,
     }
 }
 
+/// The length of an encapsulation of `alg`, i.e. the key share a server
+/// sends. For Diffie-Hellman groups this is a public key as well.
+pub fn kem_ct_len(alg: &KemScheme) -> usize {
+    match alg {
+        KemScheme::MlKem768 => 1088,
+        KemScheme::X25519MlKem768 => 1088 + 32,
+        _ => kem_pub_len(alg),
+    }
+}
+
+// The X25519MLKEM768 hybrid is built from X25519 and ML-KEM-768 by the
+// `hybrid_*` functions, because the libcrux hybrid puts the X25519 share first.
+// XXX: libcrux does not implement X448 and P-521.
 pub fn to_libcrux_kem_alg(alg: &KemScheme) -> Result<kem::Algorithm, TLSError> {
     match alg {
         KemScheme::X25519 => Ok(kem::Algorithm::X25519),
         KemScheme::Secp256r1 => Ok(kem::Algorithm::Secp256r1),
+        KemScheme::Secp384r1 => Ok(kem::Algorithm::Secp384r1),
+        KemScheme::MlKem768 => Ok(kem::Algorithm::MlKem768),
         _ => tlserr(UNSUPPORTED_ALGORITHM),
     }
 }
+
+/// The prefix of a key share of `alg` that libcrux does not encode.
+fn encoding_prefix(alg: &KemScheme) -> Bytes {
+    match alg {
+        KemScheme::Secp256r1 | KemScheme::Secp384r1 => Bytes::from(&[0x04u8]),
+        _ => Bytes::new(),
+    }
+}
+
+/// Check that the key share `pk` of `alg` is `len` bytes long and well
+/// formed, and strip the prefix that libcrux does not expect.
+fn check_kem_pk(alg: &KemScheme, pk: &Bytes, len: usize) -> Result<Bytes, TLSError> {
+    let prefix = encoding_prefix(alg);
+    if pk.len() != len || !eq(&pk.slice(0, prefix.len()), &prefix) {
+        tlserr(INVALID_KEY_SHARE)
+    } else {
+        Ok(pk.slice_range(prefix.len()..pk.len()))
+    }
+}
+
+/// Generate a key pair for `alg` from the `kem_priv_len(alg)` bytes of `ent`.
 pub fn kem_keygen(alg: &KemScheme, ent: Bytes) -> Result<(KemSk, KemPk), TLSError> {
-    //let sk = ent.clone();
-    //let pk = kem::secret_to_public(to_libcrux_kem_alg(alg)?,&sk.declassify());
-    let res = kem::key_gen(to_libcrux_kem_alg(alg)?, &mut rand::thread_rng());
-    match res {
-        Ok((sk, pk)) => Ok((Bytes::from(sk.encode()), Bytes::from(pk.encode()))),
-        Err(_) => tlserr(CRYPTO_ERROR),
+    if ent.len() < kem_priv_len(alg) {
+        tlserr(INSUFFICIENT_ENTROPY)
+    } else if alg == &KemScheme::X25519MlKem768 {
+        hybrid_keygen(ent)
+    } else {
+        let res = kem::key_gen(to_libcrux_kem_alg(alg)?, &mut entropy_rng(&ent)?);
+        match res {
+            Ok((sk, pk)) => Ok((
+                Bytes::from(sk.encode()),
+                encoding_prefix(alg).concat(&Bytes::from(pk.encode())),
+            )),
+            Err(_) => tlserr(CRYPTO_ERROR),
+        }
     }
 }
 
+/// Encapsulate to the key share `pk` of the peer, with the
+/// `kem_priv_len(alg)` bytes of `ent` as randomness.
+///
+/// Returns the shared secret and our key share.
 pub fn kem_encap(alg: &KemScheme, pk: &Bytes, ent: Bytes) -> Result<(Bytes, Bytes), TLSError> {
-    let pk = PublicKey::decode(to_libcrux_kem_alg(alg)?, &pk.declassify()).unwrap();
-    let res = kem::encapsulate(&pk, &mut rand::thread_rng());
-    match res {
-        Ok((gxy, gy)) => Ok((Bytes::from(gxy.encode()), Bytes::from(gy.encode()))),
-        Err(_) => tlserr(CRYPTO_ERROR),
+    if ent.len() < kem_priv_len(alg) {
+        tlserr(INSUFFICIENT_ENTROPY)
+    } else if alg == &KemScheme::X25519MlKem768 {
+        hybrid_encap(pk, ent)
+    } else {
+        let kem_alg = to_libcrux_kem_alg(alg)?;
+        let pk = check_kem_pk(alg, pk, kem_pub_len(alg))?;
+        let pk = match PublicKey::decode(kem_alg, &pk.declassify()) {
+            Ok(pk) => Ok(pk),
+            Err(_) => Err(INVALID_KEY_SHARE),
+        }?;
+        let res = kem::encapsulate(&pk, &mut entropy_rng(&ent)?);
+        match res {
+            Ok((gxy, gy)) => Ok((
+                Bytes::from(gxy.encode()),
+                encoding_prefix(alg).concat(&Bytes::from(gy.encode())),
+            )),
+            Err(_) => tlserr(CRYPTO_ERROR),
+        }
     }
 }
 
+/// Decapsulate the key share `ct` of the peer with our key `sk`.
 pub fn kem_decap(alg: &KemScheme, ct: &Bytes, sk: &Bytes) -> Result<Bytes, TLSError> {
-    let alg = to_libcrux_kem_alg(alg)?;
-    let sk = PrivateKey::decode(alg, &sk.declassify()).unwrap();
-    let ct = Ct::decode(alg, &ct.declassify()).unwrap();
-    let res = kem::decapsulate(&ct, &sk);
-    match res {
-        Ok(x) => Ok(x.encode().into()),
-        Err(_) => tlserr(CRYPTO_ERROR),
+    if alg == &KemScheme::X25519MlKem768 {
+        hybrid_decap(ct, sk)
+    } else {
+        let kem_alg = to_libcrux_kem_alg(alg)?;
+        let ct = check_kem_pk(alg, ct, kem_ct_len(alg))?;
+        let ct = match Ct::decode(kem_alg, &ct.declassify()) {
+            Ok(ct) => Ok(ct),
+            Err(_) => Err(INVALID_KEY_SHARE),
+        }?;
+        let res = match PrivateKey::decode(kem_alg, &sk.declassify()) {
+            Ok(sk) => kem::decapsulate(&ct, &sk),
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(x) => Ok(x.encode().into()),
+            Err(_) => tlserr(CRYPTO_ERROR),
+        }
     }
 }
 
+// The X25519MLKEM768 key shares are the ML-KEM-768 share followed by the
+// X25519 share, and the shared secret is the ML-KEM-768 secret followed by
+// the X25519 secret. Our private key is the X25519 key followed by the
+// ML-KEM-768 key. The entropy of each is taken in the same order as the key
+// shares.
+
+fn hybrid_keygen(ent: Bytes) -> Result<(KemSk, KemPk), TLSError> {
+    let (mlkem_ent, x25519_ent) = split_hybrid_entropy(&ent);
+    let (mlkem_sk, mlkem_pk) = kem_keygen(&KemScheme::MlKem768, mlkem_ent)?;
+    let (x25519_sk, x25519_pk) = kem_keygen(&KemScheme::X25519, x25519_ent)?;
+    Ok((x25519_sk.concat(&mlkem_sk), mlkem_pk.concat(&x25519_pk)))
+}
+
+fn hybrid_encap(pk: &Bytes, ent: Bytes) -> Result<(Bytes, Bytes), TLSError> {
+    let mlkem_len = kem_pub_len(&KemScheme::MlKem768);
+    check_kem_pk(&KemScheme::X25519MlKem768, pk, mlkem_len + 32)?;
+    let (mlkem_ent, x25519_ent) = split_hybrid_entropy(&ent);
+    let (mlkem_ss, mlkem_ct) = kem_encap(&KemScheme::MlKem768, &pk.slice(0, mlkem_len), mlkem_ent)?;
+    let (x25519_ss, x25519_ct) =
+        kem_encap(&KemScheme::X25519, &pk.slice(mlkem_len, 32), x25519_ent)?;
+    Ok((mlkem_ss.concat(&x25519_ss), mlkem_ct.concat(&x25519_ct)))
+}
+
+fn split_hybrid_entropy(ent: &Bytes) -> (Bytes, Bytes) {
+    let mlkem_len = kem_priv_len(&KemScheme::MlKem768);
+    (
+        ent.slice(0, mlkem_len),
+        ent.slice(mlkem_len, kem_priv_len(&KemScheme::X25519)),
+    )
+}
+
+fn hybrid_decap(ct: &Bytes, sk: &Bytes) -> Result<Bytes, TLSError> {
+    let mlkem_len = kem_ct_len(&KemScheme::MlKem768);
+    check_kem_pk(&KemScheme::X25519MlKem768, ct, mlkem_len + 32)?;
+    check(sk.len() > 32)?;
+    let mlkem_ss = kem_decap(
+        &KemScheme::MlKem768,
+        &ct.slice(0, mlkem_len),
+        &sk.slice_range(32..sk.len()),
+    )?;
+    let x25519_ss = kem_decap(
+        &KemScheme::X25519,
+        &ct.slice(mlkem_len, 32),
+        &sk.slice(0, 32),
+    )?;
+    Ok(mlkem_ss.concat(&x25519_ss))
+}
+
 // Algorithmns(ha, ae, sa, gn, psk_mode, zero_rtt)
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Algorithms(
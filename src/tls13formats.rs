@@ -13,6 +13,9 @@ pub const LABEL_RES_BINDER: [u8; 10] = [114, 101, 115, 032, 098, 105, 110, 100,
 pub const LABEL_EXT_BINDER: [u8; 10] = [101, 120, 116, 032, 098, 105, 110, 100, 101, 114];
 pub const LABEL_EXP_MASTER: [u8; 10] = [101, 120, 112, 032, 109, 097, 115, 116, 101, 114];
 pub const LABEL_RES_MASTER: [u8; 10] = [114, 101, 115, 032, 109, 097, 115, 116, 101, 114];
+pub const LABEL_RESUMPTION: [u8; 10] = [114, 101, 115, 117, 109, 112, 116, 105, 111, 110];
+pub const LABEL_EXPORTER: [u8; 8] = [101, 120, 112, 111, 114, 116, 101, 114];
+pub const LABEL_TRAFFIC_UPD: [u8; 11] = [116, 114, 097, 102, 102, 105, 099, 032, 117, 112, 100];
 pub const LABEL_C_E_TRAFFIC: [u8; 11] = [099, 032, 101, 032, 116, 114, 097, 102, 102, 105, 099];
 pub const LABEL_E_EXP_MASTER: [u8; 12] =
     [101, 032, 101, 120, 112, 032, 109, 097, 115, 116, 101, 114];
@@ -25,6 +28,16 @@ pub const LABEL_C_AP_TRAFFIC: [u8; 12] =
 pub const LABEL_S_AP_TRAFFIC: [u8; 12] =
     [115, 032, 097, 112, 032, 116, 114, 097, 102, 102, 105, 099];
 
+pub const PREFIX_CLIENT_SIGNATURE: [u8; 98] = [
+    0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20,
+    0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20,
+    0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20,
+    0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20,
+    0x54, 0x4c, 0x53, 0x20, 0x31, 0x2e, 0x33, 0x2c, 0x20, 0x63, 0x6c, 0x69, 0x65, 0x6e, 0x74, 0x20,
+    0x43, 0x65, 0x72, 0x74, 0x69, 0x66, 0x69, 0x63, 0x61, 0x74, 0x65, 0x56, 0x65, 0x72, 0x69, 0x66,
+    0x79, 0x00,
+];
+
 pub const PREFIX_SERVER_SIGNATURE: [u8; 98] = [
     0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20,
     0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20,
@@ -35,6 +48,13 @@ pub const PREFIX_SERVER_SIGNATURE: [u8; 98] = [
     0x79, 0x00,
 ];
 
+/// The `Random` of a ServerHello that is a HelloRetryRequest, i.e.
+/// SHA-256("HelloRetryRequest").
+pub const HELLO_RETRY_REQUEST_RANDOM: [u8; 32] = [
+    0xCF, 0x21, 0xAD, 0x74, 0xE5, 0x9A, 0x61, 0x11, 0xBE, 0x1D, 0x8C, 0x02, 0x1E, 0x65, 0xB8, 0x91,
+    0xC2, 0xA2, 0x11, 0x16, 0x7A, 0xBB, 0x8C, 0x5E, 0x07, 0x9E, 0x09, 0xE2, 0xC8, 0xA8, 0x33, 0x9C,
+];
+
 /*
 const SHA256_EMPTY: [u8; 3]2 = [u8; 3]2(([
     0xe3, 0xb0, 0xc4, 0x42, 0x98, 0xfc, 0x1c, 0x14, 0x9a, 0xfb, 0xf4, 0xc8, 0x99, 0x6f, 0xb9, 0x24,
@@ -51,24 +71,104 @@ fn ciphersuite(algs: &Algorithms) -> Result<Bytes, TLSError> {
     }
 }
 
-fn supported_group(algs: &Algorithms) -> Result<Bytes, TLSError> {
-    match kem_alg(algs) {
+fn get_ciphersuite(b: &Bytes) -> Result<(HashAlgorithm, AeadAlgorithm), TLSError> {
+    check(b.len() == 2)?;
+    match (b[0].declassify(), b[1].declassify()) {
+        (0x13, 0x01) => Ok((HashAlgorithm::SHA256, AeadAlgorithm::Aes128Gcm)),
+        (0x13, 0x02) => Ok((HashAlgorithm::SHA384, AeadAlgorithm::Aes256Gcm)),
+        (0x13, 0x03) => Ok((HashAlgorithm::SHA256, AeadAlgorithm::Chacha20Poly1305)),
+        _ => tlserr(UNSUPPORTED_ALGORITHM),
+    }
+}
+
+/// The cipher suites of all `algs`, in order and without duplicates.
+fn ciphersuites(algs: &[Algorithms]) -> Result<Bytes, TLSError> {
+    let mut css = Bytes::new();
+    for a in algs {
+        let cs = ciphersuite(a)?;
+        if check_mem(&cs, &css).is_err() {
+            css = css.concat(&cs);
+        }
+    }
+    Ok(css)
+}
+
+/// The client configuration is an ordered list of `Algorithms`. The first
+/// entry determines everything that is not negotiated from the list.
+pub fn first_algorithms(algs: &[Algorithms]) -> Result<Algorithms, TLSError> {
+    if algs.is_empty() {
+        tlserr(UNSUPPORTED_ALGORITHM)
+    } else {
+        Ok(algs[0])
+    }
+}
+
+fn named_group(ks: &KemScheme) -> Result<Bytes, TLSError> {
+    match ks {
         KemScheme::X25519 => Ok(bytes2(0x00, 0x1D)),
         KemScheme::Secp256r1 => Ok(bytes2(0x00, 0x17)),
-        KemScheme::X448 => tlserr(UNSUPPORTED_ALGORITHM),
-        KemScheme::Secp384r1 => tlserr(UNSUPPORTED_ALGORITHM),
-        KemScheme::Secp521r1 => tlserr(UNSUPPORTED_ALGORITHM),
+        KemScheme::X448 => Ok(bytes2(0x00, 0x1E)),
+        KemScheme::Secp384r1 => Ok(bytes2(0x00, 0x18)),
+        KemScheme::Secp521r1 => Ok(bytes2(0x00, 0x19)),
+        KemScheme::MlKem768 => Ok(bytes2(0x02, 0x01)),
+        KemScheme::X25519MlKem768 => Ok(bytes2(0x11, 0xEC)),
+    }
+}
+
+pub fn get_named_group(b: &Bytes) -> Result<KemScheme, TLSError> {
+    check(b.len() == 2)?;
+    match (b[0].declassify(), b[1].declassify()) {
+        (0x00, 0x1D) => Ok(KemScheme::X25519),
+        (0x00, 0x17) => Ok(KemScheme::Secp256r1),
+        (0x00, 0x1E) => Ok(KemScheme::X448),
+        (0x00, 0x18) => Ok(KemScheme::Secp384r1),
+        (0x00, 0x19) => Ok(KemScheme::Secp521r1),
+        (0x02, 0x01) => Ok(KemScheme::MlKem768),
+        (0x11, 0xEC) => Ok(KemScheme::X25519MlKem768),
+        _ => tlserr(UNSUPPORTED_ALGORITHM),
     }
 }
 
-fn signature_algorithm(algs: &Algorithms) -> Result<Bytes, TLSError> {
-    match sig_alg(algs) {
+fn supported_group(algs: &Algorithms) -> Result<Bytes, TLSError> {
+    named_group(&kem_alg(algs))
+}
+
+fn signature_algorithm(sa: &SignatureScheme) -> Result<Bytes, TLSError> {
+    match sa {
         SignatureScheme::RsaPssRsaSha256 => Ok(bytes2(0x08, 0x04)),
+        SignatureScheme::RsaPssRsaSha384 => Ok(bytes2(0x08, 0x05)),
+        SignatureScheme::RsaPssRsaSha512 => Ok(bytes2(0x08, 0x06)),
         SignatureScheme::EcdsaSecp256r1Sha256 => Ok(bytes2(0x04, 0x03)),
-        SignatureScheme::ED25519 => tlserr(UNSUPPORTED_ALGORITHM),
+        SignatureScheme::EcdsaSecp384r1Sha384 => Ok(bytes2(0x05, 0x03)),
+        SignatureScheme::ED25519 => Ok(bytes2(0x08, 0x07)),
     }
 }
 
+pub fn get_signature_scheme(b: &Bytes) -> Result<SignatureScheme, TLSError> {
+    check(b.len() == 2)?;
+    match (b[0].declassify(), b[1].declassify()) {
+        (0x08, 0x04) => Ok(SignatureScheme::RsaPssRsaSha256),
+        (0x08, 0x05) => Ok(SignatureScheme::RsaPssRsaSha384),
+        (0x08, 0x06) => Ok(SignatureScheme::RsaPssRsaSha512),
+        (0x08, 0x07) => Ok(SignatureScheme::ED25519),
+        (0x04, 0x03) => Ok(SignatureScheme::EcdsaSecp256r1Sha256),
+        (0x05, 0x03) => Ok(SignatureScheme::EcdsaSecp384r1Sha384),
+        _ => tlserr(UNSUPPORTED_ALGORITHM),
+    }
+}
+
+/// The signature schemes of the offered algorithms, in order and without
+/// duplicates.
+pub fn signature_schemes(algs: &[Algorithms]) -> Vec<SignatureScheme> {
+    let mut sas = Vec::new();
+    for a in algs {
+        if !sas.contains(&sig_alg(a)) {
+            sas.push(sig_alg(a));
+        }
+    }
+    sas
+}
+
 fn check_ciphersuites(algs: &Algorithms, b: &Bytes) -> Result<usize, TLSError> {
     let len = check_lbytes2(b)?;
     let cs = ciphersuite(algs)?;
@@ -105,8 +205,16 @@ fn check_server_supported_version(_algs: &Algorithms, b: &Bytes) -> Result<(), T
     check_eq(&bytes2(3, 4), b)
 }
 
-fn supported_groups(algs: &Algorithms) -> Result<Bytes, TLSError> {
-    Ok(bytes2(0, 0x0a).concat(&lbytes2(&lbytes2(&supported_group(algs)?)?)?))
+/// Only the groups of the configured algorithms are offered, in order.
+fn supported_groups(algs: &[Algorithms]) -> Result<Bytes, TLSError> {
+    let mut groups = Bytes::new();
+    for a in algs {
+        let g = named_group(&kem_alg(a))?;
+        if check_mem(&g, &groups).is_err() {
+            groups = groups.concat(&g);
+        }
+    }
+    Ok(bytes2(0, 0x0a).concat(&lbytes2(&lbytes2(&groups)?)?))
 }
 
 fn check_supported_groups(algs: &Algorithms, ch: &Bytes) -> Result<(), TLSError> {
@@ -114,13 +222,34 @@ fn check_supported_groups(algs: &Algorithms, ch: &Bytes) -> Result<(), TLSError>
     check_mem(&supported_group(algs)?, &ch.slice_range(2..ch.len()))
 }
 
-fn signature_algorithms(algs: &Algorithms) -> Result<Bytes, TLSError> {
-    Ok(bytes2(0, 0x0d).concat(&lbytes2(&lbytes2(&signature_algorithm(algs)?)?)?))
+fn signature_algorithms(schemes: &[SignatureScheme]) -> Result<Bytes, TLSError> {
+    let mut sas = Bytes::new();
+    for sa in schemes {
+        sas = sas.concat(&signature_algorithm(sa)?);
+    }
+    Ok(bytes2(0, 0x0d).concat(&lbytes2(&lbytes2(&sas)?)?))
+}
+
+/// The schemes in a signature_algorithms list that we know, in order.
+fn get_signature_schemes(b: &Bytes) -> Result<Vec<SignatureScheme>, TLSError> {
+    check_lbytes2_full(b)?;
+    let n = (b.len() - 2) / 2;
+    check(b.len() == 2 + 2 * n)?;
+    let mut sas = Vec::new();
+    for i in 0..n {
+        if let Ok(sa) = get_signature_scheme(&b.slice_range(2 + 2 * i..4 + 2 * i)) {
+            sas.push(sa);
+        }
+    }
+    Ok(sas)
 }
 
 fn check_signature_algorithms(algs: &Algorithms, ch: &Bytes) -> Result<(), TLSError> {
     check_lbytes2_full(ch)?;
-    check_mem(&signature_algorithm(algs)?, &ch.slice_range(2..ch.len()))
+    check_mem(
+        &signature_algorithm(&sig_alg(algs))?,
+        &ch.slice_range(2..ch.len()),
+    )
 }
 
 pub fn psk_key_exchange_modes(_algs: &Algorithms) -> Result<Bytes, TLSError> {
@@ -132,24 +261,30 @@ pub fn check_psk_key_exchange_modes(_algs: &Algorithms, ch: &Bytes) -> Result<()
     check_eq(&bytes1(1), &ch.slice_range(1..2))
 }
 
-pub fn key_shares(algs: &Algorithms, gx: &KemPk) -> Result<Bytes, TLSError> {
-    let ks = supported_group(algs)?.concat(&lbytes2(gx)?);
-    Ok(bytes2(0, 0x33).concat(&lbytes2(&lbytes2(&ks)?)?))
+pub fn key_shares(gxs: &[(KemScheme, KemPk)]) -> Result<Bytes, TLSError> {
+    let mut kss = Bytes::new();
+    for (ks, gx) in gxs {
+        kss = kss.concat(&named_group(ks)?.concat(&lbytes2(gx)?));
+    }
+    Ok(bytes2(0, 0x33).concat(&lbytes2(&lbytes2(&kss)?)?))
 }
 
-pub fn find_key_share(g: &Bytes, ch: &Bytes) -> Result<Bytes, TLSError> {
-    if ch.len() < 4 {
+/// Find the key share for group `g`, or `None` if the client did not send one.
+pub fn find_key_share(g: &Bytes, ch: &Bytes) -> Result<Option<Bytes>, TLSError> {
+    if ch.is_empty() {
+        Ok(None)
+    } else if ch.len() < 4 {
         tlserr(parse_failed())
     } else if eq(g, &ch.slice_range(0..2)) {
         let len = check_lbytes2(&ch.slice_range(2..ch.len()))?;
-        Ok(ch.slice_range(4..4 + len))
+        Ok(Some(ch.slice_range(4..4 + len)))
     } else {
         let len = check_lbytes2(&ch.slice_range(2..ch.len()))?;
         find_key_share(g, &ch.slice_range(4 + len..ch.len()))
     }
 }
 
-pub fn check_key_shares(algs: &Algorithms, ch: &Bytes) -> Result<Bytes, TLSError> {
+pub fn check_key_shares(algs: &Algorithms, ch: &Bytes) -> Result<Option<Bytes>, TLSError> {
     check_lbytes2_full(ch)?;
     find_key_share(&supported_group(algs)?, &ch.slice_range(2..ch.len()))
 }
@@ -165,6 +300,10 @@ pub fn check_server_key_share(algs: &Algorithms, b: &Bytes) -> Result<Bytes, TLS
     Ok(b.slice_range(4..b.len()))
 }
 
+fn cookie(c: &Bytes) -> Result<Bytes, TLSError> {
+    Ok(bytes2(0, 0x2c).concat(&lbytes2(&lbytes2(c)?)?))
+}
+
 pub fn pre_shared_key(algs: &Algorithms, tkt: &Bytes) -> Result<(Bytes, usize), TLSError> {
     let identities = lbytes2(&lbytes2(tkt)?.concat(&U32::from(0xffffffff).to_be_bytes()))?;
     let binders = lbytes2(&lbytes1(&zero_key(&hash_alg(algs)))?)?;
@@ -172,16 +311,20 @@ pub fn pre_shared_key(algs: &Algorithms, tkt: &Bytes) -> Result<(Bytes, usize),
     Ok((ext, binders.len()))
 }
 
-pub fn check_psk_shared_key(_algs: &Algorithms, ch: &Bytes) -> Result<(), TLSError> {
+/// Check the client's pre_shared_key extension, which holds exactly one
+/// identity, and return its ticket and binder.
+pub fn check_psk_shared_key(algs: &Algorithms, ch: &Bytes) -> Result<(Bytes, Bytes), TLSError> {
     let len_id = check_lbytes2(ch)?;
     let len_tkt = check_lbytes2(&ch.slice_range(2..2 + len_id))?;
     if len_id == len_tkt + 6 {
         check_lbytes2_full(&ch.slice_range(2 + len_id..ch.len()))?;
         check_lbytes1_full(&ch.slice_range(4 + len_id..ch.len()))?;
-        if ch.len() - 6 - len_id != 32 {
+        if ch.len() - 5 - len_id != hash_len(&hash_alg(algs)) {
             tlserr(parse_failed())
         } else {
-            Ok(())
+            let tkt = ch.slice_range(4..4 + len_tkt);
+            let binder = ch.slice_range(5 + len_id..ch.len());
+            Ok((tkt, binder))
         }
     } else {
         tlserr(parse_failed())
@@ -196,11 +339,48 @@ pub fn check_server_psk_shared_key(_algs: &Algorithms, b: &Bytes) -> Result<(),
     check_eq(&bytes2(0, 0), b)
 }
 
+/// The empty early_data extension of ClientHello and EncryptedExtensions.
+pub fn early_data() -> Result<Bytes, TLSError> {
+    Ok(bytes2(0, 0x2a).concat(&lbytes2(&Bytes::new())?))
+}
+
+/// The early_data extension of NewSessionTicket.
+pub fn max_early_data_size(size: U32) -> Result<Bytes, TLSError> {
+    Ok(bytes2(0, 0x2a).concat(&lbytes2(&size.to_be_bytes())?))
+}
+
+/// The application_layer_protocol_negotiation extension with the protocol
+/// names in `protocols`. The server sends it with the single selected name.
+pub fn alpn(protocols: &[Bytes]) -> Result<Bytes, TLSError> {
+    let mut names = Bytes::new();
+    for p in protocols {
+        check(!p.is_empty())?;
+        names = names.concat(&lbytes1(p)?);
+    }
+    Ok(bytes2(0, 0x10).concat(&lbytes2(&lbytes2(&names)?)?))
+}
+
+fn get_alpn_protocols(b: &Bytes) -> Result<Vec<Bytes>, TLSError> {
+    check_lbytes2_full(b)?;
+    let mut protocols = Vec::new();
+    let mut next = 2;
+    while next < b.len() {
+        let len = check_lbytes1(&b.slice_range(next..b.len()))?;
+        check(len > 0)?;
+        protocols.push(b.slice_range(next + 1..next + 1 + len));
+        next = next + 1 + len;
+    }
+    check(!protocols.is_empty())?;
+    Ok(protocols)
+}
+
 pub struct EXTS(
-    pub Option<Bytes>, //SNI
-    pub Option<Bytes>, //KeyShare
-    pub Option<Bytes>, //Ticket
-    pub Option<Bytes>, //Binder
+    pub Option<Bytes>,      //SNI
+    pub Option<Bytes>,      //KeyShare
+    pub Option<Bytes>,      //Ticket
+    pub Option<Bytes>,      //Binder
+    pub Option<Bytes>,      //EarlyData
+    pub Option<Vec<Bytes>>, //ALPN
 );
 
 pub fn merge_opts<T>(o1: Option<T>, o2: Option<T>) -> Result<Option<T>, TLSError> {
@@ -212,13 +392,15 @@ pub fn merge_opts<T>(o1: Option<T>, o2: Option<T>) -> Result<Option<T>, TLSError
     }
 }
 pub fn merge_exts(e1: EXTS, e2: EXTS) -> Result<EXTS, TLSError> {
-    let EXTS(sn1, ks1, tkt1, bd1) = e1;
-    let EXTS(sn2, ks2, tkt2, bd2) = e2;
+    let EXTS(sn1, ks1, tkt1, bd1, ed1, alpn1) = e1;
+    let EXTS(sn2, ks2, tkt2, bd2, ed2, alpn2) = e2;
     Ok(EXTS(
         merge_opts(sn1, sn2)?,
         merge_opts(ks1, ks2)?,
         merge_opts(tkt1, tkt2)?,
         merge_opts(bd1, bd2)?,
+        merge_opts(ed1, ed2)?,
+        merge_opts(alpn1, alpn2)?,
     ))
 }
 
@@ -226,11 +408,32 @@ fn missing_key_share() -> Result<(usize, EXTS), TLSError> {
     Result::<(usize, EXTS), TLSError>::Err(MISSING_KEY_SHARE)
 }
 
+/// The name of the extension at the start of `b`, for errors.
+fn extension_name(b: &Bytes) -> &'static str {
+    if b.len() < 2 {
+        "extensions"
+    } else {
+        match (b[0].declassify(), b[1].declassify()) {
+            (0, 0) => "server_name extension",
+            (0, 0x0a) => "supported_groups extension",
+            (0, 0x0d) => "signature_algorithms extension",
+            (0, 0x10) => "application_layer_protocol_negotiation extension",
+            (0, 0x29) => "pre_shared_key extension",
+            (0, 0x2a) => "early_data extension",
+            (0, 0x2b) => "supported_versions extension",
+            (0, 0x2c) => "cookie extension",
+            (0, 0x2d) => "psk_key_exchange_modes extension",
+            (0, 0x33) => "key_share extension",
+            _ => "extensions",
+        }
+    }
+}
+
 fn check_extension(algs: &Algorithms, b: &Bytes) -> Result<(usize, EXTS), TLSError> {
     let l0 = b[0].declassify() as usize;
     let l1 = b[1].declassify() as usize;
     let len = check_lbytes2(&b.slice_range(2..b.len()))?;
-    let out = EXTS(None, None, None, None);
+    let out = EXTS(None, None, None, None, None, None);
     match (l0 as u8, l1 as u8) {
         (0, 0) => Ok((
             4 + len,
@@ -239,6 +442,8 @@ fn check_extension(algs: &Algorithms, b: &Bytes) -> Result<(usize, EXTS), TLSErr
                 None,
                 None,
                 None,
+                None,
+                None,
             ),
         )),
         (0, 0x2d) => {
@@ -257,13 +462,27 @@ fn check_extension(algs: &Algorithms, b: &Bytes) -> Result<(usize, EXTS), TLSErr
             check_signature_algorithms(algs, &b.slice_range(4..4 + len))?;
             Ok((4 + len, out))
         }
-        (0, 0x33) => match check_key_shares(algs, &b.slice_range(4..4 + len)) {
-            Ok(gx) => Ok((4 + len, EXTS(None, Some(gx), None, None))),
-            Err(_) => tlserr(MISSING_KEY_SHARE),
-        },
+        (0, 0x33) => {
+            let gx = check_key_shares(algs, &b.slice_range(4..4 + len))?;
+            Ok((4 + len, EXTS(None, gx, None, None, None, None)))
+        }
         (0, 41) => {
-            check_psk_shared_key(algs, &b.slice_range(4..4 + len))?;
-            Ok((4 + len, out))
+            let (tkt, binder) = check_psk_shared_key(algs, &b.slice_range(4..4 + len))?;
+            Ok((
+                4 + len,
+                EXTS(None, None, Some(tkt), Some(binder), None, None),
+            ))
+        }
+        (0, 0x2a) => {
+            check(len == 0)?;
+            Ok((
+                4 + len,
+                EXTS(None, None, None, None, Some(Bytes::new()), None),
+            ))
+        }
+        (0, 0x10) => {
+            let protocols = get_alpn_protocols(&b.slice_range(4..4 + len))?;
+            Ok((4 + len, EXTS(None, None, None, None, None, Some(protocols))))
         }
         _ => Ok((4 + len, out)),
     }
@@ -289,8 +508,48 @@ pub fn check_server_extension(
     Ok((4 + len, out))
 }
 
+fn check_hello_retry_extension(
+    algs: &Algorithms,
+    b: &Bytes,
+) -> Result<(usize, Option<KemScheme>, Option<Bytes>), TLSError> {
+    check(b.len() >= 4)?;
+    let l0 = b[0].declassify() as usize;
+    let l1 = b[1].declassify() as usize;
+    let len = check_lbytes2(&b.slice_range(2..b.len()))?;
+    let ext = b.slice_range(4..4 + len);
+    match (l0 as u8, l1 as u8) {
+        (0, 0x2b) => {
+            check_server_supported_version(algs, &ext)?;
+            Ok((4 + len, None, None))
+        }
+        (0, 0x33) => Ok((4 + len, Some(get_named_group(&ext)?), None)),
+        (0, 0x2c) => {
+            check_lbytes2_full(&ext)?;
+            Ok((4 + len, None, Some(ext.slice_range(2..ext.len()))))
+        }
+        _ => Ok((4 + len, None, None)),
+    }
+}
+
+fn check_hello_retry_extensions(
+    algs: &Algorithms,
+    b: &Bytes,
+) -> Result<(Option<KemScheme>, Option<Bytes>), TLSError> {
+    let (len, group, cookie) = in_context(check_hello_retry_extension(algs, b), extension_name(b))?;
+    if len == b.len() {
+        Ok((group, cookie))
+    } else {
+        let (group_rest, cookie_rest) =
+            check_hello_retry_extensions(algs, &b.slice_range(len..b.len()))?;
+        Ok((
+            merge_opts(group, group_rest)?,
+            merge_opts(cookie, cookie_rest)?,
+        ))
+    }
+}
+
 fn check_extensions(algs: &Algorithms, b: &Bytes) -> Result<EXTS, TLSError> {
-    let (len, out) = check_extension(algs, b)?;
+    let (len, out) = in_context(check_extension(algs, b), extension_name(b))?;
     //println!("checked 1 extension");
     if len == b.len() {
         Ok(out)
@@ -301,7 +560,7 @@ fn check_extensions(algs: &Algorithms, b: &Bytes) -> Result<EXTS, TLSError> {
 }
 
 pub fn check_server_extensions(algs: &Algorithms, b: &Bytes) -> Result<Option<Bytes>, TLSError> {
-    let (len, out) = check_server_extension(algs, b)?;
+    let (len, out) = in_context(check_server_extension(algs, b), extension_name(b))?;
     if len == b.len() {
         Ok(out)
     } else {
@@ -440,7 +699,7 @@ pub fn get_alert_level(t: u8) -> Result<AlertLevel, TLSError> {
 ///     no_application_protocol(120),
 ///     (255)
 /// } AlertDescription;
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AlertDescription {
     CloseNotify,
     UnexpectedMessage,
@@ -536,6 +795,91 @@ pub fn get_alert_description(t: u8) -> Result<AlertDescription, TLSError> {
     }
 }
 
+/// The level to send an alert with: close_notify and user_canceled are
+/// warnings, all other alerts are fatal.
+pub fn alert_level_of(ad: AlertDescription) -> AlertLevel {
+    match ad {
+        AlertDescription::CloseNotify | AlertDescription::UserCanceled => AlertLevel::Warning,
+        _ => AlertLevel::Fatal,
+    }
+}
+
+/// The alert to send when a connection fails with `e`.
+pub fn error_alert(e: TLSError) -> AlertDescription {
+    match e.kind() {
+        ErrorKind::UnsupportedAlgorithm
+        | ErrorKind::NegotiationMismatch
+        | ErrorKind::PskModeMismatch
+        | ErrorKind::MissingKeyShare => AlertDescription::HandshakeFailure,
+        ErrorKind::CryptoError | ErrorKind::InvalidSignature => AlertDescription::DecryptError,
+        ErrorKind::BadRecordMac => AlertDescription::BadRecordMac,
+        ErrorKind::PayloadTooLong | ErrorKind::RecordOverflow => AlertDescription::RecordOverflow,
+        ErrorKind::ParseFailed | ErrorKind::InsufficientData | ErrorKind::IncorrectArrayLength => {
+            AlertDescription::DecodeError
+        }
+        ErrorKind::IncorrectState
+        | ErrorKind::ZeroRttDisabled
+        | ErrorKind::ApplicationDataInsteadOfHandshake
+        | ErrorKind::MissingContentType
+        | ErrorKind::UnexpectedMessage => AlertDescription::UnexpectedMessage,
+        ErrorKind::InvalidCompressionList
+        | ErrorKind::InvalidKeyShare
+        | ErrorKind::IllegalParameter => AlertDescription::IllegalParameter,
+        ErrorKind::ProtocolVersion => AlertDescription::ProtocolVersion,
+        ErrorKind::MissingClientCertificate => AlertDescription::CertificateRequired,
+        ErrorKind::NoApplicationProtocol => AlertDescription::NoApplicationProtocol,
+        ErrorKind::BadCertificate
+        | ErrorKind::Asn1SequenceTooLong
+        | ErrorKind::Asn1InvalidTag
+        | ErrorKind::Asn1InvalidCertificate
+        | ErrorKind::Asn1Error => AlertDescription::BadCertificate,
+        ErrorKind::Asn1UnsupportedAlgorithm => AlertDescription::UnsupportedCertificate,
+        ErrorKind::UnknownCa => AlertDescription::UnknownCa,
+        ErrorKind::CertificateExpired => AlertDescription::CertificateExpired,
+        // The peer already aborted the connection, we only close our side.
+        ErrorKind::GotAlert(_) => AlertDescription::CloseNotify,
+        ErrorKind::InsufficientEntropy | ErrorKind::Unsupported => AlertDescription::InternalError,
+    }
+}
+
+impl TLSError {
+    /// The alert to send to the peer for this error.
+    pub fn alert(&self) -> AlertDescription {
+        error_alert(*self)
+    }
+}
+
+pub fn alert(ad: AlertDescription) -> Bytes {
+    bytes2(alert_level(alert_level_of(ad)), alert_description(ad))
+}
+
+pub fn parse_alert(b: &Bytes) -> Result<(AlertLevel, AlertDescription), TLSError> {
+    if b.len() != 2 {
+        tlserr(parse_failed())
+    } else {
+        Ok((
+            get_alert_level(b[0].declassify())?,
+            get_alert_description(b[1].declassify())?,
+        ))
+    }
+}
+
+/// An unencrypted alert record, for alerts before the handshake keys are set.
+pub fn alert_record(ad: AlertDescription) -> Result<Bytes, TLSError> {
+    let ty = bytes1(content_type(ContentType::Alert));
+    let ver = bytes2(3, 3);
+    Ok(ty.concat(&ver).concat(&lbytes2(&alert(ad))?))
+}
+
+/// Parse an unencrypted alert record.
+pub fn parse_alert_record(p: &Bytes) -> Result<(AlertLevel, AlertDescription), TLSError> {
+    if p.len() != 7 || p[0].declassify() != content_type(ContentType::Alert) {
+        tlserr(parse_failed())
+    } else {
+        parse_alert(&p.slice_range(5..7))
+    }
+}
+
 // Tagged Handshake Data
 
 pub fn handshake_message(ty: HandshakeType, by: &Bytes) -> Result<HandshakeData, TLSError> {
@@ -556,6 +900,13 @@ pub fn get_first_handshake_message(
     }
 }
 
+/// Whether `p` starts with a complete handshake message, or only holds a
+/// part of one that continues in the next record.
+pub fn has_handshake_message(p: &HandshakeData) -> bool {
+    let HandshakeData(p) = p;
+    p.len() >= 4 && check_lbytes3(&p.slice_range(1..p.len())).is_ok()
+}
+
 pub fn get_handshake_message(p: &HandshakeData) -> Result<HandshakeData, TLSError> {
     let (m1, p) = get_first_handshake_message(p)?;
     if handshake_data_len(&p) != 0 {
@@ -600,6 +951,23 @@ pub fn get_handshake_messages4(
     }
 }
 
+pub fn get_handshake_messages5(
+    p: &HandshakeData,
+) -> Result<
+    (
+        HandshakeData,
+        HandshakeData,
+        HandshakeData,
+        HandshakeData,
+        HandshakeData,
+    ),
+    TLSError,
+> {
+    let (m1, p) = get_first_handshake_message(p)?;
+    let (m2, m3, m4, m5) = get_handshake_messages4(&p)?;
+    Ok((m1, m2, m3, m4, m5))
+}
+
 pub fn find_handshake_message(ty: HandshakeType, payload: &HandshakeData, start: usize) -> bool {
     let HandshakeData(p) = payload;
     if p.len() < start + 4 {
@@ -618,30 +986,50 @@ pub fn find_handshake_message(ty: HandshakeType, payload: &HandshakeData, start:
     }
 }
 
+/// The ClientHello offers the cipher suites of all `offered` algorithms, in
+/// order, the key shares in `gxs`, and the application protocols in
+/// `protocols`, if any. It names the server `sn` unless that is an IP
+/// address, which RFC 6066 does not allow as a server name.
 pub fn client_hello(
-    algs: &Algorithms,
+    offered: &[Algorithms],
     cr: &Random,
-    gx: &KemPk,
+    gxs: &[(KemScheme, KemPk)],
     sn: &Bytes,
     tkt: &Option<Bytes>,
+    ck: &Option<Bytes>,
+    protocols: &[Bytes],
 ) -> Result<(HandshakeData, usize), TLSError> {
+    let algs = &first_algorithms(offered)?;
     let ver = bytes2(3, 3);
     let sid = lbytes1(&Bytes::zeroes(32))?;
-    let cip = lbytes2(&ciphersuite(algs)?)?;
+    let cip = lbytes2(&ciphersuites(offered)?)?;
     let comp = bytes2(1, 0);
-    let sn = server_name(sn)?;
+    let sn = match ip_address(sn) {
+        Some(_) => Bytes::new(),
+        None => server_name(sn)?,
+    };
     let sv = supported_versions(algs)?;
-    let sg = supported_groups(algs)?;
-    let sa = signature_algorithms(algs)?;
-    let ks = key_shares(algs, gx)?;
+    let sg = supported_groups(offered)?;
+    let sa = signature_algorithms(&signature_schemes(offered))?;
+    let ks = key_shares(gxs)?;
     let mut exts = sn.concat(&sv).concat(&sg).concat(&sa).concat(&ks);
-    let mut trunc_len = 0;
+    if let Some(ck) = ck {
+        exts = exts.concat(&cookie(ck)?);
+    }
+    if !protocols.is_empty() {
+        exts = exts.concat(&alpn(protocols)?);
+    }
+    let mut binders_len = 0;
     match (psk_mode(algs), tkt) {
         (true, Some(tkt)) => {
             let pskm = psk_key_exchange_modes(algs)?;
+            if zero_rtt(algs) {
+                exts = exts.concat(&early_data()?);
+            }
+            // The pre_shared_key extension has to come last.
             let (psk, len) = pre_shared_key(algs, tkt)?;
             exts = exts.concat(&pskm).concat(&psk);
-            trunc_len = len;
+            binders_len = len;
         }
         (false, None) => {}
         _ => tlserr(PSK_MODE_MISMATCH)?,
@@ -655,6 +1043,12 @@ pub fn client_hello(
             .concat(&comp)
             .concat(&lbytes2(&exts)?),
     )?;
+    // The truncated ClientHello that the binder covers ends before the binders list.
+    let trunc_len = if binders_len > 0 {
+        handshake_data_len(&ch) - binders_len
+    } else {
+        0
+    };
     Ok((ch, trunc_len))
 }
 
@@ -669,8 +1063,9 @@ pub fn set_client_hello_binder(
     let hlen = hash_len(&hash_alg(algs));
     match (binder, trunc_len) {
         (Some(m), Some(trunc_len)) => {
-            if chlen - hlen == trunc_len {
-                Ok(HandshakeData(ch.update_slice(trunc_len, m, 0, hlen)))
+            // Skip the binders list length and the binder length.
+            if chlen - hlen - 3 == trunc_len {
+                Ok(HandshakeData(ch.update_slice(trunc_len + 3, m, 0, hlen)))
             } else {
                 tlserr(parse_failed())
             }
@@ -693,10 +1088,12 @@ pub fn parse_client_hello(
         Random,
         Bytes,
         Bytes,
-        Bytes,
+        Option<Bytes>,
         Option<Bytes>,
         Option<Bytes>,
         usize,
+        bool,
+        Option<Vec<Bytes>>,
     ),
     TLSError,
 > {
@@ -722,13 +1119,27 @@ pub fn parse_client_hello(
     next = next + 2;
     let exts = check_extensions(algs, &ch.slice_range(next..ch.len()))?;
     //println!("check_extensions");
-    let trunc_len = ch.len() - hash_len(&hash_alg(algs)) - 3;
+    // The truncated ClientHello includes the 4 byte message header and ends
+    // before the binders list.
+    let trunc_len = ch.len() + 4 - hash_len(&hash_alg(algs)) - 3;
+    // A missing key share for our group is not an error here: the caller
+    // answers with a HelloRetryRequest.
+    // The early data flag tells whether the client offers early data, which
+    // it can only do with a PSK. The last element lists the application
+    // protocols offered by the client.
     match (psk_mode(algs), exts) {
-        (_, EXTS(_, None, _, _)) => tlserr(MISSING_KEY_SHARE),
-        (true, EXTS(Some(sn), Some(gx), Some(tkt), Some(binder))) => {
-            Ok((crand, sid, sn, gx, Some(tkt), Some(binder), trunc_len))
-        }
-        (true, EXTS(None, Some(gx), Some(tkt), Some(binder))) => Ok((
+        (true, EXTS(Some(sn), gx, Some(tkt), Some(binder), ed, alpn)) => Ok((
+            crand,
+            sid,
+            sn,
+            gx,
+            Some(tkt),
+            Some(binder),
+            trunc_len,
+            ed.is_some(),
+            alpn,
+        )),
+        (true, EXTS(None, gx, Some(tkt), Some(binder), ed, alpn)) => Ok((
             crand,
             sid,
             Bytes::new(),
@@ -736,15 +1147,75 @@ pub fn parse_client_hello(
             Some(tkt),
             Some(binder),
             trunc_len,
+            ed.is_some(),
+            alpn,
         )),
-        (false, EXTS(Some(sn), Some(gx), None, None)) => Ok((crand, sid, sn, gx, None, None, 0)),
-        (false, EXTS(None, Some(gx), None, None)) => {
-            Ok((crand, sid, Bytes::new(), gx, None, None, 0))
+        (false, EXTS(Some(sn), gx, None, None, _, alpn)) => {
+            Ok((crand, sid, sn, gx, None, None, 0, false, alpn))
+        }
+        (false, EXTS(None, gx, None, None, _, alpn)) => {
+            Ok((crand, sid, Bytes::new(), gx, None, None, 0, false, alpn))
         }
         _ => tlserr(parse_failed()),
     }
 }
 
+/// Check that a ClientHello sent in answer to a HelloRetryRequest follows it:
+/// it offers the cipher suite of `algs`, its key_share extension holds a
+/// single key share, for the requested group, and it echoes the cookie `ck`.
+pub fn check_retried_client_hello(
+    algs: &Algorithms,
+    ch: &HandshakeData,
+    ck: &Bytes,
+) -> Result<(), TLSError> {
+    let HandshakeData(ch) = get_handshake_message_ty(HandshakeType::ClientHello, ch)?;
+    let mut next = 34;
+    let sidlen = check_lbytes1(&ch.slice_range(next..ch.len()))?;
+    next = next + 1 + sidlen;
+    let cslen = check_lbytes2(&ch.slice_range(next..ch.len()))?;
+    if check_mem(
+        &ciphersuite(algs)?,
+        &ch.slice_range(next + 2..next + 2 + cslen),
+    )
+    .is_err()
+    {
+        tlserr(ILLEGAL_PARAMETER)?
+    }
+    next = next + 2 + cslen;
+    let complen = check_lbytes1(&ch.slice_range(next..ch.len()))?;
+    next = next + 1 + complen;
+    check_lbytes2_full(&ch.slice_range(next..ch.len()))?;
+    next = next + 2;
+    let mut shares = None;
+    let mut cookie = None;
+    while next < ch.len() {
+        check(ch.len() >= next + 4)?;
+        let len = check_lbytes2(&ch.slice_range(next + 2..ch.len()))?;
+        let ext = ch.slice_range(next + 4..next + 4 + len);
+        match (ch[next].declassify(), ch[next + 1].declassify()) {
+            (0, 0x33) => shares = Some(ext),
+            (0, 0x2c) => cookie = Some(ext),
+            _ => (),
+        }
+        next = next + 4 + len;
+    }
+    match (shares, cookie) {
+        (Some(shares), Some(cookie)) => {
+            check(shares.len() >= 6)?;
+            let len = check_lbytes2(&shares.slice_range(4..shares.len()))?;
+            if shares.len() == 6 + len
+                && eq(&supported_group(algs)?, &shares.slice_range(2..4))
+                && eq(&lbytes2(ck)?, &cookie)
+            {
+                Ok(())
+            } else {
+                tlserr(ILLEGAL_PARAMETER)
+            }
+        }
+        _ => tlserr(ILLEGAL_PARAMETER),
+    }
+}
+
 pub fn server_hello(
     algs: &Algorithms,
     sr: &Random,
@@ -773,10 +1244,96 @@ pub fn server_hello(
     Ok(sh)
 }
 
+/// A HelloRetryRequest is a ServerHello with a fixed random that carries the
+/// group the server wants a key share for and, optionally, a cookie.
+pub fn hello_retry_request(
+    algs: &Algorithms,
+    sid: &Bytes,
+    ck: &Option<Bytes>,
+) -> Result<HandshakeData, TLSError> {
+    let ver = bytes2(3, 3);
+    let sr = Bytes::from(HELLO_RETRY_REQUEST_RANDOM);
+    let sid = lbytes1(sid)?;
+    let cip = ciphersuite(algs)?;
+    let comp = bytes1(0);
+    let ks = bytes2(0, 0x33).concat(&lbytes2(&supported_group(algs)?)?);
+    let sv = server_supported_version(algs)?;
+    let mut exts = ks.concat(&sv);
+    if let Some(ck) = ck {
+        exts = exts.concat(&cookie(ck)?);
+    }
+    handshake_message(
+        HandshakeType::ServerHello,
+        &ver.concat(&sr)
+            .concat(&sid)
+            .concat(&cip)
+            .concat(&comp)
+            .concat(&lbytes2(&exts)?),
+    )
+}
+
+pub fn is_hello_retry_request(sh: &HandshakeData) -> bool {
+    let HandshakeData(sh) = sh;
+    sh.len() >= 38
+        && eq(
+            &Bytes::from(HELLO_RETRY_REQUEST_RANDOM),
+            &sh.slice_range(6..38),
+        )
+}
+
 fn unsupported_cipher_alert() -> Result<(), TLSError> {
     tlserr(UNSUPPORTED_ALGORITHM)
 }
 
+/// Parse a HelloRetryRequest, returning the requested group and cookie.
+pub fn parse_hello_retry_request(
+    algs: &Algorithms,
+    hrr: &HandshakeData,
+) -> Result<(Option<KemScheme>, Option<Bytes>), TLSError> {
+    let HandshakeData(hrr) = get_handshake_message_ty(HandshakeType::ServerHello, hrr)?;
+    let ver = bytes2(3, 3);
+    let cip = ciphersuite(algs)?;
+    let comp = bytes1(0);
+    check(hrr.len() >= 35)?;
+    let mut next = 0;
+    match check_eq(&ver, &hrr.slice_range(next..next + 2)) {
+        Ok(_) => (),
+        Err(_) => protocol_version_alert()?,
+    };
+    next = next + 2;
+    check_eq(
+        &Bytes::from(HELLO_RETRY_REQUEST_RANDOM),
+        &hrr.slice_range(next..next + 32),
+    )?;
+    next = next + 32;
+    let sidlen = check_lbytes1(&hrr.slice_range(next..hrr.len()))?;
+    next = next + 1 + sidlen;
+    check(hrr.len() >= next + 5)?;
+    match check_eq(&cip, &hrr.slice_range(next..next + 2)) {
+        Ok(_) => (),
+        Err(_) => unsupported_cipher_alert()?,
+    };
+    next = next + 2;
+    check_eq(&comp, &hrr.slice_range(next..next + 1))?;
+    next = next + 1;
+    check_lbytes2_full(&hrr.slice_range(next..hrr.len()))?;
+    next = next + 2;
+    check_hello_retry_extensions(algs, &hrr.slice_range(next..hrr.len()))
+}
+
+/// The cipher suite picked by a ServerHello or HelloRetryRequest.
+pub fn server_hello_ciphersuite(
+    sh: &HandshakeData,
+) -> Result<(HashAlgorithm, AeadAlgorithm), TLSError> {
+    let HandshakeData(sh) = get_handshake_message_ty(HandshakeType::ServerHello, sh)?;
+    check(sh.len() > 34)?;
+    let next = 34;
+    let sidlen = check_lbytes1(&sh.slice_range(next..sh.len()))?;
+    let next = next + 1 + sidlen;
+    check(sh.len() >= next + 2)?;
+    get_ciphersuite(&sh.slice_range(next..next + 2))
+}
+
 pub fn parse_server_hello(
     algs: &Algorithms,
     sh: &HandshakeData,
@@ -812,118 +1369,305 @@ pub fn parse_server_hello(
     }
 }
 
-pub fn encrypted_extensions(_algs: &Algorithms) -> Result<HandshakeData, TLSError> {
+/// The server's EncryptedExtensions. In `algs`, the zero-RTT flag tells
+/// whether the server accepts early data; `protocol` is the selected
+/// application protocol, if any.
+pub fn encrypted_extensions(
+    algs: &Algorithms,
+    protocol: &Option<Bytes>,
+) -> Result<HandshakeData, TLSError> {
     let ty = bytes1(hs_type(HandshakeType::EncryptedExtensions));
-    Ok(HandshakeData(
-        ty.concat(&lbytes3(&lbytes2(&Bytes::new())?)?),
-    ))
+    let mut exts = Bytes::new();
+    if zero_rtt(algs) {
+        exts = exts.concat(&early_data()?);
+    }
+    if let Some(protocol) = protocol {
+        exts = exts.concat(&alpn(std::slice::from_ref(protocol))?);
+    }
+    Ok(HandshakeData(ty.concat(&lbytes3(&lbytes2(&exts)?)?)))
 }
 
-pub fn parse_encrypted_extensions(_algs: &Algorithms, ee: &HandshakeData) -> Result<(), TLSError> {
-    let HandshakeData(ee) = ee;
-    let ty = bytes1(hs_type(HandshakeType::EncryptedExtensions));
-    check_eq(&ty, &ee.slice_range(0..1))?;
-    check_lbytes3_full(&ee.slice_range(1..ee.len()))
+fn check_encrypted_extension(
+    _algs: &Algorithms,
+    b: &Bytes,
+) -> Result<(usize, Option<Bytes>, Option<Bytes>), TLSError> {
+    check(b.len() >= 4)?;
+    let l0 = b[0].declassify() as usize;
+    let l1 = b[1].declassify() as usize;
+    let len = check_lbytes2(&b.slice_range(2..b.len()))?;
+    match (l0 as u8, l1 as u8) {
+        (0, 0x2a) => {
+            check(len == 0)?;
+            Ok((4 + len, Some(Bytes::new()), None))
+        }
+        (0, 0x10) => {
+            let mut protocols = get_alpn_protocols(&b.slice_range(4..4 + len))?;
+            // The server selects exactly one protocol.
+            check(protocols.len() == 1)?;
+            Ok((4 + len, None, protocols.pop()))
+        }
+        _ => Ok((4 + len, None, None)),
+    }
 }
 
-pub fn server_certificate(_algs: &Algorithms, cert: &Bytes) -> Result<HandshakeData, TLSError> {
+fn check_encrypted_extensions(
+    algs: &Algorithms,
+    b: &Bytes,
+) -> Result<(Option<Bytes>, Option<Bytes>), TLSError> {
+    if b.is_empty() {
+        Ok((None, None))
+    } else {
+        let (len, ed, protocol) = check_encrypted_extension(algs, b)?;
+        let (ed_rest, protocol_rest) =
+            check_encrypted_extensions(algs, &b.slice_range(len..b.len()))?;
+        Ok((
+            merge_opts(ed, ed_rest)?,
+            merge_opts(protocol, protocol_rest)?,
+        ))
+    }
+}
+
+/// Parse the server's EncryptedExtensions and return whether the server
+/// accepted our early data, and the application protocol it selected.
+pub fn parse_encrypted_extensions(
+    algs: &Algorithms,
+    ee: &HandshakeData,
+) -> Result<(bool, Option<Bytes>), TLSError> {
+    let HandshakeData(exts) = get_handshake_message_ty(HandshakeType::EncryptedExtensions, ee)?;
+    check_lbytes2_full(&exts)?;
+    let (ed, protocol) = check_encrypted_extensions(algs, &exts.slice_range(2..exts.len()))?;
+    if ed.is_some() && !zero_rtt(algs) {
+        Err(NEGOTIATION_MISMATCH)
+    } else {
+        Ok((ed.is_some(), protocol))
+    }
+}
+
+/// A CertificateEntry: a DER encoded certificate and the extensions sent
+/// with it, without their length prefix.
+#[derive(Clone, Debug)]
+pub struct CertificateEntry(pub Bytes, pub Bytes);
+
+/// The Certificate message with `chain`, the end-entity certificate first,
+/// followed by the intermediates. We send no per-entry extensions, as they
+/// have to answer extensions of the ClientHello we do not support.
+pub fn server_certificate(_algs: &Algorithms, chain: &[Bytes]) -> Result<HandshakeData, TLSError> {
+    check(!chain.is_empty())?;
     let creq = lbytes1(&Bytes::new())?;
-    let crt = lbytes3(cert)?;
-    let ext = lbytes2(&Bytes::new())?;
-    let crts = lbytes3(&crt.concat(&ext))?;
-    handshake_message(HandshakeType::Certificate, &creq.concat(&crts))
+    let mut entries = Bytes::new();
+    for cert in chain {
+        let crt = lbytes3(cert)?;
+        let ext = lbytes2(&Bytes::new())?;
+        entries = entries.concat(&crt).concat(&ext);
+    }
+    handshake_message(
+        HandshakeType::Certificate,
+        &creq.concat(&lbytes3(&entries)?),
+    )
 }
 
-pub fn parse_server_certificate(_algs: &Algorithms, sc: &HandshakeData) -> Result<Bytes, TLSError> {
-    let HandshakeData(sc) = get_handshake_message_ty(HandshakeType::Certificate, sc)?;
+fn parse_certificate_entries(b: &Bytes) -> Result<Vec<CertificateEntry>, TLSError> {
+    let mut entries = Vec::new();
     let mut next = 0;
-    let creqlen = check_lbytes1(&sc.slice_range(4..sc.len()))?;
-    next = next + 1 + creqlen;
+    while next < b.len() {
+        let crtlen = check_lbytes3(&b.slice_range(next..b.len()))?;
+        let crt = b.slice_range(next + 3..next + 3 + crtlen);
+        next = next + 3 + crtlen;
+        let extlen = check_lbytes2(&b.slice_range(next..b.len()))?;
+        let ext = b.slice_range(next + 2..next + 2 + extlen);
+        next = next + 2 + extlen;
+        entries.push(CertificateEntry(crt, ext));
+    }
+    Ok(entries)
+}
+
+/// Parse a Certificate message into its entries, the end-entity certificate
+/// first. The list may only be empty for a client without a certificate.
+fn parse_certificate(sc: &HandshakeData) -> Result<Vec<CertificateEntry>, TLSError> {
+    let HandshakeData(sc) = get_handshake_message_ty(HandshakeType::Certificate, sc)?;
+    let creqlen = check_lbytes1(&sc)?;
+    let next = 1 + creqlen;
     check_lbytes3_full(&sc.slice_range(next..sc.len()))?;
-    next = next + 3;
-    let crtlen = check_lbytes3(&sc.slice_range(next..sc.len()))?;
-    next = next + 3;
-    let crt = sc.slice_range(next..next + crtlen);
-    next = next + crtlen;
-    let _extlen = check_lbytes2(&sc.slice_range(next..sc.len()))?;
-    Ok(crt)
+    parse_certificate_entries(&sc.slice_range(next + 3..sc.len()))
 }
 
-fn ecdsa_signature(sv: &Bytes) -> Result<Bytes, TLSError> {
-    if sv.len() != 64 {
-        tlserr(parse_failed())
+pub fn parse_server_certificate(
+    _algs: &Algorithms,
+    sc: &HandshakeData,
+) -> Result<Vec<CertificateEntry>, TLSError> {
+    let chain = parse_certificate(sc)?;
+    check(!chain.is_empty())?;
+    Ok(chain)
+}
+
+/// The client Certificate with the client's certificate chain, which is
+/// empty if the client has no certificate for the server's
+/// CertificateRequest.
+pub fn client_certificate(algs: &Algorithms, chain: &[Bytes]) -> Result<HandshakeData, TLSError> {
+    if chain.is_empty() {
+        handshake_message(
+            HandshakeType::Certificate,
+            &lbytes1(&Bytes::new())?.concat(&lbytes3(&Bytes::new())?),
+        )
     } else {
-        let b0 = bytes1(0x0);
-        let b1 = bytes1(0x30);
-        let b2 = bytes1(0x02);
-        let mut r: Bytes = sv.slice(0, 32);
-        let mut s: Bytes = sv.slice(32, 32);
-        if (r[0] as U8).declassify() >= 128 {
-            r = b0.concat(&r);
-        }
-        if (s[0] as U8).declassify() >= 128 {
-            s = b0.concat(&s);
+        server_certificate(algs, chain)
+    }
+}
+
+/// Parse the client Certificate into the client's certificate chain, the
+/// end-entity certificate first, which is empty if the client has no
+/// certificate.
+pub fn parse_client_certificate(
+    _algs: &Algorithms,
+    cc: &HandshakeData,
+) -> Result<Vec<Bytes>, TLSError> {
+    let HandshakeData(c) = get_handshake_message_ty(HandshakeType::Certificate, cc)?;
+    check(!c.is_empty() && c[0].declassify() == 0)?;
+    let chain = parse_certificate(cc)?;
+    Ok(chain
+        .into_iter()
+        .map(|CertificateEntry(cert, _)| cert)
+        .collect())
+}
+
+/// A CertificateRequest during the handshake, with an empty context and the
+/// signature schemes that the server accepts.
+pub fn certificate_request(
+    _algs: &Algorithms,
+    schemes: &[SignatureScheme],
+) -> Result<HandshakeData, TLSError> {
+    let creq = lbytes1(&Bytes::new())?;
+    let exts = lbytes2(&signature_algorithms(schemes)?)?;
+    handshake_message(HandshakeType::CertificateRequest, &creq.concat(&exts))
+}
+
+fn check_certificate_request_extensions(b: &Bytes) -> Result<Vec<SignatureScheme>, TLSError> {
+    if b.is_empty() {
+        Ok(Vec::new())
+    } else {
+        check(b.len() >= 4)?;
+        let len = check_lbytes2(&b.slice_range(2..b.len()))?;
+        let mut sas = check_certificate_request_extensions(&b.slice_range(4 + len..b.len()))?;
+        if b[0].declassify() == 0 && b[1].declassify() == 0x0d {
+            sas = get_signature_schemes(&b.slice_range(4..4 + len))?;
         }
-        Ok(b1.concat(&lbytes1(
-            &b2.concat(&lbytes1(&r)?).concat(&b2).concat(&lbytes1(&s)?),
-        )?))
+        Ok(sas)
     }
 }
 
-fn check_r_len(rlen: usize) -> Result<(), TLSError> {
-    if rlen < 32 || rlen > 33 {
-        Result::<(), TLSError>::Err(INVALID_SIGNATURE)
+/// Parse a CertificateRequest and return the signature schemes the server
+/// accepts.
+pub fn parse_certificate_request(
+    _algs: &Algorithms,
+    cr: &HandshakeData,
+) -> Result<Vec<SignatureScheme>, TLSError> {
+    let HandshakeData(cr) = get_handshake_message_ty(HandshakeType::CertificateRequest, cr)?;
+    // During the handshake, the request context is empty.
+    check_lbytes1(&cr)?;
+    check(cr[0].declassify() == 0)?;
+    check_lbytes2_full(&cr.slice_range(1..cr.len()))?;
+    check_certificate_request_extensions(&cr.slice_range(3..cr.len()))
+}
+
+/// DER encode the unsigned big-endian integer `i` without leading zeros.
+fn ecdsa_integer(i: &Bytes) -> Result<Bytes, TLSError> {
+    let mut start = 0;
+    while start + 1 < i.len() && i[start].declassify() == 0 {
+        start = start + 1;
+    }
+    let mut i = i.slice_range(start..i.len());
+    if i[0].declassify() >= 128 {
+        i = bytes1(0x0).concat(&i);
+    }
+    Ok(bytes1(0x02).concat(&lbytes1(&i)?))
+}
+
+/// DER encode the raw `r || s` ECDSA signature `sv` of the scheme `sa`.
+fn ecdsa_signature(sa: &SignatureScheme, sv: &Bytes) -> Result<Bytes, TLSError> {
+    let len = ecdsa_scalar_len(sa)?;
+    if sv.len() != 2 * len {
+        tlserr(parse_failed())
+    } else {
+        let rs = ecdsa_integer(&sv.slice(0, len))?.concat(&ecdsa_integer(&sv.slice(len, len))?);
+        Ok(bytes1(0x30).concat(&lbytes1(&rs)?))
+    }
+}
+
+/// Parse the DER encoded integer at the start of `b` into a `len` byte
+/// big-endian value.
+///
+/// Returns the value and the rest of the bytes.
+fn parse_ecdsa_integer(b: &Bytes, len: usize) -> Result<(Bytes, Bytes), TLSError> {
+    check(b.len() >= 2)?;
+    check_eq(&bytes1(0x02), &b.slice_range(0..1))?;
+    let ilen = check_lbytes1(&b.slice_range(1..b.len()))?;
+    if ilen == 0 || ilen > len + 1 || (ilen == len + 1 && b[2].declassify() != 0) {
+        tlserr(INVALID_SIGNATURE)
     } else {
-        Result::<(), TLSError>::Ok(())
+        let i = b.slice(2, ilen);
+        let i = if ilen > len {
+            i.slice(1, len)
+        } else {
+            Bytes::zeroes(len - ilen).concat(&i)
+        };
+        Ok((i, b.slice_range(2 + ilen..b.len())))
     }
 }
 
-fn parse_ecdsa_signature(sig: Bytes) -> Result<Bytes, TLSError> {
-    if sig.len() < 4 {
+/// Convert a DER encoded ECDSA signature of the scheme `sa` into the raw
+/// `r || s` form.
+pub fn parse_ecdsa_signature(sa: &SignatureScheme, sig: Bytes) -> Result<Bytes, TLSError> {
+    let len = ecdsa_scalar_len(sa)?;
+    if sig.len() < 2 {
         tlserr(parse_failed())
     } else {
         check_eq(&bytes1(0x30), &sig.slice_range(0..1))?;
         check_lbytes1_full(&sig.slice_range(1..sig.len()))?;
-        check_eq(&bytes1(0x02), &sig.slice_range(2..3))?;
-        let rlen = check_lbytes1(&sig.slice_range(3..sig.len()))?;
-        check_r_len(rlen)?;
-        let r = sig.slice(4 + rlen - 32, 32);
-        if sig.len() < 6 + rlen + 32 {
-            tlserr(INVALID_SIGNATURE)
-        } else {
-            check_eq(&bytes1(0x02), &sig.slice_range(4 + rlen..5 + rlen))?;
-            check_lbytes1_full(&sig.slice_range(5 + rlen..sig.len()))?;
-            let s = sig.slice(sig.len() - 32, 32);
-            Ok(r.concat(&s))
-        }
+        let (r, rest) = parse_ecdsa_integer(&sig.slice_range(2..sig.len()), len)?;
+        let (s, rest) = parse_ecdsa_integer(&rest, len)?;
+        check(rest.is_empty())?;
+        Ok(r.concat(&s))
     }
 }
+
+/// Build a CertificateVerify message from a signature as returned by `sign`.
+/// ECDSA signatures are DER encoded, others are sent as they are.
 pub fn certificate_verify(algs: &Algorithms, cv: &Bytes) -> Result<HandshakeData, TLSError> {
-    if cv.len() != 64 {
-        tlserr(parse_failed())
+    let sv = if is_ecdsa(&sig_alg(algs)) {
+        ecdsa_signature(&sig_alg(algs), cv)?
     } else {
-        let sv = ecdsa_signature(cv)?;
-        let sig = signature_algorithm(algs)?.concat(&lbytes2(&sv)?);
-        Ok(handshake_message(HandshakeType::CertificateVerify, &sig)?)
-    }
+        cv.clone()
+    };
+    let sig = signature_algorithm(&sig_alg(algs))?.concat(&lbytes2(&sv)?);
+    handshake_message(HandshakeType::CertificateVerify, &sig)
 }
 
-pub fn parse_certificate_verify(algs: &Algorithms, cv: &HandshakeData) -> Result<Bytes, TLSError> {
+/// Parse a CertificateVerify message into the signature scheme the peer used
+/// and the signature.
+pub fn parse_certificate_verify(
+    _algs: &Algorithms,
+    cv: &HandshakeData,
+) -> Result<(SignatureScheme, Bytes), TLSError> {
     let HandshakeData(cv) = get_handshake_message_ty(HandshakeType::CertificateVerify, cv)?;
-    let sa = sig_alg(algs);
-    check_eq(&signature_algorithm(algs)?, &cv.slice_range(0..2))?;
+    check(cv.len() >= 4)?;
+    let sa = get_signature_scheme(&cv.slice_range(0..2))?;
     check_lbytes2_full(&cv.slice_range(2..cv.len()))?;
-    match sa {
-        SignatureScheme::EcdsaSecp256r1Sha256 => parse_ecdsa_signature(cv.slice_range(4..cv.len())),
-        SignatureScheme::RsaPssRsaSha256 => Ok(cv.slice_range(4..cv.len())),
+    let sig = match sa {
+        SignatureScheme::EcdsaSecp256r1Sha256 | SignatureScheme::EcdsaSecp384r1Sha384 => {
+            parse_ecdsa_signature(&sa, cv.slice_range(4..cv.len()))
+        }
+        SignatureScheme::RsaPssRsaSha256
+        | SignatureScheme::RsaPssRsaSha384
+        | SignatureScheme::RsaPssRsaSha512 => Ok(cv.slice_range(4..cv.len())),
         SignatureScheme::ED25519 => {
             if cv.len() - 4 == 64 {
-                Ok(cv.slice_range(8..cv.len()))
+                Ok(cv.slice_range(4..cv.len()))
             } else {
                 tlserr(INVALID_SIGNATURE)
             }
         }
-    }
+    }?;
+    Ok((sa, sig))
 }
 
 pub fn finished(_algs: &Algorithms, vd: &Bytes) -> Result<HandshakeData, TLSError> {
@@ -935,13 +1679,54 @@ pub fn parse_finished(_algs: &Algorithms, fin: &HandshakeData) -> Result<Bytes,
     Ok(fin)
 }
 
-pub fn session_ticket(_algs: &Algorithms, tkt: &Bytes) -> Result<HandshakeData, TLSError> {
-    let lifetime = U32::from(172800).to_be_bytes();
-    let age = U32::from(9999).to_be_bytes();
-    let nonce = lbytes1(&bytes1(1))?;
+/// A KeyUpdate, asking the peer to update its keys as well if
+/// `update_requested` is set.
+pub fn key_update(_algs: &Algorithms, update_requested: bool) -> Result<HandshakeData, TLSError> {
+    handshake_message(HandshakeType::KeyUpdate, &bytes1(update_requested as u8))
+}
+
+/// Parse a KeyUpdate and return whether the peer requests an update.
+pub fn parse_key_update(_algs: &Algorithms, ku: &HandshakeData) -> Result<bool, TLSError> {
+    let HandshakeData(ku) = get_handshake_message_ty(HandshakeType::KeyUpdate, ku)?;
+    if ku.len() != 1 {
+        tlserr(parse_failed())
+    } else {
+        match ku[0].declassify() {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => tlserr(parse_failed()),
+        }
+    }
+}
+
+pub fn end_of_early_data(_algs: &Algorithms) -> Result<HandshakeData, TLSError> {
+    handshake_message(HandshakeType::EndOfEarlyData, &Bytes::new())
+}
+
+pub fn parse_end_of_early_data(_algs: &Algorithms, eoed: &HandshakeData) -> Result<(), TLSError> {
+    let HandshakeData(eoed) = get_handshake_message_ty(HandshakeType::EndOfEarlyData, eoed)?;
+    check(eoed.is_empty())
+}
+
+/// A NewSessionTicket. If `max_early_data` is not zero, the ticket allows
+/// that much early data.
+pub fn session_ticket(
+    _algs: &Algorithms,
+    lifetime: U32,
+    age_add: U32,
+    max_early_data: U32,
+    nonce: &Bytes,
+    tkt: &Bytes,
+) -> Result<HandshakeData, TLSError> {
+    let lifetime = lifetime.to_be_bytes();
+    let age = age_add.to_be_bytes();
+    let nonce = lbytes1(nonce)?;
     let stkt = lbytes2(tkt)?;
-    let grease_ext = bytes2(0x5a, 0x5a).concat(&lbytes2(&Bytes::new())?);
-    let ext = lbytes2(&grease_ext)?;
+    let mut exts = bytes2(0x5a, 0x5a).concat(&lbytes2(&Bytes::new())?);
+    if max_early_data.declassify() > 0 {
+        exts = exts.concat(&max_early_data_size(max_early_data)?);
+    }
+    let ext = lbytes2(&exts)?;
     handshake_message(
         HandshakeType::NewSessionTicket,
         &lifetime
@@ -952,18 +1737,41 @@ pub fn session_ticket(_algs: &Algorithms, tkt: &Bytes) -> Result<HandshakeData,
     )
 }
 
+fn check_session_ticket_extensions(b: &Bytes) -> Result<U32, TLSError> {
+    if b.is_empty() {
+        Ok(U32::from(0))
+    } else {
+        check(b.len() >= 4)?;
+        let len = check_lbytes2(&b.slice_range(2..b.len()))?;
+        let rest = check_session_ticket_extensions(&b.slice_range(4 + len..b.len()))?;
+        match (b[0].declassify(), b[1].declassify()) {
+            (0, 0x2a) => {
+                check(len == 4)?;
+                U32::from_be_bytes(&b.slice_range(4..8))
+            }
+            _ => Ok(rest),
+        }
+    }
+}
+
+/// Parse a NewSessionTicket message into its lifetime, age_add, the maximum
+/// amount of early data (zero if the ticket allows none), nonce and ticket.
 pub fn parse_session_ticket(
     _algs: &Algorithms,
     tkt: &HandshakeData,
-) -> Result<(U32, Bytes), TLSError> {
+) -> Result<(U32, U32, U32, Bytes, Bytes), TLSError> {
     let HandshakeData(tkt) = get_handshake_message_ty(HandshakeType::NewSessionTicket, tkt)?;
+    check(tkt.len() >= 9)?;
     let lifetime = U32::from_be_bytes(&tkt.slice_range(0..4))?;
     let age = U32::from_be_bytes(&tkt.slice_range(4..8))?;
     let nonce_len = check_lbytes1(&tkt.slice_range(8..tkt.len()))?;
+    let nonce = tkt.slice_range(9..9 + nonce_len);
     let stkt_len = check_lbytes2(&tkt.slice_range(9 + nonce_len..tkt.len()))?;
     let stkt = tkt.slice_range(11 + nonce_len..11 + nonce_len + stkt_len);
-    check_lbytes2_full(&tkt.slice_range(11 + nonce_len + stkt_len..tkt.len()))?;
-    Ok((lifetime + age, stkt))
+    let exts = tkt.slice_range(11 + nonce_len + stkt_len..tkt.len());
+    check_lbytes2_full(&exts)?;
+    let max_early_data = check_session_ticket_extensions(&exts.slice_range(2..exts.len()))?;
+    Ok((lifetime, age, max_early_data, nonce, stkt))
 }
 
 /* Record Layer Serialization and Parsing */
@@ -1007,11 +1815,45 @@ pub fn get_content_type(t: u8) -> Result<ContentType, TLSError> {
     }
 }
 
-pub fn handshake_record(p: &HandshakeData) -> Result<Bytes, TLSError> {
+/// The maximum length of the plaintext of a record.
+pub const MAX_FRAGMENT_LEN: usize = 16384;
+
+/// Plaintext records with the legacy record version `ver` for the handshake
+/// messages in `p`, fragmented at `MAX_FRAGMENT_LEN` bytes.
+fn handshake_records(ver: &Bytes, p: &HandshakeData) -> Result<Bytes, TLSError> {
     let HandshakeData(p) = p;
     let ty = bytes1(content_type(ContentType::Handshake));
-    let ver = bytes2(3, 3);
-    Ok(ty.concat(&ver).concat(&lbytes2(p)?))
+    let mut recs = Bytes::new();
+    let mut start = 0;
+    while start < p.len() {
+        let len = std::cmp::min(MAX_FRAGMENT_LEN, p.len() - start);
+        recs = recs
+            .concat(&ty)
+            .concat(ver)
+            .concat(&lbytes2(&p.slice(start, len))?);
+        start += len;
+    }
+    Ok(recs)
+}
+
+pub fn handshake_record(p: &HandshakeData) -> Result<Bytes, TLSError> {
+    handshake_records(&bytes2(3, 3), p)
+}
+
+/// The records of the first ClientHello carry the legacy record version
+/// 0x0301 for compatibility.
+pub fn client_hello_record(p: &HandshakeData) -> Result<Bytes, TLSError> {
+    handshake_records(&bytes2(3, 1), p)
+}
+
+/// Splits the first record off `p`.
+pub fn get_first_record(p: &Bytes) -> Result<(Bytes, Bytes), TLSError> {
+    if p.len() < 5 {
+        tlserr(parse_failed())
+    } else {
+        let len = check_lbytes2(&p.slice_range(3..p.len()))?;
+        Ok((p.slice_range(0..5 + len), p.slice_range(5 + len..p.len())))
+    }
 }
 
 fn protocol_version_alert() -> Result<(), TLSError> {
@@ -1022,6 +1864,27 @@ fn application_data_instead_of_handshake() -> Result<(), TLSError> {
     Result::<(), TLSError>::Err(APPLICATION_DATA_INSTEAD_OF_HANDSHAKE)
 }
 
+/// A plaintext record `p` that is not a handshake record fails with the
+/// alert it carries, if it is an alert.
+fn not_a_handshake_record(p: &Bytes) -> Result<(), TLSError> {
+    if p[0].declassify() == content_type(ContentType::Alert) {
+        let len = check_lbytes2(&p.slice_range(3..p.len()))?;
+        let (_, ad) = parse_alert_record(&p.slice_range(0..5 + len))?;
+        Result::<(), TLSError>::Err(got_alert(ad))
+    } else {
+        application_data_instead_of_handshake()
+    }
+}
+
+/// Plaintext records carry at most `MAX_FRAGMENT_LEN` bytes.
+fn check_record_len(len: usize) -> Result<(), TLSError> {
+    if len > MAX_FRAGMENT_LEN {
+        Err(RECORD_OVERFLOW)
+    } else {
+        Ok(())
+    }
+}
+
 pub fn check_handshake_record(p: &Bytes) -> Result<(HandshakeData, usize), TLSError> {
     if p.len() < 5 {
         tlserr(parse_failed())
@@ -1030,13 +1893,14 @@ pub fn check_handshake_record(p: &Bytes) -> Result<(HandshakeData, usize), TLSEr
         let ver = bytes2(3, 3);
         match check_eq(&ty, &p.slice_range(0..1)) {
             Ok(_) => (),
-            Err(_) => application_data_instead_of_handshake()?,
+            Err(_) => not_a_handshake_record(p)?,
         };
         match check_eq(&ver, &p.slice_range(1..3)) {
             Ok(_) => (),
             Err(_) => protocol_version_alert()?,
         };
         let len = check_lbytes2(&p.slice_range(3..p.len()))?;
+        check_record_len(len)?;
         Ok((HandshakeData(p.slice_range(5..5 + len)), 5 + len))
     }
 }
@@ -1070,6 +1934,24 @@ pub fn get_transcript_hash(tx: &Transcript) -> Result<Digest, TLSError> {
     Ok(th)
 }
 
+/// The client only knows which hash the transcript uses once the server has
+/// picked a cipher suite.
+pub fn transcript_with_hash(tx: Transcript, ha: HashAlgorithm) -> Transcript {
+    let Transcript(_, tx) = tx;
+    Transcript(ha, tx)
+}
+
+/// After a HelloRetryRequest the first ClientHello is replaced in the
+/// transcript by a synthetic `message_hash` message holding its hash.
+pub fn transcript_hello_retry(tx: Transcript) -> Result<Transcript, TLSError> {
+    let th = get_transcript_hash(&tx)?;
+    let Transcript(ha, _) = tx;
+    Ok(Transcript(
+        ha,
+        handshake_message(HandshakeType::MessageHash, &th)?,
+    ))
+}
+
 pub fn get_transcript_hash_truncated_client_hello(
     tx: &Transcript,
     ch: &HandshakeData,
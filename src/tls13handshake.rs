@@ -74,6 +74,25 @@ pub fn derive_0rtt_keys(
     Ok((sender_write_key_iv, early_exporter_master_secret))
 }
 
+/// The exporter value of `len` bytes for `label` and `context`, from an
+/// (early) exporter master secret `k`, see RFC 8446 Section 7.5.
+pub fn derive_exporter(
+    ha: &HashAlgorithm,
+    k: &Key,
+    label: &Bytes,
+    context: &Bytes,
+    len: usize,
+) -> Result<Bytes, TLSError> {
+    let secret = derive_secret(ha, k, label, &hash_empty(ha)?)?;
+    hkdf_expand_label(
+        ha,
+        &secret,
+        &bytes(&LABEL_EXPORTER),
+        &hash(ha, context)?,
+        len,
+    )
+}
+
 pub fn derive_finished_key(ha: &HashAlgorithm, k: &Key) -> Result<MacKey, TLSError> {
     hkdf_expand_label(
         ha,
@@ -132,7 +151,7 @@ pub fn derive_app_keys(
     ae: &AeadAlgorithm,
     master_secret: &Key,
     tx: &Digest,
-) -> Result<(AeadKeyIV, AeadKeyIV, Key), TLSError> {
+) -> Result<(AeadKeyIV, AeadKeyIV, Key, Key, Key), TLSError> {
     let client_application_traffic_secret_0 =
         derive_secret(ha, master_secret, &bytes(&LABEL_C_AP_TRAFFIC), tx)?;
     let server_application_traffic_secret_0 =
@@ -143,26 +162,125 @@ pub fn derive_app_keys(
     Ok((
         client_write_key_iv,
         server_write_key_iv,
+        client_application_traffic_secret_0,
+        server_application_traffic_secret_0,
         exporter_master_secret,
     ))
 }
 
+/// Derive the next application traffic secret and its keys for a KeyUpdate.
+pub fn derive_next_app_keys(
+    ha: &HashAlgorithm,
+    ae: &AeadAlgorithm,
+    ts: &Key,
+) -> Result<(AeadKeyIV, Key), TLSError> {
+    let next_traffic_secret = hkdf_expand_label(
+        ha,
+        ts,
+        &bytes(&LABEL_TRAFFIC_UPD),
+        &Bytes::new(),
+        hash_len(ha),
+    )?;
+    let write_key_iv = derive_aead_key_iv(ha, ae, &next_traffic_secret)?;
+    Ok((write_key_iv, next_traffic_secret))
+}
+
 pub fn derive_rms(ha: &HashAlgorithm, master_secret: &Key, tx: &Digest) -> Result<Key, TLSError> {
     derive_secret(ha, master_secret, &bytes(&LABEL_RES_MASTER), tx)
 }
 
+pub fn derive_resumption_psk(
+    ha: &HashAlgorithm,
+    rms: &Key,
+    nonce: &Bytes,
+) -> Result<PSK, TLSError> {
+    hkdf_expand_label(ha, rms, &bytes(&LABEL_RESUMPTION), nonce, hash_len(ha))
+}
+
 /* Handshake State Machine */
 /* We implement a simple linear state machine:
 PostClientHello -> PostServerHello -> PostCertificateVerify ->
 PostServerFinished -> PostClientFinished
 There are no optional steps, all states must be traversed, even if the traversals are NOOPS.
-See "put_psk_skip_server_signature" below */
+See "put_psk_skip_server_signature" below.
+The only loop is a HelloRetryRequest, which takes PostClientHello back to itself
+on the client and inserts PostHelloRetryRequest before PostClientHello on the server. */
 
-pub struct ClientPostClientHello(Random, Algorithms, KemSk, Option<PSK>, Transcript);
-pub struct ClientPostServerHello(Random, Random, Algorithms, Key, MacKey, MacKey, Transcript);
-pub struct ClientPostCertificateVerify(Random, Random, Algorithms, Key, MacKey, MacKey, Transcript);
-pub struct ClientPostServerFinished(Random, Random, Algorithms, Key, MacKey, Transcript);
-pub struct ClientPostClientFinished(Random, Random, Algorithms, Key, Transcript);
+pub struct ClientPostClientHello(
+    Random,
+    Algorithms,
+    Vec<Algorithms>,
+    Vec<(KemScheme, KemSk)>,
+    Option<PSK>,
+    Bytes,
+    Option<Bytes>,
+    bool,
+    Option<(Vec<Bytes>, SignatureKey)>,
+    Vec<Bytes>,
+    Option<(Vec<Bytes>, u64)>,
+    Entropy,
+    Transcript,
+);
+/// The client states carry the offered application protocols until the
+/// EncryptedExtensions, and the selected one afterwards.
+/// The server name, and the trust anchors and current time to validate the
+/// server's certificate chain with, if any, are carried until it is validated.
+/// The entropy left after the key shares is kept for the key shares of a
+/// HelloRetryRequest and for the client's signature.
+pub struct ClientPostServerHello(
+    Random,
+    Random,
+    Algorithms,
+    Vec<SignatureScheme>,
+    Key,
+    MacKey,
+    MacKey,
+    Option<(Vec<Bytes>, SignatureKey)>,
+    Vec<Bytes>,
+    Bytes,
+    Option<(Vec<Bytes>, u64)>,
+    Entropy,
+    Transcript,
+);
+/// The client certificate chain and key, if any, the signature schemes of the
+/// server's CertificateRequest, if it sent one, and the server's certificate
+/// chain, which is empty in PSK mode.
+pub struct ClientPostCertificateVerify(
+    Random,
+    Random,
+    Algorithms,
+    Key,
+    MacKey,
+    MacKey,
+    Option<(Vec<Bytes>, SignatureKey)>,
+    Option<Vec<SignatureScheme>>,
+    Option<Bytes>,
+    Vec<CertificateEntry>,
+    Entropy,
+    Transcript,
+);
+pub struct ClientPostServerFinished(
+    Random,
+    Random,
+    Algorithms,
+    Key,
+    MacKey,
+    Option<(Vec<Bytes>, SignatureKey)>,
+    Option<Vec<SignatureScheme>>,
+    Option<Bytes>,
+    Vec<CertificateEntry>,
+    Entropy,
+    Transcript,
+);
+pub struct ClientPostClientFinished(
+    Random,
+    Random,
+    Algorithms,
+    Key,
+    Option<Bytes>,
+    Vec<CertificateEntry>,
+    Transcript,
+);
 
 pub fn algs_post_client_hello(st: &ClientPostClientHello) -> Algorithms {
     st.1
@@ -173,42 +291,106 @@ pub fn algs_post_server_hello(st: &ClientPostServerHello) -> Algorithms {
 pub fn algs_post_client_finished(st: &ClientPostClientFinished) -> Algorithms {
     st.2
 }
+pub fn algs_post_server_client_finished(st: &ServerPostClientFinished) -> Algorithms {
+    st.2
+}
+pub fn peer_certificate_post_client_finished(st: &ServerPostClientFinished) -> Option<Bytes> {
+    st.4.clone()
+}
+pub fn alpn_post_client_finished(st: &ClientPostClientFinished) -> Option<Bytes> {
+    st.4.clone()
+}
+pub fn peer_certificates_post_client_finished(
+    st: &ClientPostClientFinished,
+) -> Vec<CertificateEntry> {
+    st.5.clone()
+}
+pub fn algs_post_server_finished(st: &ServerPostServerFinished) -> Algorithms {
+    st.2
+}
+pub fn alpn_post_server_finished(st: &ServerPostServerFinished) -> Option<Bytes> {
+    st.6.clone()
+}
+pub fn alpn_post_server_client_finished(st: &ServerPostClientFinished) -> Option<Bytes> {
+    st.5.clone()
+}
 
+pub struct ServerPostHelloRetryRequest(Algorithms, ServerDB, Bytes, Transcript);
+// When the server requests a client certificate, the following server states
+// hold the trust anchors and current time to validate the client's chain with.
+// The option after them is the selected application protocol.
 pub struct ServerPostClientHello(
     Random,
     Algorithms,
     Bytes,
     Bytes,
-    Bytes,
+    Vec<Bytes>,
     SignatureKey,
     Option<PSK>,
+    Option<(Vec<Bytes>, u64)>,
+    Option<Bytes>,
     Transcript,
 );
 pub struct ServerPostServerHello(
     Random,
     Random,
     Algorithms,
-    Bytes,
+    Vec<Bytes>,
     SignatureKey,
     Key,
     MacKey,
     MacKey,
+    Option<(Vec<Bytes>, u64)>,
+    Option<Bytes>,
+    Transcript,
+);
+pub struct ServerPostCertificateVerify(
+    Random,
+    Random,
+    Algorithms,
+    Key,
+    MacKey,
+    MacKey,
+    Option<(Vec<Bytes>, u64)>,
+    Option<Bytes>,
+    Transcript,
+);
+pub struct ServerPostServerFinished(
+    Random,
+    Random,
+    Algorithms,
+    Key,
+    MacKey,
+    Option<(Vec<Bytes>, u64)>,
+    Option<Bytes>,
+    Transcript,
+);
+/// The server state after the handshake, with the client certificate if the
+/// client authenticated, and the selected application protocol.
+pub struct ServerPostClientFinished(
+    Random,
+    Random,
+    Algorithms,
+    Key,
+    Option<Bytes>,
+    Option<Bytes>,
     Transcript,
 );
-pub struct ServerPostCertificateVerify(Random, Random, Algorithms, Key, MacKey, MacKey, Transcript);
-pub struct ServerPostServerFinished(Random, Random, Algorithms, Key, MacKey, Transcript);
-pub struct ServerPostClientFinished(Random, Random, Algorithms, Key, Transcript);
 
 /* Handshake Core Functions: See RFC 8446 Section 4 */
 /* We delegate all details of message formatting and transcript Digestes to the caller */
 
 /* TLS 1.3 Client Side Handshake Functions */
 
+#[allow(clippy::too_many_arguments)]
 fn get_client_hello(
-    algs0: Algorithms,
+    offered: &[Algorithms],
     sn: &Bytes,
     tkt: Option<Bytes>,
     psk: Option<PSK>,
+    ccert: Option<(Vec<Bytes>, SignatureKey)>,
+    protocols: &[Bytes],
+    trust: Option<(Vec<Bytes>, u64)>,
     ent: Entropy,
 ) -> Result<
     (
@@ -218,19 +400,191 @@ fn get_client_hello(
     ),
     TLSError,
 > {
-    let gx_len = kem_priv_len(&kem_alg(&algs0));
-    if ent.len() < 32 + gx_len {
+    let algs0 = first_algorithms(offered)?;
+    if ent.len() < 32 {
         Err(INSUFFICIENT_ENTROPY)
     } else {
         let tx = transcript_empty(hash_alg(&algs0));
         let cr = ent.slice_range(0..32);
-        let (x, gx) = kem_keygen(&kem_alg(&algs0), ent.slice_range(32..32 + gx_len))?;
-        let (ch, trunc_len) = client_hello(&algs0, &cr, &gx, sn, &tkt)?;
+        let (xs, gxs, ent) =
+            generate_key_shares(&key_share_groups(offered), ent.slice_range(32..ent.len()))?;
+        let (ch, trunc_len) = client_hello(offered, &cr, &gxs, sn, &tkt, &None, protocols)?;
         let (nch, cipher0, tx_ch) = compute_psk_binder_zero_rtt(algs0, ch, trunc_len, &psk, tx)?;
         Ok((
             nch,
             cipher0,
-            ClientPostClientHello(cr, algs0, x, psk, tx_ch),
+            ClientPostClientHello(
+                cr,
+                algs0,
+                offered.to_vec(),
+                xs,
+                psk,
+                sn.clone(),
+                tkt,
+                false,
+                ccert,
+                protocols.to_vec(),
+                trust,
+                ent,
+                tx_ch,
+            ),
+        ))
+    }
+}
+
+/// Generate a key share for each of `groups`, with the randomness taken in
+/// order from the start of `ent`. Returns the rest of `ent` as well.
+#[allow(clippy::type_complexity)]
+fn generate_key_shares(
+    groups: &[KemScheme],
+    ent: Entropy,
+) -> Result<(Vec<(KemScheme, KemSk)>, Vec<(KemScheme, KemPk)>, Entropy), TLSError> {
+    let len: usize = groups.iter().map(kem_priv_len).sum();
+    if ent.len() < len {
+        Err(INSUFFICIENT_ENTROPY)
+    } else {
+        let mut xs = Vec::new();
+        let mut gxs = Vec::new();
+        let mut start = 0;
+        for ks in groups {
+            let end = start + kem_priv_len(ks);
+            let (x, gx) = kem_keygen(ks, ent.slice_range(start..end))?;
+            xs.push((*ks, x));
+            gxs.push((*ks, gx));
+            start = end;
+        }
+        Ok((xs, gxs, ent.slice_range(len..ent.len())))
+    }
+}
+
+/// The groups of all `offered` algorithms, in order and without duplicates.
+fn offered_groups(offered: &[Algorithms]) -> Vec<KemScheme> {
+    let mut groups = Vec::new();
+    for a in offered {
+        if !groups.contains(&kem_alg(a)) {
+            groups.push(kem_alg(a));
+        }
+    }
+    groups
+}
+
+/// The maximum number of key shares in the first ClientHello.
+pub const MAX_KEY_SHARES: usize = 2;
+
+/// The groups we send key shares for: the first [MAX_KEY_SHARES] of the
+/// [offered_groups]. The server can ask for any of the others with a
+/// HelloRetryRequest.
+fn key_share_groups(offered: &[Algorithms]) -> Vec<KemScheme> {
+    let mut groups = offered_groups(offered);
+    groups.truncate(MAX_KEY_SHARES);
+    groups
+}
+
+/// Parse the ServerHello with the group of each key share we sent, and
+/// take over the group whose share the server answered.
+fn put_server_key_share(
+    sh: &HandshakeData,
+    algs: Algorithms,
+    xs: &[(KemScheme, KemSk)],
+) -> Result<(Algorithms, Random, KemPk, KemSk), TLSError> {
+    let Algorithms(ha, ae, sa, _ks, psk_mode, zero_rtt) = algs;
+    let mut res = Err(MISSING_KEY_SHARE);
+    for (ks, x) in xs {
+        if res.is_err() {
+            let algs = Algorithms(ha, ae, sa, *ks, psk_mode, zero_rtt);
+            res = match parse_server_hello(&algs, sh) {
+                Ok((sr, gy)) => Ok((algs, sr, gy, x.clone())),
+                Err(e) => Err(e),
+            };
+        }
+    }
+    res
+}
+
+/// Take over the cipher suite picked by the server in a ServerHello or
+/// HelloRetryRequest, after checking that we offered it. With a PSK, the
+/// hash has to be the one the PSK was established with.
+fn put_server_ciphersuite(
+    sh: &HandshakeData,
+    algs: Algorithms,
+    offered: &[Algorithms],
+) -> Result<Algorithms, TLSError> {
+    let Algorithms(ha0, _ae0, sa, ks, psk_mode, zero_rtt) = algs;
+    let (ha, ae) = server_hello_ciphersuite(sh)?;
+    let mut found = false;
+    for a in offered {
+        if hash_alg(a) == ha && aead_alg(a) == ae {
+            found = true;
+        }
+    }
+    if !found || (psk_mode && ha != ha0) {
+        tlserr(NEGOTIATION_MISMATCH)
+    } else {
+        Ok(Algorithms(ha, ae, sa, ks, psk_mode, zero_rtt))
+    }
+}
+
+fn put_hello_retry_request(
+    hrr: &HandshakeData,
+    st: ClientPostClientHello,
+) -> Result<(HandshakeData, ClientPostClientHello), TLSError> {
+    let ClientPostClientHello(
+        cr,
+        algs0,
+        offered,
+        xs,
+        psk,
+        sn,
+        tkt,
+        retried,
+        ccert,
+        protocols,
+        trust,
+        ent,
+        tx,
+    ) = st;
+    let algs0 = put_server_ciphersuite(hrr, algs0, &offered)?;
+    let Algorithms(ha, ae, sa, _ks, psk_mode, _zero_rtt) = algs0;
+    let (group, ck) = in_context(parse_hello_retry_request(&algs0, hrr), "HelloRetryRequest")?;
+    let mut sent = Vec::new();
+    for (ks, _x) in xs.iter() {
+        sent.push(*ks);
+    }
+    let resent = match group {
+        Some(g) => sent.contains(&g),
+        None => false,
+    };
+    let unoffered = match group {
+        Some(g) => !offered_groups(&offered).contains(&g),
+        None => false,
+    };
+    if retried {
+        // Only one HelloRetryRequest is allowed per handshake.
+        Err(INCORRECT_STATE)
+    } else if unoffered {
+        // The server asked for a group that is not in our supported_groups.
+        tlserr(ILLEGAL_PARAMETER)
+    } else if resent {
+        // The server asked for a group we already sent a share for.
+        tlserr(ILLEGAL_PARAMETER)
+    } else {
+        // Without a group in the request we send fresh shares for the same groups.
+        let groups = match group {
+            Some(g) => vec![g],
+            None => sent,
+        };
+        // Early data is not allowed after a HelloRetryRequest.
+        let algs = Algorithms(ha, ae, sa, groups[0], psk_mode, false);
+        let (xs, gxs, ent) = generate_key_shares(&groups, ent)?;
+        let tx = transcript_hello_retry(transcript_with_hash(tx, ha))?;
+        let tx = transcript_add1(tx, hrr);
+        let (ch, trunc_len) = client_hello(&offered, &cr, &gxs, &sn, &tkt, &ck, &protocols)?;
+        let (nch, _cipher0, tx_ch) = compute_psk_binder_zero_rtt(algs, ch, trunc_len, &psk, tx)?;
+        Ok((
+            nch,
+            ClientPostClientHello(
+                cr, algs, offered, xs, psk, sn, tkt, true, ccert, protocols, trust, ent, tx_ch,
+            ),
         ))
     }
 }
@@ -271,40 +625,146 @@ fn put_server_hello(
     sh: &HandshakeData,
     st: ClientPostClientHello,
 ) -> Result<(DuplexCipherStateH, ClientPostServerHello), TLSError> {
-    let ClientPostClientHello(cr, algs0, x, psk, tx) = st;
-    let Algorithms(ha, ae, _sa, ks, _psk_mode, _zero_rtt) = algs0;
-    let (sr, gy) = parse_server_hello(&algs0, sh)?;
-    let tx = transcript_add1(tx, sh);
-    let gxy = kem_decap(&ks, &gy, &x)?;
-    let th = get_transcript_hash(&tx)?;
-    let (chk, shk, cfk, sfk, ms) = derive_hk_ms(&ha, &ae, &gxy, &psk, &th)?;
-    Ok((
-        duplex_cipher_state_hs(ae, chk, 0, shk, 0),
-        ClientPostServerHello(cr, sr, algs0, ms, cfk, sfk, tx),
-    ))
+    let ClientPostClientHello(
+        cr,
+        algs0,
+        offered,
+        xs,
+        psk,
+        sn,
+        _tkt,
+        retried,
+        ccert,
+        protocols,
+        trust,
+        ent,
+        tx,
+    ) = st;
+    let algs = put_server_ciphersuite(sh, algs0, &offered)?;
+    let (algs, sr, gy, x) = put_server_key_share(sh, algs, &xs)?;
+    // After a HelloRetryRequest the server must stick to its cipher suite.
+    // Its group is one we sent a share for in the second ClientHello, i.e.
+    // the one it asked for, if any.
+    let ciphersuite_changed =
+        hash_alg(&algs) != hash_alg(&algs0) || aead_alg(&algs) != aead_alg(&algs0);
+    if retried && ciphersuite_changed {
+        tlserr(ILLEGAL_PARAMETER)
+    } else {
+        let Algorithms(ha, ae, _sa, ks, _psk_mode, _zero_rtt) = algs;
+        let tx = transcript_add1(transcript_with_hash(tx, ha), sh);
+        let gxy = kem_decap(&ks, &gy, &x)?;
+        let th = get_transcript_hash(&tx)?;
+        let (chk, shk, cfk, sfk, ms) = derive_hk_ms(&ha, &ae, &gxy, &psk, &th)?;
+        Ok((
+            duplex_cipher_state_hs(ae, chk, 0, shk, 0),
+            ClientPostServerHello(
+                cr,
+                sr,
+                algs,
+                signature_schemes(&offered),
+                ms,
+                cfk,
+                sfk,
+                ccert,
+                protocols,
+                sn,
+                trust,
+                ent,
+                tx,
+            ),
+        ))
+    }
+}
+
+/// Parse the EncryptedExtensions: record whether early data was accepted in
+/// `algs`, and check that the selected application protocol was offered.
+fn put_encrypted_extensions(
+    ee: &HandshakeData,
+    algs: Algorithms,
+    protocols: &[Bytes],
+) -> Result<(Algorithms, Option<Bytes>), TLSError> {
+    let (accepted, protocol) =
+        in_context(parse_encrypted_extensions(&algs, ee), "EncryptedExtensions")?;
+    match &protocol {
+        Some(p) if !protocols.iter().any(|o| eq(o, p)) => Err(NEGOTIATION_MISMATCH),
+        _ => Ok((early_data_algorithms(algs, accepted), protocol)),
+    }
 }
 
 fn put_server_signature(
     ee: &HandshakeData,
+    creq: &Option<HandshakeData>,
     sc: &HandshakeData,
     scv: &HandshakeData,
     st: ClientPostServerHello,
 ) -> Result<ClientPostCertificateVerify, TLSError> {
-    let ClientPostServerHello(cr, sr, algs, ms, cfk, sfk, tx) = st;
+    let ClientPostServerHello(
+        cr,
+        sr,
+        algs,
+        sas,
+        ms,
+        cfk,
+        sfk,
+        ccert,
+        protocols,
+        sn,
+        trust,
+        ent,
+        tx,
+    ) = st;
     if !psk_mode(&algs) {
-        parse_encrypted_extensions(&algs, ee)?;
+        let (algs, protocol) = put_encrypted_extensions(ee, algs, &protocols)?;
         let tx = transcript_add1(tx, ee);
-        let cert = parse_server_certificate(&algs, sc)?;
+        let (csas, tx) = match creq {
+            Some(creq) => (
+                Some(in_context(
+                    parse_certificate_request(&algs, creq),
+                    "CertificateRequest",
+                )?),
+                transcript_add1(tx, creq),
+            ),
+            None => (None, tx),
+        };
+        let chain = in_context(parse_server_certificate(&algs, sc), "Certificate")?;
+        if let Some((trust_anchors, now)) = &trust {
+            let certs: Vec<Bytes> = chain.iter().map(|entry| entry.0.clone()).collect();
+            in_context(
+                verify_certificate_chain(&certs, trust_anchors, *now, &server_auth_oid()),
+                "certificate chain",
+            )?;
+        }
+        if !sn.is_empty() {
+            in_context(
+                verify_certificate_name(&chain[0].0, &sn),
+                "certificate server name",
+            )?;
+        }
+        let CertificateEntry(cert, _exts) = &chain[0];
         let tx = transcript_add1(tx, sc);
         let th_sc = get_transcript_hash(&tx)?;
-        let spki = verification_key_from_cert(&cert)?;
+        let spki = verification_key_from_cert(cert)?;
         // println!("Server signature scheme: {:?}", spki.0);
-        let pk = cert_public_key(&cert, &spki)?;
-        let sig = parse_certificate_verify(&algs, scv)?;
-        let sigval = (Bytes::from_slice(&PREFIX_SERVER_SIGNATURE)).concat(&th_sc);
-        verify(&sig_alg(&algs), &pk, &sigval, &sig)?;
-        let tx = transcript_add1(tx, scv);
-        Ok(ClientPostCertificateVerify(cr, sr, algs, ms, cfk, sfk, tx))
+        let pk = cert_public_key(cert, &spki)?;
+        let (sa, sig) = in_context(parse_certificate_verify(&algs, scv), "CertificateVerify")?;
+        // The server has to sign with one of our schemes, using its certificate key.
+        if !sas.contains(&sa) {
+            Err(NEGOTIATION_MISMATCH)
+        } else if !signature_scheme_matches_key(&sa, &spki.0) {
+            Err(INVALID_SIGNATURE)
+        } else {
+            let sigval = (Bytes::from_slice(&PREFIX_SERVER_SIGNATURE)).concat(&th_sc);
+            in_context(
+                verify(&sa, &pk, &sigval, &sig),
+                "CertificateVerify signature",
+            )?;
+            let tx = transcript_add1(tx, scv);
+            let Algorithms(ha, ae, _sa, ks, psk_mode, zero_rtt) = algs;
+            let algs = Algorithms(ha, ae, sa, ks, psk_mode, zero_rtt);
+            Ok(ClientPostCertificateVerify(
+                cr, sr, algs, ms, cfk, sfk, ccert, csas, protocol, chain, ent, tx,
+            ))
+        }
     } else {
         Err(PSK_MODE_MISMATCH)
     }
@@ -314,11 +774,38 @@ fn put_psk_skip_server_signature(
     ee: &HandshakeData,
     st: ClientPostServerHello,
 ) -> Result<ClientPostCertificateVerify, TLSError> {
-    let ClientPostServerHello(cr, sr, algs, ms, cfk, sfk, tx) = st;
+    let ClientPostServerHello(
+        cr,
+        sr,
+        algs,
+        _sas,
+        ms,
+        cfk,
+        sfk,
+        ccert,
+        protocols,
+        _sn,
+        _trust,
+        ent,
+        tx,
+    ) = st;
     if psk_mode(&algs) {
-        parse_encrypted_extensions(&algs, ee)?;
+        let (algs, protocol) = put_encrypted_extensions(ee, algs, &protocols)?;
         let tx = transcript_add1(tx, ee);
-        Ok(ClientPostCertificateVerify(cr, sr, algs, ms, cfk, sfk, tx))
+        Ok(ClientPostCertificateVerify(
+            cr,
+            sr,
+            algs,
+            ms,
+            cfk,
+            sfk,
+            ccert,
+            None,
+            protocol,
+            Vec::new(),
+            ent,
+            tx,
+        ))
     } else {
         Err(PSK_MODE_MISMATCH)
     }
@@ -328,41 +815,149 @@ fn put_server_finished(
     sfin: &HandshakeData,
     st: ClientPostCertificateVerify,
 ) -> Result<(DuplexCipherState1, ClientPostServerFinished), TLSError> {
-    let ClientPostCertificateVerify(cr, sr, algs, ms, cfk, sfk, tx) = st;
+    let ClientPostCertificateVerify(
+        cr,
+        sr,
+        algs,
+        ms,
+        cfk,
+        sfk,
+        ccert,
+        csas,
+        protocol,
+        chain,
+        ent,
+        tx,
+    ) = st;
     let Algorithms(ha, ae, _sa, _gn, _psk_mode, _zero_rtt) = algs;
     let th = get_transcript_hash(&tx)?;
-    let vd = parse_finished(&algs, sfin)?;
-    hmac_verify(&ha, &sfk, &th, &vd)?;
+    let vd = in_context(parse_finished(&algs, sfin), "Finished")?;
+    in_context(hmac_verify(&ha, &sfk, &th, &vd), "Finished MAC")?;
     let tx = transcript_add1(tx, sfin);
     let th_sfin = get_transcript_hash(&tx)?;
-    let (cak, sak, exp) = derive_app_keys(&ha, &ae, &ms, &th_sfin)?;
-    let cipher1 = duplex_cipher_state1(ae, cak, 0, sak, 0, exp);
-    Ok((cipher1, ClientPostServerFinished(cr, sr, algs, ms, cfk, tx)))
+    let (cak, sak, cats, sats, exp) = derive_app_keys(&ha, &ae, &ms, &th_sfin)?;
+    let cipher1 = duplex_cipher_state1(ae, cak, 0, sak, 0, exp, cats, sats);
+    Ok((
+        cipher1,
+        ClientPostServerFinished(cr, sr, algs, ms, cfk, ccert, csas, protocol, chain, ent, tx),
+    ))
+}
+
+/// Sign `input` with the key `sk` of the certificate `cert`.
+/// An RSA key is only the private exponent, the rest is read from `cert`.
+fn sign_with_certificate(
+    sa: &SignatureScheme,
+    cert: &Bytes,
+    sk: &SignatureKey,
+    input: &Bytes,
+    ent: Entropy,
+) -> Result<Bytes, TLSError> {
+    if is_rsa_pss(sa) {
+        let (_key, indices) = verification_key_from_cert(cert)?;
+        let (n, e) = rsa_public_key(cert, indices)?;
+        sign_rsa(sa, sk, &n, &e, input, ent)
+    } else {
+        sign(sa, sk, input, ent)
+    }
 }
 
+/// Answer a CertificateRequest with our certificate and signature, or with an
+/// empty Certificate if we have no certificate for one of the `csas` schemes.
+fn get_client_signature(
+    algs: &Algorithms,
+    ccert: &Option<(Vec<Bytes>, SignatureKey)>,
+    csas: &[SignatureScheme],
+    tx: Transcript,
+    ent: Entropy,
+) -> Result<(HandshakeData, Transcript), TLSError> {
+    let signer = match ccert {
+        Some((chain, sk)) if !chain.is_empty() => {
+            let (sa, _pk) = verification_key_from_cert(&chain[0])?;
+            if csas.contains(&sa) {
+                Some((sa, chain, sk))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+    match signer {
+        Some((sa, chain, sk)) => {
+            let cc = client_certificate(algs, chain)?;
+            let tx = transcript_add1(tx, &cc);
+            let th = get_transcript_hash(&tx)?;
+            let sigval = Bytes::from_slice(&PREFIX_CLIENT_SIGNATURE).concat(&th);
+            let sig = sign_with_certificate(&sa, &chain[0], sk, &sigval, ent)?;
+            let Algorithms(ha, ae, _sa, ks, psk_mode, zero_rtt) = *algs;
+            let ccv = certificate_verify(&Algorithms(ha, ae, sa, ks, psk_mode, zero_rtt), &sig)?;
+            let tx = transcript_add1(tx, &ccv);
+            Ok((handshake_concat(cc, &ccv), tx))
+        }
+        None => {
+            let cc = client_certificate(algs, &[])?;
+            let tx = transcript_add1(tx, &cc);
+            Ok((cc, tx))
+        }
+    }
+}
+
+/// Record whether early data was accepted in the zero_rtt field.
+fn early_data_algorithms(algs: Algorithms, accepted: bool) -> Algorithms {
+    let Algorithms(ha, ae, sa, ks, psk_mode, _zero_rtt) = algs;
+    Algorithms(ha, ae, sa, ks, psk_mode, accepted)
+}
+
+#[allow(clippy::type_complexity)]
 fn get_client_finished(
     st: ClientPostServerFinished,
-) -> Result<(HandshakeData, ClientPostClientFinished), TLSError> {
-    let ClientPostServerFinished(cr, sr, algs, ms, cfk, tx) = st;
+) -> Result<
+    (
+        Option<HandshakeData>,
+        HandshakeData,
+        ClientPostClientFinished,
+    ),
+    TLSError,
+> {
+    let ClientPostServerFinished(cr, sr, algs, ms, cfk, ccert, csas, protocol, chain, ent, tx) = st;
+    // If the server accepted early data, the client ends it before its Finished.
+    let (eoed, tx) = if zero_rtt(&algs) {
+        let eoed = end_of_early_data(&algs)?;
+        let tx = transcript_add1(tx, &eoed);
+        (Some(eoed), tx)
+    } else {
+        (None, tx)
+    };
+    let (cflight, tx) = match csas {
+        Some(csas) => get_client_signature(&algs, &ccert, &csas, tx, ent)?,
+        None => (handshake_data(Bytes::new()), tx),
+    };
     let th = get_transcript_hash(&tx)?;
     let vd = hmac_tag(&hash_alg(&algs), &cfk, &th)?;
     let cfin = finished(&algs, &vd)?;
     let tx = transcript_add1(tx, &cfin);
     let th = get_transcript_hash(&tx)?;
     let rms = derive_rms(&hash_alg(&algs), &ms, &th)?;
-    Ok((cfin, ClientPostClientFinished(cr, sr, algs, rms, tx)))
+    Ok((
+        eoed,
+        handshake_concat(cflight, &cfin),
+        ClientPostClientFinished(cr, sr, algs, rms, protocol, chain, tx),
+    ))
 }
 
 // Client-Side Handshake API: Usable by Quic and TLS
-// client_init -> (encrypt_zerortt)* ->
+// client_init -> (encrypt_zerortt)* -> (client_retry)? ->
 // client_set_params -> (encrypt_handshake | decrypt_handshake)* ->
 // client_finish -> (encrypt_data | decrypt_data)*
 
+#[allow(clippy::too_many_arguments)]
 pub fn client_init(
-    algs: Algorithms,
+    algs: &[Algorithms],
     sn: &Bytes,
     tkt: Option<Bytes>,
     psk: Option<PSK>,
+    ccert: Option<(Vec<Bytes>, SignatureKey)>,
+    protocols: &[Bytes],
+    trust: Option<(Vec<Bytes>, u64)>,
     ent: Entropy,
 ) -> Result<
     (
@@ -372,7 +967,14 @@ pub fn client_init(
     ),
     TLSError,
 > {
-    get_client_hello(algs, sn, tkt, psk, ent)
+    get_client_hello(algs, sn, tkt, psk, ccert, protocols, trust, ent)
+}
+
+pub fn client_retry(
+    payload: &HandshakeData,
+    st: ClientPostClientHello,
+) -> Result<(HandshakeData, ClientPostClientHello), TLSError> {
+    put_hello_retry_request(payload, st)
 }
 
 pub fn client_set_params(
@@ -382,24 +984,46 @@ pub fn client_set_params(
     put_server_hello(payload, st)
 }
 
+/// Process the server's encrypted flight and compute the client's flight:
+/// its Certificate and CertificateVerify if the server asked for them, and
+/// the Finished.
+/// If the server accepted early data, this also returns the EndOfEarlyData
+/// message, which has to be sent under the 0-RTT keys before the Finished.
+#[allow(clippy::type_complexity)]
 pub fn client_finish(
     payload: &HandshakeData,
     st: ClientPostServerHello,
-) -> Result<(HandshakeData, DuplexCipherState1, ClientPostClientFinished), TLSError> {
+) -> Result<
+    (
+        Option<HandshakeData>,
+        HandshakeData,
+        DuplexCipherState1,
+        ClientPostClientFinished,
+    ),
+    TLSError,
+> {
     match psk_mode(&algs_post_server_hello(&st)) {
         false => {
-            let (ee, sc, scv, sfin) = get_handshake_messages4(payload)?;
-            let cstate_cv = put_server_signature(&ee, &sc, &scv, st)?;
+            // The server may request a client certificate after its EncryptedExtensions.
+            let (ee, creq, sc, scv, sfin) =
+                if find_handshake_message(HandshakeType::CertificateRequest, payload, 0) {
+                    let (ee, creq, sc, scv, sfin) = get_handshake_messages5(payload)?;
+                    (ee, Some(creq), sc, scv, sfin)
+                } else {
+                    let (ee, sc, scv, sfin) = get_handshake_messages4(payload)?;
+                    (ee, None, sc, scv, sfin)
+                };
+            let cstate_cv = put_server_signature(&ee, &creq, &sc, &scv, st)?;
             let (cipher, cstate_fin) = put_server_finished(&sfin, cstate_cv)?;
-            let (cfin, cstate) = get_client_finished(cstate_fin)?;
-            Ok((cfin, cipher, cstate))
+            let (eoed, cfin, cstate) = get_client_finished(cstate_fin)?;
+            Ok((eoed, cfin, cipher, cstate))
         }
         true => {
             let (ee, sfin) = get_handshake_messages2(payload)?;
             let cstate_cv = put_psk_skip_server_signature(&ee, st)?;
             let (cipher, cstate_fin) = put_server_finished(&sfin, cstate_cv)?;
-            let (cfin, cstate) = get_client_finished(cstate_fin)?;
-            Ok((cfin, cipher, cstate))
+            let (eoed, cfin, cstate) = get_client_finished(cstate_fin)?;
+            Ok((eoed, cfin, cipher, cstate))
         }
     }
 }
@@ -410,21 +1034,155 @@ fn put_client_hello(
     algs: Algorithms,
     ch: &HandshakeData,
     db: ServerDB,
+    tx: Transcript,
 ) -> Result<(Option<ServerCipherState0>, ServerPostClientHello), TLSError> {
-    let (cr, sid, sni, gx, tkto, bindero, trunc_len) = parse_client_hello(&algs, ch)?;
+    let algs = certificate_algorithms(algs, &db)?;
+    let (cr, sid, sni, gxo, tkto, bindero, trunc_len, edo, alpn) =
+        in_context(parse_client_hello(&algs, ch), "ClientHello")?;
     //println!("parse_client_hello");
-    let tx = transcript_empty(hash_alg(&algs));
+    let gx = match gxo {
+        Some(gx) => gx,
+        None => tlserr(MISSING_KEY_SHARE)?,
+    };
     let th_trunc = get_transcript_hash_truncated_client_hello(&tx, ch, trunc_len)?;
     let tx = transcript_add1(tx, ch);
     let th = get_transcript_hash(&tx)?;
     let (cert, sigk, psko) = lookup_db(algs, &db, &sni, &tkto)?;
-    let cipher0 = process_psk_binder_zero_rtt(algs, th, th_trunc, &psko, bindero)?;
+    // Client certificates are not requested in PSK mode.
+    let ServerDB(_sn, _cert, _sk, _psk, client_auth, protocols, _padding) = db;
+    let client_auth = if psk_mode(&algs) { None } else { client_auth };
+    let protocol = select_protocol(&protocols, &alpn)?;
+    // Early data is only accepted if both sides want it.
+    let algs = early_data_algorithms(algs, zero_rtt(&algs) && edo);
+    let cipher0 = process_psk_binder_zero_rtt(algs, th_trunc, th, &psko, bindero)?;
     Ok((
         cipher0,
-        ServerPostClientHello(cr, algs, sid, gx, cert, sigk, psko, tx),
+        ServerPostClientHello(
+            cr,
+            algs,
+            sid,
+            gx,
+            cert,
+            sigk,
+            psko,
+            client_auth,
+            protocol,
+            tx,
+        ),
     ))
 }
 
+/// Pick the first of our application protocols that the client offers.
+/// Without a protocol in common, the handshake fails.
+fn select_protocol(
+    protocols: &[Bytes],
+    offered: &Option<Vec<Bytes>>,
+) -> Result<Option<Bytes>, TLSError> {
+    match offered {
+        Some(offered) if !protocols.is_empty() => {
+            match protocols.iter().find(|p| offered.iter().any(|o| eq(o, p))) {
+                Some(p) => Ok(Some(p.clone())),
+                None => Err(NO_APPLICATION_PROTOCOL),
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+/// The server signs with the scheme that fits the key in its certificate,
+/// whatever signature scheme it was configured with.
+fn certificate_algorithms(algs: Algorithms, db: &ServerDB) -> Result<Algorithms, TLSError> {
+    if psk_mode(&algs) {
+        Ok(algs)
+    } else {
+        let ServerDB(_sn, chain, _sk, _psk, _client_auth, _protocols, _padding) = db;
+        let cert = match chain.first() {
+            Some(cert) => cert,
+            None => Err(parse_failed())?,
+        };
+        let (key, _pk) = verification_key_from_cert(cert)?;
+        // Keep our signature scheme if the certificate key can be used with it.
+        let Algorithms(ha, ae, sa, ks, psk_mode, zero_rtt) = algs;
+        let sa = if signature_scheme_matches_key(&sa, &key) {
+            sa
+        } else {
+            key
+        };
+        Ok(Algorithms(ha, ae, sa, ks, psk_mode, zero_rtt))
+    }
+}
+
+/// The candidates for signing with the key in the server's certificate: `algs`
+/// with the scheme `certificate_algorithms` picks, followed by `algs` with
+/// each other scheme we support for that key, e.g. the other RSA-PSS schemes
+/// for an RSA key.
+fn certificate_candidates(algs: Algorithms, db: &ServerDB) -> Result<Vec<Algorithms>, TLSError> {
+    let algs = certificate_algorithms(algs, db)?;
+    let mut candidates = vec![algs];
+    if !psk_mode(&algs) {
+        let Algorithms(ha, ae, sa, ks, psk_mode, zero_rtt) = algs;
+        for s in verification_schemes() {
+            if s != sa && signature_scheme_matches_key(&s, &sa) {
+                candidates.push(Algorithms(ha, ae, s, ks, psk_mode, zero_rtt));
+            }
+        }
+    }
+    Ok(candidates)
+}
+
+/// The first of `candidates` that the ClientHello supports.
+/// If none fits, the error is the one for the last candidate.
+fn select_candidate(candidates: &[Algorithms], ch: &HandshakeData) -> Result<Algorithms, TLSError> {
+    let mut res = Err(NEGOTIATION_MISMATCH);
+    for c in candidates {
+        if res.is_err() {
+            res = match parse_client_hello(c, ch) {
+                Ok(_) => Ok(*c),
+                Err(e) => Err(e),
+            };
+        }
+    }
+    res
+}
+
+/// Pick the first of the server's algorithms that the ClientHello supports,
+/// with the first signature scheme for our certificate key that is also in
+/// the client's signature_algorithms.
+/// If none fits, the error is the one for the last candidate.
+fn select_algorithms(
+    algs: &[Algorithms],
+    ch: &HandshakeData,
+    db: &ServerDB,
+) -> Result<Algorithms, TLSError> {
+    let mut res = Err(NEGOTIATION_MISMATCH);
+    for a in algs {
+        if res.is_err() {
+            res = match certificate_candidates(*a, db) {
+                Ok(candidates) => select_candidate(&candidates, ch),
+                Err(e) => Err(e),
+            };
+        }
+    }
+    res
+}
+
+fn get_hello_retry_request(
+    algs: Algorithms,
+    ch: &HandshakeData,
+    db: ServerDB,
+) -> Result<(HandshakeData, ServerPostHelloRetryRequest), TLSError> {
+    let (_cr, sid, _sni, _gx, _tkto, _bindero, _trunc_len, _edo, _alpn) =
+        in_context(parse_client_hello(&algs, ch), "ClientHello")?;
+    let tx = transcript_add1(transcript_empty(hash_alg(&algs)), ch);
+    // The cookie is the hash of the first ClientHello, which the client has
+    // to send back in its second one.
+    let ck = get_transcript_hash(&tx)?;
+    let tx = transcript_hello_retry(tx)?;
+    let hrr = hello_retry_request(&algs, &sid, &Some(ck.clone()))?;
+    let tx = transcript_add1(tx, &hrr);
+    Ok((hrr, ServerPostHelloRetryRequest(algs, db, ck, tx)))
+}
+
 fn process_psk_binder_zero_rtt(
     algs: Algorithms,
     th_trunc: Digest,
@@ -436,7 +1194,7 @@ fn process_psk_binder_zero_rtt(
     match (psk_mode, psko, bindero) {
         (true, Some(k), Some(binder)) => {
             let mk = derive_binder_key(&ha, k)?;
-            hmac_verify(&ha, &mk, &th_trunc, &binder)?;
+            in_context(hmac_verify(&ha, &mk, &th_trunc, &binder), "PSK binder")?;
             if zero_rtt {
                 let (aek, key) = derive_0rtt_keys(&ha, &ae, k, &th)?;
                 let cipher0 = Some(server_cipher_state0(ae, aek, 0, key));
@@ -454,7 +1212,7 @@ fn get_server_hello(
     st: ServerPostClientHello,
     ent: Entropy,
 ) -> Result<(HandshakeData, DuplexCipherStateH, ServerPostServerHello), TLSError> {
-    let ServerPostClientHello(cr, algs, sid, gx, cert, sigk, psk, tx) = st;
+    let ServerPostClientHello(cr, algs, sid, gx, cert, sigk, psk, client_auth, protocol, tx) = st;
     let Algorithms(ha, ae, _sa, ks, _psk_mode, _zero_rtt) = algs;
     if ent.len() < 32 + kem_priv_len(&ks) {
         Err(INSUFFICIENT_ENTROPY)
@@ -468,39 +1226,62 @@ fn get_server_hello(
         Ok((
             sh,
             duplex_cipher_state_hs(ae, shk, 0, chk, 0),
-            ServerPostServerHello(cr, sr, algs, cert, sigk, ms, cfk, sfk, tx),
+            ServerPostServerHello(
+                cr,
+                sr,
+                algs,
+                cert,
+                sigk,
+                ms,
+                cfk,
+                sfk,
+                client_auth,
+                protocol,
+                tx,
+            ),
         ))
     }
 }
 
+#[allow(clippy::type_complexity)]
 fn get_server_signature(
     st: ServerPostServerHello,
     ent: Entropy,
 ) -> Result<
     (
         HandshakeData,
+        Option<HandshakeData>,
         HandshakeData,
         HandshakeData,
         ServerPostCertificateVerify,
     ),
     TLSError,
 > {
-    let ServerPostServerHello(cr, sr, algs, cert, sigk, ms, cfk, sfk, tx) = st;
-    let ee = encrypted_extensions(&algs)?;
+    let ServerPostServerHello(cr, sr, algs, cert, sigk, ms, cfk, sfk, client_auth, protocol, tx) =
+        st;
+    let ee = encrypted_extensions(&algs, &protocol)?;
     let tx = transcript_add1(tx, &ee);
     if !psk_mode(&algs) {
+        let (creq, tx) = if client_auth.is_some() {
+            let creq = certificate_request(&algs, &verification_schemes())?;
+            let tx = transcript_add1(tx, &creq);
+            (Some(creq), tx)
+        } else {
+            (None, tx)
+        };
         let sc = server_certificate(&algs, &cert)?;
         let tx = transcript_add1(tx, &sc);
         let th = get_transcript_hash(&tx)?;
         let sigval = Bytes::from_slice(&PREFIX_SERVER_SIGNATURE).concat(&th);
-        let sig = sign(&sig_alg(&algs), &sigk, &sigval, ent)?;
+        let sig = sign_with_certificate(&sig_alg(&algs), &cert[0], &sigk, &sigval, ent)?;
         let scv = certificate_verify(&algs, &sig)?;
         let tx = transcript_add1(tx, &scv);
         Ok((
             ee,
+            creq,
             sc,
             scv,
-            ServerPostCertificateVerify(cr, sr, algs, ms, cfk, sfk, tx),
+            ServerPostCertificateVerify(cr, sr, algs, ms, cfk, sfk, client_auth, protocol, tx),
         ))
     } else {
         Err(PSK_MODE_MISMATCH)
@@ -510,13 +1291,14 @@ fn get_server_signature(
 fn get_skip_server_signature(
     st: ServerPostServerHello,
 ) -> Result<(HandshakeData, ServerPostCertificateVerify), TLSError> {
-    let ServerPostServerHello(cr, sr, algs, _cert, _sigk, ms, cfk, sfk, tx) = st;
+    let ServerPostServerHello(cr, sr, algs, _cert, _sigk, ms, cfk, sfk, client_auth, protocol, tx) =
+        st;
     if psk_mode(&algs) {
-        let ee = encrypted_extensions(&algs)?;
+        let ee = encrypted_extensions(&algs, &protocol)?;
         let tx = transcript_add1(tx, &ee);
         Ok((
             ee,
-            ServerPostCertificateVerify(cr, sr, algs, ms, cfk, sfk, tx),
+            ServerPostCertificateVerify(cr, sr, algs, ms, cfk, sfk, client_auth, protocol, tx),
         ))
     } else {
         Err(PSK_MODE_MISMATCH)
@@ -526,45 +1308,123 @@ fn get_skip_server_signature(
 fn get_server_finished(
     st: ServerPostCertificateVerify,
 ) -> Result<(HandshakeData, DuplexCipherState1, ServerPostServerFinished), TLSError> {
-    let ServerPostCertificateVerify(cr, sr, algs, ms, cfk, sfk, tx) = st;
+    let ServerPostCertificateVerify(cr, sr, algs, ms, cfk, sfk, client_auth, protocol, tx) = st;
     let Algorithms(ha, ae, _sa, _gn, _psk_mode, _zero_rtt) = algs;
     let th_scv = get_transcript_hash(&tx)?;
     let vd = hmac_tag(&ha, &sfk, &th_scv)?;
     let sfin = finished(&algs, &vd)?;
     let tx = transcript_add1(tx, &sfin);
     let th_sfin = get_transcript_hash(&tx)?;
-    let (cak, sak, exp) = derive_app_keys(&ha, &ae, &ms, &th_sfin)?;
-    let cipher1 = duplex_cipher_state1(ae, sak, 0, cak, 0, exp);
+    let (cak, sak, cats, sats, exp) = derive_app_keys(&ha, &ae, &ms, &th_sfin)?;
+    let cipher1 = duplex_cipher_state1(ae, sak, 0, cak, 0, exp, sats, cats);
     Ok((
         sfin,
         cipher1,
-        ServerPostServerFinished(cr, sr, algs, ms, cfk, tx),
+        ServerPostServerFinished(cr, sr, algs, ms, cfk, client_auth, protocol, tx),
     ))
 }
 
+/// Process the client's Certificate and CertificateVerify at the start of its
+/// flight. The certificate chain must not be empty, has to lead to one of the
+/// `trust_anchors`, be valid at `now` and allow client authentication, and
+/// the CertificateVerify has to be signed with its key.
+/// Returns the client's end-entity certificate, the rest of the flight and
+/// the updated transcript.
+fn put_client_signature(
+    algs: &Algorithms,
+    cflight: &HandshakeData,
+    trust_anchors: &[Bytes],
+    now: u64,
+    tx: Transcript,
+) -> Result<(Bytes, HandshakeData, Transcript), TLSError> {
+    let (cc, rest) = get_first_handshake_message(cflight)?;
+    let chain = in_context(parse_client_certificate(algs, &cc), "Certificate")?;
+    match chain.first() {
+        None => Err(MISSING_CLIENT_CERTIFICATE),
+        Some(cert) => {
+            in_context(
+                verify_certificate_chain(&chain, trust_anchors, now, &client_auth_oid()),
+                "client certificate chain",
+            )?;
+            let cert = cert.clone();
+            let tx = transcript_add1(tx, &cc);
+            let th_cc = get_transcript_hash(&tx)?;
+            let (ccv, cfin) = get_first_handshake_message(&rest)?;
+            let spki = verification_key_from_cert(&cert)?;
+            let pk = cert_public_key(&cert, &spki)?;
+            let (sa, sig) = in_context(parse_certificate_verify(algs, &ccv), "CertificateVerify")?;
+            // The client has to sign with one of our schemes, using its certificate key.
+            if !verification_schemes().contains(&sa) {
+                Err(NEGOTIATION_MISMATCH)
+            } else if !signature_scheme_matches_key(&sa, &spki.0) {
+                Err(INVALID_SIGNATURE)
+            } else {
+                let sigval = Bytes::from_slice(&PREFIX_CLIENT_SIGNATURE).concat(&th_cc);
+                in_context(
+                    verify(&sa, &pk, &sigval, &sig),
+                    "CertificateVerify signature",
+                )?;
+                let tx = transcript_add1(tx, &ccv);
+                Ok((cert, cfin, tx))
+            }
+        }
+    }
+}
+
 fn put_client_finished(
-    cfin: &HandshakeData,
+    cflight: &HandshakeData,
     st: ServerPostServerFinished,
 ) -> Result<ServerPostClientFinished, TLSError> {
-    let ServerPostServerFinished(cr, sr, algs, ms, cfk, tx) = st;
+    let ServerPostServerFinished(cr, sr, algs, ms, cfk, client_auth, protocol, tx) = st;
+    let (ccert, cfin, tx) = match client_auth {
+        Some((trust_anchors, now)) => {
+            let (cert, cfin, tx) = put_client_signature(&algs, cflight, &trust_anchors, now, tx)?;
+            (Some(cert), cfin, tx)
+        }
+        None => (None, handshake_data(handshake_data_bytes(cflight)), tx),
+    };
     let th = get_transcript_hash(&tx)?;
-    let vd = parse_finished(&algs, cfin)?;
-    hmac_verify(&hash_alg(&algs), &cfk, &th, &vd)?;
-    let tx = transcript_add1(tx, cfin);
+    let vd = in_context(parse_finished(&algs, &cfin), "Finished")?;
+    in_context(
+        hmac_verify(&hash_alg(&algs), &cfk, &th, &vd),
+        "Finished MAC",
+    )?;
+    let tx = transcript_add1(tx, &cfin);
     let th = get_transcript_hash(&tx)?;
     let rms = derive_rms(&hash_alg(&algs), &ms, &th)?;
-    Ok(ServerPostClientFinished(cr, sr, algs, rms, tx))
+    Ok(ServerPostClientFinished(
+        cr, sr, algs, rms, ccert, protocol, tx,
+    ))
 }
 
-// Server-Side Handshake API: Usable by Quic and TLS
-// server_init -> (decrypt_zerortt)* | (encrypt_handshake | decrypt_handshake)* ->
-// server_finish -> (encrypt_data | decrypt_data)*
+fn put_end_of_early_data(
+    eoed: &HandshakeData,
+    st: ServerPostServerFinished,
+) -> Result<ServerPostServerFinished, TLSError> {
+    let ServerPostServerFinished(cr, sr, algs, ms, cfk, client_auth, protocol, tx) = st;
+    if zero_rtt(&algs) {
+        in_context(parse_end_of_early_data(&algs, eoed), "EndOfEarlyData")?;
+        let tx = transcript_add1(tx, eoed);
+        Ok(ServerPostServerFinished(
+            cr,
+            sr,
+            algs,
+            ms,
+            cfk,
+            client_auth,
+            protocol,
+            tx,
+        ))
+    } else {
+        Err(ZERO_RTT_DISABLED)
+    }
+}
 
 #[allow(clippy::type_complexity)]
-pub fn server_init(
+fn get_server_flight(
     algs: Algorithms,
-    ch: &HandshakeData,
-    db: ServerDB,
+    cipher0: Option<ServerCipherState0>,
+    st: ServerPostClientHello,
     ent: Entropy,
 ) -> Result<
     (
@@ -577,16 +1437,20 @@ pub fn server_init(
     ),
     TLSError,
 > {
-    let (cipher0, st) = put_client_hello(algs, ch, db)?;
-    //println!("put_client_hello");
-    let (sh, cipher_hs, st) =
-        get_server_hello(st, ent.slice(0, 32 + kem_priv_len(&kem_alg(&algs))))?;
+    // The entropy after the ServerHello's is used for the signature.
+    let hello_len = std::cmp::min(32 + kem_priv_len(&kem_alg(&algs)), ent.len());
+    let (sh, cipher_hs, st) = get_server_hello(st, ent.slice(0, hello_len))?;
     //println!("get_server_hello");
     match psk_mode(&algs) {
         false => {
-            let (ee, sc, scv, st) = get_server_signature(st, ent.slice(0, 32))?; //FIX: use 32 extra bytes
+            let (ee, creq, sc, scv, st) =
+                get_server_signature(st, ent.slice_range(hello_len..ent.len()))?;
             let (sfin, cipher1, st) = get_server_finished(st)?;
-            let flight = handshake_concat(ee, &handshake_concat(sc, &handshake_concat(scv, &sfin)));
+            let flight = handshake_concat(sc, &handshake_concat(scv, &sfin));
+            let flight = match creq {
+                Some(creq) => handshake_concat(ee, &handshake_concat(creq, &flight)),
+                None => handshake_concat(ee, &flight),
+            };
             Ok((sh, flight, cipher0, cipher_hs, cipher1, st))
         }
         true => {
@@ -598,9 +1462,204 @@ pub fn server_init(
     }
 }
 
+// Server-Side Handshake API: Usable by Quic and TLS
+// server_select -> (server_needs_retry -> server_retry -> server_init_retry) | server_init ->
+// (decrypt_zerortt)* | (encrypt_handshake | decrypt_handshake)* ->
+// server_finish -> (encrypt_data | decrypt_data)*
+
+pub fn server_select(
+    algs: &[Algorithms],
+    ch: &HandshakeData,
+    db: &ServerDB,
+) -> Result<Algorithms, TLSError> {
+    select_algorithms(algs, ch, db)
+}
+
+/// Check whether the ClientHello lacks a key share for our group, in which
+/// case the server has to answer with a HelloRetryRequest.
+pub fn server_needs_retry(algs: &Algorithms, ch: &HandshakeData) -> Result<bool, TLSError> {
+    let (_cr, _sid, _sni, gx, _tkto, _bindero, _trunc_len, _edo, _alpn) =
+        in_context(parse_client_hello(algs, ch), "ClientHello")?;
+    Ok(gx.is_none())
+}
+
+/// Check whether the ClientHello offers early data. If the server does not
+/// accept it, it has to skip it.
+pub fn early_data_offered(algs: &Algorithms, ch: &HandshakeData) -> Result<bool, TLSError> {
+    let (_cr, _sid, _sni, _gx, _tkto, _bindero, _trunc_len, edo, _alpn) =
+        in_context(parse_client_hello(algs, ch), "ClientHello")?;
+    Ok(edo)
+}
+
+pub fn server_retry(
+    algs: Algorithms,
+    ch: &HandshakeData,
+    db: ServerDB,
+) -> Result<(HandshakeData, ServerPostHelloRetryRequest), TLSError> {
+    get_hello_retry_request(algs, ch, db)
+}
+
+#[allow(clippy::type_complexity)]
+pub fn server_init(
+    algs: Algorithms,
+    ch: &HandshakeData,
+    db: ServerDB,
+    ent: Entropy,
+) -> Result<
+    (
+        HandshakeData,
+        HandshakeData,
+        Option<ServerCipherState0>,
+        DuplexCipherStateH,
+        DuplexCipherState1,
+        ServerPostServerFinished,
+    ),
+    TLSError,
+> {
+    let (cipher0, st) = put_client_hello(algs, ch, db, transcript_empty(hash_alg(&algs)))?;
+    //println!("put_client_hello");
+    get_server_flight(algs, cipher0, st, ent)
+}
+
+#[allow(clippy::type_complexity)]
+pub fn server_init_retry(
+    ch: &HandshakeData,
+    st: ServerPostHelloRetryRequest,
+    ent: Entropy,
+) -> Result<
+    (
+        HandshakeData,
+        HandshakeData,
+        Option<ServerCipherState0>,
+        DuplexCipherStateH,
+        DuplexCipherState1,
+        ServerPostServerFinished,
+    ),
+    TLSError,
+> {
+    let ServerPostHelloRetryRequest(algs, db, ck, tx) = st;
+    in_context(check_retried_client_hello(&algs, ch, &ck), "ClientHello")?;
+    // Early data is rejected after a HelloRetryRequest.
+    let Algorithms(ha, ae, sa, ks, psk_mode, _zero_rtt) = algs;
+    let algs = Algorithms(ha, ae, sa, ks, psk_mode, false);
+    let (cipher0, st) = put_client_hello(algs, ch, db, tx)?;
+    get_server_flight(algs, cipher0, st, ent)
+}
+
+/// Process the EndOfEarlyData message that ends accepted early data.
+pub fn server_end_of_early_data(
+    eoed: &HandshakeData,
+    st: ServerPostServerFinished,
+) -> Result<ServerPostServerFinished, TLSError> {
+    put_end_of_early_data(eoed, st)
+}
+
 pub fn server_finish(
     cf: &HandshakeData,
     st: ServerPostServerFinished,
 ) -> Result<ServerPostClientFinished, TLSError> {
     put_client_finished(cf, st)
 }
+
+/* Session Tickets */
+
+/// A session ticket as delivered to the client application: the ticket
+/// identity, its lifetime in seconds, the age_add obfuscation value, the
+/// maximum amount of early data (zero if none is allowed), the ticket nonce,
+/// and the resumption PSK derived from it.
+/// Resume with
+/// `client_connect(algs, sn, Some(ticket), None, &[], None, pad, ent)`,
+/// where `algs` are in PSK mode and use the same hash algorithm.
+#[derive(Clone, Debug)]
+pub struct SessionTicket(pub Bytes, pub u32, pub u32, pub u32, pub Bytes, pub PSK);
+
+/// Longest ticket lifetime allowed by RFC 8446: 7 days.
+pub const MAX_TICKET_LIFETIME: u32 = 604800;
+
+/// Largest amount of early data a ticket may allow. A server that rejects
+/// early data skips at most this much of it.
+pub const MAX_EARLY_DATA_SIZE: u32 = 16384;
+
+/// Issue a NewSessionTicket for the ticket identity `tkt`.
+/// The entropy provides age_add (4 bytes) and the ticket nonce (8 bytes).
+/// A non-zero `max_early_data`, up to `MAX_EARLY_DATA_SIZE`, allows the client
+/// to send early data when it resumes with this ticket.
+/// Returns the message and the resumption PSK that the server has to store
+/// with `tkt`, e.g. in a `ServerDB`.
+pub fn server_ticket(
+    tkt: &Bytes,
+    lifetime: u32,
+    max_early_data: u32,
+    st: &ServerPostClientFinished,
+    ent: Entropy,
+) -> Result<(HandshakeData, PSK), TLSError> {
+    let ServerPostClientFinished(_cr, _sr, algs, rms, _ccert, _protocol, _tx) = st;
+    if ent.len() < 12 {
+        Err(INSUFFICIENT_ENTROPY)
+    } else if lifetime > MAX_TICKET_LIFETIME
+        || max_early_data > MAX_EARLY_DATA_SIZE
+        || tkt.is_empty()
+    {
+        Err(UNSUPPORTED)
+    } else {
+        let age_add = U32::from_be_bytes(&ent.slice_range(0..4))?;
+        let nonce = ent.slice_range(4..12);
+        let psk = derive_resumption_psk(&hash_alg(algs), rms, &nonce)?;
+        let nst = session_ticket(
+            algs,
+            U32::from(lifetime),
+            age_add,
+            U32::from(max_early_data),
+            &nonce,
+            tkt,
+        )?;
+        Ok((nst, psk))
+    }
+}
+
+/// Read a NewSessionTicket and derive the resumption PSK for it.
+pub fn client_ticket(
+    nst: &HandshakeData,
+    st: &ClientPostClientFinished,
+) -> Result<SessionTicket, TLSError> {
+    let ClientPostClientFinished(_cr, _sr, algs, rms, _protocol, _chain, _tx) = st;
+    let (lifetime, age_add, max_early_data, nonce, tkt) =
+        in_context(parse_session_ticket(algs, nst), "NewSessionTicket")?;
+    let psk = derive_resumption_psk(&hash_alg(algs), rms, &nonce)?;
+    Ok(SessionTicket(
+        tkt,
+        lifetime.declassify(),
+        age_add.declassify(),
+        max_early_data.declassify(),
+        nonce,
+        psk,
+    ))
+}
+
+/* Key Update */
+
+/// Move to the next sending keys, after sending a KeyUpdate.
+pub fn update_sender_keys(
+    algs: &Algorithms,
+    st: DuplexCipherState1,
+) -> Result<DuplexCipherState1, TLSError> {
+    let (kiv, ts) = derive_next_app_keys(
+        &hash_alg(algs),
+        &aead_alg(algs),
+        &sender_traffic_secret(&st),
+    )?;
+    Ok(set_sender_keys(kiv, ts, st))
+}
+
+/// Move to the next receiving keys, after receiving a KeyUpdate.
+pub fn update_receiver_keys(
+    algs: &Algorithms,
+    st: DuplexCipherState1,
+) -> Result<DuplexCipherState1, TLSError> {
+    let (kiv, ts) = derive_next_app_keys(
+        &hash_alg(algs),
+        &aead_alg(algs),
+        &receiver_traffic_secret(&st),
+    )?;
+    Ok(set_receiver_keys(kiv, ts, st))
+}
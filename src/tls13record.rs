@@ -36,7 +36,11 @@ pub fn duplex_cipher_state_hs(
     DuplexCipherStateH(ae, kiv1, c1, kiv2, c2)
 }
 
-pub struct DuplexCipherState1(AeadAlgorithm, AeadKeyIV, u64, AeadKeyIV, u64, Key);
+/// The application data cipher state: the sender key and counter, the
+/// receiver key and counter, the exporter master secret, and the sender and
+/// receiver traffic secrets from which the next keys are derived on KeyUpdate.
+pub struct DuplexCipherState1(AeadAlgorithm, AeadKeyIV, u64, AeadKeyIV, u64, Key, Key, Key);
+#[allow(clippy::too_many_arguments)]
 pub fn duplex_cipher_state1(
     ae: AeadAlgorithm,
     kiv1: AeadKeyIV,
@@ -44,8 +48,97 @@ pub fn duplex_cipher_state1(
     kiv2: AeadKeyIV,
     c2: u64,
     k: Key,
+    ts1: Key,
+    ts2: Key,
 ) -> DuplexCipherState1 {
-    DuplexCipherState1(ae, kiv1, c1, kiv2, c2, k)
+    DuplexCipherState1(ae, kiv1, c1, kiv2, c2, k, ts1, ts2)
+}
+
+pub fn sender_traffic_secret(st: &DuplexCipherState1) -> Key {
+    st.6.clone()
+}
+
+pub fn receiver_traffic_secret(st: &DuplexCipherState1) -> Key {
+    st.7.clone()
+}
+
+pub fn exporter_master_secret(st: &DuplexCipherState1) -> Key {
+    st.5.clone()
+}
+
+pub fn client_early_exporter_master_secret(st: &ClientCipherState0) -> Key {
+    st.3.clone()
+}
+
+pub fn server_early_exporter_master_secret(st: &ServerCipherState0) -> Key {
+    st.3.clone()
+}
+
+/// Switch to new sender keys, restarting the sequence number.
+pub fn set_sender_keys(kiv: AeadKeyIV, ts: Key, st: DuplexCipherState1) -> DuplexCipherState1 {
+    let DuplexCipherState1(ae, _kiv, _n, x, y, exp, _ts, ts2) = st;
+    DuplexCipherState1(ae, kiv, 0, x, y, exp, ts, ts2)
+}
+
+/// Switch to new receiver keys, restarting the sequence number.
+pub fn set_receiver_keys(kiv: AeadKeyIV, ts: Key, st: DuplexCipherState1) -> DuplexCipherState1 {
+    let DuplexCipherState1(ae, x, y, _kiv, _n, exp, ts1, _ts) = st;
+    DuplexCipherState1(ae, x, y, kiv, 0, exp, ts1, ts)
+}
+
+/// The sending half of a cipher state, used to protect alerts.
+pub struct SenderCipherState(AeadAlgorithm, AeadKeyIV, u64);
+
+pub fn sender_cipher_state_hs(st: &DuplexCipherStateH) -> SenderCipherState {
+    let DuplexCipherStateH(ae, kiv, n, _, _) = st;
+    SenderCipherState(*ae, kiv.clone(), *n)
+}
+
+pub fn sender_cipher_state1(st: &DuplexCipherState1) -> SenderCipherState {
+    let DuplexCipherState1(ae, kiv, n, _, _, _, _, _) = st;
+    SenderCipherState(*ae, kiv.clone(), *n)
+}
+
+/// How much zero padding to add to the records of a connection, to hide the
+/// length of their content.
+#[derive(Clone, Copy)]
+pub enum PaddingPolicy {
+    NoPadding,
+    /// Pad the content of each record to a multiple of the block size.
+    Block(usize),
+    /// Pad the content of each record to a fixed size.
+    Fixed(usize),
+    /// The padding for a record with the given content type and length.
+    Custom(fn(ContentType, usize) -> usize),
+}
+
+/// The padding for a record with `len` bytes of content of type `ct`, within
+/// the maximum record size.
+pub fn padding_len(policy: &PaddingPolicy, ct: ContentType, len: usize) -> usize {
+    let pad = match policy {
+        PaddingPolicy::NoPadding => 0,
+        PaddingPolicy::Block(0) => 0,
+        PaddingPolicy::Block(b) => (b - len % b) % b,
+        PaddingPolicy::Fixed(size) => size.saturating_sub(len),
+        PaddingPolicy::Custom(f) => f(ct, len),
+    };
+    std::cmp::min(pad, MAX_FRAGMENT_LEN.saturating_sub(len))
+}
+
+/// The longest fragment of content of type `ct` that still leaves room for the
+/// padding of `policy`, so that every record of a long payload is padded: a
+/// multiple of the block size, the fixed size, or the maximum record size
+/// less the custom padding of a full record.
+fn fragment_len(policy: &PaddingPolicy, ct: ContentType) -> usize {
+    let len = match policy {
+        PaddingPolicy::NoPadding => MAX_FRAGMENT_LEN,
+        PaddingPolicy::Block(b) if *b == 0 || *b > MAX_FRAGMENT_LEN => MAX_FRAGMENT_LEN,
+        PaddingPolicy::Block(b) => MAX_FRAGMENT_LEN - MAX_FRAGMENT_LEN % b,
+        PaddingPolicy::Fixed(size) if *size == 0 || *size > MAX_FRAGMENT_LEN => MAX_FRAGMENT_LEN,
+        PaddingPolicy::Fixed(size) => *size,
+        PaddingPolicy::Custom(f) => MAX_FRAGMENT_LEN.saturating_sub(f(ct, MAX_FRAGMENT_LEN)),
+    };
+    std::cmp::max(len, 1)
 }
 
 pub fn derive_iv_ctr(_ae: &AeadAlgorithm, iv: &AeadIV, n: u64) -> AeadIV {
@@ -74,7 +167,8 @@ pub fn encrypt_record_payload(
         .concat(&bytes1(content_type(ct)))
         .concat(&Bytes::zeroes(pad));
     let clen = inner_plaintext.len() + 16;
-    if clen <= 65536 {
+    // The content type and padding count against the record size.
+    if inner_plaintext.len() <= MAX_FRAGMENT_LEN + 1 {
         let clenb = (clen as u16).to_be_bytes();
         let ad = [23, 3, 3, clenb[0], clenb[1]].into();
         let cip = aead_encrypt(ae, k, &iv_ctr, &inner_plaintext, &ad)?;
@@ -85,13 +179,23 @@ pub fn encrypt_record_payload(
     }
 }
 
-pub fn padlen(b: &Bytes, n: usize) -> usize {
-    if n > 0 && b[n - 1].declassify() == 0 {
-        1 + padlen(b, n - 1)
+/// Content type and padding included, a record holds at most
+/// `MAX_FRAGMENT_LEN + 1` bytes.
+fn check_inner_plaintext_len(plain: &Bytes) -> Result<(), TLSError> {
+    if plain.len() > MAX_FRAGMENT_LEN + 1 {
+        Err(RECORD_OVERFLOW)
     } else {
-        0
+        Ok(())
     }
 }
+
+pub fn padlen(b: &Bytes, n: usize) -> usize {
+    let mut len = 0;
+    while len < n && b[n - 1 - len].declassify() == 0 {
+        len += 1;
+    }
+    len
+}
 pub fn decrypt_record_payload(
     ae: &AeadAlgorithm,
     kiv: &AeadKeyIV,
@@ -101,16 +205,28 @@ pub fn decrypt_record_payload(
     let (k, iv) = kiv;
     let iv_ctr = derive_iv_ctr(ae, iv, n);
     let clen = ciphertext.len() - 5;
-    if clen <= 65536 && clen > 16 {
+    if clen > MAX_FRAGMENT_LEN + 256 {
+        Err(RECORD_OVERFLOW)
+    } else if clen > 16 {
         let clenb = (clen as u16).to_be_bytes();
         let ad = [23, 3, 3, clenb[0], clenb[1]].into();
         check_eq(&ad, &ciphertext.slice_range(0..5))?;
         let cip = ciphertext.slice_range(5..ciphertext.len());
-        let plain = aead_decrypt(ae, k, &iv_ctr, &cip, &ad)?;
-        let payload_len = plain.len() - padlen(&plain, plain.len()) - 1;
-        let ct = get_content_type(plain[payload_len].declassify())?;
-        let payload = plain.slice_range(0..payload_len);
-        Ok((ct, payload))
+        let plain = match aead_decrypt(ae, k, &iv_ctr, &cip, &ad) {
+            Ok(plain) => plain,
+            Err(_) => Err(BAD_RECORD_MAC)?,
+        };
+        check_inner_plaintext_len(&plain)?;
+        let pad = padlen(&plain, plain.len());
+        if pad == plain.len() {
+            // Padding only, without a content type.
+            Err(MISSING_CONTENT_TYPE)
+        } else {
+            let payload_len = plain.len() - pad - 1;
+            let ct = get_content_type(plain[payload_len].declassify())?;
+            let payload = plain.slice_range(0..payload_len);
+            Ok((ct, payload))
+        }
     } else {
         Err(PAYLOAD_TOO_LONG)
     }
@@ -120,13 +236,13 @@ pub fn decrypt_record_payload(
 
 pub fn encrypt_zerortt(
     payload: AppData,
-    pad: usize,
+    pad: &PaddingPolicy,
     st: ClientCipherState0,
 ) -> Result<(Bytes, ClientCipherState0), TLSError> {
     let ClientCipherState0(ae, kiv, n, exp) = st;
     let payload = app_data_bytes(payload);
-    let rec = encrypt_record_payload(&ae, &kiv, n, ContentType::ApplicationData, &payload, pad)?;
-    Ok((rec, ClientCipherState0(ae, kiv, n + 1, exp)))
+    let (recs, n) = encrypt_fragments(&ae, &kiv, n, ContentType::ApplicationData, &payload, pad)?;
+    Ok((recs, ClientCipherState0(ae, kiv, n, exp)))
 }
 
 pub fn decrypt_zerortt(
@@ -139,15 +255,79 @@ pub fn decrypt_zerortt(
     Ok((app_data(payload), ServerCipherState0(ae, kiv, n + 1, exp)))
 }
 
+/// Encrypt the EndOfEarlyData message under the 0-RTT keys.
+pub fn encrypt_zerortt_handshake(
+    payload: HandshakeData,
+    pad: &PaddingPolicy,
+    st: ClientCipherState0,
+) -> Result<(Bytes, ClientCipherState0), TLSError> {
+    let ClientCipherState0(ae, kiv, n, exp) = st;
+    let payload = handshake_data_bytes(&payload);
+    let padding = padding_len(pad, ContentType::Handshake, payload.len());
+    let rec = encrypt_record_payload(&ae, &kiv, n, ContentType::Handshake, &payload, padding)?;
+    Ok((rec, ClientCipherState0(ae, kiv, n + 1, exp)))
+}
+
+pub fn decrypt_zerortt_or_hs(
+    ciphertext: &Bytes,
+    st: ServerCipherState0,
+) -> Result<(ContentType, Bytes, ServerCipherState0), TLSError> {
+    let ServerCipherState0(ae, kiv, n, exp) = st;
+    let (ct, payload) = decrypt_record_payload(&ae, &kiv, n, ciphertext)?;
+    Ok((ct, payload, ServerCipherState0(ae, kiv, n + 1, exp)))
+}
+
+/// Encrypt an alert under the sending keys of `st`, padded according to `pad`.
+pub fn encrypt_alert(
+    payload: &Bytes,
+    pad: &PaddingPolicy,
+    st: &SenderCipherState,
+) -> Result<Bytes, TLSError> {
+    let SenderCipherState(ae, kiv, n) = st;
+    let padding = padding_len(pad, ContentType::Alert, payload.len());
+    encrypt_record_payload(ae, kiv, *n, ContentType::Alert, payload, padding)
+}
+
+/// Encrypt `payload` in records of at most `MAX_FRAGMENT_LEN` bytes, starting
+/// at sequence number `n`, each padded according to `pad`, see
+/// [fragment_len].
+/// Returns the records and the next sequence number.
+fn encrypt_fragments(
+    ae: &AeadAlgorithm,
+    kiv: &AeadKeyIV,
+    n: u64,
+    ct: ContentType,
+    payload: &Bytes,
+    pad: &PaddingPolicy,
+) -> Result<(Bytes, u64), TLSError> {
+    let len = fragment_len(pad, ct);
+    let mut recs = Bytes::new();
+    let mut n = n;
+    let mut start = 0;
+    while payload.len() - start > len {
+        let fragment = payload.slice(start, len);
+        let padding = padding_len(pad, ct, len);
+        let rec = encrypt_record_payload(ae, kiv, n, ct, &fragment, padding)?;
+        recs = recs.concat(&rec);
+        n += 1;
+        start += len;
+    }
+    let fragment = payload.slice_range(start..payload.len());
+    let padding = padding_len(pad, ct, fragment.len());
+    let rec = encrypt_record_payload(ae, kiv, n, ct, &fragment, padding)?;
+    Ok((recs.concat(&rec), n + 1))
+}
+
+/// Encrypt a handshake flight, fragmented into as many records as needed.
 pub fn encrypt_handshake(
     payload: HandshakeData,
-    pad: usize,
+    pad: &PaddingPolicy,
     st: DuplexCipherStateH,
 ) -> Result<(Bytes, DuplexCipherStateH), TLSError> {
     let DuplexCipherStateH(ae, kiv, n, x, y) = st;
     let payload = handshake_data_bytes(&payload);
-    let rec = encrypt_record_payload(&ae, &kiv, n, ContentType::Handshake, &payload, pad)?;
-    Ok((rec, DuplexCipherStateH(ae, kiv, n + 1, x, y)))
+    let (recs, n) = encrypt_fragments(&ae, &kiv, n, ContentType::Handshake, &payload, pad)?;
+    Ok((recs, DuplexCipherStateH(ae, kiv, n, x, y)))
 }
 
 pub fn decrypt_handshake(
@@ -157,7 +337,8 @@ pub fn decrypt_handshake(
     let DuplexCipherStateH(ae, x, y, kiv, n) = st;
     let (ct, payload) = decrypt_record_payload(&ae, &kiv, n, ciphertext)?;
     if ct == ContentType::Alert {
-        Result::<(HandshakeData, DuplexCipherStateH), TLSError>::Err(GOT_HANDSHAKE_FAILURE_ALERT)
+        let (_, ad) = parse_alert(&payload)?;
+        Result::<(HandshakeData, DuplexCipherStateH), TLSError>::Err(got_alert(ad))
     } else {
         check(ct == ContentType::Handshake)?;
         //println!("dec payload hs");
@@ -168,34 +349,69 @@ pub fn decrypt_handshake(
     }
 }
 
+/// Whether the record `ciphertext` decrypts under the receiving handshake keys
+/// of `st`. A server that rejected early data skips the records that do not.
+pub fn decrypts_as_handshake(ciphertext: &Bytes, st: &DuplexCipherStateH) -> bool {
+    let DuplexCipherStateH(ae, _x, _y, kiv, n) = st;
+    decrypt_record_payload(ae, kiv, *n, ciphertext).is_ok()
+}
+
+/// The amount of early data in the encrypted record `rec`, without its
+/// header, content type and authentication tag.
+pub fn early_data_len(rec: &Bytes) -> u32 {
+    if rec.len() > 5 + 1 + 16 {
+        (rec.len() - 5 - 1 - 16) as u32
+    } else {
+        0
+    }
+}
+
+/// Encrypt application data, fragmented into as many records as needed.
 pub fn encrypt_data(
     payload: AppData,
-    pad: usize,
+    pad: &PaddingPolicy,
     st: DuplexCipherState1,
 ) -> Result<(Bytes, DuplexCipherState1), TLSError> {
-    let DuplexCipherState1(ae, kiv, n, x, y, exp) = st;
+    let DuplexCipherState1(ae, kiv, n, x, y, exp, ts1, ts2) = st;
     let payload = app_data_bytes(payload);
-    let rec = encrypt_record_payload(&ae, &kiv, n, ContentType::ApplicationData, &payload, pad)?;
-    Ok((rec, DuplexCipherState1(ae, kiv, n + 1, x, y, exp)))
+    let (recs, n) = encrypt_fragments(&ae, &kiv, n, ContentType::ApplicationData, &payload, pad)?;
+    Ok((recs, DuplexCipherState1(ae, kiv, n, x, y, exp, ts1, ts2)))
+}
+
+/// Encrypt a post-handshake message, e.g. a NewSessionTicket, under the
+/// application traffic keys.
+pub fn encrypt_post_handshake(
+    payload: HandshakeData,
+    pad: &PaddingPolicy,
+    st: DuplexCipherState1,
+) -> Result<(Bytes, DuplexCipherState1), TLSError> {
+    let DuplexCipherState1(ae, kiv, n, x, y, exp, ts1, ts2) = st;
+    let payload = handshake_data_bytes(&payload);
+    let (recs, n) = encrypt_fragments(&ae, &kiv, n, ContentType::Handshake, &payload, pad)?;
+    Ok((recs, DuplexCipherState1(ae, kiv, n, x, y, exp, ts1, ts2)))
 }
 
 pub fn decrypt_data_or_hs(
     ciphertext: &Bytes,
     st: DuplexCipherState1,
 ) -> Result<(ContentType, Bytes, DuplexCipherState1), TLSError> {
-    let DuplexCipherState1(ae, x, y, kiv, n, exp) = st;
+    let DuplexCipherState1(ae, x, y, kiv, n, exp, ts1, ts2) = st;
     let (ct, payload) = decrypt_record_payload(&ae, &kiv, n, ciphertext)?;
-    Ok((ct, payload, DuplexCipherState1(ae, x, y, kiv, n + 1, exp)))
+    Ok((
+        ct,
+        payload,
+        DuplexCipherState1(ae, x, y, kiv, n + 1, exp, ts1, ts2),
+    ))
 }
 pub fn decrypt_data(
     ciphertext: &Bytes,
     st: DuplexCipherState1,
 ) -> Result<(AppData, DuplexCipherState1), TLSError> {
-    let DuplexCipherState1(ae, x, y, kiv, n, exp) = st;
+    let DuplexCipherState1(ae, x, y, kiv, n, exp, ts1, ts2) = st;
     let (ct, payload) = decrypt_record_payload(&ae, &kiv, n, ciphertext)?;
     check(ct == ContentType::ApplicationData)?;
     Ok((
         app_data(payload),
-        DuplexCipherState1(ae, x, y, kiv, n + 1, exp),
+        DuplexCipherState1(ae, x, y, kiv, n + 1, exp, ts1, ts2),
     ))
 }
@@ -1,5 +1,7 @@
 use core::ops::Range;
 
+use crate::tls13formats::AlertDescription;
+
 // FIXME: NOT HACSPEC | ONLY FOR DEBUGGING
 pub(crate) fn parse_failed() -> TLSError {
     let bt = backtrace::Backtrace::new();
@@ -7,30 +9,224 @@ pub(crate) fn parse_failed() -> TLSError {
     PARSE_FAILED
 }
 
-// Local error codes
-pub type TLSError = u8;
-pub const UNSUPPORTED_ALGORITHM: TLSError = 1u8;
-pub const CRYPTO_ERROR: TLSError = 2u8;
-pub const INSUFFICIENT_ENTROPY: TLSError = 3u8;
-pub const INCORRECT_ARRAY_LENGTH: TLSError = 4u8;
-
-pub const INCORRECT_STATE: TLSError = 128u8;
-pub const ZERO_RTT_DISABLED: TLSError = 129u8;
-pub const PAYLOAD_TOO_LONG: TLSError = 130u8;
-pub const PSK_MODE_MISMATCH: TLSError = 131u8;
-pub const NEGOTIATION_MISMATCH: TLSError = 132u8;
-pub const PARSE_FAILED: TLSError = 133u8;
-pub const INSUFFICIENT_DATA: TLSError = 134u8;
-pub const UNSUPPORTED: TLSError = 135u8;
-pub const INVALID_COMPRESSION_LIST: TLSError = 136u8;
-pub const PROTOCOL_VERSION_ALERT: TLSError = 137u8;
-pub const APPLICATION_DATA_INSTEAD_OF_HANDSHAKE: TLSError = 138u8;
-pub const MISSING_KEY_SHARE: TLSError = 139u8;
-pub const INVALID_SIGNATURE: TLSError = 140u8;
-pub const GOT_HANDSHAKE_FAILURE_ALERT: TLSError = 141u8;
-
-pub fn error_string(c: u8) -> String {
-    format!("{}", c)
+/// The kinds of errors.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorKind {
+    UnsupportedAlgorithm,
+    CryptoError,
+    InsufficientEntropy,
+    IncorrectArrayLength,
+    IncorrectState,
+    ZeroRttDisabled,
+    PayloadTooLong,
+    PskModeMismatch,
+    NegotiationMismatch,
+    ParseFailed,
+    InsufficientData,
+    Unsupported,
+    InvalidCompressionList,
+    ProtocolVersion,
+    ApplicationDataInsteadOfHandshake,
+    MissingKeyShare,
+    InvalidSignature,
+    /// The peer aborted the handshake with this alert.
+    GotAlert(AlertDescription),
+    MissingClientCertificate,
+    BadRecordMac,
+    NoApplicationProtocol,
+    BadCertificate,
+    UnknownCa,
+    CertificateExpired,
+    InvalidKeyShare,
+    RecordOverflow,
+    MissingContentType,
+    IllegalParameter,
+    UnexpectedMessage,
+    Asn1SequenceTooLong,
+    Asn1InvalidTag,
+    Asn1InvalidCertificate,
+    Asn1UnsupportedAlgorithm,
+    Asn1Error,
+}
+
+impl core::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let s = match self {
+            ErrorKind::UnsupportedAlgorithm => "unsupported algorithm",
+            ErrorKind::CryptoError => "cryptographic operation failed",
+            ErrorKind::InsufficientEntropy => "insufficient entropy",
+            ErrorKind::IncorrectArrayLength => "incorrect array length",
+            ErrorKind::IncorrectState => "operation not allowed in this state",
+            ErrorKind::ZeroRttDisabled => "0-RTT is disabled",
+            ErrorKind::PayloadTooLong => "payload too long",
+            ErrorKind::PskModeMismatch => "PSK mode mismatch",
+            ErrorKind::NegotiationMismatch => "negotiation mismatch",
+            ErrorKind::ParseFailed => "parse failed",
+            ErrorKind::InsufficientData => "insufficient data",
+            ErrorKind::Unsupported => "unsupported",
+            ErrorKind::InvalidCompressionList => "invalid compression list",
+            ErrorKind::ProtocolVersion => "unsupported protocol version",
+            ErrorKind::ApplicationDataInsteadOfHandshake => {
+                "application data instead of a handshake message"
+            }
+            ErrorKind::MissingKeyShare => "missing key share",
+            ErrorKind::InvalidSignature => "invalid signature",
+            ErrorKind::GotAlert(ad) => return write!(f, "the peer sent a {:?} alert", ad),
+            ErrorKind::MissingClientCertificate => "missing client certificate",
+            ErrorKind::BadRecordMac => "bad record MAC",
+            ErrorKind::NoApplicationProtocol => "no common application protocol",
+            ErrorKind::BadCertificate => "bad certificate",
+            ErrorKind::UnknownCa => "unknown certificate authority",
+            ErrorKind::CertificateExpired => "certificate expired or not yet valid",
+            ErrorKind::InvalidKeyShare => "invalid key share",
+            ErrorKind::RecordOverflow => "record overflow",
+            ErrorKind::MissingContentType => "record without content type",
+            ErrorKind::IllegalParameter => "illegal parameter",
+            ErrorKind::UnexpectedMessage => "unexpected message",
+            ErrorKind::Asn1SequenceTooLong => "ASN.1 sequence too long",
+            ErrorKind::Asn1InvalidTag => "invalid ASN.1 tag",
+            ErrorKind::Asn1InvalidCertificate => "invalid certificate encoding",
+            ErrorKind::Asn1UnsupportedAlgorithm => "unsupported certificate algorithm",
+            ErrorKind::Asn1Error => "ASN.1 error",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// The stage of a connection in which an error occurred.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stage {
+    /// Sending or receiving a ClientHello.
+    ClientHello,
+    /// Receiving a ServerHello or HelloRetryRequest.
+    ServerHello,
+    /// Receiving the server's encrypted flight, up to its Finished.
+    ServerFlight,
+    /// Receiving the client's flight, up to its Finished.
+    ClientFlight,
+    /// Sending or receiving early data.
+    EarlyData,
+    /// After the handshake.
+    Connected,
+}
+
+impl core::fmt::Display for Stage {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let s = match self {
+            Stage::ClientHello => "ClientHello",
+            Stage::ServerHello => "ServerHello",
+            Stage::ServerFlight => "server flight",
+            Stage::ClientFlight => "client flight",
+            Stage::EarlyData => "early data",
+            Stage::Connected => "connection",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// An error: its kind, the stage of the connection, and what was being
+/// processed, e.g. an extension or a message. The stage and context are
+/// filled in as the error propagates, when known.
+/// Errors compare equal when they are of the same kind.
+#[derive(Clone, Copy, Debug)]
+pub struct TLSError(pub ErrorKind, pub Option<Stage>, pub Option<&'static str>);
+
+impl TLSError {
+    pub fn kind(&self) -> ErrorKind {
+        self.0
+    }
+
+    pub fn stage(&self) -> Option<Stage> {
+        self.1
+    }
+
+    pub fn context(&self) -> Option<&'static str> {
+        self.2
+    }
+}
+
+impl PartialEq for TLSError {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for TLSError {}
+
+impl core::fmt::Display for TLSError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.0)?;
+        if let Some(context) = self.2 {
+            write!(f, " in {}", context)?;
+        }
+        if let Some(stage) = self.1 {
+            write!(f, " ({})", stage)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for TLSError {}
+
+const fn tls_error(kind: ErrorKind) -> TLSError {
+    TLSError(kind, None, None)
+}
+
+/// Records the stage in which `r` failed, unless it is already known.
+pub fn in_stage<T>(r: Result<T, TLSError>, stage: Stage) -> Result<T, TLSError> {
+    match r {
+        Err(TLSError(kind, None, context)) => Err(TLSError(kind, Some(stage), context)),
+        r => r,
+    }
+}
+
+/// Records what was being processed when `r` failed, unless a more specific
+/// context is already known.
+pub fn in_context<T>(r: Result<T, TLSError>, context: &'static str) -> Result<T, TLSError> {
+    match r {
+        Err(TLSError(kind, stage, None)) => Err(TLSError(kind, stage, Some(context))),
+        r => r,
+    }
+}
+
+pub const UNSUPPORTED_ALGORITHM: TLSError = tls_error(ErrorKind::UnsupportedAlgorithm);
+pub const CRYPTO_ERROR: TLSError = tls_error(ErrorKind::CryptoError);
+pub const INSUFFICIENT_ENTROPY: TLSError = tls_error(ErrorKind::InsufficientEntropy);
+pub const INCORRECT_ARRAY_LENGTH: TLSError = tls_error(ErrorKind::IncorrectArrayLength);
+
+pub const INCORRECT_STATE: TLSError = tls_error(ErrorKind::IncorrectState);
+pub const ZERO_RTT_DISABLED: TLSError = tls_error(ErrorKind::ZeroRttDisabled);
+pub const PAYLOAD_TOO_LONG: TLSError = tls_error(ErrorKind::PayloadTooLong);
+pub const PSK_MODE_MISMATCH: TLSError = tls_error(ErrorKind::PskModeMismatch);
+pub const NEGOTIATION_MISMATCH: TLSError = tls_error(ErrorKind::NegotiationMismatch);
+pub const PARSE_FAILED: TLSError = tls_error(ErrorKind::ParseFailed);
+pub const INSUFFICIENT_DATA: TLSError = tls_error(ErrorKind::InsufficientData);
+pub const UNSUPPORTED: TLSError = tls_error(ErrorKind::Unsupported);
+pub const INVALID_COMPRESSION_LIST: TLSError = tls_error(ErrorKind::InvalidCompressionList);
+pub const PROTOCOL_VERSION_ALERT: TLSError = tls_error(ErrorKind::ProtocolVersion);
+pub const APPLICATION_DATA_INSTEAD_OF_HANDSHAKE: TLSError =
+    tls_error(ErrorKind::ApplicationDataInsteadOfHandshake);
+pub const MISSING_KEY_SHARE: TLSError = tls_error(ErrorKind::MissingKeyShare);
+pub const INVALID_SIGNATURE: TLSError = tls_error(ErrorKind::InvalidSignature);
+pub const MISSING_CLIENT_CERTIFICATE: TLSError = tls_error(ErrorKind::MissingClientCertificate);
+pub const BAD_RECORD_MAC: TLSError = tls_error(ErrorKind::BadRecordMac);
+pub const NO_APPLICATION_PROTOCOL: TLSError = tls_error(ErrorKind::NoApplicationProtocol);
+pub const BAD_CERTIFICATE: TLSError = tls_error(ErrorKind::BadCertificate);
+pub const UNKNOWN_CA: TLSError = tls_error(ErrorKind::UnknownCa);
+pub const CERTIFICATE_EXPIRED: TLSError = tls_error(ErrorKind::CertificateExpired);
+pub const INVALID_KEY_SHARE: TLSError = tls_error(ErrorKind::InvalidKeyShare);
+pub const RECORD_OVERFLOW: TLSError = tls_error(ErrorKind::RecordOverflow);
+pub const MISSING_CONTENT_TYPE: TLSError = tls_error(ErrorKind::MissingContentType);
+pub const ILLEGAL_PARAMETER: TLSError = tls_error(ErrorKind::IllegalParameter);
+pub const UNEXPECTED_MESSAGE: TLSError = tls_error(ErrorKind::UnexpectedMessage);
+
+/// The error for an alert `ad` received from the peer.
+pub const fn got_alert(ad: AlertDescription) -> TLSError {
+    tls_error(ErrorKind::GotAlert(ad))
+}
+
+pub fn error_string(e: TLSError) -> String {
+    format!("{}", e)
 }
 
 pub fn tlserr<T>(err: TLSError) -> Result<T, TLSError> {
@@ -10,6 +10,20 @@ fn load_hex(s: &str) -> Bytes {
     Bytes::from_hex(&s_no_ws)
 }
 
+// Split off the first record of a flight.
+fn split_record(b: &Bytes) -> (Bytes, Bytes) {
+    let len = 5 + ((b[3].declassify() as usize) << 8) + b[4].declassify() as usize;
+    (b.slice_range(0..len), b.slice_range(len..b.len()))
+}
+
+// The application data returned by a read.
+fn received_data(r: Option<Received>) -> Bytes {
+    match r {
+        Some(Received::AppData(d)) => app_data_bytes(d),
+        _ => panic!("expected application data"),
+    }
+}
+
 // ECDH keys
 
 const client_x25519_priv: &str = "49 af 42 ba 7f 79 94 85 2d 71 3e f2 78
@@ -65,6 +79,313 @@ const ECDSA_P256_SHA256_Key: [u8; 32] = [
     0xDB, 0x7A, 0x36, 0x20, 0x08, 0xE9, 0x52, 0xEE, 0xDB, 0xCE, 0xAC, 0x3B, 0x26, 0xF9, 0x20, 0xBD,
 ];
 
+// A test PKI: a root CA, an intermediate CA and a certificate for
+// bertie.hacspec issued by the intermediate.
+const TEST_ROOT_CERT: [u8; 421] = [
+    0x30, 0x82, 0x01, 0xA1, 0x30, 0x82, 0x01, 0x46, 0xA0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x14, 0x0F,
+    0x27, 0x23, 0x48, 0x3E, 0xFE, 0x4A, 0x5F, 0x2F, 0x36, 0x81, 0x8E, 0x3C, 0x9C, 0xB8, 0x6D, 0x6C,
+    0x02, 0x5F, 0x79, 0x30, 0x0A, 0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x02, 0x30,
+    0x2D, 0x31, 0x10, 0x30, 0x0E, 0x06, 0x03, 0x55, 0x04, 0x0A, 0x0C, 0x07, 0x68, 0x61, 0x63, 0x73,
+    0x70, 0x65, 0x63, 0x31, 0x19, 0x30, 0x17, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0C, 0x10, 0x62, 0x65,
+    0x72, 0x74, 0x69, 0x65, 0x20, 0x74, 0x65, 0x73, 0x74, 0x20, 0x72, 0x6F, 0x6F, 0x74, 0x30, 0x20,
+    0x17, 0x0D, 0x32, 0x36, 0x31, 0x30, 0x31, 0x37, 0x30, 0x33, 0x30, 0x35, 0x33, 0x31, 0x5A, 0x18,
+    0x0F, 0x32, 0x31, 0x32, 0x36, 0x30, 0x39, 0x32, 0x33, 0x30, 0x33, 0x30, 0x35, 0x33, 0x31, 0x5A,
+    0x30, 0x2D, 0x31, 0x10, 0x30, 0x0E, 0x06, 0x03, 0x55, 0x04, 0x0A, 0x0C, 0x07, 0x68, 0x61, 0x63,
+    0x73, 0x70, 0x65, 0x63, 0x31, 0x19, 0x30, 0x17, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0C, 0x10, 0x62,
+    0x65, 0x72, 0x74, 0x69, 0x65, 0x20, 0x74, 0x65, 0x73, 0x74, 0x20, 0x72, 0x6F, 0x6F, 0x74, 0x30,
+    0x59, 0x30, 0x13, 0x06, 0x07, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01, 0x06, 0x08, 0x2A, 0x86,
+    0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00, 0x04, 0xFF, 0x09, 0xC6, 0x09, 0x1F, 0x61,
+    0xC0, 0xE6, 0x5A, 0xF6, 0x9D, 0x54, 0x8A, 0xD2, 0xD9, 0xF0, 0xF5, 0xCC, 0x8D, 0x17, 0xD2, 0x55,
+    0xF4, 0x0F, 0x03, 0x55, 0xBC, 0x8D, 0x38, 0x75, 0x50, 0xB7, 0x3B, 0x8B, 0x9D, 0x4A, 0x7B, 0xF0,
+    0x34, 0x15, 0x74, 0x96, 0xEF, 0x3F, 0xE3, 0x8E, 0xCC, 0xBB, 0x2E, 0xAF, 0xEC, 0xC4, 0x91, 0xB7,
+    0xB8, 0x34, 0x08, 0xD8, 0xE2, 0xDD, 0xB0, 0x2B, 0x8D, 0x36, 0xA3, 0x42, 0x30, 0x40, 0x30, 0x0F,
+    0x06, 0x03, 0x55, 0x1D, 0x13, 0x01, 0x01, 0xFF, 0x04, 0x05, 0x30, 0x03, 0x01, 0x01, 0xFF, 0x30,
+    0x0E, 0x06, 0x03, 0x55, 0x1D, 0x0F, 0x01, 0x01, 0xFF, 0x04, 0x04, 0x03, 0x02, 0x01, 0x06, 0x30,
+    0x1D, 0x06, 0x03, 0x55, 0x1D, 0x0E, 0x04, 0x16, 0x04, 0x14, 0x6B, 0x06, 0xC3, 0x38, 0x5C, 0x1A,
+    0xEC, 0x82, 0x6F, 0x8D, 0x9D, 0xC9, 0xE6, 0x22, 0x49, 0x70, 0x08, 0xB0, 0x59, 0x35, 0x30, 0x0A,
+    0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x02, 0x03, 0x49, 0x00, 0x30, 0x46, 0x02,
+    0x21, 0x00, 0xA6, 0x14, 0x02, 0x25, 0xBA, 0x02, 0x7E, 0x88, 0xB7, 0xEA, 0xE2, 0xC5, 0x70, 0xE9,
+    0x45, 0x74, 0x69, 0xDB, 0xBE, 0x59, 0x77, 0xFD, 0x1C, 0xF4, 0xA6, 0xF4, 0x02, 0xCC, 0xCF, 0x7C,
+    0x7F, 0x11, 0x02, 0x21, 0x00, 0x9B, 0xD3, 0xE2, 0x14, 0xC1, 0xF0, 0xC5, 0x80, 0xE7, 0x46, 0x38,
+    0x9C, 0x96, 0x39, 0x8F, 0x47, 0xC8, 0x90, 0x00, 0x7F, 0xAD, 0x2B, 0x69, 0x39, 0x76, 0x5D, 0xB1,
+    0xF7, 0x9B, 0x9C, 0x8A, 0x28,
+];
+const TEST_INTERMEDIATE_CERT: [u8; 442] = [
+    0x30, 0x82, 0x01, 0xB6, 0x30, 0x82, 0x01, 0x5C, 0xA0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x02,
+    0x30, 0x0A, 0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x02, 0x30, 0x2D, 0x31, 0x10,
+    0x30, 0x0E, 0x06, 0x03, 0x55, 0x04, 0x0A, 0x0C, 0x07, 0x68, 0x61, 0x63, 0x73, 0x70, 0x65, 0x63,
+    0x31, 0x19, 0x30, 0x17, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0C, 0x10, 0x62, 0x65, 0x72, 0x74, 0x69,
+    0x65, 0x20, 0x74, 0x65, 0x73, 0x74, 0x20, 0x72, 0x6F, 0x6F, 0x74, 0x30, 0x20, 0x17, 0x0D, 0x32,
+    0x36, 0x31, 0x30, 0x31, 0x37, 0x30, 0x33, 0x30, 0x35, 0x33, 0x31, 0x5A, 0x18, 0x0F, 0x32, 0x31,
+    0x32, 0x36, 0x30, 0x39, 0x32, 0x33, 0x30, 0x33, 0x30, 0x35, 0x33, 0x31, 0x5A, 0x30, 0x35, 0x31,
+    0x10, 0x30, 0x0E, 0x06, 0x03, 0x55, 0x04, 0x0A, 0x0C, 0x07, 0x68, 0x61, 0x63, 0x73, 0x70, 0x65,
+    0x63, 0x31, 0x21, 0x30, 0x1F, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0C, 0x18, 0x62, 0x65, 0x72, 0x74,
+    0x69, 0x65, 0x20, 0x74, 0x65, 0x73, 0x74, 0x20, 0x69, 0x6E, 0x74, 0x65, 0x72, 0x6D, 0x65, 0x64,
+    0x69, 0x61, 0x74, 0x65, 0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02,
+    0x01, 0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00, 0x04, 0xA0,
+    0xFF, 0x03, 0xE8, 0x47, 0xD9, 0xA4, 0x5A, 0x8C, 0xC4, 0x65, 0xCD, 0xFE, 0x4C, 0x05, 0xC1, 0xC3,
+    0xE4, 0x17, 0x41, 0x4A, 0x57, 0x5B, 0x72, 0x0B, 0x33, 0x62, 0x3B, 0xA7, 0x18, 0xAC, 0x3A, 0x0F,
+    0x41, 0x2D, 0x8F, 0x76, 0x8A, 0xD1, 0x60, 0x2E, 0x49, 0x3C, 0x43, 0x31, 0x5B, 0xF9, 0xC9, 0x7A,
+    0xAC, 0x82, 0xE5, 0xA6, 0x40, 0x12, 0x36, 0xBF, 0x9A, 0x3E, 0xE3, 0xD2, 0x9B, 0x64, 0xFB, 0xA3,
+    0x63, 0x30, 0x61, 0x30, 0x0F, 0x06, 0x03, 0x55, 0x1D, 0x13, 0x01, 0x01, 0xFF, 0x04, 0x05, 0x30,
+    0x03, 0x01, 0x01, 0xFF, 0x30, 0x0E, 0x06, 0x03, 0x55, 0x1D, 0x0F, 0x01, 0x01, 0xFF, 0x04, 0x04,
+    0x03, 0x02, 0x01, 0x06, 0x30, 0x1D, 0x06, 0x03, 0x55, 0x1D, 0x0E, 0x04, 0x16, 0x04, 0x14, 0x2A,
+    0xA4, 0xCE, 0x6A, 0xD2, 0x4C, 0xAC, 0x6E, 0xDE, 0x63, 0x05, 0xD6, 0xA9, 0x9F, 0x9F, 0x6B, 0xFE,
+    0x80, 0x8E, 0xB4, 0x30, 0x1F, 0x06, 0x03, 0x55, 0x1D, 0x23, 0x04, 0x18, 0x30, 0x16, 0x80, 0x14,
+    0x6B, 0x06, 0xC3, 0x38, 0x5C, 0x1A, 0xEC, 0x82, 0x6F, 0x8D, 0x9D, 0xC9, 0xE6, 0x22, 0x49, 0x70,
+    0x08, 0xB0, 0x59, 0x35, 0x30, 0x0A, 0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x02,
+    0x03, 0x48, 0x00, 0x30, 0x45, 0x02, 0x20, 0x2A, 0x84, 0xD7, 0xEA, 0xE4, 0xDF, 0x78, 0x98, 0x26,
+    0x7D, 0x02, 0xED, 0x76, 0x49, 0x09, 0x75, 0xE8, 0x3F, 0x49, 0x3A, 0x18, 0x50, 0xE7, 0xA0, 0xD9,
+    0x72, 0x57, 0x00, 0x87, 0x58, 0xFE, 0x93, 0x02, 0x21, 0x00, 0xC5, 0x1C, 0xDA, 0xE8, 0xE3, 0x26,
+    0xE6, 0xCA, 0x08, 0xCC, 0xB4, 0x1D, 0x05, 0xB0, 0x89, 0xE1, 0xFD, 0x81, 0xD5, 0x71, 0xF2, 0x3C,
+    0x42, 0x7B, 0x8A, 0x30, 0xA8, 0x96, 0xEB, 0x1A, 0x0D, 0x33,
+];
+const TEST_LEAF_CERT: [u8; 464] = [
+    0x30, 0x82, 0x01, 0xCC, 0x30, 0x82, 0x01, 0x72, 0xA0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x03,
+    0x30, 0x0A, 0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x02, 0x30, 0x35, 0x31, 0x10,
+    0x30, 0x0E, 0x06, 0x03, 0x55, 0x04, 0x0A, 0x0C, 0x07, 0x68, 0x61, 0x63, 0x73, 0x70, 0x65, 0x63,
+    0x31, 0x21, 0x30, 0x1F, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0C, 0x18, 0x62, 0x65, 0x72, 0x74, 0x69,
+    0x65, 0x20, 0x74, 0x65, 0x73, 0x74, 0x20, 0x69, 0x6E, 0x74, 0x65, 0x72, 0x6D, 0x65, 0x64, 0x69,
+    0x61, 0x74, 0x65, 0x30, 0x20, 0x17, 0x0D, 0x32, 0x36, 0x31, 0x30, 0x31, 0x37, 0x30, 0x33, 0x30,
+    0x35, 0x33, 0x31, 0x5A, 0x18, 0x0F, 0x32, 0x31, 0x32, 0x36, 0x30, 0x39, 0x32, 0x33, 0x30, 0x33,
+    0x30, 0x35, 0x33, 0x31, 0x5A, 0x30, 0x2B, 0x31, 0x10, 0x30, 0x0E, 0x06, 0x03, 0x55, 0x04, 0x0A,
+    0x0C, 0x07, 0x68, 0x61, 0x63, 0x73, 0x70, 0x65, 0x63, 0x31, 0x17, 0x30, 0x15, 0x06, 0x03, 0x55,
+    0x04, 0x03, 0x0C, 0x0E, 0x62, 0x65, 0x72, 0x74, 0x69, 0x65, 0x2E, 0x68, 0x61, 0x63, 0x73, 0x70,
+    0x65, 0x63, 0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01, 0x06,
+    0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00, 0x04, 0xAE, 0x86, 0x92,
+    0xE3, 0x37, 0x4A, 0xBB, 0xAC, 0x11, 0x07, 0xB8, 0xE7, 0xB4, 0xB4, 0x6D, 0x9C, 0x9E, 0xB6, 0xF0,
+    0x5D, 0x95, 0x59, 0x20, 0x45, 0x7F, 0xC3, 0xB5, 0x22, 0x69, 0x9F, 0xC5, 0xCC, 0x2C, 0xE1, 0xC1,
+    0xE9, 0x38, 0x7D, 0xC0, 0x18, 0xB0, 0x45, 0x49, 0x27, 0xBC, 0x81, 0x7A, 0xDF, 0x57, 0x1D, 0xD5,
+    0x68, 0xE9, 0x90, 0xDD, 0x0C, 0x91, 0xC1, 0x72, 0x5D, 0xDE, 0xD5, 0xEC, 0x4B, 0xA3, 0x7B, 0x30,
+    0x79, 0x30, 0x0C, 0x06, 0x03, 0x55, 0x1D, 0x13, 0x01, 0x01, 0xFF, 0x04, 0x02, 0x30, 0x00, 0x30,
+    0x0E, 0x06, 0x03, 0x55, 0x1D, 0x0F, 0x01, 0x01, 0xFF, 0x04, 0x04, 0x03, 0x02, 0x07, 0x80, 0x30,
+    0x19, 0x06, 0x03, 0x55, 0x1D, 0x11, 0x04, 0x12, 0x30, 0x10, 0x82, 0x0E, 0x62, 0x65, 0x72, 0x74,
+    0x69, 0x65, 0x2E, 0x68, 0x61, 0x63, 0x73, 0x70, 0x65, 0x63, 0x30, 0x1D, 0x06, 0x03, 0x55, 0x1D,
+    0x0E, 0x04, 0x16, 0x04, 0x14, 0xF0, 0x9F, 0x85, 0xBF, 0x49, 0x27, 0x49, 0xBD, 0xD3, 0x4E, 0x69,
+    0xAF, 0x36, 0x80, 0x89, 0xCC, 0x47, 0xED, 0xF3, 0x11, 0x30, 0x1F, 0x06, 0x03, 0x55, 0x1D, 0x23,
+    0x04, 0x18, 0x30, 0x16, 0x80, 0x14, 0x2A, 0xA4, 0xCE, 0x6A, 0xD2, 0x4C, 0xAC, 0x6E, 0xDE, 0x63,
+    0x05, 0xD6, 0xA9, 0x9F, 0x9F, 0x6B, 0xFE, 0x80, 0x8E, 0xB4, 0x30, 0x0A, 0x06, 0x08, 0x2A, 0x86,
+    0x48, 0xCE, 0x3D, 0x04, 0x03, 0x02, 0x03, 0x48, 0x00, 0x30, 0x45, 0x02, 0x20, 0x50, 0x0C, 0xD1,
+    0x97, 0x8D, 0xD2, 0xD4, 0xA9, 0xEC, 0x2B, 0x1B, 0xBA, 0x0A, 0x51, 0x15, 0x93, 0x63, 0x5F, 0xC3,
+    0x74, 0x33, 0x4F, 0x5C, 0xD7, 0xA6, 0xB9, 0x80, 0xD0, 0x4A, 0x37, 0x05, 0xDD, 0x02, 0x21, 0x00,
+    0x9F, 0x20, 0xE2, 0x9C, 0x2B, 0x72, 0x16, 0x7E, 0x0B, 0x34, 0xB3, 0x00, 0x20, 0xD9, 0xFC, 0xE4,
+    0xD6, 0xEC, 0x6D, 0xC2, 0x25, 0x18, 0xF0, 0xDA, 0xD9, 0x4A, 0x81, 0x9B, 0xB6, 0x6E, 0x48, 0x4D,
+];
+const TEST_LEAF_Key: [u8; 32] = [
+    0xDD, 0x34, 0xBB, 0x09, 0x51, 0x44, 0x21, 0xD1, 0x2E, 0xB4, 0xF4, 0x7E, 0x90, 0xCD, 0x3C, 0xCB,
+    0x89, 0x2A, 0x22, 0x8F, 0x86, 0x70, 0x77, 0xF2, 0x31, 0x11, 0xC2, 0x24, 0x20, 0x28, 0xB4, 0xCD,
+];
+
+// An Ed25519 root CA and a certificate for bertie.hacspec it issued.
+const TEST_ED25519_ROOT_CERT: [u8; 372] = [
+    0x30, 0x82, 0x01, 0x70, 0x30, 0x82, 0x01, 0x22, 0xA0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x14, 0x78,
+    0x61, 0x6B, 0xFD, 0x81, 0x6D, 0xD6, 0x6D, 0x13, 0x00, 0xF1, 0x8B, 0x68, 0xAA, 0xE8, 0x40, 0x04,
+    0x12, 0x5A, 0xBD, 0x30, 0x05, 0x06, 0x03, 0x2B, 0x65, 0x70, 0x30, 0x35, 0x31, 0x10, 0x30, 0x0E,
+    0x06, 0x03, 0x55, 0x04, 0x0A, 0x0C, 0x07, 0x68, 0x61, 0x63, 0x73, 0x70, 0x65, 0x63, 0x31, 0x21,
+    0x30, 0x1F, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0C, 0x18, 0x62, 0x65, 0x72, 0x74, 0x69, 0x65, 0x20,
+    0x74, 0x65, 0x73, 0x74, 0x20, 0x65, 0x64, 0x32, 0x35, 0x35, 0x31, 0x39, 0x20, 0x72, 0x6F, 0x6F,
+    0x74, 0x30, 0x20, 0x17, 0x0D, 0x32, 0x36, 0x31, 0x30, 0x31, 0x37, 0x30, 0x33, 0x32, 0x32, 0x31,
+    0x30, 0x5A, 0x18, 0x0F, 0x32, 0x31, 0x32, 0x36, 0x30, 0x39, 0x32, 0x33, 0x30, 0x33, 0x32, 0x32,
+    0x31, 0x30, 0x5A, 0x30, 0x35, 0x31, 0x10, 0x30, 0x0E, 0x06, 0x03, 0x55, 0x04, 0x0A, 0x0C, 0x07,
+    0x68, 0x61, 0x63, 0x73, 0x70, 0x65, 0x63, 0x31, 0x21, 0x30, 0x1F, 0x06, 0x03, 0x55, 0x04, 0x03,
+    0x0C, 0x18, 0x62, 0x65, 0x72, 0x74, 0x69, 0x65, 0x20, 0x74, 0x65, 0x73, 0x74, 0x20, 0x65, 0x64,
+    0x32, 0x35, 0x35, 0x31, 0x39, 0x20, 0x72, 0x6F, 0x6F, 0x74, 0x30, 0x2A, 0x30, 0x05, 0x06, 0x03,
+    0x2B, 0x65, 0x70, 0x03, 0x21, 0x00, 0xA1, 0xE5, 0xA1, 0x13, 0xDA, 0x86, 0x5C, 0x36, 0x71, 0xD9,
+    0xF9, 0x8F, 0xFE, 0xD0, 0xC9, 0x56, 0x41, 0xC3, 0xC1, 0xD5, 0x00, 0x47, 0xFB, 0xB1, 0xD6, 0x38,
+    0x78, 0x52, 0xDD, 0x3B, 0x9E, 0xD9, 0xA3, 0x42, 0x30, 0x40, 0x30, 0x0F, 0x06, 0x03, 0x55, 0x1D,
+    0x13, 0x01, 0x01, 0xFF, 0x04, 0x05, 0x30, 0x03, 0x01, 0x01, 0xFF, 0x30, 0x0E, 0x06, 0x03, 0x55,
+    0x1D, 0x0F, 0x01, 0x01, 0xFF, 0x04, 0x04, 0x03, 0x02, 0x01, 0x06, 0x30, 0x1D, 0x06, 0x03, 0x55,
+    0x1D, 0x0E, 0x04, 0x16, 0x04, 0x14, 0xD5, 0xD7, 0x70, 0xD1, 0x67, 0x40, 0x45, 0xE6, 0xD1, 0x7F,
+    0x34, 0x8F, 0x03, 0xDF, 0xA8, 0x75, 0x4C, 0x20, 0x52, 0x97, 0x30, 0x05, 0x06, 0x03, 0x2B, 0x65,
+    0x70, 0x03, 0x41, 0x00, 0xD0, 0x78, 0x0B, 0xDA, 0x8A, 0x26, 0x77, 0xCE, 0x17, 0x43, 0xA6, 0x26,
+    0xA3, 0x25, 0x38, 0xE3, 0xF0, 0xB3, 0xFF, 0xDE, 0x51, 0xFE, 0xB5, 0xDB, 0xE0, 0x61, 0x66, 0xB8,
+    0x1F, 0x8A, 0x3F, 0x80, 0xA3, 0x35, 0x78, 0xFD, 0x98, 0xD5, 0x1C, 0xEB, 0x22, 0x6E, 0xEC, 0x91,
+    0xDE, 0x5C, 0xCE, 0xD4, 0x00, 0x6B, 0x00, 0xB3, 0x7E, 0x7D, 0xDF, 0xE4, 0x7C, 0x9A, 0x82, 0x64,
+    0x82, 0x57, 0x64, 0x0B,
+];
+const TEST_ED25519_LEAF_CERT: [u8; 400] = [
+    0x30, 0x82, 0x01, 0x8C, 0x30, 0x82, 0x01, 0x3E, 0xA0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x04,
+    0x30, 0x05, 0x06, 0x03, 0x2B, 0x65, 0x70, 0x30, 0x35, 0x31, 0x10, 0x30, 0x0E, 0x06, 0x03, 0x55,
+    0x04, 0x0A, 0x0C, 0x07, 0x68, 0x61, 0x63, 0x73, 0x70, 0x65, 0x63, 0x31, 0x21, 0x30, 0x1F, 0x06,
+    0x03, 0x55, 0x04, 0x03, 0x0C, 0x18, 0x62, 0x65, 0x72, 0x74, 0x69, 0x65, 0x20, 0x74, 0x65, 0x73,
+    0x74, 0x20, 0x65, 0x64, 0x32, 0x35, 0x35, 0x31, 0x39, 0x20, 0x72, 0x6F, 0x6F, 0x74, 0x30, 0x20,
+    0x17, 0x0D, 0x32, 0x36, 0x31, 0x30, 0x31, 0x37, 0x30, 0x33, 0x32, 0x32, 0x31, 0x30, 0x5A, 0x18,
+    0x0F, 0x32, 0x31, 0x32, 0x36, 0x30, 0x39, 0x32, 0x33, 0x30, 0x33, 0x32, 0x32, 0x31, 0x30, 0x5A,
+    0x30, 0x2B, 0x31, 0x10, 0x30, 0x0E, 0x06, 0x03, 0x55, 0x04, 0x0A, 0x0C, 0x07, 0x68, 0x61, 0x63,
+    0x73, 0x70, 0x65, 0x63, 0x31, 0x17, 0x30, 0x15, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0C, 0x0E, 0x62,
+    0x65, 0x72, 0x74, 0x69, 0x65, 0x2E, 0x68, 0x61, 0x63, 0x73, 0x70, 0x65, 0x63, 0x30, 0x2A, 0x30,
+    0x05, 0x06, 0x03, 0x2B, 0x65, 0x70, 0x03, 0x21, 0x00, 0xFA, 0x06, 0x21, 0xA3, 0x9B, 0xD8, 0x8B,
+    0xB2, 0x14, 0x3A, 0xF4, 0x69, 0x89, 0x2D, 0xF6, 0x10, 0x36, 0xAE, 0xEC, 0x0B, 0xCF, 0x7E, 0xE3,
+    0xB2, 0x8C, 0x0E, 0x34, 0xE9, 0x6D, 0x21, 0x04, 0xDE, 0xA3, 0x7B, 0x30, 0x79, 0x30, 0x0C, 0x06,
+    0x03, 0x55, 0x1D, 0x13, 0x01, 0x01, 0xFF, 0x04, 0x02, 0x30, 0x00, 0x30, 0x0E, 0x06, 0x03, 0x55,
+    0x1D, 0x0F, 0x01, 0x01, 0xFF, 0x04, 0x04, 0x03, 0x02, 0x07, 0x80, 0x30, 0x19, 0x06, 0x03, 0x55,
+    0x1D, 0x11, 0x04, 0x12, 0x30, 0x10, 0x82, 0x0E, 0x62, 0x65, 0x72, 0x74, 0x69, 0x65, 0x2E, 0x68,
+    0x61, 0x63, 0x73, 0x70, 0x65, 0x63, 0x30, 0x1D, 0x06, 0x03, 0x55, 0x1D, 0x0E, 0x04, 0x16, 0x04,
+    0x14, 0xE0, 0x2F, 0xEB, 0x47, 0x81, 0xA6, 0x55, 0x40, 0xA6, 0xC9, 0xAA, 0x68, 0xFB, 0xC4, 0xDC,
+    0x96, 0x81, 0x18, 0x9E, 0xC7, 0x30, 0x1F, 0x06, 0x03, 0x55, 0x1D, 0x23, 0x04, 0x18, 0x30, 0x16,
+    0x80, 0x14, 0xD5, 0xD7, 0x70, 0xD1, 0x67, 0x40, 0x45, 0xE6, 0xD1, 0x7F, 0x34, 0x8F, 0x03, 0xDF,
+    0xA8, 0x75, 0x4C, 0x20, 0x52, 0x97, 0x30, 0x05, 0x06, 0x03, 0x2B, 0x65, 0x70, 0x03, 0x41, 0x00,
+    0x19, 0x76, 0x87, 0x5B, 0x4D, 0x6C, 0xA1, 0xCE, 0xB7, 0xBA, 0x9E, 0x20, 0x09, 0xC5, 0x00, 0x1D,
+    0xAD, 0x2E, 0x23, 0xE5, 0x03, 0xFC, 0x49, 0x65, 0x8B, 0x42, 0xF4, 0xB7, 0xB2, 0xD2, 0xD5, 0x9D,
+    0x3D, 0xB7, 0x03, 0x20, 0xC0, 0xEB, 0x88, 0x12, 0xD9, 0xD3, 0x3B, 0x29, 0xEA, 0x99, 0x76, 0xC7,
+    0xA9, 0x43, 0xFA, 0x5E, 0x1D, 0x23, 0x70, 0x5A, 0x13, 0xE0, 0x8A, 0x83, 0xB4, 0x50, 0xB4, 0x0F,
+];
+const TEST_ED25519_LEAF_Key: [u8; 32] = [
+    0x1B, 0x1D, 0x9B, 0x8C, 0xD1, 0x94, 0x81, 0xDD, 0xE4, 0xC9, 0xF2, 0x8E, 0x69, 0x6B, 0x3A, 0x36,
+    0x67, 0xF3, 0x97, 0xDC, 0xFA, 0xE0, 0xBB, 0xD8, 0xAB, 0x34, 0x34, 0x5A, 0xE9, 0x61, 0xC4, 0x10,
+];
+
+// A P-384 root CA and a certificate for bertie.hacspec it issued.
+const TEST_P384_ROOT_CERT: [u8; 524] = [
+    0x30, 0x82, 0x02, 0x08, 0x30, 0x82, 0x01, 0x8E, 0xA0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x14, 0x4E,
+    0x00, 0x92, 0x81, 0x70, 0x90, 0x58, 0xAA, 0x59, 0x99, 0xB7, 0xA2, 0x11, 0x5D, 0x57, 0x21, 0x3E,
+    0x17, 0x3F, 0xDE, 0x30, 0x0A, 0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x03, 0x30,
+    0x32, 0x31, 0x10, 0x30, 0x0E, 0x06, 0x03, 0x55, 0x04, 0x0A, 0x0C, 0x07, 0x68, 0x61, 0x63, 0x73,
+    0x70, 0x65, 0x63, 0x31, 0x1E, 0x30, 0x1C, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0C, 0x15, 0x62, 0x65,
+    0x72, 0x74, 0x69, 0x65, 0x20, 0x74, 0x65, 0x73, 0x74, 0x20, 0x70, 0x33, 0x38, 0x34, 0x20, 0x72,
+    0x6F, 0x6F, 0x74, 0x30, 0x20, 0x17, 0x0D, 0x32, 0x36, 0x31, 0x30, 0x31, 0x37, 0x30, 0x33, 0x32,
+    0x37, 0x32, 0x33, 0x5A, 0x18, 0x0F, 0x32, 0x31, 0x32, 0x36, 0x30, 0x39, 0x32, 0x33, 0x30, 0x33,
+    0x32, 0x37, 0x32, 0x33, 0x5A, 0x30, 0x32, 0x31, 0x10, 0x30, 0x0E, 0x06, 0x03, 0x55, 0x04, 0x0A,
+    0x0C, 0x07, 0x68, 0x61, 0x63, 0x73, 0x70, 0x65, 0x63, 0x31, 0x1E, 0x30, 0x1C, 0x06, 0x03, 0x55,
+    0x04, 0x03, 0x0C, 0x15, 0x62, 0x65, 0x72, 0x74, 0x69, 0x65, 0x20, 0x74, 0x65, 0x73, 0x74, 0x20,
+    0x70, 0x33, 0x38, 0x34, 0x20, 0x72, 0x6F, 0x6F, 0x74, 0x30, 0x76, 0x30, 0x10, 0x06, 0x07, 0x2A,
+    0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01, 0x06, 0x05, 0x2B, 0x81, 0x04, 0x00, 0x22, 0x03, 0x62, 0x00,
+    0x04, 0x31, 0x17, 0xF1, 0x41, 0x1F, 0x70, 0xB0, 0x2C, 0xB7, 0x88, 0x94, 0x59, 0x30, 0x48, 0xF0,
+    0x08, 0x95, 0x64, 0x5A, 0xBB, 0xE1, 0x1A, 0xF2, 0xA7, 0xB5, 0x99, 0xEF, 0x11, 0x0B, 0x76, 0x4D,
+    0x44, 0xFD, 0x69, 0xA5, 0x25, 0xFE, 0x92, 0x9E, 0x43, 0xB0, 0xB4, 0xFD, 0x92, 0x09, 0x78, 0x76,
+    0x85, 0x4A, 0x76, 0x56, 0xDA, 0x6A, 0xBD, 0xEF, 0x9B, 0x70, 0xB8, 0x29, 0x10, 0x2D, 0xA4, 0x94,
+    0x8B, 0x50, 0x03, 0x1C, 0xFB, 0xA8, 0x8B, 0x77, 0x63, 0xDA, 0x05, 0x24, 0x67, 0x24, 0x71, 0xBE,
+    0xB3, 0x64, 0x86, 0xB3, 0x15, 0x0E, 0x13, 0xAC, 0x53, 0x2A, 0xC7, 0x5E, 0xE7, 0xAF, 0xE5, 0x66,
+    0xCA, 0xA3, 0x63, 0x30, 0x61, 0x30, 0x1D, 0x06, 0x03, 0x55, 0x1D, 0x0E, 0x04, 0x16, 0x04, 0x14,
+    0x65, 0x52, 0x16, 0x36, 0x6F, 0xB4, 0x4D, 0x38, 0x33, 0x56, 0x3B, 0x00, 0xF0, 0xBE, 0x7E, 0x98,
+    0x62, 0x71, 0x36, 0x27, 0x30, 0x1F, 0x06, 0x03, 0x55, 0x1D, 0x23, 0x04, 0x18, 0x30, 0x16, 0x80,
+    0x14, 0x65, 0x52, 0x16, 0x36, 0x6F, 0xB4, 0x4D, 0x38, 0x33, 0x56, 0x3B, 0x00, 0xF0, 0xBE, 0x7E,
+    0x98, 0x62, 0x71, 0x36, 0x27, 0x30, 0x0F, 0x06, 0x03, 0x55, 0x1D, 0x13, 0x01, 0x01, 0xFF, 0x04,
+    0x05, 0x30, 0x03, 0x01, 0x01, 0xFF, 0x30, 0x0E, 0x06, 0x03, 0x55, 0x1D, 0x0F, 0x01, 0x01, 0xFF,
+    0x04, 0x04, 0x03, 0x02, 0x01, 0x06, 0x30, 0x0A, 0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04,
+    0x03, 0x03, 0x03, 0x68, 0x00, 0x30, 0x65, 0x02, 0x31, 0x00, 0x84, 0x7E, 0x03, 0xEC, 0xEB, 0x3B,
+    0x92, 0xF4, 0x6C, 0x10, 0xD0, 0xF3, 0x6E, 0xD6, 0xA2, 0xF0, 0x88, 0x56, 0xA2, 0xC4, 0x89, 0x6B,
+    0x9B, 0xD0, 0xB1, 0xA9, 0xD5, 0x8E, 0xC2, 0x4B, 0x59, 0x86, 0x29, 0xAF, 0x77, 0x04, 0xE5, 0x3A,
+    0x7A, 0xA3, 0x70, 0x72, 0x48, 0x7F, 0xB3, 0x68, 0x15, 0x3D, 0x02, 0x30, 0x4B, 0xEF, 0x2D, 0xFF,
+    0xA8, 0xFC, 0xF3, 0x3A, 0x9C, 0x4D, 0x64, 0xCB, 0x4D, 0x55, 0x56, 0x76, 0x83, 0x50, 0x46, 0xBB,
+    0xA4, 0x73, 0x4B, 0x93, 0x52, 0x9C, 0x04, 0x05, 0x30, 0xC6, 0xCE, 0x6E, 0x41, 0x88, 0x38, 0x26,
+    0xC6, 0x6E, 0x54, 0x15, 0x50, 0x40, 0xF8, 0x74, 0x57, 0xEC, 0xE1, 0x52,
+];
+const TEST_P384_LEAF_CERT: [u8; 522] = [
+    0x30, 0x82, 0x02, 0x06, 0x30, 0x82, 0x01, 0x8C, 0xA0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x01, 0x05,
+    0x30, 0x0A, 0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x03, 0x30, 0x32, 0x31, 0x10,
+    0x30, 0x0E, 0x06, 0x03, 0x55, 0x04, 0x0A, 0x0C, 0x07, 0x68, 0x61, 0x63, 0x73, 0x70, 0x65, 0x63,
+    0x31, 0x1E, 0x30, 0x1C, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0C, 0x15, 0x62, 0x65, 0x72, 0x74, 0x69,
+    0x65, 0x20, 0x74, 0x65, 0x73, 0x74, 0x20, 0x70, 0x33, 0x38, 0x34, 0x20, 0x72, 0x6F, 0x6F, 0x74,
+    0x30, 0x20, 0x17, 0x0D, 0x32, 0x36, 0x31, 0x30, 0x31, 0x37, 0x30, 0x33, 0x32, 0x37, 0x32, 0x33,
+    0x5A, 0x18, 0x0F, 0x32, 0x31, 0x32, 0x36, 0x30, 0x39, 0x32, 0x33, 0x30, 0x33, 0x32, 0x37, 0x32,
+    0x33, 0x5A, 0x30, 0x2B, 0x31, 0x10, 0x30, 0x0E, 0x06, 0x03, 0x55, 0x04, 0x0A, 0x0C, 0x07, 0x68,
+    0x61, 0x63, 0x73, 0x70, 0x65, 0x63, 0x31, 0x17, 0x30, 0x15, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0C,
+    0x0E, 0x62, 0x65, 0x72, 0x74, 0x69, 0x65, 0x2E, 0x68, 0x61, 0x63, 0x73, 0x70, 0x65, 0x63, 0x30,
+    0x76, 0x30, 0x10, 0x06, 0x07, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01, 0x06, 0x05, 0x2B, 0x81,
+    0x04, 0x00, 0x22, 0x03, 0x62, 0x00, 0x04, 0xF5, 0xE6, 0xB9, 0x4A, 0x8B, 0xF8, 0xD5, 0xB9, 0x28,
+    0x0A, 0xF6, 0x20, 0xDE, 0xD2, 0x94, 0x87, 0xB8, 0x97, 0xF1, 0x7B, 0xA7, 0x8F, 0xF8, 0x98, 0x24,
+    0xA3, 0x0F, 0xD4, 0xA3, 0x36, 0xEA, 0x7F, 0x58, 0xE5, 0x84, 0x0E, 0xB8, 0xB4, 0x7B, 0xFC, 0x1F,
+    0x8B, 0x52, 0xD4, 0x91, 0x95, 0x15, 0x30, 0x51, 0x4A, 0x55, 0x55, 0xB5, 0x03, 0xA1, 0x71, 0xFC,
+    0x7D, 0x5E, 0xE7, 0x8C, 0xE6, 0xA5, 0x79, 0xBE, 0x8E, 0x74, 0x86, 0x8C, 0x31, 0x6D, 0x3D, 0x6E,
+    0xD3, 0xA1, 0xA4, 0xC7, 0xA3, 0xD5, 0x2D, 0x59, 0x89, 0x9E, 0x94, 0xB8, 0x98, 0xEB, 0x8D, 0x40,
+    0x80, 0x1C, 0xB9, 0xE1, 0xE6, 0xB0, 0xE6, 0xA3, 0x7B, 0x30, 0x79, 0x30, 0x0C, 0x06, 0x03, 0x55,
+    0x1D, 0x13, 0x01, 0x01, 0xFF, 0x04, 0x02, 0x30, 0x00, 0x30, 0x0E, 0x06, 0x03, 0x55, 0x1D, 0x0F,
+    0x01, 0x01, 0xFF, 0x04, 0x04, 0x03, 0x02, 0x07, 0x80, 0x30, 0x19, 0x06, 0x03, 0x55, 0x1D, 0x11,
+    0x04, 0x12, 0x30, 0x10, 0x82, 0x0E, 0x62, 0x65, 0x72, 0x74, 0x69, 0x65, 0x2E, 0x68, 0x61, 0x63,
+    0x73, 0x70, 0x65, 0x63, 0x30, 0x1D, 0x06, 0x03, 0x55, 0x1D, 0x0E, 0x04, 0x16, 0x04, 0x14, 0x70,
+    0x57, 0x74, 0x25, 0x60, 0xFC, 0xB8, 0xD4, 0x5E, 0x74, 0xD1, 0x81, 0xD1, 0x57, 0xBD, 0xA2, 0xF8,
+    0x9C, 0x0B, 0x08, 0x30, 0x1F, 0x06, 0x03, 0x55, 0x1D, 0x23, 0x04, 0x18, 0x30, 0x16, 0x80, 0x14,
+    0x65, 0x52, 0x16, 0x36, 0x6F, 0xB4, 0x4D, 0x38, 0x33, 0x56, 0x3B, 0x00, 0xF0, 0xBE, 0x7E, 0x98,
+    0x62, 0x71, 0x36, 0x27, 0x30, 0x0A, 0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x04, 0x03, 0x03,
+    0x03, 0x68, 0x00, 0x30, 0x65, 0x02, 0x30, 0x34, 0x2A, 0xF7, 0xD3, 0x4A, 0xF2, 0x3C, 0x26, 0xA7,
+    0x15, 0x62, 0x1A, 0xE5, 0x89, 0x17, 0xE4, 0x6F, 0xDA, 0xE7, 0xAF, 0x1A, 0xF3, 0xC0, 0xE2, 0x7D,
+    0x97, 0xE9, 0x8E, 0x36, 0xB7, 0x56, 0xC3, 0x0A, 0x04, 0xEC, 0xE4, 0x49, 0xAF, 0xB7, 0xC7, 0xCD,
+    0xB3, 0x61, 0xDD, 0x5A, 0x63, 0x31, 0xCC, 0x02, 0x31, 0x00, 0xDC, 0x0A, 0x4F, 0xBD, 0xDF, 0xCE,
+    0x3A, 0x68, 0x0B, 0xDB, 0x00, 0xD8, 0x14, 0x12, 0x93, 0x89, 0x85, 0x90, 0x52, 0x6C, 0x0A, 0xF3,
+    0x93, 0x2B, 0xEF, 0x24, 0x7E, 0x31, 0xB8, 0xD9, 0xC0, 0x53, 0x34, 0xD2, 0xFD, 0x72, 0xAC, 0x43,
+    0x8B, 0xE4, 0xB4, 0x5F, 0x33, 0x2A, 0x61, 0xB9, 0x1F, 0x14,
+];
+const TEST_P384_LEAF_Key: [u8; 48] = [
+    0x2B, 0xE1, 0xD6, 0x88, 0xBA, 0x55, 0xFB, 0x6D, 0x97, 0x25, 0x40, 0x1F, 0x3C, 0x92, 0xD7, 0xA3,
+    0x40, 0x1B, 0x88, 0x2D, 0xA1, 0xB1, 0x07, 0x45, 0xAD, 0xCE, 0x2A, 0xE8, 0x60, 0xA9, 0xAC, 0x43,
+    0xA0, 0xA5, 0xAF, 0x97, 0x47, 0xAA, 0xE1, 0x08, 0x51, 0x45, 0xE8, 0x5F, 0xA3, 0x2E, 0xD0, 0x20,
+];
+
+// A self-signed RSA-2048 certificate for bertie.hacspec and its private exponent.
+const RSA_2048_CERT: [u8; 829] = [
+    0x30, 0x82, 0x03, 0x39, 0x30, 0x82, 0x02, 0x21, 0xA0, 0x03, 0x02, 0x01, 0x02, 0x02, 0x14, 0x00,
+    0xE7, 0xC2, 0xDF, 0xBC, 0x40, 0xDA, 0xBC, 0x23, 0x61, 0x51, 0xBF, 0x94, 0x5D, 0x59, 0xF4, 0x71,
+    0xD2, 0x07, 0x9A, 0x30, 0x0D, 0x06, 0x09, 0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01, 0x0B,
+    0x05, 0x00, 0x30, 0x2B, 0x31, 0x10, 0x30, 0x0E, 0x06, 0x03, 0x55, 0x04, 0x0A, 0x0C, 0x07, 0x68,
+    0x61, 0x63, 0x73, 0x70, 0x65, 0x63, 0x31, 0x17, 0x30, 0x15, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0C,
+    0x0E, 0x62, 0x65, 0x72, 0x74, 0x69, 0x65, 0x2E, 0x68, 0x61, 0x63, 0x73, 0x70, 0x65, 0x63, 0x30,
+    0x20, 0x17, 0x0D, 0x32, 0x36, 0x31, 0x30, 0x31, 0x37, 0x30, 0x33, 0x31, 0x39, 0x31, 0x34, 0x5A,
+    0x18, 0x0F, 0x32, 0x31, 0x32, 0x36, 0x30, 0x39, 0x32, 0x33, 0x30, 0x33, 0x31, 0x39, 0x31, 0x34,
+    0x5A, 0x30, 0x2B, 0x31, 0x10, 0x30, 0x0E, 0x06, 0x03, 0x55, 0x04, 0x0A, 0x0C, 0x07, 0x68, 0x61,
+    0x63, 0x73, 0x70, 0x65, 0x63, 0x31, 0x17, 0x30, 0x15, 0x06, 0x03, 0x55, 0x04, 0x03, 0x0C, 0x0E,
+    0x62, 0x65, 0x72, 0x74, 0x69, 0x65, 0x2E, 0x68, 0x61, 0x63, 0x73, 0x70, 0x65, 0x63, 0x30, 0x82,
+    0x01, 0x22, 0x30, 0x0D, 0x06, 0x09, 0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01, 0x01, 0x05,
+    0x00, 0x03, 0x82, 0x01, 0x0F, 0x00, 0x30, 0x82, 0x01, 0x0A, 0x02, 0x82, 0x01, 0x01, 0x00, 0xC2,
+    0xCE, 0x69, 0x1F, 0xB8, 0x5A, 0x29, 0x6C, 0x63, 0x9A, 0xCC, 0xBD, 0xB8, 0x54, 0x96, 0x80, 0xE3,
+    0x7D, 0x50, 0x35, 0x70, 0x5E, 0x45, 0x60, 0x47, 0x58, 0xCE, 0xA2, 0x44, 0x55, 0x65, 0xC9, 0x61,
+    0xF7, 0x02, 0x43, 0xCA, 0x9A, 0xE9, 0xDF, 0x39, 0x69, 0x04, 0x6B, 0xBB, 0xC3, 0x94, 0x35, 0x5E,
+    0x67, 0x42, 0xD6, 0xDF, 0x03, 0x85, 0x2F, 0x3B, 0xCC, 0xC3, 0x2B, 0xBA, 0x79, 0xEF, 0xB0, 0xB5,
+    0x5D, 0x9A, 0xFF, 0x2D, 0x4F, 0xA0, 0x8F, 0x94, 0x07, 0xFB, 0x0B, 0xED, 0xD1, 0xE0, 0x04, 0x23,
+    0x5C, 0x06, 0xFF, 0x68, 0x13, 0x73, 0x1E, 0xBB, 0x3D, 0x88, 0xFC, 0x59, 0x0F, 0x7E, 0x0C, 0x2E,
+    0x9A, 0x41, 0x04, 0x17, 0xA1, 0xFE, 0x2D, 0xDC, 0x33, 0x3A, 0x50, 0x92, 0xC3, 0x33, 0x88, 0xA1,
+    0xD9, 0x0F, 0x40, 0x97, 0x38, 0xB1, 0x08, 0x97, 0x2F, 0x97, 0x48, 0xAA, 0xB2, 0xDB, 0x21, 0xD9,
+    0x54, 0x44, 0x8C, 0xE0, 0xA7, 0x63, 0x29, 0x92, 0xCC, 0x26, 0xBE, 0xCE, 0xE9, 0xF3, 0x1F, 0xB1,
+    0xCE, 0xB4, 0xD9, 0x54, 0x7F, 0x3A, 0x5D, 0x26, 0xAF, 0x75, 0x8E, 0x94, 0xC6, 0x78, 0xB9, 0xA2,
+    0x7C, 0x70, 0x65, 0x2F, 0x5D, 0xCE, 0x2E, 0x20, 0x45, 0x4A, 0x47, 0xA4, 0x67, 0x1B, 0xA4, 0x95,
+    0xF5, 0xDA, 0x8C, 0x21, 0x9B, 0x21, 0x1D, 0xB2, 0xC7, 0x92, 0x48, 0x5C, 0x20, 0x9B, 0x2A, 0x18,
+    0x29, 0x7D, 0x6B, 0x52, 0xBB, 0x4A, 0xA2, 0x10, 0x77, 0xC4, 0x7D, 0xEA, 0x26, 0x9D, 0xD8, 0x33,
+    0xB7, 0xA7, 0x52, 0xF0, 0x42, 0xA3, 0x95, 0x9A, 0x89, 0x8A, 0x0F, 0x70, 0xD5, 0x72, 0xDB, 0x7C,
+    0x88, 0xB0, 0x57, 0x96, 0x8E, 0x9A, 0xF7, 0xD8, 0x39, 0xFE, 0x23, 0xD9, 0x80, 0x34, 0xE6, 0x68,
+    0x2E, 0x5A, 0x95, 0x34, 0x08, 0xC8, 0x34, 0x05, 0x19, 0xE5, 0x62, 0x11, 0x16, 0x5D, 0x8F, 0x02,
+    0x03, 0x01, 0x00, 0x01, 0xA3, 0x53, 0x30, 0x51, 0x30, 0x1D, 0x06, 0x03, 0x55, 0x1D, 0x0E, 0x04,
+    0x16, 0x04, 0x14, 0x47, 0x7A, 0x46, 0xC7, 0x76, 0xDD, 0xF4, 0x99, 0x6A, 0xA8, 0x8D, 0xCB, 0xE1,
+    0x0C, 0x90, 0x08, 0xD6, 0xE6, 0xEF, 0x8C, 0x30, 0x1F, 0x06, 0x03, 0x55, 0x1D, 0x23, 0x04, 0x18,
+    0x30, 0x16, 0x80, 0x14, 0x47, 0x7A, 0x46, 0xC7, 0x76, 0xDD, 0xF4, 0x99, 0x6A, 0xA8, 0x8D, 0xCB,
+    0xE1, 0x0C, 0x90, 0x08, 0xD6, 0xE6, 0xEF, 0x8C, 0x30, 0x0F, 0x06, 0x03, 0x55, 0x1D, 0x13, 0x01,
+    0x01, 0xFF, 0x04, 0x05, 0x30, 0x03, 0x01, 0x01, 0xFF, 0x30, 0x0D, 0x06, 0x09, 0x2A, 0x86, 0x48,
+    0x86, 0xF7, 0x0D, 0x01, 0x01, 0x0B, 0x05, 0x00, 0x03, 0x82, 0x01, 0x01, 0x00, 0x6F, 0x32, 0xCE,
+    0x3B, 0x4D, 0x21, 0x55, 0x16, 0xE9, 0x1E, 0xD2, 0xF2, 0xE9, 0x41, 0x1F, 0xED, 0xF1, 0xF9, 0xBB,
+    0x7E, 0x6F, 0xBB, 0x3D, 0xE2, 0x70, 0x45, 0x36, 0x41, 0x44, 0x75, 0x3E, 0x09, 0x2D, 0xD5, 0xEF,
+    0x7E, 0x0A, 0xFF, 0x4F, 0x57, 0xA4, 0x03, 0x41, 0xDB, 0x15, 0xB9, 0x14, 0x4B, 0xA5, 0xFE, 0xC4,
+    0x96, 0x58, 0xF2, 0x17, 0x9A, 0x8B, 0xBC, 0x12, 0x94, 0x4B, 0x38, 0xE2, 0xC0, 0x02, 0x40, 0x1C,
+    0xCB, 0x4B, 0xAD, 0x12, 0x8B, 0xC1, 0x87, 0x63, 0x94, 0x8E, 0xFC, 0x97, 0x87, 0xED, 0x61, 0x5A,
+    0xE6, 0xA9, 0x50, 0xF3, 0x8E, 0xA2, 0xD8, 0xA7, 0x78, 0x1F, 0x57, 0xAB, 0xE6, 0x5E, 0xEE, 0xD1,
+    0xBA, 0x40, 0x5D, 0x49, 0xD0, 0x37, 0xB5, 0xF2, 0x72, 0xBE, 0x54, 0x4E, 0x5B, 0xFD, 0x08, 0x58,
+    0x69, 0xBA, 0x17, 0x8E, 0x80, 0xE5, 0x0C, 0x02, 0x54, 0xD5, 0xD4, 0x39, 0x0F, 0x14, 0x32, 0x5E,
+    0x80, 0x28, 0x44, 0x41, 0x4E, 0xC7, 0x90, 0x20, 0x32, 0xCF, 0x29, 0xDE, 0xBC, 0x3F, 0x3D, 0x34,
+    0x5D, 0x0F, 0x97, 0xFD, 0x49, 0xB3, 0x6A, 0x84, 0xC0, 0x4D, 0x42, 0xDC, 0x9B, 0x12, 0x9C, 0x51,
+    0x31, 0x9A, 0x9B, 0x41, 0x4E, 0xC8, 0x1D, 0x25, 0xC9, 0x24, 0x56, 0x72, 0xF0, 0x34, 0x87, 0x38,
+    0x2F, 0xDC, 0xD0, 0xAA, 0xA6, 0x61, 0x63, 0x3D, 0x3B, 0x3E, 0xCE, 0x24, 0x68, 0xC6, 0x8C, 0xE5,
+    0x4F, 0x2C, 0x5D, 0x0C, 0xEB, 0x7E, 0x6B, 0xD0, 0xEB, 0xDF, 0x91, 0xCF, 0x03, 0xB5, 0xF1, 0x05,
+    0x7C, 0x03, 0xB4, 0xC3, 0xF2, 0x61, 0x3D, 0x7B, 0x76, 0x0D, 0x6F, 0x9A, 0xCC, 0xB0, 0x90, 0xCF,
+    0x02, 0x24, 0xED, 0x9C, 0x61, 0x4F, 0x26, 0xBE, 0xDC, 0x63, 0xF2, 0x5B, 0xB9, 0x3B, 0xE4, 0x76,
+    0x8E, 0xF3, 0x2C, 0x6D, 0xA7, 0xF9, 0x3F, 0xFC, 0x21, 0xE0, 0xBE, 0xA8, 0x0D,
+];
+const RSA_2048_Key: [u8; 256] = [
+    0x1F, 0x4E, 0xF6, 0xE4, 0x93, 0x07, 0x8E, 0x93, 0xC3, 0x23, 0x07, 0x17, 0xD0, 0xB9, 0xC0, 0xE1,
+    0xE0, 0x6D, 0xEC, 0x9D, 0x02, 0x12, 0x6C, 0x9A, 0x4F, 0x82, 0x82, 0xDC, 0x8A, 0x6D, 0xF7, 0xD3,
+    0x6E, 0x01, 0x64, 0x65, 0x9F, 0xCE, 0x2B, 0x9B, 0x3A, 0xDF, 0x59, 0x72, 0x0A, 0x75, 0xCC, 0x3F,
+    0xE8, 0x37, 0x0C, 0x03, 0x7B, 0x9E, 0x80, 0x62, 0x64, 0x7D, 0x2C, 0x10, 0x3E, 0xED, 0x47, 0xAC,
+    0x01, 0xF7, 0x69, 0x7D, 0xEC, 0x7A, 0xD9, 0x31, 0x14, 0xD7, 0x54, 0x2E, 0x33, 0xCA, 0x76, 0x1D,
+    0xEE, 0xCE, 0xE4, 0xB8, 0x5A, 0x07, 0x92, 0x34, 0xBF, 0x5E, 0xB6, 0xEC, 0x3E, 0x50, 0xB7, 0xEF,
+    0x33, 0x9D, 0x8A, 0xA8, 0x77, 0xE1, 0xE0, 0x4C, 0xDF, 0x90, 0x83, 0x7A, 0x9D, 0x49, 0x19, 0x0D,
+    0xB3, 0x06, 0xAF, 0x25, 0xB0, 0x32, 0xD0, 0xCF, 0xB2, 0xCE, 0x02, 0x70, 0x3A, 0x85, 0xA4, 0xA3,
+    0xB1, 0x6B, 0xF4, 0xA8, 0xEC, 0xA2, 0x7C, 0x94, 0x87, 0xDC, 0x21, 0xE6, 0xC2, 0x4D, 0x8A, 0xDB,
+    0x54, 0x70, 0x6F, 0xCB, 0xF2, 0xF9, 0x7F, 0xB7, 0xCA, 0x00, 0x25, 0xCF, 0x8F, 0x1D, 0x3F, 0x51,
+    0x35, 0xB7, 0x57, 0x18, 0x24, 0xD5, 0x68, 0xA2, 0x7F, 0xDE, 0x26, 0xDC, 0x89, 0x36, 0x47, 0xCA,
+    0xD2, 0x7A, 0xA8, 0x60, 0xC2, 0x59, 0xEE, 0x9E, 0x5D, 0xD2, 0x2C, 0x39, 0xD1, 0x82, 0x0F, 0x12,
+    0x3A, 0x3C, 0xF4, 0x33, 0xC1, 0x1E, 0xB0, 0x19, 0x2C, 0x0C, 0x34, 0x46, 0x31, 0x8E, 0xB5, 0x5C,
+    0x95, 0x20, 0xF4, 0x91, 0x9B, 0x1D, 0x58, 0xA5, 0x16, 0x98, 0x32, 0x99, 0xF4, 0xF1, 0xE1, 0x27,
+    0xC8, 0x9D, 0xAA, 0x0A, 0xAC, 0xF9, 0x9A, 0x07, 0x78, 0x2D, 0x35, 0xF3, 0xA8, 0x86, 0x16, 0xBB,
+    0x19, 0xDC, 0x3A, 0x74, 0xCC, 0xF7, 0x68, 0xBC, 0x8B, 0xC1, 0x71, 0xC3, 0x61, 0xB2, 0x2B, 0xD1,
+];
+
 const TLS_AES_128_GCM_SHA256_X25519_RSA: Algorithms = Algorithms(
     HashAlgorithm::SHA256,
     AeadAlgorithm::Aes128Gcm,
@@ -90,34 +411,176 @@ const TLS_CHACHA20_POLY1305_SHA256_X25519: Algorithms = Algorithms(
     false,
     false,
 );
+const TLS_AES_256_GCM_SHA384_X25519: Algorithms = Algorithms(
+    HashAlgorithm::SHA384,
+    AeadAlgorithm::Aes256Gcm,
+    SignatureScheme::EcdsaSecp256r1Sha256,
+    KemScheme::X25519,
+    false,
+    false,
+);
+const TLS_CHACHA20_POLY1305_SHA256_X25519_PSK: Algorithms = Algorithms(
+    HashAlgorithm::SHA256,
+    AeadAlgorithm::Chacha20Poly1305,
+    SignatureScheme::EcdsaSecp256r1Sha256,
+    KemScheme::X25519,
+    true,
+    false,
+);
+
+const TLS_CHACHA20_POLY1305_SHA256_X25519_PSK_0RTT: Algorithms = Algorithms(
+    HashAlgorithm::SHA256,
+    AeadAlgorithm::Chacha20Poly1305,
+    SignatureScheme::EcdsaSecp256r1Sha256,
+    KemScheme::X25519,
+    true,
+    true,
+);
+
+const TLS_CHACHA20_POLY1305_SHA256_P256: Algorithms = Algorithms(
+    HashAlgorithm::SHA256,
+    AeadAlgorithm::Chacha20Poly1305,
+    SignatureScheme::EcdsaSecp256r1Sha256,
+    KemScheme::Secp256r1,
+    false,
+    false,
+);
+
+const TLS_CHACHA20_POLY1305_SHA256_P384: Algorithms = Algorithms(
+    HashAlgorithm::SHA256,
+    AeadAlgorithm::Chacha20Poly1305,
+    SignatureScheme::EcdsaSecp256r1Sha256,
+    KemScheme::Secp384r1,
+    false,
+    false,
+);
+
+// The server name and server configuration shared by most tests: an ECDSA
+// P-256 certificate, no tickets, no client authentication and no ALPN.
+fn test_sn() -> Bytes {
+    Bytes::from("bertie.hacspec".as_bytes())
+}
+
+fn test_server_db() -> ServerDB {
+    ServerDB(
+        test_sn(),
+        vec![Bytes::from(&ECDSA_P256_SHA256_CERT)],
+        bertie::tls13crypto::SignatureKey::from(&ECDSA_P256_SHA256_Key),
+        None,
+        None,
+        vec![],
+        PaddingPolicy::NoPadding,
+    )
+}
+
+// Start a handshake with the default client options.
+fn test_client_connect(algs: &[Algorithms]) -> (Bytes, Client) {
+    client_connect(
+        algs,
+        &test_sn(),
+        None,
+        None,
+        &[],
+        None,
+        PaddingPolicy::NoPadding,
+        random_bytes(256),
+    )
+    .unwrap()
+}
+
+// Start a handshake in which the client sends X25519 and P-384 key shares
+// and also offers `algs`, so that a server configured with `algs` asks for
+// its group with a HelloRetryRequest.
+fn test_client_connect_retry(algs: Algorithms) -> (Bytes, Client) {
+    test_client_connect(&[
+        TLS_CHACHA20_POLY1305_SHA256_X25519,
+        TLS_CHACHA20_POLY1305_SHA256_P384,
+        algs,
+    ])
+}
+
+// Run the rest of a handshake that does not need a HelloRetryRequest.
+fn complete_handshake(
+    ch: &Bytes,
+    cstate: Client,
+    algs: &[Algorithms],
+    db: ServerDB,
+) -> (Client, Server) {
+    let (sh, sf, sstate) = server_accept(algs, db, ch, random_bytes(256)).unwrap();
+    let (none, cstate) = client_read_handshake(&sh, cstate).unwrap();
+    assert!(none.is_none());
+    let (cf, cstate) = client_read_handshake(&sf, cstate).unwrap();
+    let sstate = server_read_handshake(&cf.unwrap(), sstate).unwrap();
+    (cstate, sstate)
+}
+
+// Run the rest of a handshake after the server sent a HelloRetryRequest.
+fn complete_retried_handshake(hrr: &Bytes, cstate: Client, sstate: Server) -> (Client, Server) {
+    let (ch, cstate) = client_read_handshake(hrr, cstate).unwrap();
+    let (sh, sf, sstate) = server_accept_retry(&ch.unwrap(), sstate, random_bytes(256)).unwrap();
+    assert!(!in_hello_retry(&sstate));
+    let (none, cstate) = client_read_handshake(&sh, cstate).unwrap();
+    assert!(none.is_none());
+    let (cf, cstate) = client_read_handshake(&sf, cstate).unwrap();
+    let sstate = server_read_handshake(&cf.unwrap(), sstate).unwrap();
+    (cstate, sstate)
+}
+
+// A full handshake between a default client and the default server.
+fn handshake(client_algs: &[Algorithms], server_algs: &[Algorithms]) -> (Client, Server) {
+    let (ch, cstate) = test_client_connect(client_algs);
+    complete_handshake(&ch, cstate, server_algs, test_server_db())
+}
+
+// Send application data from the client and check that the server gets it.
+fn check_client_to_server(cstate: Client, sstate: Server) -> (Client, Server) {
+    let data = Bytes::from(b"Hello server, here is the client");
+    let (ap, cstate) = client_write(app_data(data.clone()), cstate).unwrap();
+    let (apo, sstate) = server_read(&ap, sstate).unwrap();
+    assert!(eq(&data, &received_data(apo)));
+    (cstate, sstate)
+}
 
 #[test]
 fn test_full_round_trip() {
     let cr = random_bytes(32);
     let x = load_hex(client_x25519_priv);
     let ent_c = cr.concat(&x);
-    let sn = load_hex("6c 6f 63 61 6c 68 6f 73 74");
-    let sn_ = load_hex("6c 6f 63 61 6c 68 6f 73 74");
+    let sn = Bytes::from("bertie.hacspec".as_bytes());
+    let sn_ = Bytes::from("bertie.hacspec".as_bytes());
     let sr = random_bytes(32);
     let y = load_hex(server_x25519_priv);
-    let ent_s = sr.concat(&y);
+    // The server random, the key share and the signature.
+    let ent_s = sr.concat(&y).concat(&random_bytes(32));
 
     let db = ServerDB(
         sn_,
-        Bytes::from(&ECDSA_P256_SHA256_CERT),
+        vec![Bytes::from(&ECDSA_P256_SHA256_CERT)],
         bertie::tls13crypto::SignatureKey::from(&ECDSA_P256_SHA256_Key),
         None,
+        None,
+        vec![],
+        PaddingPolicy::NoPadding,
     );
 
     let mut b = true;
-    match client_connect(TLS_CHACHA20_POLY1305_SHA256_X25519, &sn, None, None, ent_c) {
+    match client_connect(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        &sn,
+        None,
+        None,
+        &[],
+        None,
+        PaddingPolicy::NoPadding,
+        ent_c,
+    ) {
         Err(x) => {
             println!("Client0 Error {}", x);
             b = false;
         }
         Ok((ch, cstate)) => {
             println!("Client0 Complete");
-            match server_accept(TLS_CHACHA20_POLY1305_SHA256_X25519, db, &ch, ent_s) {
+            match server_accept(&[TLS_CHACHA20_POLY1305_SHA256_X25519], db, &ch, ent_s) {
                 Err(x) => {
                     println!("ServerInit Error {}", x);
                     b = false;
@@ -157,7 +620,7 @@ fn test_full_round_trip() {
                                         let (ap, cstate) =
                                             client_write(app_data(data.clone()), cstate).unwrap();
                                         let (apo, sstate) = server_read(&ap, sstate).unwrap();
-                                        assert!(eq(&data, &app_data_bytes(apo.unwrap())));
+                                        assert!(eq(&data, &received_data(apo)));
 
                                         // Send data from server to client.
                                         let data =
@@ -165,7 +628,7 @@ fn test_full_round_trip() {
                                         let (ap, _sstate) =
                                             server_write(app_data(data.clone()), sstate).unwrap();
                                         let (apo, _cstate) = client_read(&ap, cstate).unwrap();
-                                        assert!(eq(&data, &app_data_bytes(apo.unwrap())));
+                                        assert!(eq(&data, &received_data(apo)));
                                     }
                                 }
                             }
@@ -177,3 +640,1992 @@ fn test_full_round_trip() {
     }
     assert!(b);
 }
+
+#[test]
+fn test_hello_retry_request() {
+    // The client sends X25519 and P-384 shares, the server wants P-256.
+    let (ch, cstate) = test_client_connect_retry(TLS_CHACHA20_POLY1305_SHA256_P256);
+    let (hrr, sf, sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_P256],
+        test_server_db(),
+        &ch,
+        random_bytes(128),
+    )
+    .unwrap();
+    assert!(sf.is_empty());
+    assert!(in_hello_retry(&sstate));
+    // The HelloRetryRequest carries a cookie with a SHA-256 hash.
+    assert!(hrr
+        .declassify()
+        .windows(6)
+        .any(|w| w == [0x00, 0x2c, 0x00, 0x22, 0x00, 0x20]));
+
+    let (cstate, sstate) = complete_retried_handshake(&hrr, cstate, sstate);
+    check_client_to_server(cstate, sstate);
+}
+
+#[test]
+fn test_hello_retry_request_checks() {
+    let retry = || {
+        let (ch, cstate) = test_client_connect_retry(TLS_CHACHA20_POLY1305_SHA256_P256);
+        let (hrr, _sf, sstate) = server_accept(
+            &[TLS_CHACHA20_POLY1305_SHA256_P256],
+            test_server_db(),
+            &ch,
+            random_bytes(128),
+        )
+        .unwrap();
+        let (ch2, _cstate) = client_read_handshake(&hrr, cstate).unwrap();
+        (ch, ch2.unwrap(), sstate)
+    };
+
+    // Sending the first ClientHello again, with its X25519 and P-384 key
+    // shares and without the cookie, is rejected.
+    let (ch, _ch2, sstate) = retry();
+    let err = server_accept_retry(&ch, sstate, random_bytes(128))
+        .err()
+        .unwrap();
+    assert_eq!(err, ILLEGAL_PARAMETER);
+    assert_eq!(err.alert(), AlertDescription::IllegalParameter);
+
+    // So is a second ClientHello with a modified cookie.
+    let (_ch, mut ch2, sstate) = retry();
+    let pos = ch2
+        .declassify()
+        .windows(6)
+        .position(|w| w == [0x00, 0x2c, 0x00, 0x22, 0x00, 0x20])
+        .unwrap();
+    ch2[pos + 6] = ch2[pos + 6] ^ U8::from(1);
+    let err = server_accept_retry(&ch2, sstate, random_bytes(128))
+        .err()
+        .unwrap();
+    assert_eq!(err, ILLEGAL_PARAMETER);
+
+    // The client rejects a HelloRetryRequest for a group it already sent a
+    // key share for.
+    let (ch, _cstate) = test_client_connect_retry(TLS_CHACHA20_POLY1305_SHA256_P256);
+    let (hrr, _sf, _sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_P256],
+        test_server_db(),
+        &ch,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (_ch, cstate) = test_client_connect(&[
+        TLS_CHACHA20_POLY1305_SHA256_X25519,
+        TLS_CHACHA20_POLY1305_SHA256_P256,
+    ]);
+    let err = client_read_handshake(&hrr, cstate).err().unwrap();
+    assert_eq!(err, ILLEGAL_PARAMETER);
+
+    // And a ServerHello whose cipher suite differs from the HelloRetryRequest's,
+    // even if it was offered.
+    let (ch, cstate) = test_client_connect(&[
+        TLS_CHACHA20_POLY1305_SHA256_X25519,
+        TLS_CHACHA20_POLY1305_SHA256_P384,
+        TLS_CHACHA20_POLY1305_SHA256_P256,
+        TLS_AES_128_GCM_SHA256_X25519,
+    ]);
+    let (hrr, _sf, sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_P256],
+        test_server_db(),
+        &ch,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (ch2, cstate) = client_read_handshake(&hrr, cstate).unwrap();
+    let (mut sh, _sf, _sstate) =
+        server_accept_retry(&ch2.unwrap(), sstate, random_bytes(128)).unwrap();
+    let suite = 5 + 4 + 2 + 32 + 1 + sh[43].declassify() as usize;
+    assert_eq!(sh[suite + 1].declassify(), 0x03);
+    sh[suite + 1] = U8::from(0x01);
+    let err = client_read_handshake(&sh, cstate).err().unwrap();
+    assert_eq!(err, ILLEGAL_PARAMETER);
+    assert_eq!(err.stage(), Some(Stage::ServerHello));
+}
+
+#[test]
+fn test_hello_retry_request_parsing() {
+    let algs = TLS_CHACHA20_POLY1305_SHA256_X25519;
+    let (ch, _cstate) = test_client_connect_retry(TLS_CHACHA20_POLY1305_SHA256_P256);
+    let (hrr, _sf, _sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_P256],
+        test_server_db(),
+        &ch,
+        random_bytes(128),
+    )
+    .unwrap();
+    let body = hrr.slice_range(9..hrr.len());
+    let message = |body: &Bytes| {
+        let len = body.len();
+        HandshakeData(Bytes::from(&[2, 0, (len >> 8) as u8, len as u8]).concat(body))
+    };
+    let (group, cookie) = parse_hello_retry_request(&algs, &message(&body)).unwrap();
+    assert_eq!(group, Some(KemScheme::Secp256r1));
+    assert!(cookie.is_some());
+
+    // Every truncation of the HelloRetryRequest is a decode error.
+    for len in 0..body.len() {
+        assert!(parse_hello_retry_request(&algs, &message(&body.slice_range(0..len))).is_err());
+    }
+
+    // So is an extension shorter than its type and length.
+    let sidlen = body[34].declassify() as usize;
+    let exts_len = 35 + sidlen + 3;
+    let mut longer = body.concat(&Bytes::from(&[0]));
+    let len = body.len() - exts_len - 2 + 1;
+    longer[exts_len] = U8::from((len >> 8) as u8);
+    longer[exts_len + 1] = U8::from(len as u8);
+    assert_eq!(
+        parse_hello_retry_request(&algs, &message(&longer)),
+        Err(PARSE_FAILED)
+    );
+}
+
+#[test]
+fn test_supported_groups() {
+    // The client only offers the groups of its configuration.
+    let (ch, _cstate) = test_client_connect(&[TLS_CHACHA20_POLY1305_SHA256_X25519]);
+    assert!(ch
+        .declassify()
+        .windows(8)
+        .any(|w| w == [0x00, 0x0a, 0x00, 0x04, 0x00, 0x02, 0x00, 0x1d]));
+    let res = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_P256],
+        test_server_db(),
+        &ch,
+        random_bytes(128),
+    );
+    assert!(res.is_err());
+
+    // A HelloRetryRequest for a group the client did not offer is rejected.
+    let (ch, _cstate) = test_client_connect_retry(TLS_CHACHA20_POLY1305_SHA256_P256);
+    let (hrr, _sf, _sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_P256],
+        test_server_db(),
+        &ch,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (_ch, cstate) = test_client_connect(&[TLS_CHACHA20_POLY1305_SHA256_X25519]);
+    let err = client_read_handshake(&hrr, cstate).err().unwrap();
+    assert_eq!(err, ILLEGAL_PARAMETER);
+    assert_eq!(err.alert(), AlertDescription::IllegalParameter);
+}
+
+#[test]
+fn test_ciphersuite_negotiation() {
+    // The client prefers AES-256/SHA-384, the server only does ChaCha20.
+    let (cstate, sstate) = handshake(
+        &[
+            TLS_AES_256_GCM_SHA384_X25519,
+            TLS_CHACHA20_POLY1305_SHA256_X25519,
+        ],
+        &[
+            TLS_CHACHA20_POLY1305_SHA256_X25519,
+            TLS_AES_128_GCM_SHA256_X25519,
+        ],
+    );
+
+    check_client_to_server(cstate, sstate);
+}
+
+#[test]
+fn test_multiple_key_shares() {
+    // The client sends X25519 and P-256 shares, so the server can use its
+    // preferred P-256 without a HelloRetryRequest.
+    let (cstate, sstate) = handshake(
+        &[
+            TLS_CHACHA20_POLY1305_SHA256_X25519,
+            TLS_CHACHA20_POLY1305_SHA256_P256,
+        ],
+        &[TLS_CHACHA20_POLY1305_SHA256_P256],
+    );
+
+    check_client_to_server(cstate, sstate);
+
+    // The entropy has to cover the key shares for both groups.
+    let res = client_connect(
+        &[
+            TLS_CHACHA20_POLY1305_SHA256_X25519,
+            TLS_CHACHA20_POLY1305_SHA256_P256,
+        ],
+        &test_sn(),
+        None,
+        None,
+        &[],
+        None,
+        PaddingPolicy::NoPadding,
+        random_bytes(64),
+    );
+    assert_eq!(res.err(), Some(INSUFFICIENT_ENTROPY));
+
+    // The server's has to cover its random, its key share and its signature.
+    let (ch, _cstate) = test_client_connect(&[TLS_CHACHA20_POLY1305_SHA256_X25519]);
+    let res = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        test_server_db(),
+        &ch,
+        random_bytes(64),
+    );
+    assert_eq!(res.err(), Some(INSUFFICIENT_ENTROPY));
+}
+
+#[test]
+fn test_signature_scheme_negotiation() {
+    // The client prefers RSA-PSS but also offers ECDSA. The server is
+    // configured for RSA-PSS, but has to sign with the ECDSA key in its
+    // certificate.
+    let (cstate, sstate) = handshake(
+        &[
+            TLS_AES_128_GCM_SHA256_X25519_RSA,
+            TLS_AES_128_GCM_SHA256_X25519,
+        ],
+        &[TLS_AES_128_GCM_SHA256_X25519_RSA],
+    );
+
+    check_client_to_server(cstate, sstate);
+
+    // A client that only offers RSA-PSS can't talk to an ECDSA server.
+    let (ch, _cstate) = test_client_connect(&[TLS_AES_128_GCM_SHA256_X25519_RSA]);
+    assert!(server_accept(
+        &[TLS_AES_128_GCM_SHA256_X25519],
+        test_server_db(),
+        &ch,
+        random_bytes(128)
+    )
+    .is_err());
+}
+
+#[test]
+fn test_session_resumption() {
+    // Full handshake, after which the server issues a ticket.
+    let (cstate, sstate) = handshake(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+    );
+
+    let tkt = Bytes::from(b"ticket-0");
+    let (nst, server_psk, _sstate) =
+        server_send_ticket(&tkt, 3600, 0, sstate, random_bytes(12)).unwrap();
+    let (ad, cstate) = client_read(&nst, cstate).unwrap();
+    assert!(ad.is_none());
+    let (tickets, cstate) = client_take_tickets(cstate);
+    assert_eq!(tickets.len(), 1);
+    let (tickets_again, _cstate) = client_take_tickets(cstate);
+    assert!(tickets_again.is_empty());
+
+    let SessionTicket(identity, lifetime, _age_add, max_early_data, _nonce, client_psk) =
+        tickets[0].clone();
+    assert!(eq(&identity, &tkt));
+    assert_eq!(lifetime, 3600);
+    assert_eq!(max_early_data, 0);
+    assert!(eq(&client_psk, &server_psk));
+
+    // Resume with the ticket.
+    let db = ServerDB {
+        3: Some((tkt, server_psk)),
+        ..test_server_db()
+    };
+    let (ch, cstate) = client_connect(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519_PSK],
+        &test_sn(),
+        Some(tickets[0].clone()),
+        None,
+        &[],
+        None,
+        PaddingPolicy::NoPadding,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (sh, sf, sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519_PSK],
+        db,
+        &ch,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (_, cstate) = client_read_handshake(&sh, cstate).unwrap();
+    assert!(in_psk_mode(&cstate));
+    let (cf, cstate) = client_read_handshake(&sf, cstate).unwrap();
+    let sstate = server_read_handshake(&cf.unwrap(), sstate).unwrap();
+
+    check_client_to_server(cstate, sstate);
+}
+
+// A NewSessionTicket with a one-byte ticket, an empty nonce and the given
+// extensions.
+fn session_ticket(exts: &[u8]) -> HandshakeData {
+    let mut body = vec![0, 0, 0x0e, 0x10, 0, 0, 0, 0, 0, 0, 1, b'x'];
+    body.extend_from_slice(&[0, exts.len() as u8]);
+    body.extend_from_slice(exts);
+    let mut msg = vec![4, 0, 0, body.len() as u8];
+    msg.extend_from_slice(&body);
+    HandshakeData(Bytes::from(&msg[..]))
+}
+
+#[test]
+fn test_session_ticket_parsing() {
+    let algs = TLS_CHACHA20_POLY1305_SHA256_X25519_PSK;
+    let (lifetime, _age_add, max_early_data, _nonce, tkt) =
+        parse_session_ticket(&algs, &session_ticket(&[0, 0x2a, 0, 4, 0, 0, 0x40, 0])).unwrap();
+    assert_eq!(lifetime.declassify(), 3600);
+    assert_eq!(max_early_data.declassify(), 16384);
+    assert!(eq(&tkt, &Bytes::from(b"x")));
+
+    // Extensions shorter than their type and length are a decode error.
+    for exts in [&[0u8][..], &[0, 0x2a], &[0, 0x2a, 0]] {
+        let err = parse_session_ticket(&algs, &session_ticket(exts))
+            .err()
+            .unwrap();
+        assert_eq!(err, PARSE_FAILED);
+        assert_eq!(error_alert(err), AlertDescription::DecodeError);
+    }
+}
+
+#[test]
+fn test_zero_rtt() {
+    // Without a ticket there is no early data.
+    let (_, cstate) = test_client_connect(&[TLS_CHACHA20_POLY1305_SHA256_X25519]);
+    assert!(client_write_early(app_data(Bytes::from(b"x")), cstate).is_err());
+
+    // Full handshake, after which the server issues a ticket allowing early data.
+    let (ch, cstate) = test_client_connect(&[TLS_CHACHA20_POLY1305_SHA256_X25519]);
+    let (sh, sf, sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        test_server_db(),
+        &ch,
+        random_bytes(128),
+    )
+    .unwrap();
+    assert!(!in_early_data(&sstate));
+    let (_, cstate) = client_read_handshake(&sh, cstate).unwrap();
+    let (cf, cstate) = client_read_handshake(&sf, cstate).unwrap();
+    assert!(!early_data_accepted(&cstate));
+    let sstate = server_read_handshake(&cf.unwrap(), sstate).unwrap();
+
+    let tkt = Bytes::from(b"ticket-0");
+    let (nst, server_psk, _sstate) =
+        server_send_ticket(&tkt, 3600, 16384, sstate, random_bytes(12)).unwrap();
+    let (_, cstate) = client_read(&nst, cstate).unwrap();
+    let (tickets, _cstate) = client_take_tickets(cstate);
+    let SessionTicket(_identity, _lifetime, _age_add, max_early_data, _nonce, _psk) =
+        tickets[0].clone();
+    assert_eq!(max_early_data, 16384);
+
+    // Resume with the ticket and send a request in the first flight.
+    let db = ServerDB {
+        3: Some((tkt, server_psk)),
+        ..test_server_db()
+    };
+    let (ch, cstate) = client_connect(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519_PSK_0RTT],
+        &test_sn(),
+        Some(tickets[0].clone()),
+        None,
+        &[],
+        None,
+        PaddingPolicy::NoPadding,
+        random_bytes(128),
+    )
+    .unwrap();
+    let early = Bytes::from(b"GET / HTTP/1.1");
+    let (ed, cstate) = client_write_early(app_data(early.clone()), cstate).unwrap();
+    let (sh, sf, sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519_PSK_0RTT],
+        db,
+        &ch,
+        random_bytes(128),
+    )
+    .unwrap();
+    assert!(in_early_data(&sstate));
+    let (edo, sstate) = server_read_early(&ed, sstate).unwrap();
+    assert!(eq(&early, &app_data_bytes(edo.unwrap())));
+
+    // The client Finished flight starts with the EndOfEarlyData record.
+    let (_, cstate) = client_read_handshake(&sh, cstate).unwrap();
+    let (cf, cstate) = client_read_handshake(&sf, cstate).unwrap();
+    assert!(early_data_accepted(&cstate));
+    let (eoed, cfin) = split_record(&cf.unwrap());
+    let (edo, sstate) = server_read_early(&eoed, sstate).unwrap();
+    assert!(edo.is_none());
+    assert!(!in_early_data(&sstate));
+    let sstate = server_read_handshake(&cfin, sstate).unwrap();
+
+    check_client_to_server(cstate, sstate);
+}
+
+// Run a full handshake after which the server issues a ticket allowing
+// `max_early_data` bytes of early data. Returns the client's ticket and the
+// server configuration that accepts it.
+fn resumption_ticket(max_early_data: u32) -> (SessionTicket, ServerDB) {
+    let (cstate, sstate) = handshake(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+    );
+    let tkt = Bytes::from(b"ticket-0");
+    let (nst, server_psk, _sstate) =
+        server_send_ticket(&tkt, 3600, max_early_data, sstate, random_bytes(12)).unwrap();
+    let (_, cstate) = client_read(&nst, cstate).unwrap();
+    let (tickets, _cstate) = client_take_tickets(cstate);
+    let db = ServerDB {
+        3: Some((tkt, server_psk)),
+        ..test_server_db()
+    };
+    (tickets[0].clone(), db)
+}
+
+// Resume with `ticket`, offering `algs`.
+fn resume(ticket: SessionTicket, algs: &[Algorithms]) -> (Bytes, Client) {
+    client_connect(
+        algs,
+        &test_sn(),
+        Some(ticket),
+        None,
+        &[],
+        None,
+        PaddingPolicy::NoPadding,
+        random_bytes(256),
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_zero_rtt_rejected() {
+    let early = Bytes::from(b"GET / HTTP/1.1");
+
+    // Without early data in the ticket, the client offers none.
+    let (ticket, db) = resumption_ticket(0);
+    let (_, cstate) = resume(
+        ticket.clone(),
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519_PSK_0RTT],
+    );
+    assert!(matches!(
+        client_write_early(app_data(early.clone()), cstate),
+        Err(e) if e == ZERO_RTT_DISABLED
+    ));
+    let (ch, cstate) = resume(ticket, &[TLS_CHACHA20_POLY1305_SHA256_X25519_PSK_0RTT]);
+    let (cstate, sstate) = complete_handshake(
+        &ch,
+        cstate,
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519_PSK_0RTT],
+        db,
+    );
+    assert!(!early_data_accepted(&cstate));
+    check_client_to_server(cstate, sstate);
+
+    // Tickets allow at most MAX_EARLY_DATA_SIZE bytes of early data.
+    let (_cstate, sstate) = handshake(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+    );
+    let tkt = Bytes::from(b"ticket-0");
+    let err = server_send_ticket(
+        &tkt,
+        3600,
+        MAX_EARLY_DATA_SIZE + 1,
+        sstate,
+        random_bytes(12),
+    )
+    .err()
+    .unwrap();
+    assert_eq!(err, UNSUPPORTED);
+
+    // A server without 0-RTT declines the early data, skips it and completes
+    // the handshake.
+    let (ticket, db) = resumption_ticket(MAX_EARLY_DATA_SIZE);
+    let (ch, cstate) = resume(ticket, &[TLS_CHACHA20_POLY1305_SHA256_X25519_PSK_0RTT]);
+    let (ed, cstate) = client_write_early(app_data(early.clone()), cstate).unwrap();
+    let (sh, sf, sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519_PSK],
+        db,
+        &ch,
+        random_bytes(256),
+    )
+    .unwrap();
+    assert!(!in_early_data(&sstate));
+    let sstate = server_read_handshake(&ed, sstate).unwrap();
+    assert!(in_handshake(&sstate));
+    let (_, cstate) = client_read_handshake(&sh, cstate).unwrap();
+    let (cf, cstate) = client_read_handshake(&sf, cstate).unwrap();
+    assert!(!early_data_accepted(&cstate));
+    let sstate = server_read_handshake(&cf.unwrap(), sstate).unwrap();
+    check_client_to_server(cstate, sstate);
+
+    // It skips no more than MAX_EARLY_DATA_SIZE bytes.
+    let (ticket, db) = resumption_ticket(MAX_EARLY_DATA_SIZE);
+    let (ch, cstate) = resume(ticket, &[TLS_CHACHA20_POLY1305_SHA256_X25519_PSK_0RTT]);
+    let (ed, cstate) = client_write_early(app_data(Bytes::zeroes(16000)), cstate).unwrap();
+    let (ed2, _cstate) = client_write_early(app_data(Bytes::zeroes(1000)), cstate).unwrap();
+    let (_, _, sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519_PSK],
+        db,
+        &ch,
+        random_bytes(256),
+    )
+    .unwrap();
+    let sstate = server_read_handshake(&ed, sstate).unwrap();
+    let err = server_read_handshake(&ed2, sstate).err().unwrap();
+    assert_eq!(err, APPLICATION_DATA_INSTEAD_OF_HANDSHAKE);
+    assert_eq!(err.alert(), AlertDescription::UnexpectedMessage);
+
+    // After a HelloRetryRequest, the server skips the early data that comes
+    // before the second ClientHello.
+    let psk_0rtt = |ks| {
+        Algorithms(
+            HashAlgorithm::SHA256,
+            AeadAlgorithm::Chacha20Poly1305,
+            SignatureScheme::EcdsaSecp256r1Sha256,
+            ks,
+            true,
+            true,
+        )
+    };
+    let (ticket, db) = resumption_ticket(MAX_EARLY_DATA_SIZE);
+    let (ch, cstate) = resume(
+        ticket,
+        &[
+            TLS_CHACHA20_POLY1305_SHA256_X25519_PSK_0RTT,
+            psk_0rtt(KemScheme::Secp384r1),
+            psk_0rtt(KemScheme::Secp256r1),
+        ],
+    );
+    let (ed, cstate) = client_write_early(app_data(early), cstate).unwrap();
+    let (hrr, _, sstate) = server_accept(
+        &[psk_0rtt(KemScheme::Secp256r1)],
+        db,
+        &ch,
+        random_bytes(256),
+    )
+    .unwrap();
+    assert!(in_hello_retry(&sstate));
+    let (none, _, sstate) = server_accept_retry(&ed, sstate, random_bytes(256)).unwrap();
+    assert!(none.is_empty());
+    assert!(in_hello_retry(&sstate));
+    let (cstate, sstate) = complete_retried_handshake(&hrr, cstate, sstate);
+    assert!(!early_data_accepted(&cstate));
+    check_client_to_server(cstate, sstate);
+}
+
+#[test]
+fn test_hello_record_boundaries() {
+    let algs = [TLS_CHACHA20_POLY1305_SHA256_X25519];
+    // An empty EncryptedExtensions, appended to the hello in its record.
+    let extra = Bytes::from(b"\x08\x00\x00\x00");
+
+    // The ServerHello has to end its record.
+    let (ch, cstate) = test_client_connect(&algs);
+    let (sh, _, _) = server_accept(&algs, test_server_db(), &ch, random_bytes(256)).unwrap();
+    let sh = sh.slice_range(5..sh.len()).concat(&extra);
+    let sh = handshake_record(&handshake_data(sh)).unwrap();
+    let err = client_read_handshake(&sh, cstate).err().unwrap();
+    assert_eq!(err, UNEXPECTED_MESSAGE);
+    assert_eq!(err.alert(), AlertDescription::UnexpectedMessage);
+    assert_eq!(err.stage(), Some(Stage::ServerHello));
+
+    // So has the ClientHello.
+    let (ch, _) = test_client_connect(&algs);
+    let ch = ch.slice_range(5..ch.len()).concat(&extra);
+    let ch = client_hello_record(&handshake_data(ch)).unwrap();
+    let err = server_accept(&algs, test_server_db(), &ch, random_bytes(256))
+        .err()
+        .unwrap();
+    assert_eq!(err, UNEXPECTED_MESSAGE);
+
+    // The server's encrypted flight may come in the records after the
+    // ServerHello.
+    let (ch, cstate) = test_client_connect(&algs);
+    let (sh, sf, sstate) = server_accept(&algs, test_server_db(), &ch, random_bytes(256)).unwrap();
+    let (cf, cstate) = client_read_handshake(&sh.concat(&sf), cstate).unwrap();
+    let sstate = server_read_handshake(&cf.unwrap(), sstate).unwrap();
+    check_client_to_server(cstate, sstate);
+
+    // Early data may come in the records after the ClientHello. The server
+    // keeps the early data it accepts.
+    let early = Bytes::from(b"GET / HTTP/1.1");
+    let (ticket, db) = resumption_ticket(MAX_EARLY_DATA_SIZE);
+    let (ch, cstate) = resume(ticket, &[TLS_CHACHA20_POLY1305_SHA256_X25519_PSK_0RTT]);
+    let (ed, cstate) = client_write_early(app_data(early.clone()), cstate).unwrap();
+    let (ed2, cstate) = client_write_early(app_data(early.clone()), cstate).unwrap();
+    let (sh, sf, sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519_PSK_0RTT],
+        db,
+        &ch.concat(&ed),
+        random_bytes(256),
+    )
+    .unwrap();
+    assert!(in_early_data(&sstate));
+    let (edo, sstate) = server_take_early_data(sstate);
+    assert!(eq(&early, &app_data_bytes(edo.unwrap())));
+    let (edo, sstate) = server_take_early_data(sstate);
+    assert!(edo.is_none());
+    let (edo, sstate) = server_read_early(&ed2, sstate).unwrap();
+    assert!(eq(&early, &app_data_bytes(edo.unwrap())));
+    let (_, cstate) = client_read_handshake(&sh, cstate).unwrap();
+    let (cf, cstate) = client_read_handshake(&sf, cstate).unwrap();
+    assert!(early_data_accepted(&cstate));
+    let (eoed, cfin) = split_record(&cf.unwrap());
+    let (edo, sstate) = server_read_early(&eoed, sstate).unwrap();
+    assert!(edo.is_none());
+    let sstate = server_read_handshake(&cfin, sstate).unwrap();
+    check_client_to_server(cstate, sstate);
+
+    // It skips the early data it rejects.
+    let (ticket, db) = resumption_ticket(MAX_EARLY_DATA_SIZE);
+    let (ch, cstate) = resume(ticket, &[TLS_CHACHA20_POLY1305_SHA256_X25519_PSK_0RTT]);
+    let (ed, cstate) = client_write_early(app_data(early), cstate).unwrap();
+    let (sh, sf, sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519_PSK],
+        db,
+        &ch.concat(&ed),
+        random_bytes(256),
+    )
+    .unwrap();
+    assert!(in_handshake(&sstate));
+    let (edo, sstate) = server_take_early_data(sstate);
+    assert!(edo.is_none());
+    let (_, cstate) = client_read_handshake(&sh, cstate).unwrap();
+    let (cf, cstate) = client_read_handshake(&sf, cstate).unwrap();
+    assert!(!early_data_accepted(&cstate));
+    let sstate = server_read_handshake(&cf.unwrap(), sstate).unwrap();
+    check_client_to_server(cstate, sstate);
+}
+
+#[test]
+fn test_key_update() {
+    let (cstate, sstate) = handshake(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+    );
+
+    // The client updates its keys and asks the server to do the same.
+    let (ku, cstate) = client_send_key_update(true, cstate).unwrap();
+    let (apo, sstate) = server_read(&ku, sstate).unwrap();
+    assert!(apo.is_none());
+    let data = Bytes::from(b"Hello server, here is the client");
+    let (ap, cstate) = client_write(app_data(data.clone()), cstate).unwrap();
+    let (apo, sstate) = server_read(&ap, sstate).unwrap();
+    assert!(eq(&data, &received_data(apo)));
+
+    // The server answers with its own KeyUpdate before its next data.
+    let data = Bytes::from(b"Hello client, here is the server");
+    let (flight, sstate) = server_write(app_data(data.clone()), sstate).unwrap();
+    let (ku, ap) = split_record(&flight);
+    let (apo, cstate) = client_read(&ku, cstate).unwrap();
+    assert!(apo.is_none());
+    let (apo, cstate) = client_read(&ap, cstate).unwrap();
+    assert!(eq(&data, &received_data(apo)));
+
+    // The server updates its keys without a request: the client does not answer.
+    let (ku, sstate) = server_send_key_update(false, sstate).unwrap();
+    let (_, cstate) = client_read(&ku, cstate).unwrap();
+    let (ap, sstate) = server_write(app_data(data.clone()), sstate).unwrap();
+    let (apo, cstate) = client_read(&ap, cstate).unwrap();
+    assert!(eq(&data, &received_data(apo)));
+    let (ap, _cstate) = client_write(app_data(data.clone()), cstate).unwrap();
+    let (apo, _sstate) = server_read(&ap, sstate).unwrap();
+    assert!(eq(&data, &received_data(apo)));
+}
+
+#[test]
+fn test_client_authentication() {
+    let client_cert = Bytes::from(&ECDSA_P256_SHA256_CERT);
+    let client_key = bertie::tls13crypto::SignatureKey::from(&ECDSA_P256_SHA256_Key);
+    let server_db = |client_auth| ServerDB {
+        4: client_auth,
+        ..test_server_db()
+    };
+
+    // The server requests a certificate and the client presents one.
+    let (ch, cstate) = client_connect(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        &test_sn(),
+        None,
+        Some((vec![client_cert.clone()], client_key.clone())),
+        &[],
+        None,
+        PaddingPolicy::NoPadding,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (cstate, sstate) = complete_handshake(
+        &ch,
+        cstate,
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        server_db(Some((vec![client_cert.clone()], TEST_NOW))),
+    );
+    assert!(eq(&server_peer_certificate(&sstate).unwrap(), &client_cert));
+    check_client_to_server(cstate, sstate);
+
+    // Without a request, the client does not present its certificate.
+    let (ch, cstate) = client_connect(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        &test_sn(),
+        None,
+        Some((vec![client_cert.clone()], client_key.clone())),
+        &[],
+        None,
+        PaddingPolicy::NoPadding,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (_cstate, sstate) = complete_handshake(
+        &ch,
+        cstate,
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        server_db(None),
+    );
+    assert!(server_peer_certificate(&sstate).is_none());
+
+    // A client without a certificate is rejected when the server requests one.
+    let (ch, cstate) = test_client_connect(&[TLS_CHACHA20_POLY1305_SHA256_X25519]);
+    let (sh, sf, sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        server_db(Some((vec![client_cert.clone()], TEST_NOW))),
+        &ch,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (_, cstate) = client_read_handshake(&sh, cstate).unwrap();
+    let (cf, _cstate) = client_read_handshake(&sf, cstate).unwrap();
+    assert!(matches!(
+        server_read_handshake(&cf.unwrap(), sstate),
+        Err(e) if e == MISSING_CLIENT_CERTIFICATE
+    ));
+}
+
+#[test]
+fn test_alerts() {
+    let (cstate, sstate) = handshake(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+    );
+
+    // A tampered record fails to decrypt, and the server answers with an
+    // encrypted bad_record_mac alert.
+    let data = Bytes::from(b"Hello server, here is the client");
+    let (mut ap, cstate) = client_write(app_data(data), cstate).unwrap();
+    let last = ap.len() - 1;
+    ap[last] = ap[last] ^ U8::from(1);
+    let alerts = server_alert_sender(&sstate);
+    let err = server_read(&ap, sstate).err().unwrap();
+    assert_eq!(err, BAD_RECORD_MAC);
+    let alert = send_alert(error_alert(err), &alerts).unwrap();
+    match client_read(&alert, cstate).unwrap() {
+        (Some(Received::Alert(level, ad)), _) => {
+            assert_eq!(level, AlertLevel::Fatal);
+            assert_eq!(ad, AlertDescription::BadRecordMac);
+        }
+        _ => panic!("expected an alert"),
+    }
+
+    // Before the handshake keys, alerts are sent in plaintext.
+    let alert = alert_record(error_alert(PROTOCOL_VERSION_ALERT)).unwrap();
+    assert!(eq(&alert, &Bytes::from(&[21, 3, 3, 0, 2, 2, 70])));
+    assert_eq!(
+        parse_alert_record(&alert).unwrap(),
+        (AlertLevel::Fatal, AlertDescription::ProtocolVersion)
+    );
+    assert_eq!(
+        error_alert(INVALID_COMPRESSION_LIST),
+        AlertDescription::IllegalParameter
+    );
+    assert_eq!(
+        error_alert(MISSING_CLIENT_CERTIFICATE),
+        AlertDescription::CertificateRequired
+    );
+    assert_eq!(
+        error_alert(INSUFFICIENT_ENTROPY),
+        AlertDescription::InternalError
+    );
+
+    // A plaintext alert instead of the ServerHello fails with its description.
+    let (_, cstate) = test_client_connect(&[TLS_CHACHA20_POLY1305_SHA256_X25519]);
+    let alert = alert_record(AlertDescription::HandshakeFailure).unwrap();
+    let err = client_read_handshake(&alert, cstate).err().unwrap();
+    assert_eq!(err, got_alert(AlertDescription::HandshakeFailure));
+    assert_eq!(err.stage(), Some(Stage::ServerHello));
+    assert_eq!(err.alert(), AlertDescription::CloseNotify);
+
+    // So does an encrypted alert instead of the client's flight.
+    let (ch, cstate) = test_client_connect(&[TLS_CHACHA20_POLY1305_SHA256_X25519]);
+    let (sh, _, sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        test_server_db(),
+        &ch,
+        random_bytes(256),
+    )
+    .unwrap();
+    let (_, cstate) = client_read_handshake(&sh, cstate).unwrap();
+    let alerts = client_alert_sender(&cstate);
+    let alert = send_alert(AlertDescription::BadCertificate, &alerts).unwrap();
+    assert_eq!(alert[0].declassify(), 23);
+    let err = server_read_handshake(&alert, sstate).err().unwrap();
+    assert_eq!(err, got_alert(AlertDescription::BadCertificate));
+    assert_eq!(err.stage(), Some(Stage::ClientFlight));
+}
+
+#[test]
+fn test_close_notify() {
+    let (cstate, sstate) = handshake(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+    );
+
+    // The server answers a request and closes the connection.
+    let data = Bytes::from(b"Hello server, here is the client");
+    let (ap, cstate) = client_write(app_data(data.clone()), cstate).unwrap();
+    let (apo, sstate) = server_read(&ap, sstate).unwrap();
+    assert!(eq(&data, &received_data(apo)));
+    let data = Bytes::from(b"Hello client, here is the server");
+    let (ap, sstate) = server_write(app_data(data.clone()), sstate).unwrap();
+    let close = server_close(sstate).unwrap();
+    let (apo, cstate) = client_read(&ap, cstate).unwrap();
+    assert!(eq(&data, &received_data(apo)));
+    let (closed, cstate) = client_read(&close, cstate).unwrap();
+    assert!(matches!(closed, Some(Received::Closed)));
+
+    // The client's close_notify is encrypted with its application keys.
+    let close = client_close(cstate).unwrap();
+    assert_eq!(close[0].declassify(), 23);
+
+    // Before the handshake keys, the close_notify is sent in plaintext.
+    let (_, cstate) = test_client_connect(&[TLS_CHACHA20_POLY1305_SHA256_X25519]);
+    let close = client_close(cstate).unwrap();
+    assert_eq!(
+        parse_alert_record(&close).unwrap(),
+        (AlertLevel::Warning, AlertDescription::CloseNotify)
+    );
+}
+
+#[test]
+fn test_alpn() {
+    let h2 = Bytes::from(b"h2");
+    let http11 = Bytes::from(b"http/1.1");
+    let rpc = Bytes::from(b"rpc");
+    let server_db = |protocols: Vec<Bytes>| ServerDB {
+        5: protocols,
+        ..test_server_db()
+    };
+
+    // The server picks its most preferred protocol among the client's.
+    let (ch, cstate) = client_connect(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        &test_sn(),
+        None,
+        None,
+        &[h2.clone(), http11.clone()],
+        None,
+        PaddingPolicy::NoPadding,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (sh, sf, sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        server_db(vec![rpc.clone(), http11.clone(), h2.clone()]),
+        &ch,
+        random_bytes(128),
+    )
+    .unwrap();
+    assert!(eq(&server_alpn(&sstate).unwrap(), &http11));
+    let (_, cstate) = client_read_handshake(&sh, cstate).unwrap();
+    let (cf, cstate) = client_read_handshake(&sf, cstate).unwrap();
+    let sstate = server_read_handshake(&cf.unwrap(), sstate).unwrap();
+    assert!(eq(&client_alpn(&cstate).unwrap(), &http11));
+    assert!(eq(&server_alpn(&sstate).unwrap(), &http11));
+
+    // Without ALPN on either side, no protocol is selected.
+    let (ch, cstate) = test_client_connect(&[TLS_CHACHA20_POLY1305_SHA256_X25519]);
+    let (cstate, sstate) = complete_handshake(
+        &ch,
+        cstate,
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        server_db(vec![rpc.clone()]),
+    );
+    assert!(client_alpn(&cstate).is_none());
+    assert!(server_alpn(&sstate).is_none());
+
+    // Without a protocol in common, the server refuses the connection.
+    let (ch, _cstate) = client_connect(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        &test_sn(),
+        None,
+        None,
+        &[h2],
+        None,
+        PaddingPolicy::NoPadding,
+        random_bytes(128),
+    )
+    .unwrap();
+    let err = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        server_db(vec![rpc]),
+        &ch,
+        random_bytes(128),
+    )
+    .err()
+    .unwrap();
+    assert_eq!(err, NO_APPLICATION_PROTOCOL);
+    assert_eq!(error_alert(err), AlertDescription::NoApplicationProtocol);
+}
+
+#[test]
+fn test_encrypted_extensions_parsing() {
+    let algs = TLS_CHACHA20_POLY1305_SHA256_X25519;
+    let ee = |exts: &[u8]| {
+        let mut msg = vec![8, 0, 0, exts.len() as u8 + 2, 0, exts.len() as u8];
+        msg.extend_from_slice(exts);
+        HandshakeData(Bytes::from(&msg[..]))
+    };
+    let (ed, protocol) =
+        parse_encrypted_extensions(&algs, &ee(&[0, 0x10, 0, 5, 0, 3, 2, b'h', b'2'])).unwrap();
+    assert!(!ed);
+    assert!(eq(&protocol.unwrap(), &Bytes::from(b"h2")));
+
+    // Extensions shorter than their type and length are a decode error.
+    for exts in [&[0u8][..], &[0, 0x10], &[0, 0x10, 0]] {
+        let err = parse_encrypted_extensions(&algs, &ee(exts)).err().unwrap();
+        assert_eq!(err, PARSE_FAILED);
+        assert_eq!(error_alert(err), AlertDescription::DecodeError);
+    }
+}
+
+#[test]
+fn test_certificate_chain() {
+    let leaf = Bytes::from(&ECDSA_P256_SHA256_CERT);
+    let intermediate = Bytes::from(&ECDSA_P256_SHA256_CERT);
+    let db = ServerDB {
+        1: vec![leaf.clone(), intermediate.clone()],
+        ..test_server_db()
+    };
+    let (ch, cstate) = test_client_connect(&[TLS_CHACHA20_POLY1305_SHA256_X25519]);
+    let (sh, sf, sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        db,
+        &ch,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (_, cstate) = client_read_handshake(&sh, cstate).unwrap();
+    assert!(client_peer_certificates(&cstate).is_empty());
+    let (cf, cstate) = client_read_handshake(&sf, cstate).unwrap();
+    let _sstate = server_read_handshake(&cf.unwrap(), sstate).unwrap();
+
+    // The client sees the whole chain, leaf first.
+    let chain = client_peer_certificates(&cstate);
+    assert_eq!(chain.len(), 2);
+    let CertificateEntry(cert, exts) = &chain[0];
+    assert!(eq(cert, &leaf));
+    assert!(exts.is_empty());
+    let CertificateEntry(cert, exts) = &chain[1];
+    assert!(eq(cert, &intermediate));
+    assert!(exts.is_empty());
+}
+
+// A time at which all test certificates are valid: 2027-01-15.
+const TEST_NOW: u64 = 1_800_000_000;
+
+// Run a handshake with a server presenting `chain` and a client connecting
+// to `sn` and trusting `trust_anchors` at time `now`, and return the client's
+// result for the server's flight.
+fn handshake_with_trust_anchors(
+    sn: &str,
+    chain: Vec<Bytes>,
+    key: &[u8; 32],
+    trust_anchors: Vec<Bytes>,
+    now: u64,
+) -> Result<(), TLSError> {
+    let sn = Bytes::from(sn.as_bytes());
+    let db = ServerDB {
+        0: sn.clone(),
+        1: chain,
+        2: bertie::tls13crypto::SignatureKey::from(key),
+        ..test_server_db()
+    };
+    let (ch, cstate) = client_connect(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        &sn,
+        None,
+        None,
+        &[],
+        Some((trust_anchors, now)),
+        PaddingPolicy::NoPadding,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (sh, sf, _sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        db,
+        &ch,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (_, cstate) = client_read_handshake(&sh, cstate).unwrap();
+    client_read_handshake(&sf, cstate).map(|_| ())
+}
+
+#[test]
+fn test_certificate_path_validation() {
+    let root = Bytes::from(&TEST_ROOT_CERT);
+    let intermediate = Bytes::from(&TEST_INTERMEDIATE_CERT);
+    let leaf = Bytes::from(&TEST_LEAF_CERT);
+    let self_signed = Bytes::from(&ECDSA_P256_SHA256_CERT);
+
+    // A path from the leaf through the intermediate to the root.
+    let res = handshake_with_trust_anchors(
+        "bertie.hacspec",
+        vec![leaf.clone(), intermediate.clone()],
+        &TEST_LEAF_Key,
+        vec![self_signed.clone(), root.clone()],
+        TEST_NOW,
+    );
+    assert!(res.is_ok());
+
+    // An intermediate or the certificate itself can be a trust anchor.
+    let res = handshake_with_trust_anchors(
+        "bertie.hacspec",
+        vec![leaf.clone(), intermediate.clone()],
+        &TEST_LEAF_Key,
+        vec![intermediate.clone()],
+        TEST_NOW,
+    );
+    assert!(res.is_ok());
+    let res = handshake_with_trust_anchors(
+        "bertie.hacspec",
+        vec![self_signed.clone()],
+        &ECDSA_P256_SHA256_Key,
+        vec![self_signed.clone()],
+        TEST_NOW,
+    );
+    assert!(res.is_ok());
+
+    // Without the intermediate or the right root there is no path.
+    let res = handshake_with_trust_anchors(
+        "bertie.hacspec",
+        vec![leaf.clone()],
+        &TEST_LEAF_Key,
+        vec![root.clone()],
+        TEST_NOW,
+    );
+    assert_eq!(res, Err(UNKNOWN_CA));
+    let res = handshake_with_trust_anchors(
+        "bertie.hacspec",
+        vec![leaf.clone(), intermediate.clone()],
+        &TEST_LEAF_Key,
+        vec![self_signed.clone()],
+        TEST_NOW,
+    );
+    assert_eq!(res, Err(UNKNOWN_CA));
+    let res = handshake_with_trust_anchors(
+        "bertie.hacspec",
+        vec![self_signed],
+        &ECDSA_P256_SHA256_Key,
+        vec![],
+        TEST_NOW,
+    );
+    assert_eq!(res, Err(UNKNOWN_CA));
+
+    // Trust anchors and intermediates that can't be parsed are skipped.
+    let garbage = Bytes::from(&[0x30u8, 0x03, 0x02, 0x01]);
+    let chain = vec![leaf.clone(), intermediate.clone()];
+    assert!(verify_certificate_chain(
+        &chain,
+        &[garbage.clone(), root.clone()],
+        TEST_NOW,
+        &server_auth_oid()
+    )
+    .is_ok());
+    let chain = vec![leaf.clone(), garbage, intermediate.clone()];
+    assert!(verify_certificate_chain(
+        &chain,
+        std::slice::from_ref(&root),
+        TEST_NOW,
+        &server_auth_oid()
+    )
+    .is_ok());
+
+    // Each copy of an intermediate is only used once on a path, and the
+    // search gives up after MAX_SIGNATURE_CHECKS signatures.
+    let mut chain = vec![leaf];
+    chain.extend(vec![intermediate; MAX_SIGNATURE_CHECKS + 1]);
+    assert!(verify_certificate_chain(&chain, &[root], TEST_NOW, &server_auth_oid()).is_ok());
+    assert_eq!(
+        verify_certificate_chain(&chain[..4], &[], TEST_NOW, &server_auth_oid()),
+        Err(UNKNOWN_CA)
+    );
+    assert_eq!(
+        verify_certificate_chain(&chain, &[], TEST_NOW, &server_auth_oid()),
+        Err(BAD_CERTIFICATE)
+    );
+}
+
+// Run a handshake in which the client presents `chain` signed with `key` to a
+// server trusting `trust_anchors` at time `now`, and return the server's
+// result for the client's flight.
+fn client_auth_with_trust_anchors(
+    chain: Vec<Bytes>,
+    key: &[u8; 32],
+    trust_anchors: Vec<Bytes>,
+    now: u64,
+) -> Result<(), TLSError> {
+    let db = ServerDB {
+        4: Some((trust_anchors, now)),
+        ..test_server_db()
+    };
+    let (ch, cstate) = client_connect(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        &test_sn(),
+        None,
+        Some((chain, bertie::tls13crypto::SignatureKey::from(key))),
+        &[],
+        None,
+        PaddingPolicy::NoPadding,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (sh, sf, sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        db,
+        &ch,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (_, cstate) = client_read_handshake(&sh, cstate).unwrap();
+    let (cf, _cstate) = client_read_handshake(&sf, cstate).unwrap();
+    server_read_handshake(&cf.unwrap(), sstate).map(|_| ())
+}
+
+#[test]
+fn test_client_certificate_validation() {
+    let root = Bytes::from(&TEST_ROOT_CERT);
+    let intermediate = Bytes::from(&TEST_INTERMEDIATE_CERT);
+    let leaf = Bytes::from(&TEST_LEAF_CERT);
+    let self_signed = Bytes::from(&ECDSA_P256_SHA256_CERT);
+
+    // The server follows the client's full chain to its trust anchor.
+    let res = client_auth_with_trust_anchors(
+        vec![leaf.clone(), intermediate.clone()],
+        &TEST_LEAF_Key,
+        vec![root.clone()],
+        TEST_NOW,
+    );
+    assert!(res.is_ok());
+
+    // Without the intermediate or a trusted root there is no path.
+    let res = client_auth_with_trust_anchors(
+        vec![leaf.clone()],
+        &TEST_LEAF_Key,
+        vec![root.clone()],
+        TEST_NOW,
+    );
+    assert_eq!(res, Err(UNKNOWN_CA));
+    let res = client_auth_with_trust_anchors(
+        vec![leaf.clone(), intermediate.clone()],
+        &TEST_LEAF_Key,
+        vec![self_signed],
+        TEST_NOW,
+    );
+    assert_eq!(res, Err(UNKNOWN_CA));
+
+    // A chain outside its validity period is rejected.
+    let res = client_auth_with_trust_anchors(
+        vec![leaf, intermediate],
+        &TEST_LEAF_Key,
+        vec![root],
+        1_700_000_000,
+    );
+    assert_eq!(res, Err(CERTIFICATE_EXPIRED));
+}
+
+#[test]
+fn test_server_name_verification() {
+    let chain = vec![
+        Bytes::from(&TEST_LEAF_CERT),
+        Bytes::from(&TEST_INTERMEDIATE_CERT),
+    ];
+    let root = Bytes::from(&TEST_ROOT_CERT);
+    let self_signed = Bytes::from(&ECDSA_P256_SHA256_CERT);
+
+    // The name is checked against the subjectAltName, ignoring case.
+    let res = handshake_with_trust_anchors(
+        "Bertie.Hacspec",
+        chain.clone(),
+        &TEST_LEAF_Key,
+        vec![root.clone()],
+        TEST_NOW,
+    );
+    assert!(res.is_ok());
+    let res = handshake_with_trust_anchors(
+        "localhost",
+        chain.clone(),
+        &TEST_LEAF_Key,
+        vec![root.clone()],
+        TEST_NOW,
+    );
+    assert_eq!(res, Err(BAD_CERTIFICATE));
+    let res = handshake_with_trust_anchors(
+        "www.bertie.hacspec",
+        chain.clone(),
+        &TEST_LEAF_Key,
+        vec![root],
+        TEST_NOW,
+    );
+    assert_eq!(res, Err(BAD_CERTIFICATE));
+
+    // Without a subjectAltName, the common name is used.
+    let res = handshake_with_trust_anchors(
+        "bertie.hacspec",
+        vec![self_signed.clone()],
+        &ECDSA_P256_SHA256_Key,
+        vec![self_signed.clone()],
+        TEST_NOW,
+    );
+    assert!(res.is_ok());
+    let res = handshake_with_trust_anchors(
+        "localhost",
+        vec![self_signed.clone()],
+        &ECDSA_P256_SHA256_Key,
+        vec![self_signed],
+        TEST_NOW,
+    );
+    assert_eq!(res, Err(BAD_CERTIFICATE));
+
+    // The name is also checked without trust anchors.
+    let sn = Bytes::from("localhost".as_bytes());
+    let db = ServerDB {
+        0: sn.clone(),
+        1: chain,
+        2: bertie::tls13crypto::SignatureKey::from(&TEST_LEAF_Key),
+        ..test_server_db()
+    };
+    let (ch, cstate) = client_connect(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        &sn,
+        None,
+        None,
+        &[],
+        None,
+        PaddingPolicy::NoPadding,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (sh, sf, _sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        db,
+        &ch,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (_, cstate) = client_read_handshake(&sh, cstate).unwrap();
+    let res = client_read_handshake(&sf, cstate).map(|_| ());
+    assert_eq!(res, Err(BAD_CERTIFICATE));
+
+    // An IP address is not sent as the server name, and the server accepts
+    // the ClientHello without one.
+    let contains = |ch: &Bytes, sn: &Bytes| {
+        let sn = sn.declassify();
+        ch.declassify().windows(sn.len()).any(|w| w == &sn[..])
+    };
+    let (ch, _) = test_client_connect(&[TLS_CHACHA20_POLY1305_SHA256_X25519]);
+    assert!(contains(&ch, &test_sn()));
+    for sn in ["127.0.0.1", "::1"] {
+        let sn = Bytes::from(sn.as_bytes());
+        let (ch, _) = client_connect(
+            &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+            &sn,
+            None,
+            None,
+            &[],
+            None,
+            PaddingPolicy::NoPadding,
+            random_bytes(128),
+        )
+        .unwrap();
+        assert!(!contains(&ch, &sn));
+        let res = server_accept(
+            &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+            test_server_db(),
+            &ch,
+            random_bytes(128),
+        );
+        assert!(res.is_ok());
+    }
+}
+
+#[test]
+fn test_certificate_validity() {
+    let chain = vec![
+        Bytes::from(&TEST_LEAF_CERT),
+        Bytes::from(&TEST_INTERMEDIATE_CERT),
+    ];
+    let root = Bytes::from(&TEST_ROOT_CERT);
+    let self_signed = Bytes::from(&ECDSA_P256_SHA256_CERT);
+
+    // The test PKI is not valid before October 2026.
+    let res = handshake_with_trust_anchors(
+        "bertie.hacspec",
+        chain.clone(),
+        &TEST_LEAF_Key,
+        vec![root.clone()],
+        1_700_000_000,
+    );
+    assert_eq!(res, Err(CERTIFICATE_EXPIRED));
+
+    // The self-signed certificate expires in April 2031, even if trusted.
+    let res = handshake_with_trust_anchors(
+        "bertie.hacspec",
+        vec![self_signed.clone()],
+        &ECDSA_P256_SHA256_Key,
+        vec![self_signed.clone()],
+        1_950_000_000,
+    );
+    assert_eq!(res, Err(CERTIFICATE_EXPIRED));
+    let res = handshake_with_trust_anchors(
+        "bertie.hacspec",
+        vec![self_signed.clone()],
+        &ECDSA_P256_SHA256_Key,
+        vec![self_signed],
+        1_900_000_000,
+    );
+    assert!(res.is_ok());
+}
+
+#[test]
+fn test_rsa_pss_signatures() {
+    let db = || ServerDB {
+        1: vec![Bytes::from(&RSA_2048_CERT)],
+        2: bertie::tls13crypto::SignatureKey::from(&RSA_2048_Key),
+        ..test_server_db()
+    };
+    let rsa_pss = |sa| {
+        Algorithms(
+            HashAlgorithm::SHA256,
+            AeadAlgorithm::Chacha20Poly1305,
+            sa,
+            KemScheme::X25519,
+            false,
+            false,
+        )
+    };
+    // The server signs with the RSA-PSS scheme the client offers, whichever
+    // one it is configured with.
+    for sa in [
+        SignatureScheme::RsaPssRsaSha256,
+        SignatureScheme::RsaPssRsaSha384,
+        SignatureScheme::RsaPssRsaSha512,
+    ] {
+        for configured in [
+            SignatureScheme::RsaPssRsaSha256,
+            SignatureScheme::RsaPssRsaSha512,
+        ] {
+            let (ch, cstate) =
+                test_client_connect(&[TLS_CHACHA20_POLY1305_SHA256_X25519, rsa_pss(sa)]);
+            let (cstate, sstate) = complete_handshake(&ch, cstate, &[rsa_pss(configured)], db());
+            assert_eq!(sig_alg(&client_algorithms(&cstate).unwrap()), sa);
+            check_client_to_server(cstate, sstate);
+        }
+    }
+
+    // A client that only offers ECDSA can't verify an RSA certificate.
+    let (ch, _cstate) = test_client_connect(&[TLS_CHACHA20_POLY1305_SHA256_X25519]);
+    assert!(server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        db(),
+        &ch,
+        random_bytes(128)
+    )
+    .is_err());
+}
+
+#[test]
+fn test_ed25519_signatures() {
+    let algs = Algorithms(
+        HashAlgorithm::SHA256,
+        AeadAlgorithm::Chacha20Poly1305,
+        SignatureScheme::ED25519,
+        KemScheme::X25519,
+        false,
+        false,
+    );
+    let db = ServerDB {
+        1: vec![Bytes::from(&TEST_ED25519_LEAF_CERT)],
+        2: bertie::tls13crypto::SignatureKey::from(&TEST_ED25519_LEAF_Key),
+        ..test_server_db()
+    };
+    let (ch, cstate) = client_connect(
+        &[algs],
+        &test_sn(),
+        None,
+        None,
+        &[],
+        Some((vec![Bytes::from(&TEST_ED25519_ROOT_CERT)], TEST_NOW)),
+        PaddingPolicy::NoPadding,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (_cstate, _sstate) = complete_handshake(&ch, cstate, &[algs], db);
+
+    // The Ed25519 root does not validate the ECDSA test PKI.
+    let res = handshake_with_trust_anchors(
+        "bertie.hacspec",
+        vec![
+            Bytes::from(&TEST_LEAF_CERT),
+            Bytes::from(&TEST_INTERMEDIATE_CERT),
+        ],
+        &TEST_LEAF_Key,
+        vec![Bytes::from(&TEST_ED25519_ROOT_CERT)],
+        TEST_NOW,
+    );
+    assert_eq!(res, Err(UNKNOWN_CA));
+}
+
+#[test]
+fn test_ecdsa_p384_signatures() {
+    let algs = Algorithms(
+        HashAlgorithm::SHA384,
+        AeadAlgorithm::Aes256Gcm,
+        SignatureScheme::EcdsaSecp384r1Sha384,
+        KemScheme::X25519,
+        false,
+        false,
+    );
+    let db = ServerDB {
+        1: vec![Bytes::from(&TEST_P384_LEAF_CERT)],
+        2: bertie::tls13crypto::SignatureKey::from(&TEST_P384_LEAF_Key),
+        ..test_server_db()
+    };
+    let (ch, cstate) = client_connect(
+        &[algs],
+        &test_sn(),
+        None,
+        None,
+        &[],
+        Some((vec![Bytes::from(&TEST_P384_ROOT_CERT)], TEST_NOW)),
+        PaddingPolicy::NoPadding,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (_cstate, _sstate) = complete_handshake(&ch, cstate, &[algs], db);
+
+    // The P-384 root does not validate the P-256 test PKI.
+    let res = handshake_with_trust_anchors(
+        "bertie.hacspec",
+        vec![
+            Bytes::from(&TEST_LEAF_CERT),
+            Bytes::from(&TEST_INTERMEDIATE_CERT),
+        ],
+        &TEST_LEAF_Key,
+        vec![Bytes::from(&TEST_P384_ROOT_CERT)],
+        TEST_NOW,
+    );
+    assert_eq!(res, Err(UNKNOWN_CA));
+}
+
+#[test]
+fn test_key_exchange_groups() {
+    let p384 = Algorithms(
+        HashAlgorithm::SHA256,
+        AeadAlgorithm::Chacha20Poly1305,
+        SignatureScheme::EcdsaSecp256r1Sha256,
+        KemScheme::Secp384r1,
+        false,
+        false,
+    );
+
+    // A P-384 handshake.
+    let (cstate, sstate) = handshake(&[p384], &[p384]);
+    check_client_to_server(cstate, sstate);
+
+    // A server that wants P-384 asks for it with a HelloRetryRequest.
+    let (ch, cstate) = test_client_connect(&[
+        TLS_CHACHA20_POLY1305_SHA256_X25519,
+        TLS_CHACHA20_POLY1305_SHA256_P256,
+        p384,
+    ]);
+    let (hrr, _sf, sstate) =
+        server_accept(&[p384], test_server_db(), &ch, random_bytes(128)).unwrap();
+    assert!(in_hello_retry(&sstate));
+    complete_retried_handshake(&hrr, cstate, sstate);
+
+    // Key shares have the size of their group, NIST curve points are
+    // uncompressed. They are derived from the caller's entropy.
+    for ks in [
+        KemScheme::X25519,
+        KemScheme::Secp256r1,
+        KemScheme::Secp384r1,
+        KemScheme::MlKem768,
+        KemScheme::X25519MlKem768,
+    ] {
+        let ent = random_bytes(kem_priv_len(&ks));
+        let (x, gx) = kem_keygen(&ks, ent.clone()).unwrap();
+        assert_eq!(gx.len(), kem_pub_len(&ks));
+        let (x_again, gx_again) = kem_keygen(&ks, ent.clone()).unwrap();
+        assert!(eq(&x, &x_again) && eq(&gx, &gx_again));
+        let (gxy, gy) = kem_encap(&ks, &gx, ent.clone()).unwrap();
+        let (gxy_again, gy_again) = kem_encap(&ks, &gx, ent).unwrap();
+        assert!(eq(&gxy, &gxy_again) && eq(&gy, &gy_again));
+        let short = random_bytes(kem_priv_len(&ks) - 1);
+        assert_eq!(kem_keygen(&ks, short.clone()), Err(INSUFFICIENT_ENTROPY));
+        assert_eq!(kem_encap(&ks, &gx, short), Err(INSUFFICIENT_ENTROPY));
+    }
+    let (_x, gx) = kem_keygen(&KemScheme::Secp256r1, random_bytes(32)).unwrap();
+    assert_eq!(
+        kem_encap(
+            &KemScheme::Secp256r1,
+            &gx.slice_range(1..65),
+            random_bytes(32)
+        ),
+        Err(INVALID_KEY_SHARE)
+    );
+    let off_curve = Bytes::from(&[0x04u8]).concat(&Bytes::zeroes(96));
+    assert_eq!(
+        kem_encap(&KemScheme::Secp384r1, &off_curve, random_bytes(48)),
+        Err(INVALID_KEY_SHARE)
+    );
+
+    // X448 and P-521 are recognized, but not implemented yet.
+    for (ks, group) in [
+        (KemScheme::X448, [0x00u8, 0x1E]),
+        (KemScheme::Secp521r1, [0x00u8, 0x19]),
+    ] {
+        assert_eq!(get_named_group(&Bytes::from(&group)), Ok(ks));
+        assert_eq!(
+            kem_keygen(&ks, random_bytes(kem_priv_len(&ks))),
+            Err(UNSUPPORTED_ALGORITHM)
+        );
+        let algs = Algorithms(
+            HashAlgorithm::SHA384,
+            AeadAlgorithm::Aes256Gcm,
+            SignatureScheme::EcdsaSecp256r1Sha256,
+            ks,
+            false,
+            false,
+        );
+        let res = client_connect(
+            &[algs],
+            &test_sn(),
+            None,
+            None,
+            &[],
+            None,
+            PaddingPolicy::NoPadding,
+            random_bytes(128),
+        );
+        assert_eq!(res.err(), Some(UNSUPPORTED_ALGORITHM));
+    }
+}
+
+#[test]
+fn test_hybrid_key_exchange() {
+    let algs = |ks| {
+        Algorithms(
+            HashAlgorithm::SHA256,
+            AeadAlgorithm::Chacha20Poly1305,
+            SignatureScheme::EcdsaSecp256r1Sha256,
+            ks,
+            false,
+            false,
+        )
+    };
+
+    for ks in [KemScheme::X25519MlKem768, KemScheme::MlKem768] {
+        let (cstate, sstate) = handshake(&[algs(ks)], &[algs(ks)]);
+        check_client_to_server(cstate, sstate);
+    }
+
+    // A server that wants the hybrid asks for it with a HelloRetryRequest.
+    let hybrid = algs(KemScheme::X25519MlKem768);
+    let (ch, cstate) = test_client_connect_retry(hybrid);
+    let (hrr, _sf, sstate) =
+        server_accept(&[hybrid], test_server_db(), &ch, random_bytes(128)).unwrap();
+    assert!(in_hello_retry(&sstate));
+    complete_retried_handshake(&hrr, cstate, sstate);
+
+    // The shares and secrets of the hybrid are those of ML-KEM-768 followed
+    // by those of X25519.
+    let ks = KemScheme::X25519MlKem768;
+    let (x, gx) = kem_keygen(&ks, random_bytes(96)).unwrap();
+    assert_eq!(gx.len(), 1216);
+    let (gxy, gy) = kem_encap(&ks, &gx, random_bytes(96)).unwrap();
+    assert_eq!((gy.len(), gxy.len()), (1120, 64));
+    assert!(eq(&gxy, &kem_decap(&ks, &gy, &x).unwrap()));
+    assert_eq!(
+        kem_encap(&ks, &gx.slice_range(0..1184), random_bytes(96)),
+        Err(INVALID_KEY_SHARE)
+    );
+    assert_eq!(
+        kem_decap(&ks, &gy.slice_range(0..1088), &x),
+        Err(INVALID_KEY_SHARE)
+    );
+}
+
+// Re-fragment a flight of plaintext handshake records into records of `len`
+// bytes.
+fn fragment_records(recs: &Bytes, len: usize) -> Bytes {
+    let header = recs.slice_range(0..3);
+    let mut payload = Bytes::new();
+    let mut rest = recs.clone();
+    while !rest.is_empty() {
+        let (rec, next) = split_record(&rest);
+        payload = payload.concat(&rec.slice_range(5..rec.len()));
+        rest = next;
+    }
+    let mut fragments = Bytes::new();
+    let mut start = 0;
+    while start < payload.len() {
+        let l = std::cmp::min(len, payload.len() - start);
+        fragments = fragments
+            .concat(&header)
+            .concat(&Bytes::from(&[(l >> 8) as u8, l as u8]))
+            .concat(&payload.slice(start, l));
+        start += l;
+    }
+    fragments
+}
+
+#[test]
+fn test_handshake_fragmentation() {
+    let algs = [TLS_CHACHA20_POLY1305_SHA256_X25519];
+    // A chain long enough for the server's flight to span several records.
+    let db = ServerDB {
+        1: vec![Bytes::from(&ECDSA_P256_SHA256_CERT); 40],
+        ..test_server_db()
+    };
+    let (ch, cstate) = test_client_connect(&algs);
+    assert_eq!(ch[2].declassify(), 1);
+
+    // The ClientHello arrives in records of 100 bytes.
+    let ch = fragment_records(&ch, 100);
+    let (first, rest) = split_record(&ch);
+    let (sh, sf, sstate) = server_accept(&algs, db, &first, random_bytes(128)).unwrap();
+    assert!(sh.is_empty() && sf.is_empty());
+    assert!(in_client_hello(&sstate) && !in_hello_retry(&sstate));
+    let (sh, sf, sstate) = server_accept_retry(&rest, sstate, random_bytes(128)).unwrap();
+    assert!(!in_client_hello(&sstate));
+
+    // The server's flight is fragmented at 2^14 bytes.
+    let (sf_first, sf_rest) = split_record(&sf);
+    assert_eq!(sf_first.len(), 5 + 16384 + 1 + 16);
+    assert!(!sf_rest.is_empty());
+
+    // The client buffers partial messages until they are complete.
+    let sh = fragment_records(&sh, 10);
+    let (first, rest) = split_record(&sh);
+    let (out, cstate) = client_read_handshake(&first, cstate).unwrap();
+    assert!(out.is_none());
+    let (out, cstate) = client_read_handshake(&rest, cstate).unwrap();
+    assert!(out.is_none());
+    let (out, cstate) = client_read_handshake(&sf_first, cstate).unwrap();
+    assert!(out.is_none());
+    let (cf, cstate) = client_read_handshake(&sf_rest, cstate).unwrap();
+    assert_eq!(client_peer_certificates(&cstate).len(), 40);
+
+    assert!(in_handshake(&sstate));
+    let sstate = server_read_handshake(&cf.unwrap(), sstate).unwrap();
+    assert!(!in_handshake(&sstate));
+    check_client_to_server(cstate, sstate);
+}
+
+#[test]
+fn test_large_writes() {
+    let algs = [TLS_AES_128_GCM_SHA256_X25519];
+    let (cstate, mut sstate) = handshake(&algs, &algs);
+
+    // A write is split into records of at most 2^14 bytes, read one by one.
+    let data = random_bytes(40000);
+    let (ap, cstate) = client_write(app_data(data.clone()), cstate).unwrap();
+    let mut recs = ap;
+    let mut received = Bytes::new();
+    let mut count = 0;
+    while !recs.is_empty() {
+        let (rec, rest) = split_record(&recs);
+        assert!(rec.len() <= 5 + 16384 + 1 + 16);
+        let (apo, next) = server_read(&rec, sstate).unwrap();
+        received = received.concat(&received_data(apo));
+        sstate = next;
+        recs = rest;
+        count += 1;
+    }
+    assert_eq!(count, 3);
+    assert!(eq(&data, &received));
+
+    // The same holds for the server.
+    let (ap, _sstate) = server_write(app_data(data.clone()), sstate).unwrap();
+    let (rec, rest) = split_record(&ap);
+    assert!(!rest.is_empty());
+    let (apo, cstate) = client_read(&rec, cstate).unwrap();
+    assert_eq!(received_data(apo).len(), 16384);
+
+    // Records above 2^14 + 256 bytes are rejected before decryption.
+    let len = 16384 + 257;
+    let big = Bytes::from(&[23, 3, 3, (len >> 8) as u8, len as u8]).concat(&Bytes::zeroes(len));
+    let err = client_read(&big, cstate).err().unwrap();
+    assert_eq!(err, RECORD_OVERFLOW);
+    assert_eq!(error_alert(err), AlertDescription::RecordOverflow);
+}
+
+// Pads application data by 100 bytes and leaves other records alone.
+fn pad_app_data(ct: ContentType, _len: usize) -> usize {
+    match ct {
+        ContentType::ApplicationData => 100,
+        _ => 0,
+    }
+}
+
+#[test]
+fn test_record_padding() {
+    let algs = [TLS_CHACHA20_POLY1305_SHA256_X25519];
+    let connect = |client_pad, server_pad| {
+        let db = ServerDB {
+            6: server_pad,
+            ..test_server_db()
+        };
+        let (ch, cstate) = client_connect(
+            &algs,
+            &test_sn(),
+            None,
+            None,
+            &[],
+            None,
+            client_pad,
+            random_bytes(128),
+        )
+        .unwrap();
+        let (sh, sf, sstate) = server_accept(&algs, db, &ch, random_bytes(128)).unwrap();
+        let (_, cstate) = client_read_handshake(&sh, cstate).unwrap();
+        let (cf, cstate) = client_read_handshake(&sf, cstate).unwrap();
+        let cf = cf.unwrap();
+        let sstate = server_read_handshake(&cf, sstate).unwrap();
+        (sf, cf, cstate, sstate)
+    };
+    let data = Bytes::from(b"0123456789");
+
+    // Record lengths are the padded content, the content type and the tag.
+    let (sf, cf, cstate, sstate) = connect(PaddingPolicy::Block(64), PaddingPolicy::Fixed(4096));
+    assert_eq!(sf.len(), 5 + 4096 + 1 + 16);
+    assert_eq!(cf.len(), 5 + 64 + 1 + 16);
+    let alert = send_alert(AlertDescription::CloseNotify, &client_alert_sender(&cstate)).unwrap();
+    assert_eq!(alert.len(), 5 + 64 + 1 + 16);
+    let alert = send_alert(AlertDescription::CloseNotify, &server_alert_sender(&sstate)).unwrap();
+    assert_eq!(alert.len(), 5 + 4096 + 1 + 16);
+    let (ap, _cstate) = client_write(app_data(data.clone()), cstate).unwrap();
+    assert_eq!(ap.len(), 5 + 64 + 1 + 16);
+    let (apo, sstate) = server_read(&ap, sstate).unwrap();
+    assert!(eq(&data, &received_data(apo)));
+    let (ap, _sstate) = server_write(app_data(data.clone()), sstate).unwrap();
+    assert_eq!(ap.len(), 5 + 4096 + 1 + 16);
+
+    // Padding never exceeds the maximum record size.
+    let (sf, cf, cstate, sstate) = connect(
+        PaddingPolicy::Custom(pad_app_data),
+        PaddingPolicy::Fixed(20000),
+    );
+    assert_eq!(sf.len(), 5 + 16384 + 1 + 16);
+    assert_eq!(cf.len(), 5 + 36 + 1 + 16);
+    let (ap, _cstate) = client_write(app_data(data.clone()), cstate).unwrap();
+    assert_eq!(ap.len(), 5 + 10 + 100 + 1 + 16);
+    let (apo, _sstate) = server_read(&ap, sstate).unwrap();
+    assert!(eq(&data, &received_data(apo)));
+
+    // Long writes are fragmented so that every record is padded.
+    let record_lens = |b: &Bytes| {
+        let mut lens = Vec::new();
+        let mut rest = b.clone();
+        while !rest.is_empty() {
+            let (rec, next) = split_record(&rest);
+            lens.push(rec.len() - 5 - 1 - 16);
+            rest = next;
+        }
+        lens
+    };
+    let long = Bytes::zeroes(40000);
+    let (_sf, _cf, cstate, sstate) =
+        connect(PaddingPolicy::Block(1000), PaddingPolicy::Fixed(4096));
+    let (ap, _cstate) = client_write(app_data(long.clone()), cstate).unwrap();
+    assert_eq!(record_lens(&ap), vec![16000, 16000, 8000]);
+    let (ap, _sstate) = server_write(app_data(long.clone()), sstate).unwrap();
+    assert_eq!(record_lens(&ap), vec![4096; 10]);
+    let (_sf, _cf, cstate, sstate) = connect(
+        PaddingPolicy::Custom(pad_app_data),
+        PaddingPolicy::NoPadding,
+    );
+    let (ap, _cstate) = client_write(app_data(long.clone()), cstate).unwrap();
+    assert_eq!(record_lens(&ap), vec![16384, 16384, 7532]);
+    let mut rest = ap;
+    let mut sstate = sstate;
+    let mut received = Bytes::new();
+    while !rest.is_empty() {
+        let (rec, next) = split_record(&rest);
+        let (apo, next_sstate) = server_read(&rec, sstate).unwrap();
+        received = received.concat(&received_data(apo));
+        sstate = next_sstate;
+        rest = next;
+    }
+    assert!(eq(&long, &received));
+}
+
+#[test]
+fn test_structured_errors() {
+    // A ClientHello that only offers TLS 1.2 in its supported_versions
+    // extension is rejected while reading the ClientHello.
+    let (mut ch, _cstate) = test_client_connect(&[TLS_CHACHA20_POLY1305_SHA256_X25519]);
+    let raw = ch.declassify();
+    let pos = raw
+        .windows(7)
+        .position(|w| w == [0x00, 0x2b, 0x00, 0x03, 0x02, 0x03, 0x04])
+        .unwrap();
+    ch[pos + 6] = U8::from(3);
+    let err = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        test_server_db(),
+        &ch,
+        random_bytes(128),
+    )
+    .err()
+    .unwrap();
+    assert_eq!(err.stage(), Some(Stage::ClientHello));
+    assert_eq!(err.context(), Some("supported_versions extension"));
+    assert_eq!(err.alert(), error_alert(err));
+    assert!(err.to_string().contains("supported_versions extension"));
+
+    // A tampered application data record fails once connected.
+    let (cstate, sstate) = handshake(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+    );
+    let data = Bytes::from(b"Hello server, here is the client");
+    let (mut ap, _cstate) = client_write(app_data(data), cstate).unwrap();
+    let last = ap.len() - 1;
+    ap[last] = ap[last] ^ U8::from(1);
+    let err = server_read(&ap, sstate).err().unwrap();
+    assert_eq!(err, BAD_RECORD_MAC);
+    assert_eq!(err.kind(), ErrorKind::BadRecordMac);
+    assert_eq!(err.stage(), Some(Stage::Connected));
+    assert_eq!(err.alert(), AlertDescription::BadRecordMac);
+    assert_eq!(
+        err.to_string(),
+        format!("{} ({})", err.kind(), Stage::Connected)
+    );
+    let _: &dyn std::error::Error = &err;
+}
+
+#[test]
+fn test_exporters() {
+    let label = Bytes::from(b"EXPORTER-Channel-Binding");
+    let (ch, cstate) = test_client_connect(&[TLS_CHACHA20_POLY1305_SHA256_X25519]);
+    assert_eq!(
+        client_export_keying_material(&label, &Bytes::new(), 32, &cstate),
+        Err(INCORRECT_STATE)
+    );
+    assert_eq!(
+        client_export_early_keying_material(&label, &Bytes::new(), 32, &cstate),
+        Err(ZERO_RTT_DISABLED)
+    );
+    let (sh, sf, sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519],
+        test_server_db(),
+        &ch,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (_, cstate) = client_read_handshake(&sh, cstate).unwrap();
+    let (cf, cstate) = client_read_handshake(&sf, cstate).unwrap();
+    assert_eq!(
+        server_export_keying_material(&label, &Bytes::new(), 32, &sstate),
+        Err(INCORRECT_STATE)
+    );
+    let sstate = server_read_handshake(&cf.unwrap(), sstate).unwrap();
+
+    // Both ends export the same keying material, which depends on the label,
+    // the context and the length.
+    let ekm = client_export_keying_material(&label, &Bytes::new(), 32, &cstate).unwrap();
+    assert_eq!(ekm.len(), 32);
+    let sekm = server_export_keying_material(&label, &Bytes::new(), 32, &sstate).unwrap();
+    assert!(eq(&ekm, &sekm));
+    let other =
+        client_export_keying_material(&Bytes::from(b"EXPORTER-Other"), &Bytes::new(), 32, &cstate)
+            .unwrap();
+    assert!(!eq(&ekm, &other));
+    let ctx = Bytes::from(b"context");
+    let cekm = client_export_keying_material(&label, &ctx, 32, &cstate).unwrap();
+    assert!(!eq(&ekm, &cekm));
+    assert!(eq(
+        &cekm,
+        &server_export_keying_material(&label, &ctx, 32, &sstate).unwrap()
+    ));
+    let long = client_export_keying_material(&label, &Bytes::new(), 100, &cstate).unwrap();
+    assert!(eq(
+        &long,
+        &server_export_keying_material(&label, &Bytes::new(), 100, &sstate).unwrap()
+    ));
+    assert!(!eq(&long.slice(0, 32), &ekm));
+
+    // The exporter master secret is not affected by a KeyUpdate.
+    let (ku, cstate) = client_send_key_update(false, cstate).unwrap();
+    let (_, sstate) = server_read(&ku, sstate).unwrap();
+    assert!(eq(
+        &ekm,
+        &client_export_keying_material(&label, &Bytes::new(), 32, &cstate).unwrap()
+    ));
+    assert!(eq(
+        &ekm,
+        &server_export_keying_material(&label, &Bytes::new(), 32, &sstate).unwrap()
+    ));
+
+    // With 0-RTT, both ends export the same early keying material while early
+    // data is sent, which differs from the keying material of the connection.
+    let tkt = Bytes::from(b"ticket-0");
+    let (nst, server_psk, _sstate) =
+        server_send_ticket(&tkt, 3600, 16384, sstate, random_bytes(12)).unwrap();
+    let (_, cstate) = client_read(&nst, cstate).unwrap();
+    let (tickets, _cstate) = client_take_tickets(cstate);
+    let db = ServerDB {
+        3: Some((tkt, server_psk)),
+        ..test_server_db()
+    };
+    let (ch, cstate) = client_connect(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519_PSK_0RTT],
+        &test_sn(),
+        Some(tickets[0].clone()),
+        None,
+        &[],
+        None,
+        PaddingPolicy::NoPadding,
+        random_bytes(128),
+    )
+    .unwrap();
+    let early = client_export_early_keying_material(&label, &Bytes::new(), 32, &cstate).unwrap();
+    let (ed, cstate) =
+        client_write_early(app_data(Bytes::from(b"GET / HTTP/1.1")), cstate).unwrap();
+    let (sh, sf, sstate) = server_accept(
+        &[TLS_CHACHA20_POLY1305_SHA256_X25519_PSK_0RTT],
+        db,
+        &ch,
+        random_bytes(128),
+    )
+    .unwrap();
+    let (_, sstate) = server_read_early(&ed, sstate).unwrap();
+    assert!(eq(
+        &early,
+        &server_export_early_keying_material(&label, &Bytes::new(), 32, &sstate).unwrap()
+    ));
+    let (_, cstate) = client_read_handshake(&sh, cstate).unwrap();
+    let (cf, cstate) = client_read_handshake(&sf, cstate).unwrap();
+    assert_eq!(
+        client_export_early_keying_material(&label, &Bytes::new(), 32, &cstate),
+        Err(INCORRECT_STATE)
+    );
+    let (eoed, cfin) = split_record(&cf.unwrap());
+    let (_, sstate) = server_read_early(&eoed, sstate).unwrap();
+    assert_eq!(
+        server_export_early_keying_material(&label, &Bytes::new(), 32, &sstate),
+        Err(ZERO_RTT_DISABLED)
+    );
+    let sstate = server_read_handshake(&cfin, sstate).unwrap();
+    let ekm = client_export_keying_material(&label, &Bytes::new(), 32, &cstate).unwrap();
+    assert!(!eq(&ekm, &early));
+    assert!(eq(
+        &ekm,
+        &server_export_keying_material(&label, &Bytes::new(), 32, &sstate).unwrap()
+    ));
+}